@@ -1,5 +1,5 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
+//! This build script copies the `memory/*.x` file of the selected chip feature into
+//! a directory where the linker can always find it as `memory.x` at build time.
 //! For many projects this is optional, as the linker always searches the
 //! project root directory -- wherever `Cargo.toml` is. However, if you
 //! are using a workspace or have a more complicated build setup, this
@@ -9,35 +9,205 @@
 //! new memory settings.
 //!
 //! The build script also sets the linker flags to tell it which link script to use.
+//!
+//! Defaults of a deployment are read from `BRIDGE_*` environment variables into
+//! `config_gen.rs`, included by `src/defaults.rs`.
+//!
+//! The commit being built is embedded as `BRIDGE_GIT_HASH` for `src/version.rs`.
 
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variable, its default and the range of a numeric build time setting
+struct Setting {
+    var: &'static str,
+    name: &'static str,
+    ty: &'static str,
+    default: u32,
+    range: (u32, u32),
+}
+
+const SETTINGS: [Setting; 10] = [
+    Setting {
+        var: "BRIDGE_BAUD_HOST",
+        name: "HOST_BAUD",
+        ty: "u32",
+        default: 115_200,
+        range: (9600, 921_600),
+    },
+    Setting {
+        var: "BRIDGE_BAUD_GPS",
+        name: "GPS_BAUD",
+        ty: "u32",
+        default: 9600,
+        range: (9600, 921_600),
+    },
+    Setting {
+        var: "BRIDGE_BUFFER_SIZE",
+        name: "TX_BUFFER_SIZE",
+        ty: "usize",
+        default: 256,
+        range: (256, 8192),
+    },
+    Setting {
+        var: "BRIDGE_RS485_ADDRESS",
+        name: "RS485_ADDRESS",
+        ty: "u8",
+        default: 0,
+        range: (0, 127),
+    },
+    Setting {
+        var: "BRIDGE_RS485_ASSERT_US",
+        name: "RS485_ASSERT_US",
+        ty: "u32",
+        default: 10,
+        range: (0, 1000),
+    },
+    Setting {
+        var: "BRIDGE_RS485_DEASSERT_US",
+        name: "RS485_DEASSERT_US",
+        ty: "u32",
+        default: 10,
+        range: (0, 1000),
+    },
+    Setting {
+        var: "BRIDGE_I2C_ADDRESS",
+        name: "I2C_ADDRESS",
+        ty: "u8",
+        default: 0x42,
+        range: (0x08, 0x77),
+    },
+    Setting {
+        var: "BRIDGE_CAN_BITRATE",
+        name: "CAN_BITRATE",
+        ty: "u32",
+        default: 250_000,
+        range: (10_000, 1_000_000),
+    },
+    Setting {
+        var: "BRIDGE_CAN_ADDRESS",
+        name: "CAN_ADDRESS",
+        ty: "u8",
+        default: 0x80,
+        range: (0, 251),
+    },
+    Setting {
+        var: "BRIDGE_LORA_FREQUENCY_KHZ",
+        name: "LORA_FREQUENCY_KHZ",
+        ty: "u32",
+        default: 868_100,
+        range: (137_000, 1_020_000),
+    },
+];
 
 fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // Put the chip's memory layout in our output directory as `memory.x` and ensure it's
+    // on the linker search path. src/chip.rs rejects other than one chip feature.
+    let memory: &[u8] = if env::var_os("CARGO_FEATURE_CHIP_L476").is_some() {
+        if env::var_os("CARGO_FEATURE_AB_BOOT").is_some() {
+            include_bytes!("memory/l476-ab.x")
+        } else {
+            include_bytes!("memory/l476.x")
+        }
+    } else if env::var_os("CARGO_FEATURE_CHIP_L452").is_some() {
+        include_bytes!("memory/l452.x")
+    } else {
+        include_bytes!("memory/l432.x")
+    };
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
     // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
+    // any file in the project changes. By specifying `memory`
     // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+    // a memory layout is changed.
+    println!("cargo:rerun-if-changed=memory");
+
+    write_config(out);
+    embed_git_hash();
 
-    // Specify linker arguments.
+    // Specify linker arguments, for the firmware only, so the unit tests of the library link
+    // for the host.
 
     // `--nmagic` is required if memory section addresses are not aligned to 0x10000,
     // for example the FLASH and RAM sections in your `memory.x`.
     // See https://github.com/rust-embedded/cortex-m-quickstart/pull/95
-    println!("cargo:rustc-link-arg=--nmagic");
+    println!("cargo:rustc-link-arg-bins=--nmagic");
 
     // Set the linker script to the one provided by cortex-m-rt.
-    println!("cargo:rustc-link-arg=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+
+    // defmt places its format strings in sections defined by its own linker script
+    if env::var_os("CARGO_FEATURE_LOG").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
+}
+
+/// Set `BRIDGE_GIT_HASH` to the short hash of the commit being built, or `unknown` outside a git
+/// checkout
+fn embed_git_hash() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=BRIDGE_GIT_HASH={}", hash);
+    // Checkouts change HEAD, commits the branch it refers to
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", branch);
+        }
+    }
+}
+
+/// Generate `config_gen.rs` from the `BRIDGE_*` environment variables, panicking on values that
+/// can't be parsed or are out of range. Baud rates and filter types are checked again against
+/// the supported ones where they are used, at compile time.
+fn write_config(out: &Path) {
+    let mut generated = String::new();
+    for setting in SETTINGS {
+        println!("cargo:rerun-if-env-changed={}", setting.var);
+        let value = match env::var(setting.var) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number, not {}", setting.var, value)),
+            Err(_) => setting.default,
+        };
+        let (min, max) = setting.range;
+        if !(min..=max).contains(&value) {
+            panic!("{} must be {} to {}, not {}", setting.var, min, max, value);
+        }
+        generated += &format!("pub const {}: {} = {};\n", setting.name, setting.ty, value);
+    }
+
+    println!("cargo:rerun-if-env-changed=BRIDGE_DEFAULT_FILTERS");
+    let filters = env::var("BRIDGE_DEFAULT_FILTERS").unwrap_or_else(|_| "ALL".to_owned());
+    let filters = filters.trim().to_ascii_uppercase();
+    if filters.is_empty()
+        || !filters
+            .bytes()
+            .all(|byte| byte.is_ascii_alphabetic() || byte == b',')
+    {
+        panic!(
+            "BRIDGE_DEFAULT_FILTERS must be a comma separated list of sentence types, e.g. GGA,RMC"
+        );
+    }
+    generated += &format!("pub const DEFAULT_FILTERS: &str = {:?};\n", filters);
+
+    File::create(out.join("config_gen.rs"))
+        .unwrap()
+        .write_all(generated.as_bytes())
+        .unwrap();
 }
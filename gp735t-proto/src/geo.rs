@@ -0,0 +1,409 @@
+//! Conversion between NMEA coordinates, `(d)ddmm.mmmm` with a hemisphere field, and fixed point
+//! degrees scaled by 1e7 in an `i32`, without floating point so the firmware needs no soft f64.
+//!
+//! 1e-7 degrees is about 1.1 cm, finer than the 1e-5 or 1e-6 minutes receivers send, and
+//! ±180° fits in an `i32` with room to spare.
+//!
+//! Speed and altitude are converted from millimetres per second and millimetres into thousandths
+//! of a [`SpeedUnit`] or [`AltitudeUnit`] the same way, rounded to the nearest.
+
+use crate::nmea;
+use core::fmt::{self, Write};
+
+/// Fixed point units per degree
+pub const SCALE: i32 = 10_000_000;
+
+/// Fractional minute digits parsed, the GP-735T sends 5
+pub const MINUTE_DECIMALS: u32 = 6;
+
+/// Fractional minute digits written by [`write_nmea`]
+pub const WRITE_DECIMALS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Latitude,
+    Longitude,
+}
+
+impl Axis {
+    pub fn max_degrees(&self) -> i32 {
+        match self {
+            Axis::Latitude => 90,
+            Axis::Longitude => 180,
+        }
+    }
+
+    /// Digits of whole degrees in the NMEA field
+    pub fn degree_digits(&self) -> usize {
+        match self {
+            Axis::Latitude => 2,
+            Axis::Longitude => 3,
+        }
+    }
+
+    /// Hemisphere letters of positive and negative values
+    pub fn hemispheres(&self) -> [u8; 2] {
+        match self {
+            Axis::Latitude => [b'N', b'S'],
+            Axis::Longitude => [b'E', b'W'],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not a decimal number
+    Format,
+    /// Minutes of 60 or more, or beyond the axis' maximum
+    Range,
+    /// Hemisphere field isn't one of the axis' letters
+    Hemisphere,
+}
+
+/// Parse a coordinate field and its hemisphere field into 1e-7 degrees, `None` if the coordinate
+/// is empty as it is without a fix. Minutes are rounded to the nearest 1e-7 degree.
+pub fn parse(field: &[u8], hemisphere: &[u8], axis: Axis) -> Result<Option<i32>, Error> {
+    let Some(value) = nmea::fixed(field, MINUTE_DECIMALS).map_err(|_| Error::Format)? else {
+        return Ok(None);
+    };
+    if value < 0 {
+        return Err(Error::Format);
+    }
+    // value is dddmm.mmmmmm scaled by 1e6
+    let degrees = value / 100_000_000;
+    let micro_minutes = value % 100_000_000;
+    if micro_minutes >= 60_000_000 {
+        return Err(Error::Range);
+    }
+    // 1e-6 minutes to 1e-7 degrees is a factor of 10/60
+    let e7 = degrees * i64::from(SCALE) + (micro_minutes * 10 + 30) / 60;
+    if e7 > i64::from(axis.max_degrees() * SCALE) {
+        return Err(Error::Range);
+    }
+    let [positive, negative] = axis.hemispheres();
+    match hemisphere {
+        [letter] if *letter == positive => Ok(Some(e7 as i32)),
+        [letter] if *letter == negative => Ok(Some(-e7 as i32)),
+        _ => Err(Error::Hemisphere),
+    }
+}
+
+/// Write 1e-7 degrees as NMEA fields, `(d)ddmm.mmmmm,<hemisphere>`. Minutes are truncated to
+/// [`WRITE_DECIMALS`] digits.
+pub fn write_nmea(out: &mut impl Write, value: i32, axis: Axis) -> fmt::Result {
+    let hemisphere = axis.hemispheres()[usize::from(value < 0)];
+    let value = value.unsigned_abs();
+    let degrees = value / SCALE as u32;
+    // Minutes in 1e-5, 1e-7 degrees are 6e-6 minutes
+    let minutes = value % SCALE as u32 * 3 / 5;
+    write!(
+        out,
+        "{:0width$}{:02}.{:05},{}",
+        degrees,
+        minutes / 100_000,
+        minutes % 100_000,
+        char::from(hemisphere),
+        width = axis.degree_digits()
+    )
+}
+
+/// Write 1e-7 degrees as signed decimal degrees with 7 decimals, e.g. `-33.8568000`
+pub fn write_decimal(out: &mut impl Write, value: i32) -> fmt::Result {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+    write!(
+        out,
+        "{}{}.{:07}",
+        sign,
+        value / SCALE as u32,
+        value % SCALE as u32
+    )
+}
+
+/// Unit of speeds sent to the host, selected by `UNITS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedUnit {
+    /// Metres per second, the default
+    Mps,
+    Kmh,
+    Mph,
+    Knots,
+}
+
+impl SpeedUnit {
+    pub const ALL: [SpeedUnit; 4] = [
+        SpeedUnit::Mps,
+        SpeedUnit::Kmh,
+        SpeedUnit::Mph,
+        SpeedUnit::Knots,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedUnit::Mps => "MPS",
+            SpeedUnit::Kmh => "KMH",
+            SpeedUnit::Mph => "MPH",
+            SpeedUnit::Knots => "KNOTS",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|unit| name.eq_ignore_ascii_case(unit.name().as_bytes()))
+    }
+
+    /// Code in binary records
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(code)).copied()
+    }
+
+    /// Thousandths of the unit from millimetres per second
+    pub fn convert(&self, mm_per_s: u32) -> u32 {
+        // Units per metre per second as a fraction
+        let (numerator, denominator): (u64, u64) = match self {
+            SpeedUnit::Mps => (1, 1),
+            // 3.6
+            SpeedUnit::Kmh => (18, 5),
+            // 3600 / 1609.344
+            SpeedUnit::Mph => (3_600_000, 1_609_344),
+            // 3600 / 1852
+            SpeedUnit::Knots => (900, 463),
+        };
+        let value = (u64::from(mm_per_s) * numerator + denominator / 2) / denominator;
+        u32::try_from(value).unwrap_or(u32::MAX)
+    }
+}
+
+/// Unit of altitudes sent to the host, selected by `UNITS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltitudeUnit {
+    /// Metres, the default
+    M,
+    Ft,
+}
+
+impl AltitudeUnit {
+    pub const ALL: [AltitudeUnit; 2] = [AltitudeUnit::M, AltitudeUnit::Ft];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AltitudeUnit::M => "M",
+            AltitudeUnit::Ft => "FT",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|unit| name.eq_ignore_ascii_case(unit.name().as_bytes()))
+    }
+
+    /// Code in binary records
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(code)).copied()
+    }
+
+    /// Thousandths of the unit from millimetres, rounded half away from zero
+    pub fn convert(&self, mm: i32) -> i32 {
+        match self {
+            AltitudeUnit::M => mm,
+            AltitudeUnit::Ft => {
+                // A foot is 0.3048 m, 1250/381 feet per metre
+                let scaled = i64::from(mm) * 1250;
+                let rounded = (scaled + scaled.signum() * 381 / 2) / 381;
+                rounded.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    fn nmea(value: i32, axis: Axis) -> String<20> {
+        let mut out = String::new();
+        write_nmea(&mut out, value, axis).unwrap();
+        out
+    }
+
+    #[test]
+    fn parses_latitude_and_longitude() {
+        // 48°07.038' is 48.1173°
+        assert_eq!(
+            parse(b"4807.038", b"N", Axis::Latitude),
+            Ok(Some(481_173_000))
+        );
+        // 11°31.000' is 11.516667°, rounded
+        assert_eq!(
+            parse(b"01131.000", b"E", Axis::Longitude),
+            Ok(Some(115_166_667))
+        );
+    }
+
+    #[test]
+    fn southern_and_western_are_negative() {
+        assert_eq!(
+            parse(b"3351.40800", b"S", Axis::Latitude),
+            Ok(Some(-338_568_000))
+        );
+        assert_eq!(
+            parse(b"15112.91800", b"W", Axis::Longitude),
+            Ok(Some(-1_512_153_000))
+        );
+    }
+
+    #[test]
+    fn rounds_to_nearest_unit() {
+        // 0.000001' is 1.67e-8°, rounds down, 0.000003' is 5e-8°, rounds up
+        assert_eq!(parse(b"0000.000001", b"N", Axis::Latitude), Ok(Some(0)));
+        assert_eq!(parse(b"0000.000003", b"N", Axis::Latitude), Ok(Some(1)));
+        // Digits beyond 1e-6 minutes are truncated before rounding
+        assert_eq!(parse(b"0000.0000019", b"N", Axis::Latitude), Ok(Some(0)));
+    }
+
+    #[test]
+    fn limits_of_each_axis() {
+        assert_eq!(
+            parse(b"9000.0000", b"N", Axis::Latitude),
+            Ok(Some(900_000_000))
+        );
+        assert_eq!(
+            parse(b"18000.0000", b"W", Axis::Longitude),
+            Ok(Some(-1_800_000_000))
+        );
+        assert_eq!(parse(b"9000.0001", b"N", Axis::Latitude), Err(Error::Range));
+        assert_eq!(
+            parse(b"18100.0000", b"E", Axis::Longitude),
+            Err(Error::Range)
+        );
+        // 89°59.99999' stays below 90°
+        assert_eq!(
+            parse(b"8959.99999", b"N", Axis::Latitude),
+            Ok(Some(899_999_998))
+        );
+    }
+
+    #[test]
+    fn empty_field_is_no_position() {
+        assert_eq!(parse(b"", b"", Axis::Latitude), Ok(None));
+        assert_eq!(parse(b"", b"N", Axis::Longitude), Ok(None));
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(parse(b"4860.000", b"N", Axis::Latitude), Err(Error::Range));
+        assert_eq!(parse(b"48O7.038", b"N", Axis::Latitude), Err(Error::Format));
+        assert_eq!(
+            parse(b"-4807.038", b"N", Axis::Latitude),
+            Err(Error::Format)
+        );
+        assert_eq!(
+            parse(b"4807.038", b"E", Axis::Latitude),
+            Err(Error::Hemisphere)
+        );
+        assert_eq!(
+            parse(b"4807.038", b"", Axis::Latitude),
+            Err(Error::Hemisphere)
+        );
+        assert_eq!(
+            parse(b"4807.038", b"NN", Axis::Latitude),
+            Err(Error::Hemisphere)
+        );
+        assert_eq!(
+            parse(b"01131.000", b"n", Axis::Longitude),
+            Err(Error::Hemisphere)
+        );
+    }
+
+    #[test]
+    fn writes_nmea_fields() {
+        assert_eq!(nmea(481_173_000, Axis::Latitude), "4807.03800,N");
+        assert_eq!(nmea(-1_512_153_000, Axis::Longitude), "15112.91800,W");
+        assert_eq!(nmea(0, Axis::Longitude), "00000.00000,E");
+        assert_eq!(nmea(-1, Axis::Latitude), "0000.00000,S");
+    }
+
+    #[test]
+    fn nmea_round_trip_within_written_precision() {
+        for value in [
+            0i32,
+            1,
+            481_173_000,
+            -338_568_000,
+            899_999_999,
+            -1_799_999_999,
+        ] {
+            for axis in [Axis::Latitude, Axis::Longitude] {
+                if value.abs() > axis.max_degrees() * SCALE {
+                    continue;
+                }
+                let text = nmea(value, axis);
+                let (field, hemisphere) = text.split_once(',').unwrap();
+                let parsed = parse(field.as_bytes(), hemisphere.as_bytes(), axis)
+                    .unwrap()
+                    .unwrap();
+                // 1e-5 minutes written are 1.67e-7 degrees
+                assert!((parsed - value).abs() <= 2, "{} became {}", value, parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_speed() {
+        // 10 m/s
+        assert_eq!(SpeedUnit::Mps.convert(10_000), 10_000);
+        assert_eq!(SpeedUnit::Kmh.convert(10_000), 36_000);
+        assert_eq!(SpeedUnit::Mph.convert(10_000), 22_369);
+        assert_eq!(SpeedUnit::Knots.convert(10_000), 19_438);
+        // A knot is 1852 m/h, 514.44 mm/s
+        assert_eq!(SpeedUnit::Knots.convert(514), 999);
+        assert_eq!(SpeedUnit::Kmh.convert(0), 0);
+        assert_eq!(SpeedUnit::Kmh.convert(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn converts_altitude() {
+        assert_eq!(AltitudeUnit::M.convert(-12_345), -12_345);
+        // 545.4 m is 1789.370 ft
+        assert_eq!(AltitudeUnit::Ft.convert(545_400), 1_789_370);
+        assert_eq!(AltitudeUnit::Ft.convert(-545_400), -1_789_370);
+        assert_eq!(AltitudeUnit::Ft.convert(304_800), 1_000_000);
+        assert_eq!(AltitudeUnit::Ft.convert(i32::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn unit_names_and_codes() {
+        for unit in SpeedUnit::ALL {
+            assert_eq!(SpeedUnit::from_name(unit.name().as_bytes()), Some(unit));
+            assert_eq!(SpeedUnit::from_code(unit.code()), Some(unit));
+        }
+        for unit in AltitudeUnit::ALL {
+            assert_eq!(AltitudeUnit::from_name(unit.name().as_bytes()), Some(unit));
+            assert_eq!(AltitudeUnit::from_code(unit.code()), Some(unit));
+        }
+        assert_eq!(SpeedUnit::from_name(b"kmh"), Some(SpeedUnit::Kmh));
+        assert_eq!(SpeedUnit::from_name(b"FT"), None);
+        assert_eq!(AltitudeUnit::from_code(2), None);
+    }
+
+    #[test]
+    fn writes_decimal_degrees() {
+        let mut out = String::<16>::new();
+        write_decimal(&mut out, -338_568_000).unwrap();
+        assert_eq!(out, "-33.8568000");
+        out.clear();
+        write_decimal(&mut out, 5).unwrap();
+        assert_eq!(out, "0.0000005");
+    }
+}
@@ -0,0 +1,18 @@
+//! Protocols spoken by the GP-735T bridge, shared by the firmware and host tools.
+//!
+//! [`nmea`] and [`ubx`] parse what the GPS sends and build what it is sent, [`nmea::encode`]
+//! builds sentences for the host and [`pubx`] text configuration for the GPS, [`geo`] converts
+//! coordinates to fixed point degrees, [`protocol`] frames the binary output of `MODE BIN` and
+//! [`n2k`] builds the NMEA 2000 style CAN frames of the `can` feature.
+//! Everything is `no_std` without allocation, and unit tested on the host:
+//! `cargo test -p gp735t-proto --target <host triple>`, as `.cargo/config.toml` builds for the
+//! MCU by default.
+
+#![no_std]
+
+pub mod geo;
+pub mod n2k;
+pub mod nmea;
+pub mod protocol;
+pub mod pubx;
+pub mod ubx;
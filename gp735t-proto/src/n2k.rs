@@ -0,0 +1,181 @@
+//! CAN frames modelled on NMEA 2000 parameter groups (PGNs), broadcast by the `can` feature of the
+//! bridge.
+//!
+//! Every frame is a single 8 byte frame with a 29-bit identifier: priority in bits 26-28, the
+//! PGN in bits 8-25 and the source address in bits 0-7. All PGNs here are broadcast (PDU2), so
+//! the PGN goes into the identifier unchanged. Fields are little endian, and a value the bridge
+//! doesn't have is sent as all ones, "not available", as NMEA 2000 does:
+//!
+//! - [`PGN_POSITION`] 129025 position rapid update: latitude and longitude as i32 in 1e-7 degrees
+//! - [`PGN_COG_SOG`] 129026 COG and SOG rapid update: sequence ID, reference 0 (true) in the two
+//!   low bits of the second byte, course as u16 in 1e-4 radians, speed as u16 in 0.01 m/s
+//! - [`PGN_SYSTEM_TIME`] 126992 system time: sequence ID, source 0 (GPS) in the low nibble of the
+//!   second byte, days since 1970-01-01 as u16 and time of day as u32 in 0.1 ms
+//!
+//! Frames of one fix share the sequence ID. This is not a certified NMEA 2000 implementation:
+//! there is no address claim and no product information, the bridge uses a fixed address.
+
+use crate::nmea::{Date, Time};
+
+/// Position, rapid update
+pub const PGN_POSITION: u32 = 129_025;
+
+/// COG and SOG, rapid update
+pub const PGN_COG_SOG: u32 = 129_026;
+
+/// System time
+pub const PGN_SYSTEM_TIME: u32 = 126_992;
+
+/// Priority of the rapid updates
+pub const PRIORITY_RAPID: u8 = 2;
+
+/// Priority of the system time
+pub const PRIORITY_TIME: u8 = 3;
+
+/// Highest sequence ID, 253 to 255 are reserved
+pub const MAX_SID: u8 = 252;
+
+/// Not available latitude or longitude
+const NA_I32: i32 = i32::MAX;
+
+/// Highest valid u16 field, 0xFFFD to 0xFFFF are reserved or not available
+const MAX_U16: u16 = 0xFFFC;
+
+/// One CAN data frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// 29-bit extended identifier
+    pub id: u32,
+    pub data: [u8; 8],
+}
+
+/// Extended identifier of a broadcast `pgn` from `source`
+pub fn id(priority: u8, pgn: u32, source: u8) -> u32 {
+    u32::from(priority & 0x7) << 26 | (pgn & 0x3_FFFF) << 8 | u32::from(source)
+}
+
+/// PGN 129025, `None` without a fix
+pub fn position(source: u8, position: Option<(i32, i32)>) -> Frame {
+    let (lat, lon) = position.unwrap_or((NA_I32, NA_I32));
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&lat.to_le_bytes());
+    data[4..].copy_from_slice(&lon.to_le_bytes());
+    Frame {
+        id: id(PRIORITY_RAPID, PGN_POSITION, source),
+        data,
+    }
+}
+
+/// PGN 129026 from course in hundredths of a degree and speed in millimetres per second, `None`
+/// without a fix
+pub fn cog_sog(source: u8, sid: u8, course_speed: Option<(u16, u32)>) -> Frame {
+    let (cog, sog) = match course_speed {
+        // 1e-4 rad per hundredth of a degree is pi / 1.8, 36000 is less than 2^16 after scaling
+        Some((course, speed)) => (
+            ((u64::from(course) * 174_533 + 50_000) / 100_000) as u16,
+            ((speed + 5) / 10).min(u32::from(MAX_U16)) as u16,
+        ),
+        None => (u16::MAX, u16::MAX),
+    };
+    let mut data = [0xFF; 8];
+    data[0] = sid;
+    // True reference, reserved bits set
+    data[1] = 0xFC;
+    data[2..4].copy_from_slice(&cog.to_le_bytes());
+    data[4..6].copy_from_slice(&sog.to_le_bytes());
+    Frame {
+        id: id(PRIORITY_RAPID, PGN_COG_SOG, source),
+        data,
+    }
+}
+
+/// PGN 126992, date or time not available if unknown
+pub fn system_time(source: u8, sid: u8, date: Option<Date>, time: Option<Time>) -> Frame {
+    let days = date.map_or(u16::MAX, |date| {
+        days_since_1970(date).min(MAX_U16.into()) as u16
+    });
+    let tenths_of_ms = time.map_or(u32::MAX, |time| {
+        let seconds =
+            u32::from(time.hour) * 3600 + u32::from(time.minute) * 60 + u32::from(time.second);
+        seconds * 10_000 + u32::from(time.millisecond) * 10
+    });
+    let mut data = [0; 8];
+    data[0] = sid;
+    // GPS source, reserved bits set
+    data[1] = 0xF0;
+    data[2..4].copy_from_slice(&days.to_le_bytes());
+    data[4..].copy_from_slice(&tenths_of_ms.to_le_bytes());
+    Frame {
+        id: id(PRIORITY_TIME, PGN_SYSTEM_TIME, source),
+        data,
+    }
+}
+
+/// Days from 1970-01-01 to `date` in the proleptic Gregorian calendar, 0 before
+pub fn days_since_1970(date: Date) -> u32 {
+    // Years starting in March put the leap day last
+    let year = u32::from(date.year).saturating_sub(u32::from(date.month <= 2));
+    let month = (u32::from(date.month) + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + u32::from(date.day) - 1;
+    let days = year * 365 + year / 4 - year / 100 + year / 400 + day_of_year;
+    // Days from 0000-03-01 to 1970-01-01
+    days.saturating_sub(719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn identifier_fields() {
+        assert_eq!(id(2, PGN_POSITION, 0x80), 0x09F8_0180);
+        assert_eq!(id(3, PGN_SYSTEM_TIME, 0x23), 0x0DF0_1023);
+    }
+
+    #[test]
+    fn days_since_epoch() {
+        assert_eq!(days_since_1970(date(1970, 1, 1)), 0);
+        assert_eq!(days_since_1970(date(2000, 3, 1)), 11_017);
+        assert_eq!(days_since_1970(date(2024, 2, 29)), 19_782);
+        assert_eq!(days_since_1970(date(2025, 12, 31)), 20_453);
+    }
+
+    #[test]
+    fn position_fields() {
+        let frame = position(0x80, Some((-338_568_000, 1_512_153_000)));
+        assert_eq!(&frame.data[..4], &(-338_568_000i32).to_le_bytes());
+        assert_eq!(&frame.data[4..], &1_512_153_000i32.to_le_bytes());
+        assert_eq!(
+            position(0x80, None).data,
+            [0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn course_in_radians_and_speed_in_centimetres() {
+        // 90 degrees, 12.345 m/s
+        let frame = cog_sog(0x80, 7, Some((9000, 12_345)));
+        assert_eq!(frame.data, [7, 0xFC, 0x5C, 0x3D, 0xD3, 0x04, 0xFF, 0xFF]);
+        let frame = cog_sog(0x80, 8, None);
+        assert_eq!(frame.data, [8, 0xFC, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn time_of_day_in_tenths_of_a_millisecond() {
+        let time = Time {
+            hour: 12,
+            minute: 0,
+            second: 0,
+            millisecond: 250,
+        };
+        let frame = system_time(0x80, 1, Some(date(2024, 2, 29)), Some(time));
+        assert_eq!(&frame.data[2..4], &19_782u16.to_le_bytes());
+        assert_eq!(&frame.data[4..], &432_002_500u32.to_le_bytes());
+        let frame = system_time(0x80, 1, None, None);
+        assert_eq!(frame.data[2..], [0xFF; 6]);
+    }
+}
@@ -0,0 +1,656 @@
+//! Streaming NMEA 0183 sentence assembly and parsing.
+//! [`Parser`] assembles received bytes into sentences without allocating and
+//! [`Sentence::parse`] validates the `*hh` checksum and decodes the supported sentence types.
+//!
+//! Numbers are kept in fixed point: coordinates in 1e-7 degrees, altitude in millimetres,
+//! speed in millimetres per second, course in hundredths of a degree and DOP in hundredths.
+//! [`encode`] builds sentences from the same types.
+
+use crate::geo::{self, Axis};
+use heapless::Vec;
+
+pub mod encode;
+
+/// Longest sentence assembled by [`Parser`] including `$` and CRLF. NMEA allows 82 characters
+/// but u-blox proprietary sentences can be longer.
+pub const MAX_SENTENCE_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Missing `$`, `*hh` or sentence address
+    Framing,
+    /// Checksum doesn't match sentence contents
+    Checksum,
+    /// Valid sentence of a type not decoded here
+    Unsupported,
+    /// Field contents could not be decoded
+    Field,
+}
+
+/// Assembles a byte stream into sentences starting with `$` and ending with `\n`.
+/// Bytes outside of a sentence and sentences longer than [`MAX_SENTENCE_LEN`] are discarded.
+pub struct Parser {
+    buffer: Vec<u8, MAX_SENTENCE_LEN>,
+    in_sentence: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            in_sentence: false,
+        }
+    }
+
+    /// Feed one received byte. Returns the complete sentence including line ending once `\n`
+    /// is received.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == b'$' {
+            // Start of sentence, also resynchronizes if the previous one was cut short
+            self.buffer.clear();
+            self.in_sentence = true;
+        } else if !self.in_sentence {
+            return None;
+        }
+
+        if self.buffer.push(byte).is_err() {
+            // Too long to be a sentence, wait for the next '$'
+            self.in_sentence = false;
+            return None;
+        }
+        if byte == b'\n' {
+            self.in_sentence = false;
+            return Some(&self.buffer);
+        }
+        None
+    }
+
+    /// Discard the sentence being assembled, e.g. after a receive error corrupted it
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.in_sentence = false;
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// XOR of all bytes, as used by the `*hh` checksum over the characters between `$` and `*`.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+/// UTC time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millisecond: u16,
+}
+
+/// UTC date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+/// Coordinates in 1e-7 degrees, north and east positive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+/// Fix dimension reported by GSA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FixType {
+    None,
+    Fix2D,
+    Fix3D,
+}
+
+/// Global positioning system fix data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub position: Option<Position>,
+    /// Fix quality indicator, 0 is no fix
+    pub quality: u8,
+    pub satellites: u8,
+    pub hdop: Option<u16>,
+    /// Altitude above mean sea level in millimetres
+    pub altitude: Option<i32>,
+}
+
+/// Recommended minimum data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    /// Status `A`, data is valid
+    pub valid: bool,
+    pub position: Option<Position>,
+    /// Speed over ground in millimetres per second
+    pub speed: Option<u32>,
+    /// Course over ground in hundredths of a degree
+    pub course: Option<u16>,
+    pub date: Option<Date>,
+}
+
+/// DOP and active satellites
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gsa {
+    pub talker: [u8; 2],
+    /// Mode `A`, 2D/3D switching is automatic
+    pub automatic: bool,
+    pub fix: FixType,
+    /// Satellites used in the fix, unused slots are 0
+    pub satellites: [u8; 12],
+    pub pdop: Option<u16>,
+    pub hdop: Option<u16>,
+    pub vdop: Option<u16>,
+}
+
+/// One satellite of a GSV sentence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GsvSatellite {
+    pub prn: u8,
+    /// Elevation in degrees
+    pub elevation: Option<u8>,
+    /// Azimuth in degrees
+    pub azimuth: Option<u16>,
+    /// Signal to noise ratio in dBHz, `None` when not tracking
+    pub snr: Option<u8>,
+}
+
+/// Satellites in view, one part of a multi-sentence message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gsv {
+    pub talker: [u8; 2],
+    /// Number of sentences in this message
+    pub total: u8,
+    /// Number of this sentence, starting at 1
+    pub number: u8,
+    pub in_view: u8,
+    pub satellites: [Option<GsvSatellite>; 4],
+}
+
+/// Course over ground and ground speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vtg {
+    /// True course over ground in hundredths of a degree
+    pub course: Option<u16>,
+    /// Speed over ground in millimetres per second
+    pub speed: Option<u32>,
+}
+
+/// Geographic position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gll {
+    pub position: Option<Position>,
+    pub time: Option<Time>,
+    /// Status `A`, data is valid
+    pub valid: bool,
+}
+
+/// Sentence types told apart without decoding fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SentenceType {
+    Gga,
+    Rmc,
+    Gsa,
+    Gsv,
+    Vtg,
+    Gll,
+    /// Any other sentence, e.g. TXT or proprietary
+    Other,
+    /// `$PBRIDGE,POS` position summary generated by the bridge
+    Pos,
+}
+
+impl SentenceType {
+    pub const ALL: [SentenceType; 8] = [
+        SentenceType::Gga,
+        SentenceType::Rmc,
+        SentenceType::Gsa,
+        SentenceType::Gsv,
+        SentenceType::Vtg,
+        SentenceType::Gll,
+        SentenceType::Other,
+        SentenceType::Pos,
+    ];
+
+    /// Type of a sentence as returned by [`Parser::push`], from its address field
+    pub fn of(line: &[u8]) -> Self {
+        if line.starts_with(b"$PBRIDGE,POS,") {
+            return SentenceType::Pos;
+        }
+        match line.get(3..7) {
+            Some(b"GGA,") => SentenceType::Gga,
+            Some(b"RMC,") => SentenceType::Rmc,
+            Some(b"GSA,") => SentenceType::Gsa,
+            Some(b"GSV,") => SentenceType::Gsv,
+            Some(b"VTG,") => SentenceType::Vtg,
+            Some(b"GLL,") => SentenceType::Gll,
+            _ => SentenceType::Other,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            SentenceType::Gga => "GGA",
+            SentenceType::Rmc => "RMC",
+            SentenceType::Gsa => "GSA",
+            SentenceType::Gsv => "GSV",
+            SentenceType::Vtg => "VTG",
+            SentenceType::Gll => "GLL",
+            SentenceType::Other => "OTHER",
+            SentenceType::Pos => "POS",
+        }
+    }
+
+    /// Case insensitive inverse of [`SentenceType::name`]
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|sentence_type| name.eq_ignore_ascii_case(sentence_type.name().as_bytes()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+    Gll(Gll),
+}
+
+impl Sentence {
+    /// Validate checksum and decode a sentence as returned by [`Parser::push`].
+    /// Trailing CR/LF is optional.
+    pub fn parse(line: &[u8]) -> Result<Self, Error> {
+        let body = validate(line)?;
+        let mut fields = body.split(is_comma as fn(&u8) -> bool);
+        let address = fields.next().unwrap_or(&[]);
+        if address.len() != 5 {
+            return Err(Error::Unsupported);
+        }
+        let talker = [address[0], address[1]];
+
+        match &address[2..] {
+            b"GGA" => parse_gga(&mut fields).map(Sentence::Gga),
+            b"RMC" => parse_rmc(&mut fields).map(Sentence::Rmc),
+            b"GSA" => parse_gsa(talker, &mut fields).map(Sentence::Gsa),
+            b"GSV" => parse_gsv(talker, &mut fields).map(Sentence::Gsv),
+            b"VTG" => parse_vtg(&mut fields).map(Sentence::Vtg),
+            b"GLL" => parse_gll(&mut fields).map(Sentence::Gll),
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+/// Check framing and checksum of a sentence as returned by [`Parser::push`] without decoding it,
+/// a sentence without `*hh` fails as [`Error::Framing`]
+pub fn verify(line: &[u8]) -> Result<(), Error> {
+    validate(line).map(|_| ())
+}
+
+/// Check framing and checksum, returns the characters between `$` and `*`
+fn validate(line: &[u8]) -> Result<&[u8], Error> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_prefix(b"$").ok_or(Error::Framing)?;
+    if line.len() < 3 || line[line.len() - 3] != b'*' {
+        return Err(Error::Framing);
+    }
+    let (body, hex) = line.split_at(line.len() - 3);
+    let expected = hex_digit(hex[1])
+        .zip(hex_digit(hex[2]))
+        .map(|(high, low)| (high << 4) | low)
+        .ok_or(Error::Framing)?;
+    if checksum(body) != expected {
+        return Err(Error::Checksum);
+    }
+    Ok(body)
+}
+
+fn is_comma(byte: &u8) -> bool {
+    *byte == b','
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Next field, missing trailing fields are treated as empty
+fn next<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> &'a [u8] {
+    fields.next().unwrap_or(&[])
+}
+
+/// Parse an unsigned integer field, `None` if empty
+fn uint(field: &[u8]) -> Result<Option<u32>, Error> {
+    fixed(field, 0)?
+        .map(|value| u32::try_from(value).map_err(|_| Error::Field))
+        .transpose()
+}
+
+/// Parse a decimal field into an integer scaled by 10^`decimals`, `None` if empty.
+/// Extra fractional digits are truncated.
+pub fn fixed(field: &[u8], decimals: u32) -> Result<Option<i64>, Error> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    let (negative, digits) = match field.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, field),
+    };
+
+    let mut value: i64 = 0;
+    // Number of fractional digits seen, None before the decimal point
+    let mut fraction: Option<u32> = None;
+    for &byte in digits {
+        match byte {
+            b'.' if fraction.is_none() => fraction = Some(0),
+            b'0'..=b'9' => {
+                if let Some(count) = fraction {
+                    if count == decimals {
+                        continue;
+                    }
+                    fraction = Some(count + 1);
+                }
+                value = value
+                    .checked_mul(10)
+                    .and_then(|value| value.checked_add(i64::from(byte - b'0')))
+                    .ok_or(Error::Field)?;
+            }
+            _ => return Err(Error::Field),
+        }
+    }
+    for _ in fraction.unwrap_or(0)..decimals {
+        value = value.checked_mul(10).ok_or(Error::Field)?;
+    }
+    Ok(Some(if negative { -value } else { value }))
+}
+
+/// Parse a fixed point field that must fit in `T`
+fn fixed_as<T: TryFrom<i64>>(field: &[u8], decimals: u32) -> Result<Option<T>, Error> {
+    fixed(field, decimals)?
+        .map(|value| T::try_from(value).map_err(|_| Error::Field))
+        .transpose()
+}
+
+/// Parse `hhmmss.sss`
+fn time(field: &[u8]) -> Result<Option<Time>, Error> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() < 6 {
+        return Err(Error::Field);
+    }
+    let hour = uint(&field[0..2])?.ok_or(Error::Field)?;
+    let minute = uint(&field[2..4])?.ok_or(Error::Field)?;
+    let milliseconds = fixed(&field[4..], 3)?.ok_or(Error::Field)?;
+    if hour > 23 || minute > 59 || milliseconds >= 61_000 {
+        return Err(Error::Field);
+    }
+    Ok(Some(Time {
+        hour: hour as u8,
+        minute: minute as u8,
+        second: (milliseconds / 1000) as u8,
+        millisecond: (milliseconds % 1000) as u16,
+    }))
+}
+
+/// Parse `ddmmyy`
+fn date(field: &[u8]) -> Result<Option<Date>, Error> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    if field.len() != 6 {
+        return Err(Error::Field);
+    }
+    let day = uint(&field[0..2])?.ok_or(Error::Field)?;
+    let month = uint(&field[2..4])?.ok_or(Error::Field)?;
+    let year = uint(&field[4..6])?.ok_or(Error::Field)?;
+    if !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+        return Err(Error::Field);
+    }
+    Ok(Some(Date {
+        year: 2000 + year as u16,
+        month: month as u8,
+        day: day as u8,
+    }))
+}
+
+/// Parse a coordinate and its hemisphere field into 1e-7 degrees, see [`geo`](crate::geo)
+fn coordinate(field: &[u8], hemisphere: &[u8], axis: Axis) -> Result<Option<i32>, Error> {
+    geo::parse(field, hemisphere, axis).map_err(|_| Error::Field)
+}
+
+/// Parse latitude, N/S, longitude, E/W fields
+fn position<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<Option<Position>, Error> {
+    let latitude = coordinate(next(fields), next(fields), Axis::Latitude)?;
+    let longitude = coordinate(next(fields), next(fields), Axis::Longitude)?;
+    Ok(latitude
+        .zip(longitude)
+        .map(|(latitude, longitude)| Position {
+            latitude,
+            longitude,
+        }))
+}
+
+/// Parse speed in knots into millimetres per second
+fn knots(field: &[u8]) -> Result<Option<u32>, Error> {
+    // 1 knot is 1852 m/h
+    fixed(field, 3)?
+        .map(|knots| u32::try_from(knots * 1852 / 3600).map_err(|_| Error::Field))
+        .transpose()
+}
+
+/// Parse speed in km/h into millimetres per second
+fn kmh(field: &[u8]) -> Result<Option<u32>, Error> {
+    fixed(field, 3)?
+        .map(|kmh| u32::try_from(kmh * 10 / 36).map_err(|_| Error::Field))
+        .transpose()
+}
+
+/// Parse status field, `A` is valid
+fn status(field: &[u8]) -> bool {
+    field == b"A"
+}
+
+fn parse_gga<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<Gga, Error> {
+    let time = time(next(fields))?;
+    let position = position(fields)?;
+    let quality = fixed_as(next(fields), 0)?.unwrap_or(0);
+    let satellites = fixed_as(next(fields), 0)?.unwrap_or(0);
+    let hdop = fixed_as(next(fields), 2)?;
+    let altitude = fixed_as(next(fields), 3)?;
+    Ok(Gga {
+        time,
+        position,
+        quality,
+        satellites,
+        hdop,
+        altitude,
+    })
+}
+
+fn parse_rmc<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<Rmc, Error> {
+    let time = time(next(fields))?;
+    let valid = status(next(fields));
+    let position = position(fields)?;
+    let speed = knots(next(fields))?;
+    let course = fixed_as(next(fields), 2)?;
+    let date = date(next(fields))?;
+    Ok(Rmc {
+        time,
+        valid,
+        position,
+        speed,
+        course,
+        date,
+    })
+}
+
+fn parse_gsa<'a>(
+    talker: [u8; 2],
+    fields: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<Gsa, Error> {
+    let automatic = next(fields) == b"A";
+    let fix = match next(fields) {
+        b"2" => FixType::Fix2D,
+        b"3" => FixType::Fix3D,
+        _ => FixType::None,
+    };
+    let mut satellites = [0; 12];
+    for prn in satellites.iter_mut() {
+        *prn = fixed_as(next(fields), 0)?.unwrap_or(0);
+    }
+    let pdop = fixed_as(next(fields), 2)?;
+    let hdop = fixed_as(next(fields), 2)?;
+    let vdop = fixed_as(next(fields), 2)?;
+    Ok(Gsa {
+        talker,
+        automatic,
+        fix,
+        satellites,
+        pdop,
+        hdop,
+        vdop,
+    })
+}
+
+fn parse_gsv<'a>(
+    talker: [u8; 2],
+    fields: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<Gsv, Error> {
+    let total = fixed_as(next(fields), 0)?.ok_or(Error::Field)?;
+    let number = fixed_as(next(fields), 0)?.ok_or(Error::Field)?;
+    let in_view = fixed_as(next(fields), 0)?.unwrap_or(0);
+    let mut satellites = [None; 4];
+    for satellite in satellites.iter_mut() {
+        let prn = fixed_as(next(fields), 0)?;
+        let elevation = fixed_as(next(fields), 0)?;
+        let azimuth = fixed_as(next(fields), 0)?;
+        let snr = fixed_as(next(fields), 0)?;
+        *satellite = prn.map(|prn| GsvSatellite {
+            prn,
+            elevation,
+            azimuth,
+            snr,
+        });
+    }
+    Ok(Gsv {
+        talker,
+        total,
+        number,
+        in_view,
+        satellites,
+    })
+}
+
+fn parse_vtg<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<Vtg, Error> {
+    let course = fixed_as(next(fields), 2)?;
+    // Skip T, magnetic course, M, speed in knots, N
+    for _ in 0..5 {
+        next(fields);
+    }
+    let speed = kmh(next(fields))?;
+    Ok(Vtg { course, speed })
+}
+
+fn parse_gll<'a>(fields: &mut impl Iterator<Item = &'a [u8]>) -> Result<Gll, Error> {
+    let position = position(fields)?;
+    let time = time(next(fields))?;
+    let valid = status(next(fields));
+    Ok(Gll {
+        position,
+        time,
+        valid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &[u8] = b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+
+    #[test]
+    fn parse_gga() {
+        let Ok(Sentence::Gga(gga)) = Sentence::parse(GGA) else {
+            panic!("GGA not decoded");
+        };
+        assert_eq!(
+            gga.time,
+            Some(Time {
+                hour: 12,
+                minute: 35,
+                second: 19,
+                millisecond: 0
+            })
+        );
+        assert_eq!(
+            gga.position,
+            Some(Position {
+                latitude: 481_173_000,
+                longitude: 115_166_667,
+            })
+        );
+        assert_eq!(gga.quality, 1);
+        assert_eq!(gga.satellites, 8);
+        assert_eq!(gga.hdop, Some(90));
+        assert_eq!(gga.altitude, Some(545_400));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut line = Vec::<u8, MAX_SENTENCE_LEN>::from_slice(GGA).unwrap();
+        line[8] = b'4';
+        assert_eq!(verify(&line), Err(Error::Checksum));
+        assert_eq!(Sentence::parse(&line), Err(Error::Checksum));
+        assert_eq!(verify(b"$GPGGA,123519\r\n"), Err(Error::Framing));
+    }
+
+    #[test]
+    fn unsupported_type() {
+        assert_eq!(
+            Sentence::parse(b"$GPTXT,01,01,02,ANTSTATUS=OK*3B\r\n"),
+            Err(Error::Unsupported)
+        );
+        assert_eq!(
+            SentenceType::of(b"$GPTXT,01,01,02,ANTSTATUS=OK*3B\r\n"),
+            SentenceType::Other
+        );
+        assert_eq!(
+            SentenceType::of(b"$PBRIDGE,STATUS,10,1*00\r\n"),
+            SentenceType::Other
+        );
+        assert_eq!(
+            SentenceType::of(b"$PBRIDGE,POS,123519.50,48.117300,-11.516667*00\r\n"),
+            SentenceType::Pos
+        );
+    }
+
+    #[test]
+    fn parser_resynchronizes() {
+        let mut parser = Parser::new();
+        let mut sentences = 0;
+        // A sentence cut short by the next one is dropped
+        for &byte in b"noise$GPGGA,1235".iter().chain(GGA) {
+            if let Some(sentence) = parser.push(byte) {
+                assert_eq!(sentence, GGA);
+                sentences += 1;
+            }
+        }
+        assert_eq!(sentences, 1);
+    }
+}
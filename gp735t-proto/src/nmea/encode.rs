@@ -0,0 +1,219 @@
+//! Sentences built from the decoded types of [`crate::nmea`], the inverse of
+//! [`Sentence::parse`](super::Sentence::parse) for the fields the GPS outputs.
+//!
+//! Each writer emits `$GP<type>,...*hh\r\n` with fixed point formatting into a `heapless`
+//! buffer, at most [`MAX_SENTENCE_LEN`] characters. Fields that are `None` are left empty.
+//! Time has hundredths of a second, coordinates five decimals of minutes, altitude one
+//! decimal, speed three and course two. Digits beyond those are truncated, so a speed may read
+//! back a millimetre per second lower.
+
+use super::{checksum, Date, Gga, Position, Rmc, Time, Vtg, MAX_SENTENCE_LEN};
+use crate::geo::{self, Axis};
+use core::fmt::{self, Write};
+use heapless::String;
+
+type Body = String<MAX_SENTENCE_LEN>;
+
+/// Write a GPGGA sentence including line ending. The geoid separation is left empty.
+pub fn write_gga(out: &mut impl Write, gga: &Gga) -> fmt::Result {
+    let mut body = Body::new();
+    body.push_str("GPGGA,").map_err(|_| fmt::Error)?;
+    write_time(&mut body, gga.time)?;
+    write_position(&mut body, gga.position)?;
+    write!(body, ",{},{:02},", gga.quality, gga.satellites)?;
+    if let Some(hdop) = gga.hdop {
+        write!(body, "{}.{:02}", hdop / 100, hdop % 100)?;
+    }
+    body.push(',').map_err(|_| fmt::Error)?;
+    if let Some(altitude) = gga.altitude {
+        let sign = if altitude < 0 { "-" } else { "" };
+        let decimetres = altitude.unsigned_abs() / 100;
+        write!(body, "{}{}.{}", sign, decimetres / 10, decimetres % 10)?;
+    }
+    body.push_str(",M,,M,,").map_err(|_| fmt::Error)?;
+    finish(out, &body)
+}
+
+/// Write a GPRMC sentence including line ending, mode `A` when valid and `N` otherwise
+pub fn write_rmc(out: &mut impl Write, rmc: &Rmc) -> fmt::Result {
+    let mut body = Body::new();
+    body.push_str("GPRMC,").map_err(|_| fmt::Error)?;
+    write_time(&mut body, rmc.time)?;
+    body.push_str(if rmc.valid { ",A" } else { ",V" })
+        .map_err(|_| fmt::Error)?;
+    write_position(&mut body, rmc.position)?;
+    body.push(',').map_err(|_| fmt::Error)?;
+    write_knots(&mut body, rmc.speed)?;
+    body.push(',').map_err(|_| fmt::Error)?;
+    write_course(&mut body, rmc.course)?;
+    body.push(',').map_err(|_| fmt::Error)?;
+    write_date(&mut body, rmc.date)?;
+    body.push_str(if rmc.valid { ",,,A" } else { ",,,N" })
+        .map_err(|_| fmt::Error)?;
+    finish(out, &body)
+}
+
+/// Write a GPVTG sentence including line ending, mode `A` when it has a speed and `N` otherwise.
+/// The magnetic course is left empty.
+pub fn write_vtg(out: &mut impl Write, vtg: &Vtg) -> fmt::Result {
+    let mut body = Body::new();
+    body.push_str("GPVTG,").map_err(|_| fmt::Error)?;
+    write_course(&mut body, vtg.course)?;
+    body.push_str(",T,,M,").map_err(|_| fmt::Error)?;
+    write_knots(&mut body, vtg.speed)?;
+    body.push_str(",N,").map_err(|_| fmt::Error)?;
+    if let Some(speed) = vtg.speed {
+        // Thousandths of km/h
+        let kmh = u64::from(speed) * 36 / 10;
+        write!(body, "{}.{:03}", kmh / 1000, kmh % 1000)?;
+    }
+    body.push_str(if vtg.speed.is_some() { ",K,A" } else { ",K,N" })
+        .map_err(|_| fmt::Error)?;
+    finish(out, &body)
+}
+
+/// `$<body>*hh\r\n`
+fn finish(out: &mut impl Write, body: &str) -> fmt::Result {
+    write!(out, "${}*{:02X}\r\n", body, checksum(body.as_bytes()))
+}
+
+/// `hhmmss.ss`
+fn write_time(out: &mut impl Write, time: Option<Time>) -> fmt::Result {
+    match time {
+        Some(time) => write!(
+            out,
+            "{:02}{:02}{:02}.{:02}",
+            time.hour,
+            time.minute,
+            time.second,
+            time.millisecond / 10
+        ),
+        None => Ok(()),
+    }
+}
+
+/// `ddmmyy`
+fn write_date(out: &mut impl Write, date: Option<Date>) -> fmt::Result {
+    match date {
+        Some(date) => write!(
+            out,
+            "{:02}{:02}{:02}",
+            date.day,
+            date.month,
+            date.year % 100
+        ),
+        None => Ok(()),
+    }
+}
+
+/// `,<lat>,N|S,<lon>,E|W` including the leading comma
+fn write_position(out: &mut impl Write, position: Option<Position>) -> fmt::Result {
+    let Some(position) = position else {
+        return out.write_str(",,,,");
+    };
+    out.write_char(',')?;
+    geo::write_nmea(out, position.latitude, Axis::Latitude)?;
+    out.write_char(',')?;
+    geo::write_nmea(out, position.longitude, Axis::Longitude)
+}
+
+/// Millimetres per second as knots with three decimals
+fn write_knots(out: &mut impl Write, speed: Option<u32>) -> fmt::Result {
+    match speed {
+        Some(speed) => {
+            // Thousandths of a knot, 1 knot is 1852 m/h
+            let knots = u64::from(speed) * 3600 / 1852;
+            write!(out, "{}.{:03}", knots / 1000, knots % 1000)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Hundredths of a degree with two decimals
+fn write_course(out: &mut impl Write, course: Option<u16>) -> fmt::Result {
+    match course {
+        Some(course) => write!(out, "{}.{:02}", course / 100, course % 100),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Sentence;
+    use super::*;
+
+    const POSITION: Position = Position {
+        latitude: 481_173_000,
+        longitude: -115_166_667,
+    };
+
+    const TIME: Time = Time {
+        hour: 12,
+        minute: 35,
+        second: 19,
+        millisecond: 500,
+    };
+
+    #[test]
+    fn gga_round_trip() {
+        let gga = Gga {
+            time: Some(TIME),
+            position: Some(POSITION),
+            quality: 1,
+            satellites: 8,
+            hdop: Some(90),
+            altitude: Some(-12_300),
+        };
+        let mut line = Body::new();
+        write_gga(&mut line, &gga).unwrap();
+        assert_eq!(
+            line,
+            "$GPGGA,123519.50,4807.03800,N,01131.00000,W,1,08,0.90,-12.3,M,,M,,*46\r\n"
+        );
+        assert_eq!(Sentence::parse(line.as_bytes()), Ok(Sentence::Gga(gga)));
+    }
+
+    #[test]
+    fn rmc_round_trip() {
+        let rmc = Rmc {
+            time: Some(TIME),
+            valid: true,
+            position: Some(POSITION),
+            // 3.6 knots
+            speed: Some(1852),
+            course: Some(8_405),
+            date: Some(Date {
+                year: 2024,
+                month: 3,
+                day: 9,
+            }),
+        };
+        let mut line = Body::new();
+        write_rmc(&mut line, &rmc).unwrap();
+        assert_eq!(Sentence::parse(line.as_bytes()), Ok(Sentence::Rmc(rmc)));
+        let invalid = Rmc {
+            time: None,
+            valid: false,
+            position: None,
+            speed: None,
+            course: None,
+            date: None,
+        };
+        line.clear();
+        write_rmc(&mut line, &invalid).unwrap();
+        assert_eq!(line, "$GPRMC,,V,,,,,,,,,,N*53\r\n");
+        assert_eq!(Sentence::parse(line.as_bytes()), Ok(Sentence::Rmc(invalid)));
+    }
+
+    #[test]
+    fn vtg_round_trip() {
+        let vtg = Vtg {
+            course: Some(27_050),
+            // 3.6 km/h
+            speed: Some(1000),
+        };
+        let mut line = Body::new();
+        write_vtg(&mut line, &vtg).unwrap();
+        assert_eq!(Sentence::parse(line.as_bytes()), Ok(Sentence::Vtg(vtg)));
+    }
+}
@@ -0,0 +1,275 @@
+//! Binary output protocol selected by `MODE BIN`, compact fix records for hosts that don't
+//! want to parse NMEA.
+//!
+//! A frame is a record type byte, the record and a CRC-16/CCITT-FALSE (polynomial 0x1021,
+//! initial value 0xFFFF) of both in little endian, COBS encoded and sent between two zero bytes.
+//! COBS leaves no zero byte within the frame, so a host splits the stream at zero bytes and
+//! decodes each part with [`decode_frame`]. Response lines and `$PBRIDGE` sentences are still
+//! sent as text between frames, parts that don't decode are such text.
+//!
+//! Record [`RECORD_FIX`] is a [`FixRecord`] of [`FixRecord::LEN`] bytes, all little endian:
+//!
+//! | Offset | Type | Field |
+//! | --- | --- | --- |
+//! | 0 | u8 | Fix type, 0 none, 2 2D, 3 3D |
+//! | 1 | u8 | Satellites used |
+//! | 2 | u16 | UTC year, 0 if unknown |
+//! | 4 | u8 | UTC month |
+//! | 5 | u8 | UTC day |
+//! | 6 | u32 | UTC milliseconds of day, 0xFFFFFFFF if unknown |
+//! | 10 | i32 | Latitude in 1e-7 degrees, north positive |
+//! | 14 | i32 | Longitude in 1e-7 degrees, east positive |
+//! | 18 | i32 | Altitude above mean sea level in thousandths of the altitude unit |
+//! | 22 | u32 | Speed over ground in thousandths of the speed unit |
+//! | 26 | u16 | Course over ground in hundredths of a degree |
+//! | 28 | u16 | HDOP in hundredths |
+//! | 30 | u8 | Units, speed in bits 0-3 and altitude in bits 4-7 |
+//! | 31 | i16 | Bridge MCU temperature in tenths of a degree Celsius, -32768 if unknown |
+//!
+//! Speed units are 0 m/s, 1 km/h, 2 mph and 3 knots, altitude units 0 metres and 1 feet, as
+//! selected by `UNITS`.
+
+use crate::geo::{AltitudeUnit, SpeedUnit};
+use heapless::Vec;
+
+/// Record type of [`FixRecord`]
+pub const RECORD_FIX: u8 = 0x01;
+
+/// [`FixRecord::temperature`] before the bridge has measured it
+pub const TEMPERATURE_UNKNOWN: i16 = i16::MIN;
+
+/// Longest record carried by a frame
+pub const MAX_RECORD_LEN: usize = 64;
+
+/// Longest encoded frame including both zero delimiters: type, record and CRC, and one COBS
+/// overhead byte as they are shorter than 254 bytes
+pub const MAX_FRAME_LEN: usize = 1 + MAX_RECORD_LEN + 2 + 1 + 2;
+
+/// Frame bytes as sent, including delimiters
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Record longer than [`MAX_RECORD_LEN`]
+    TooLong,
+    /// Not a valid COBS encoding or too short for type and CRC
+    Framing,
+    Crc,
+}
+
+/// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Encode a record of type `kind` into a frame with its zero delimiters
+pub fn encode_frame(kind: u8, record: &[u8]) -> Result<Frame, Error> {
+    if record.len() > MAX_RECORD_LEN {
+        return Err(Error::TooLong);
+    }
+    let mut data = Vec::<u8, { MAX_RECORD_LEN + 3 }>::new();
+    // Length checked above
+    let _ = data.push(kind);
+    let _ = data.extend_from_slice(record);
+    let _ = data.extend_from_slice(&crc16(&data).to_le_bytes());
+
+    let mut frame = Frame::new();
+    let _ = frame.push(0);
+    // Each block is a code byte, the offset of the zero replaced, and the bytes before it.
+    // Blocks are shorter than the 254 bytes that would need a code of 0xFF.
+    for block in data.split(|&byte| byte == 0) {
+        let _ = frame.push(block.len() as u8 + 1);
+        let _ = frame.extend_from_slice(block);
+    }
+    let _ = frame.push(0);
+    Ok(frame)
+}
+
+/// Decode the bytes between two zero delimiters, returns the record type and record
+pub fn decode_frame(encoded: &[u8]) -> Result<(u8, Vec<u8, { MAX_RECORD_LEN + 3 }>), Error> {
+    let mut data = Vec::<u8, { MAX_RECORD_LEN + 3 }>::new();
+    let mut rest = encoded;
+    while let Some((&code, tail)) = rest.split_first() {
+        let len = usize::from(code).checked_sub(1).ok_or(Error::Framing)?;
+        let block = tail.get(..len).ok_or(Error::Framing)?;
+        data.extend_from_slice(block).map_err(|_| Error::Framing)?;
+        rest = &tail[len..];
+        // A zero followed the block, unless it was a full block or the end of the frame
+        if code != 0xFF && !rest.is_empty() {
+            data.push(0).map_err(|_| Error::Framing)?;
+        }
+    }
+    if data.len() < 3 {
+        return Err(Error::Framing);
+    }
+    let (body, crc) = data.split_at(data.len() - 2);
+    if crc16(body).to_le_bytes() != crc {
+        return Err(Error::Crc);
+    }
+    let kind = body[0];
+    let record = Vec::from_slice(&body[1..]).map_err(|_| Error::Framing)?;
+    Ok((kind, record))
+}
+
+/// Fix record, units as in [`crate::nmea`] except speed and altitude
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixRecord {
+    /// 0 none, 2 2D, 3 3D
+    pub fix_type: u8,
+    pub sats: u8,
+    /// 0 if the date is unknown
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// `u32::MAX` if the time is unknown
+    pub ms_of_day: u32,
+    pub lat: i32,
+    pub lon: i32,
+    /// Thousandths of `altitude_unit`
+    pub altitude: i32,
+    /// Thousandths of `speed_unit`
+    pub speed: u32,
+    pub course: u16,
+    pub hdop: u16,
+    pub speed_unit: SpeedUnit,
+    pub altitude_unit: AltitudeUnit,
+    /// Tenths of a degree Celsius, [`TEMPERATURE_UNKNOWN`] if unknown
+    pub temperature: i16,
+}
+
+impl FixRecord {
+    pub const LEN: usize = 33;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = self.fix_type;
+        bytes[1] = self.sats;
+        bytes[2..4].copy_from_slice(&self.year.to_le_bytes());
+        bytes[4] = self.month;
+        bytes[5] = self.day;
+        bytes[6..10].copy_from_slice(&self.ms_of_day.to_le_bytes());
+        bytes[10..14].copy_from_slice(&self.lat.to_le_bytes());
+        bytes[14..18].copy_from_slice(&self.lon.to_le_bytes());
+        bytes[18..22].copy_from_slice(&self.altitude.to_le_bytes());
+        bytes[22..26].copy_from_slice(&self.speed.to_le_bytes());
+        bytes[26..28].copy_from_slice(&self.course.to_le_bytes());
+        bytes[28..30].copy_from_slice(&self.hdop.to_le_bytes());
+        bytes[30] = self.speed_unit.code() | self.altitude_unit.code() << 4;
+        bytes[31..33].copy_from_slice(&self.temperature.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a record of [`FixRecord::LEN`] bytes, `None` if its units are unknown
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        Some(Self {
+            fix_type: bytes[0],
+            sats: bytes[1],
+            year: u16_at(2),
+            month: bytes[4],
+            day: bytes[5],
+            ms_of_day: u32_at(6),
+            lat: u32_at(10) as i32,
+            lon: u32_at(14) as i32,
+            altitude: u32_at(18) as i32,
+            speed: u32_at(22),
+            course: u16_at(26),
+            hdop: u16_at(28),
+            speed_unit: SpeedUnit::from_code(bytes[30] & 0x0F)?,
+            altitude_unit: AltitudeUnit::from_code(bytes[30] >> 4)?,
+            temperature: u16_at(31) as i16,
+        })
+    }
+
+    /// Frame of this record
+    pub fn encode(&self) -> Frame {
+        // Shorter than the longest record
+        encode_frame(RECORD_FIX, &self.to_bytes()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix() -> FixRecord {
+        FixRecord {
+            fix_type: 3,
+            sats: 8,
+            year: 2024,
+            month: 5,
+            day: 1,
+            ms_of_day: 43_200_250,
+            lat: -338_568_000,
+            lon: 1_512_153_000,
+            altitude: 0,
+            speed: 1200,
+            course: 0,
+            hdop: 95,
+            speed_unit: SpeedUnit::Knots,
+            altitude_unit: AltitudeUnit::Ft,
+            temperature: -125,
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = fix().encode();
+        assert_eq!(frame.first(), Some(&0));
+        assert_eq!(frame.last(), Some(&0));
+        let encoded = &frame[1..frame.len() - 1];
+        // Zero fields of the record are replaced by COBS
+        assert!(!encoded.contains(&0));
+        let (kind, record) = decode_frame(encoded).unwrap();
+        assert_eq!(kind, RECORD_FIX);
+        assert_eq!(FixRecord::from_bytes(&record), Some(fix()));
+    }
+
+    #[test]
+    fn unknown_units() {
+        let mut bytes = fix().to_bytes();
+        assert_eq!(bytes[30], 0x13);
+        bytes[30] = 0x04;
+        assert_eq!(FixRecord::from_bytes(&bytes), None);
+        bytes[30] = 0x20;
+        assert_eq!(FixRecord::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        let frame = fix().encode();
+        let mut encoded = Vec::<u8, MAX_FRAME_LEN>::from_slice(&frame[1..frame.len() - 1]).unwrap();
+        encoded[12] ^= 0x40;
+        assert!(decode_frame(&encoded).is_err());
+    }
+
+    #[test]
+    fn text_is_not_a_frame() {
+        assert!(decode_frame(b"OK\r\n").is_err());
+        assert!(decode_frame(b"$PBRIDGE,STATUS,10,1,0,0,0,0,0,0,0,0,0*4C\r\n").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! u-blox proprietary `$PUBX` configuration sentences, a text alternative to UBX-CFG-MSG and
+//! UBX-CFG-PRT for receivers talking NMEA only.
+//!
+//! `$PUBX,40` sets the output rate of one NMEA message on every port at once and `$PUBX,41`
+//! sets the protocols and baud of one port. Neither is acknowledged, and both are lost on a
+//! power cycle unless saved with UBX-CFG-CFG.
+
+use crate::nmea::{checksum, MAX_SENTENCE_LEN};
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// Port IDs of `$PUBX,41`
+pub const PORT_DDC: u8 = 0;
+pub const PORT_UART1: u8 = 1;
+pub const PORT_UART2: u8 = 2;
+pub const PORT_USB: u8 = 3;
+pub const PORT_SPI: u8 = 4;
+
+/// Protocol mask bits of `$PUBX,41`
+pub const PROTO_UBX: u16 = 0x0001;
+pub const PROTO_NMEA: u16 = 0x0002;
+pub const PROTO_RTCM: u16 = 0x0004;
+
+/// Output rate of a message on each port in navigation epochs, 0 disables it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rates {
+    pub ddc: u8,
+    pub uart1: u8,
+    pub uart2: u8,
+    pub usb: u8,
+    pub spi: u8,
+}
+
+impl Rates {
+    /// `rate` on UART1, off on the other ports
+    pub const fn uart1(rate: u8) -> Self {
+        Self {
+            ddc: 0,
+            uart1: rate,
+            uart2: 0,
+            usb: 0,
+            spi: 0,
+        }
+    }
+}
+
+/// Settings of one port. Autobauding is left off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortConfig {
+    pub port: u8,
+    /// Protocols accepted, a mask of `PROTO_*`
+    pub input: u16,
+    /// Protocols output, a mask of `PROTO_*`
+    pub output: u16,
+    pub baud: u32,
+}
+
+/// Write `$PUBX,40` setting the rates of NMEA message `msg_id`, e.g. `GSV`, including line
+/// ending
+pub fn write_rate(out: &mut impl Write, msg_id: &str, rates: &Rates) -> fmt::Result {
+    let mut body = String::<MAX_SENTENCE_LEN>::new();
+    write!(
+        body,
+        "PUBX,40,{},{},{},{},{},{},0",
+        msg_id, rates.ddc, rates.uart1, rates.uart2, rates.usb, rates.spi
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, checksum(body.as_bytes()))
+}
+
+/// Write `$PUBX,41` configuring a port, including line ending
+pub fn write_port(out: &mut impl Write, config: &PortConfig) -> fmt::Result {
+    let mut body = String::<MAX_SENTENCE_LEN>::new();
+    write!(
+        body,
+        "PUBX,41,{},{:04X},{:04X},{},0",
+        config.port, config.input, config.output, config.baud
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, checksum(body.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_sentence() {
+        // Example of the u-blox 7 receiver description
+        let mut line = String::<MAX_SENTENCE_LEN>::new();
+        let ddc = Rates {
+            ddc: 1,
+            ..Rates::default()
+        };
+        write_rate(&mut line, "GLL", &ddc).unwrap();
+        assert_eq!(line, "$PUBX,40,GLL,1,0,0,0,0,0*5D\r\n");
+        line.clear();
+        write_rate(&mut line, "GSV", &Rates::uart1(5)).unwrap();
+        assert_eq!(line, "$PUBX,40,GSV,0,5,0,0,0,0*5C\r\n");
+    }
+
+    #[test]
+    fn port_sentence() {
+        // Example of the u-blox 7 receiver description
+        let mut line = String::<MAX_SENTENCE_LEN>::new();
+        let config = PortConfig {
+            port: PORT_UART1,
+            input: PROTO_UBX | PROTO_NMEA | PROTO_RTCM,
+            output: PROTO_UBX | PROTO_NMEA,
+            baud: 19_200,
+        };
+        write_port(&mut line, &config).unwrap();
+        assert_eq!(line, "$PUBX,41,1,0007,0003,19200,0*25\r\n");
+    }
+}
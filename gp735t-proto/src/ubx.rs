@@ -0,0 +1,388 @@
+//! u-blox UBX binary protocol: frame building and streaming frame parsing.
+//!
+//! A frame is `0xB5 0x62`, class, ID, little endian payload length, payload and a two byte
+//! 8-bit Fletcher checksum over class, ID, length and payload.
+//!
+//! [`NavPvt`] decodes UBX-NAV-PVT, the one navigation message carrying a whole epoch, for a
+//! receiver switched from NMEA to UBX output.
+
+use crate::nmea::{Date, FixType, Time};
+use heapless::Vec;
+
+pub const SYNC: [u8; 2] = [0xB5, 0x62];
+
+/// Largest payload built or parsed here, that of NAV-PVT from protocol 15 on. Configuration
+/// messages and ACKs are much smaller.
+pub const MAX_PAYLOAD_LEN: usize = 92;
+
+/// Sync, class, ID, length and checksum bytes around the payload
+pub const FRAME_OVERHEAD: usize = 8;
+
+pub const MAX_FRAME_LEN: usize = MAX_PAYLOAD_LEN + FRAME_OVERHEAD;
+
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const CLASS_ACK: u8 = 0x05;
+pub const CLASS_CFG: u8 = 0x06;
+pub const ID_NAV_PVT: u8 = 0x07;
+pub const ID_ACK_NAK: u8 = 0x00;
+pub const ID_ACK_ACK: u8 = 0x01;
+
+/// NAV-PVT payload of protocol 14, the u-blox 7 of the GP-735T. Later versions append fields.
+pub const NAV_PVT_LEN: usize = 84;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Payload exceeds [`MAX_PAYLOAD_LEN`]
+    TooLong,
+}
+
+/// 8-bit Fletcher checksum `[CK_A, CK_B]`
+pub fn checksum(data: &[u8]) -> [u8; 2] {
+    update_checksum([0, 0], data)
+}
+
+/// Continue a checksum over more data
+pub fn update_checksum(checksum: [u8; 2], data: &[u8]) -> [u8; 2] {
+    data.iter().fold(checksum, |[a, b], &byte| {
+        let a = a.wrapping_add(byte);
+        [a, b.wrapping_add(a)]
+    })
+}
+
+/// Build a complete frame ready to be written to the receiver
+pub fn encode(class: u8, id: u8, payload: &[u8]) -> Result<Frame, Error> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(Error::TooLong);
+    }
+    let mut frame = Frame::new();
+    let length = (payload.len() as u16).to_le_bytes();
+    // Capacity was checked above
+    let _ = frame.extend_from_slice(&SYNC);
+    let _ = frame.extend_from_slice(&[class, id, length[0], length[1]]);
+    let _ = frame.extend_from_slice(payload);
+    let checksum = checksum(&frame[2..]);
+    let _ = frame.extend_from_slice(&checksum);
+    Ok(frame)
+}
+
+/// Frame received by [`Parser`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub class: u8,
+    pub id: u8,
+    pub payload: &'a [u8],
+}
+
+/// Acknowledgement of a configuration message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ack {
+    /// Message `class`, `id` was accepted
+    Ack { class: u8, id: u8 },
+    /// Message `class`, `id` was rejected
+    Nak { class: u8, id: u8 },
+}
+
+impl Ack {
+    /// Decode a UBX-ACK-ACK or UBX-ACK-NAK packet
+    pub fn parse(packet: &Packet) -> Option<Self> {
+        if packet.class != CLASS_ACK || packet.payload.len() != 2 {
+            return None;
+        }
+        let (class, id) = (packet.payload[0], packet.payload[1]);
+        match packet.id {
+            ID_ACK_ACK => Some(Ack::Ack { class, id }),
+            ID_ACK_NAK => Some(Ack::Nak { class, id }),
+            _ => None,
+        }
+    }
+}
+
+/// Navigation solution of one epoch from UBX-NAV-PVT, in the units of [`crate::nmea`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NavPvt {
+    /// UTC time, `None` until resolved. Milliseconds are truncated from the nanosecond
+    /// correction, which is negative for the last half millisecond before a second.
+    pub time: Option<Time>,
+    pub date: Option<Date>,
+    /// `None` unless the receiver flags the fix as within its accuracy limits
+    pub fix: FixType,
+    pub satellites: u8,
+    /// Latitude in 1e-7 degrees, north positive
+    pub latitude: i32,
+    /// Longitude in 1e-7 degrees, east positive
+    pub longitude: i32,
+    /// Height above mean sea level in millimetres
+    pub altitude: i32,
+    /// Ground speed in millimetres per second
+    pub speed: u32,
+    /// Heading of motion in hundredths of a degree
+    pub course: u16,
+    /// Position dilution of precision in hundredths, NAV-PVT carries no HDOP
+    pub pdop: u16,
+}
+
+impl NavPvt {
+    /// Decode a UBX-NAV-PVT packet of any protocol version
+    pub fn parse(packet: &Packet) -> Option<Self> {
+        let p = packet.payload;
+        if packet.class != CLASS_NAV || packet.id != ID_NAV_PVT || p.len() < NAV_PVT_LEN {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([p[i], p[i + 1]]);
+        let i32_at = |i: usize| i32::from_le_bytes([p[i], p[i + 1], p[i + 2], p[i + 3]]);
+        let valid = p[11];
+        let date = (valid & 0x01 != 0).then_some(Date {
+            year: u16_at(4),
+            month: p[6],
+            day: p[7],
+        });
+        let time = (valid & 0x02 != 0).then_some(Time {
+            hour: p[8],
+            minute: p[9],
+            second: p[10],
+            millisecond: (i32_at(16).max(0) / 1_000_000) as u16,
+        });
+        let gnss_fix_ok = p[21] & 0x01 != 0;
+        let fix = match p[20] {
+            2 if gnss_fix_ok => FixType::Fix2D,
+            // 3D and GNSS with dead reckoning
+            3 | 4 if gnss_fix_ok => FixType::Fix3D,
+            _ => FixType::None,
+        };
+        Some(Self {
+            time,
+            date,
+            fix,
+            satellites: p[23],
+            longitude: i32_at(24),
+            latitude: i32_at(28),
+            altitude: i32_at(36),
+            speed: i32_at(60).max(0) as u32,
+            // 1e-5 degrees
+            course: (i32_at(64).rem_euclid(36_000_000) / 1000) as u16,
+            pdop: u16_at(76),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Sync1,
+    Sync2,
+    Class,
+    Id,
+    Length1,
+    Length2,
+    Payload,
+    ChecksumA,
+    ChecksumB,
+}
+
+/// Finds UBX frames in a byte stream which may also carry NMEA sentences.
+/// Frames with a bad checksum or a payload longer than [`MAX_PAYLOAD_LEN`] are discarded.
+pub struct Parser {
+    state: State,
+    class: u8,
+    id: u8,
+    length: usize,
+    payload: Vec<u8, MAX_PAYLOAD_LEN>,
+    checksum_a: u8,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Sync1,
+            class: 0,
+            id: 0,
+            length: 0,
+            payload: Vec::new(),
+            checksum_a: 0,
+        }
+    }
+
+    /// Feed one received byte. Returns the packet once its checksum has been verified.
+    pub fn push(&mut self, byte: u8) -> Option<Packet<'_>> {
+        self.state = match self.state {
+            State::Sync1 if byte == SYNC[0] => State::Sync2,
+            State::Sync1 => State::Sync1,
+            State::Sync2 if byte == SYNC[1] => State::Class,
+            // Could be the first sync byte of a frame following a stray 0xB5
+            State::Sync2 if byte == SYNC[0] => State::Sync2,
+            State::Sync2 => State::Sync1,
+            State::Class => {
+                self.class = byte;
+                State::Id
+            }
+            State::Id => {
+                self.id = byte;
+                State::Length1
+            }
+            State::Length1 => {
+                self.length = byte.into();
+                State::Length2
+            }
+            State::Length2 => {
+                self.length |= usize::from(byte) << 8;
+                self.payload.clear();
+                match self.length {
+                    0 => State::ChecksumA,
+                    length if length > MAX_PAYLOAD_LEN => State::Sync1,
+                    _ => State::Payload,
+                }
+            }
+            State::Payload => {
+                // Length was checked against capacity
+                let _ = self.payload.push(byte);
+                if self.payload.len() == self.length {
+                    State::ChecksumA
+                } else {
+                    State::Payload
+                }
+            }
+            State::ChecksumA => {
+                self.checksum_a = byte;
+                State::ChecksumB
+            }
+            State::ChecksumB => {
+                self.state = State::Sync1;
+                return (self.checksum() == [self.checksum_a, byte]).then_some(Packet {
+                    class: self.class,
+                    id: self.id,
+                    payload: &self.payload,
+                });
+            }
+        };
+        None
+    }
+
+    /// Checksum over class, ID, length and received payload
+    fn checksum(&self) -> [u8; 2] {
+        let length = (self.length as u16).to_le_bytes();
+        let header = checksum(&[self.class, self.id, length[0], length[1]]);
+        update_checksum(header, &self.payload)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_cfg_rate() {
+        // 1Hz navigation rate as generated by u-center
+        let frame = encode(CLASS_CFG, 0x08, &[0xE8, 0x03, 0x01, 0x00, 0x01, 0x00]).unwrap();
+        assert_eq!(
+            frame,
+            [0xB5, 0x62, 0x06, 0x08, 0x06, 0x00, 0xE8, 0x03, 0x01, 0x00, 0x01, 0x00, 0x01, 0x39]
+        );
+    }
+
+    #[test]
+    fn encode_too_long() {
+        assert_eq!(
+            encode(CLASS_CFG, 0x00, &[0; MAX_PAYLOAD_LEN + 1]),
+            Err(Error::TooLong)
+        );
+    }
+
+    #[test]
+    fn parse_ack_between_sentences() {
+        let frame = encode(CLASS_ACK, ID_ACK_ACK, &[CLASS_CFG, 0x08]).unwrap();
+        let mut parser = Parser::new();
+        let mut acks = 0;
+        for &byte in b"$GPTXT,01*00\r\n\xB5".iter().chain(frame.iter()) {
+            if let Some(packet) = parser.push(byte) {
+                let ack = Ack::parse(&packet);
+                assert_eq!(
+                    ack,
+                    Some(Ack::Ack {
+                        class: CLASS_CFG,
+                        id: 0x08
+                    })
+                );
+                acks += 1;
+            }
+        }
+        assert_eq!(acks, 1);
+    }
+
+    #[test]
+    fn parse_nav_pvt() {
+        let mut payload = [0u8; NAV_PVT_LEN];
+        payload[4..6].copy_from_slice(&2024u16.to_le_bytes());
+        payload[6..12].copy_from_slice(&[3, 15, 12, 34, 56, 0x07]);
+        payload[16..20].copy_from_slice(&250_000_000i32.to_le_bytes());
+        payload[20..24].copy_from_slice(&[3, 0x01, 0, 9]);
+        payload[24..28].copy_from_slice(&151_234_567i32.to_le_bytes());
+        payload[28..32].copy_from_slice(&(-338_765_432i32).to_le_bytes());
+        payload[36..40].copy_from_slice(&12_345i32.to_le_bytes());
+        payload[60..64].copy_from_slice(&1_500i32.to_le_bytes());
+        payload[64..68].copy_from_slice(&27_050_000i32.to_le_bytes());
+        payload[76..78].copy_from_slice(&180u16.to_le_bytes());
+        let frame = encode(CLASS_NAV, ID_NAV_PVT, &payload).unwrap();
+        let mut parser = Parser::new();
+        let pvt = frame
+            .iter()
+            .find_map(|&byte| parser.push(byte).and_then(|packet| NavPvt::parse(&packet)));
+        assert_eq!(
+            pvt,
+            Some(NavPvt {
+                time: Some(Time {
+                    hour: 12,
+                    minute: 34,
+                    second: 56,
+                    millisecond: 250
+                }),
+                date: Some(Date {
+                    year: 2024,
+                    month: 3,
+                    day: 15
+                }),
+                fix: FixType::Fix3D,
+                satellites: 9,
+                latitude: -338_765_432,
+                longitude: 151_234_567,
+                altitude: 12_345,
+                speed: 1_500,
+                course: 27_050,
+                pdop: 180,
+            })
+        );
+    }
+
+    #[test]
+    fn nav_pvt_without_fix_ok() {
+        let mut payload = [0u8; NAV_PVT_LEN];
+        payload[20] = 3;
+        let packet = Packet {
+            class: CLASS_NAV,
+            id: ID_NAV_PVT,
+            payload: &payload,
+        };
+        let pvt = NavPvt::parse(&packet).unwrap();
+        assert_eq!((pvt.fix, pvt.time, pvt.date), (FixType::None, None, None));
+        let short = Packet {
+            payload: &payload[..NAV_PVT_LEN - 1],
+            ..packet
+        };
+        assert_eq!(NavPvt::parse(&short), None);
+    }
+
+    #[test]
+    fn discard_bad_checksum() {
+        let mut frame = encode(CLASS_ACK, ID_ACK_NAK, &[CLASS_CFG, 0x08]).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        let mut parser = Parser::new();
+        assert!(frame.iter().all(|&byte| parser.push(byte).is_none()));
+    }
+}
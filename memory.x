@@ -1,6 +0,0 @@
-/* From stm32l432kc datasheet chapter 5 */
-MEMORY
-{
-  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
-  RAM : ORIGIN = 0x20000000, LENGTH = 48K
-}
@@ -0,0 +1,21 @@
+/* From stm32l432kc datasheet chapter 5 */
+/* The last two 2K pages hold the saved configuration, see src/config.rs */
+/* The 32 pages before them hold the track log, see src/flashlog.rs */
+/* The page before the log is erased and programmed by SELFTEST, see src/selftest.rs */
+/* The first 1K of SRAM1 holds data kept through a reset, see src/persist.rs */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 186K
+  PERSIST : ORIGIN = 0x20000000, LENGTH = 1K
+  RAM : ORIGIN = 0x20000400, LENGTH = 47K
+}
+
+/* Neither loaded nor zeroed at startup, and at the same address in every build */
+SECTIONS
+{
+  .persist (NOLOAD) : ALIGN(4)
+  {
+    *(.persist .persist.*);
+  } > PERSIST
+}
+INSERT AFTER .uninit;
@@ -0,0 +1,21 @@
+/* From stm32l476rg datasheet chapter 5, SRAM2 at 0x10000000 is left unused */
+/* With the ab-boot feature an image fits in either 512K bank, see src/boot.rs */
+/* The last 35 pages of the bank mapped second hold the SELFTEST scratch page, track log and */
+/* configuration */
+/* The first 1K of SRAM1 holds data kept through a reset, see src/persist.rs */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 442K
+  PERSIST : ORIGIN = 0x20000000, LENGTH = 1K
+  RAM : ORIGIN = 0x20000400, LENGTH = 95K
+}
+
+/* Neither loaded nor zeroed at startup, and at the same address in every build */
+SECTIONS
+{
+  .persist (NOLOAD) : ALIGN(4)
+  {
+    *(.persist .persist.*);
+  } > PERSIST
+}
+INSERT AFTER .uninit;
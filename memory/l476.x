@@ -0,0 +1,21 @@
+/* From stm32l476rg datasheet chapter 5, SRAM2 at 0x10000000 is left unused */
+/* The last two 2K pages of bank 2 hold the saved configuration, see src/config.rs */
+/* The 32 pages before them hold the track log, see src/flashlog.rs */
+/* The page before the log is erased and programmed by SELFTEST, see src/selftest.rs */
+/* The first 1K of SRAM1 holds data kept through a reset, see src/persist.rs */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 954K
+  PERSIST : ORIGIN = 0x20000000, LENGTH = 1K
+  RAM : ORIGIN = 0x20000400, LENGTH = 95K
+}
+
+/* Neither loaded nor zeroed at startup, and at the same address in every build */
+SECTIONS
+{
+  .persist (NOLOAD) : ALIGN(4)
+  {
+    *(.persist .persist.*);
+  } > PERSIST
+}
+INSERT AFTER .uninit;
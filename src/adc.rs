@@ -0,0 +1,169 @@
+//! Supply voltages and temperature measured with ADC1: VDDA from the internal reference, the
+//! battery on the VBAT pin through the internal divider by 3 and the internal temperature sensor.
+//!
+//! VREFINT is calibrated by ST at VDDA 3.0V, so its reading gives VDDA, which scales the VBAT
+//! reading to millivolts. The VBAT pin takes up to 3.6V, a battery of higher voltage, e.g. a
+//! single Li-ion cell, needs an external divider in front of it and reads divided. On the
+//! NUCLEO-L432KC VBAT is tied to VDD and reads the 3.3V supply.
+//!
+//! The temperature sensor is calibrated by ST at 30°C and [`chip::TS_CAL2_C`], its reading
+//! scaled to VDDA 3.0V is interpolated between them. It measures the die, a few degrees above the
+//! air in the enclosure, and is accurate to about 2°C. The last reading is kept for the
+//! `$PBRIDGE,STATUS` sentence and binary fix records, see [`temperature`].
+//!
+//! The ADC is powered up for each [`Adc::sample`] and put back in deep power-down afterwards, and
+//! the VBAT divider and the sensor are only connected during their conversions, so none draws
+//! current in Stop mode. A sample busy-waits for about 150µs, regulator startup and calibration
+//! included.
+
+use crate::chip::{
+    self,
+    pac::{ADC1, ADC_COMMON, RCC},
+};
+use crate::clocks;
+use core::sync::atomic::{AtomicI16, Ordering};
+
+/// VREFINT_CAL, the raw reading of VREFINT at VDDA [`CAL_MV`], in system memory
+const VREFINT_CAL: *const u16 = 0x1FFF_75AA as *const u16;
+
+/// Raw readings of the temperature sensor at 30°C and [`chip::TS_CAL2_C`], VDDA [`CAL_MV`]
+const TS_CAL1: *const u16 = 0x1FFF_75A8 as *const u16;
+const TS_CAL2: *const u16 = 0x1FFF_75CA as *const u16;
+
+const TS_CAL1_C: i32 = 30;
+
+/// VDDA of the factory calibration values
+const CAL_MV: u32 = 3000;
+
+/// ADC1 input channels of VREFINT, the temperature sensor and VBAT/3
+const CHANNEL_VREFINT: u8 = 0;
+const CHANNEL_TEMPERATURE: u8 = 17;
+const CHANNEL_VBAT: u8 = 18;
+
+/// Full scale of a 12 bit conversion
+const FULL_SCALE: u32 = 4095;
+
+/// ADC_CCR PRESC value dividing the ADC clock by 4, SYSCLK of up to 80MHz gives up to 20MHz
+const PRESC_DIV4: u8 = 0b0010;
+
+/// ADC_SMPR sampling time of 640.5 cycles, 32µs at 20MHz, above the 4µs VREFINT, 5µs
+/// temperature sensor and 12µs VBAT minimums
+const SMP_640_5: u8 = 0b111;
+
+/// ADC voltage regulator startup time
+const REGULATOR_STARTUP_US: u32 = 20;
+
+/// Readings of one [`Adc::sample`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Readings {
+    /// Analog supply, the MCU supply on most boards
+    pub vdda_mv: u16,
+    pub vbat_mv: u16,
+    /// Tenths of a degree Celsius
+    pub temperature: i16,
+}
+
+/// Last temperature in tenths of a degree Celsius, `i16::MIN` before the first sample
+static TEMPERATURE: AtomicI16 = AtomicI16::new(i16::MIN);
+
+/// Last temperature in tenths of a degree Celsius, `None` before the first sample
+pub fn temperature() -> Option<i16> {
+    Some(TEMPERATURE.load(Ordering::Relaxed)).filter(|&temperature| temperature != i16::MIN)
+}
+
+pub struct Adc {
+    adc: ADC1,
+    common: ADC_COMMON,
+}
+
+impl Adc {
+    /// Clock ADC1 from SYSCLK and set the sampling times, leaving it in deep power-down.
+    /// Must be called after the AHB2 clock enables of `init`, which overwrite the register.
+    pub fn new(adc: ADC1, common: ADC_COMMON, rcc: &RCC) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.adcen().set_bit());
+        rcc.ccipr.modify(|_, w| w.adcsel().sysclk());
+        // SAFETY: CKMODE 0 is the asynchronous clock of ADCSEL, PRESC_DIV4 divides it by 4
+        common
+            .ccr
+            .modify(|_, w| unsafe { w.ckmode().bits(0).presc().bits(PRESC_DIV4) });
+        // SAFETY: SMP_640_5 is a valid sampling time
+        adc.smpr1.modify(|_, w| unsafe { w.smp0().bits(SMP_640_5) });
+        adc.smpr2
+            .modify(|_, w| unsafe { w.smp17().bits(SMP_640_5).smp18().bits(SMP_640_5) });
+        Self { adc, common }
+    }
+
+    /// Measure VDDA, VBAT and the temperature, powering the ADC up and back down. The
+    /// temperature is kept for [`temperature`].
+    pub fn sample(&mut self) -> Readings {
+        self.power_up();
+        self.common.ccr.modify(|_, w| w.vrefen().set_bit());
+        let vrefint = self.convert(CHANNEL_VREFINT);
+        self.common
+            .ccr
+            .modify(|_, w| w.vrefen().clear_bit().ch17sel().set_bit());
+        let sensor = self.convert(CHANNEL_TEMPERATURE);
+        self.common
+            .ccr
+            .modify(|_, w| w.ch17sel().clear_bit().ch18sel().set_bit());
+        let vbat = self.convert(CHANNEL_VBAT);
+        self.common.ccr.modify(|_, w| w.ch18sel().clear_bit());
+        self.power_down();
+
+        // SAFETY: the calibration values are readable in system memory of every STM32L4
+        let (cal, ts_cal1, ts_cal2) = unsafe {
+            (
+                u32::from(core::ptr::read_volatile(VREFINT_CAL)),
+                i32::from(core::ptr::read_volatile(TS_CAL1)),
+                i32::from(core::ptr::read_volatile(TS_CAL2)),
+            )
+        };
+        let vdda_mv = CAL_MV * cal / u32::from(vrefint).max(1);
+        let vbat_mv = 3 * u32::from(vbat) * vdda_mv / FULL_SCALE;
+        // Sensor reading had VDDA been CAL_MV, interpolated in tenths of a degree
+        let sensor = (u32::from(sensor) * vdda_mv / CAL_MV) as i32;
+        let temperature = TS_CAL1_C * 10
+            + (chip::TS_CAL2_C - TS_CAL1_C) * 10 * (sensor - ts_cal1) / (ts_cal2 - ts_cal1).max(1);
+        let temperature = temperature.clamp(i32::from(i16::MIN) + 1, i16::MAX.into()) as i16;
+        TEMPERATURE.store(temperature, Ordering::Relaxed);
+        Readings {
+            vdda_mv: vdda_mv.min(u16::MAX.into()) as u16,
+            vbat_mv: vbat_mv.min(u16::MAX.into()) as u16,
+            temperature,
+        }
+    }
+
+    /// Leave deep power-down, calibrate and enable the ADC
+    fn power_up(&mut self) {
+        self.adc.cr.modify(|_, w| w.deeppwd().clear_bit());
+        self.adc.cr.modify(|_, w| w.advregen().set_bit());
+        cortex_m::asm::delay(clocks::SYSCLK_HZ / 1_000_000 * REGULATOR_STARTUP_US);
+        self.adc
+            .cr
+            .modify(|_, w| w.adcaldif().clear_bit().adcal().set_bit());
+        while self.adc.cr.read().adcal().bit_is_set() {}
+        self.adc.isr.write(|w| w.adrdy().set_bit());
+        self.adc.cr.modify(|_, w| w.aden().set_bit());
+        while self.adc.isr.read().adrdy().bit_is_clear() {}
+    }
+
+    /// Disable the ADC and its regulator, losing the calibration
+    fn power_down(&mut self) {
+        self.adc.cr.modify(|_, w| w.addis().set_bit());
+        while self.adc.cr.read().aden().bit_is_set() {}
+        self.adc.cr.modify(|_, w| w.advregen().clear_bit());
+        self.adc.cr.modify(|_, w| w.deeppwd().set_bit());
+    }
+
+    /// Single conversion of `channel`
+    fn convert(&mut self, channel: u8) -> u16 {
+        // SAFETY: a sequence of one conversion of a valid channel
+        self.adc
+            .sqr1
+            .write(|w| unsafe { w.l().bits(0).sq1().bits(channel) });
+        self.adc.isr.write(|w| w.eoc().set_bit());
+        self.adc.cr.modify(|_, w| w.adstart().set_bit());
+        while self.adc.isr.read().eoc().bit_is_clear() {}
+        self.adc.dr.read().rdata().bits()
+    }
+}
@@ -0,0 +1,199 @@
+//! Aiding data uploaded from the host, such as AssistNow Offline, to shorten the time to first fix.
+//!
+//! `AID` enters aiding mode, in which the host sends UBX frames such as UBX-AID-ALP, UBX-AID-EPH
+//! or UBX-MGA-ANO on USART2 between command lines. A frame starts with the sync byte 0xB5, which
+//! no command contains, and its bytes are queued for USART1 as they arrive rather than buffered.
+//! The whole frame has to fit in the GPS TX queue, so the host sends one frame and waits for
+//! `AID NEXT`, sent once the frame has been handed to USART1. A frame that is too long, had bytes
+//! dropped or fails its checksum is answered `AID ERR <reason>` instead, the GPS discards what it
+//! received of it by the checksum.
+//!
+//! In the other direction acknowledgements of the GPS, UBX-ACK, UBX-MGA-ACK-DATA0 and the
+//! UBX-AID-ALP transfer acknowledgement, are counted and reported as `AID ACK|NAK <class> <id>`,
+//! and other UBX frames are forwarded to the host. NMEA forwarding pauses in aiding mode.
+//! `AID END` leaves it and is answered `AID END <frames> <acks> <naks>`.
+
+use crate::ubx::{self, Ack, Packet};
+use core::fmt::{self, Write};
+
+pub const CLASS_AID: u8 = 0x0B;
+pub const CLASS_MGA: u8 = 0x13;
+pub const ID_AID_ALP: u8 = 0x50;
+pub const ID_MGA_ACK: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Frame doesn't fit in the GPS TX queue
+    TooLong,
+    /// GPS TX queue was full, the host didn't wait for `AID NEXT`
+    Busy,
+    Checksum,
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::TooLong => "TOO_LONG",
+            Error::Busy => "BUSY",
+            Error::Checksum => "CHECKSUM",
+        }
+    }
+}
+
+/// Progress of the frame a byte belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// More bytes of the frame follow
+    Continue,
+    /// Last byte of a frame, all of it queued
+    Complete,
+    /// The frame is abandoned, bytes following a frame too long aren't part of a frame
+    Failed(Error),
+}
+
+/// Aiding mode state, frames tracked from the host and acknowledgements from the GPS
+pub struct Upload {
+    /// Longest frame accepted, the GPS TX queue capacity
+    max_frame_len: usize,
+    /// Bytes of the frame in progress, 0 between frames
+    received: usize,
+    /// Class, ID and length of the frame in progress
+    header: [u8; 4],
+    checksum: [u8; 2],
+    /// CK_A of the frame in progress matched
+    checksum_a_ok: bool,
+    /// A byte of the frame in progress didn't fit in the GPS TX queue
+    dropped: bool,
+    /// `AID NEXT` is due once the GPS TX queue has been emptied
+    draining: bool,
+    frames: u32,
+    acks: u32,
+    naks: u32,
+}
+
+impl Upload {
+    pub fn new(max_frame_len: usize) -> Self {
+        Self {
+            max_frame_len,
+            received: 0,
+            header: [0; 4],
+            checksum: [0; 2],
+            checksum_a_ok: false,
+            dropped: false,
+            draining: false,
+            frames: 0,
+            acks: 0,
+            naks: 0,
+        }
+    }
+
+    /// `byte` continues a frame or starts a new one, and is to be queued for the GPS
+    pub fn accepts(&self, byte: u8) -> bool {
+        self.received > 0 || byte == ubx::SYNC[0]
+    }
+
+    /// Track an accepted byte, `queued` if it fit in the GPS TX queue. `None` if a frame didn't
+    /// start after all, so the byte is handled as part of a command line.
+    pub fn push(&mut self, byte: u8, queued: bool) -> Option<Step> {
+        self.dropped |= !queued;
+        match self.received {
+            0 => {
+                self.checksum = [0; 2];
+                self.dropped = !queued;
+            }
+            1 if byte != ubx::SYNC[1] => {
+                // The stray sync byte queued for the GPS is ignored by it
+                self.received = 0;
+                return None;
+            }
+            1 => {}
+            2..=5 => {
+                self.header[self.received - 2] = byte;
+                self.checksum = ubx::update_checksum(self.checksum, &[byte]);
+            }
+            _ if self.received < self.frame_len() - 2 => {
+                self.checksum = ubx::update_checksum(self.checksum, &[byte]);
+            }
+            _ if self.received == self.frame_len() - 2 => {
+                self.checksum_a_ok = byte == self.checksum[0];
+            }
+            _ => {
+                self.received = 0;
+                return Some(if !self.checksum_a_ok || byte != self.checksum[1] {
+                    Step::Failed(Error::Checksum)
+                } else if self.dropped {
+                    Step::Failed(Error::Busy)
+                } else {
+                    self.frames += 1;
+                    self.draining = true;
+                    Step::Complete
+                });
+            }
+        }
+        self.received += 1;
+        if self.received == 6 && self.frame_len() > self.max_frame_len {
+            self.received = 0;
+            return Some(Step::Failed(Error::TooLong));
+        }
+        Some(Step::Continue)
+    }
+
+    /// Frame length including sync, header and checksum, once the header has been received
+    fn frame_len(&self) -> usize {
+        usize::from(u16::from_le_bytes([self.header[2], self.header[3]])) + ubx::FRAME_OVERHEAD
+    }
+
+    /// Called once the GPS TX queue is empty, true if `AID NEXT` is due
+    pub fn take_drained(&mut self) -> bool {
+        core::mem::take(&mut self.draining)
+    }
+
+    /// Count an acknowledgement of aiding data or configuration received from the GPS
+    pub fn on_packet(&mut self, packet: &Packet) -> Option<Ack> {
+        let ack = ack(packet)?;
+        match ack {
+            Ack::Ack { .. } => self.acks += 1,
+            Ack::Nak { .. } => self.naks += 1,
+        }
+        Some(ack)
+    }
+}
+
+/// Decode UBX-ACK, UBX-MGA-ACK-DATA0 or the UBX-AID-ALP transfer acknowledgement
+pub fn ack(packet: &Packet) -> Option<Ack> {
+    match (packet.class, packet.id, packet.payload) {
+        (CLASS_MGA, ID_MGA_ACK, &[kind, _, _, id, ..]) if packet.payload.len() == 8 => {
+            let class = CLASS_MGA;
+            Some(match kind {
+                1 => Ack::Ack { class, id },
+                _ => Ack::Nak { class, id },
+            })
+        }
+        (CLASS_AID, ID_AID_ALP, &[kind]) => {
+            let (class, id) = (CLASS_AID, ID_AID_ALP);
+            Some(match kind {
+                1 => Ack::Ack { class, id },
+                _ => Ack::Nak { class, id },
+            })
+        }
+        _ => Ack::parse(packet),
+    }
+}
+
+/// Write the report of an acknowledgement received in aiding mode
+pub fn write_ack(out: &mut impl Write, ack: &Ack) -> fmt::Result {
+    let (result, class, id) = match *ack {
+        Ack::Ack { class, id } => ("ACK", class, id),
+        Ack::Nak { class, id } => ("NAK", class, id),
+    };
+    write!(out, "AID {} {:02X} {:02X}", result, class, id)
+}
+
+/// Write the `AID END` response
+pub fn write_end(out: &mut impl Write, upload: &Upload) -> fmt::Result {
+    write!(
+        out,
+        "AID END {} {} {}",
+        upload.frames, upload.acks, upload.naks
+    )
+}
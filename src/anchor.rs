@@ -0,0 +1,192 @@
+//! Anchor watch: `ANCHOR SET [<radius_m>]` stores the current position, and the boat dragging
+//! its anchor out of the radius is reported as
+//! `$PBRIDGE,ALARM,ANCHOR,ON|OFF,<distance_m>,<radius_m>*hh`.
+//!
+//! The position checked is the mean of the last [`AVERAGE_FIXES`] fixes, so position noise of a
+//! single fix doesn't raise the alarm, and it clears once the mean is back within the radius
+//! less the geofence [`HYSTERESIS_M`]. Distances are haversine distances, see
+//! [`crate::nav::haversine_m`]. Offsets from the anchor are averaged rather than coordinates, so
+//! an anchorage on the antimeridian works too.
+//!
+//! With the `anchor-alarm` feature PA8 (D9) is driven high while the alarm is raised. PA8 powers
+//! the GPS with the `usb` feature, so the feature excludes it.
+
+use crate::fix::GpsFix;
+use crate::geofence::HYSTERESIS_M;
+use crate::nav;
+use crate::nmea;
+use core::fmt::{self, Write};
+use heapless::{Deque, String};
+
+#[cfg(all(feature = "anchor-alarm", feature = "usb"))]
+compile_error!("feature `anchor-alarm` uses PA8, the GPS power with `usb`, and excludes `usb`");
+
+/// Fixes averaged before the distance is checked
+pub const AVERAGE_FIXES: usize = 5;
+
+/// Radius of `ANCHOR SET` without one
+pub const DEFAULT_RADIUS_M: u32 = 30;
+
+/// Largest radius accepted
+pub const MAX_RADIUS_M: u32 = 10_000;
+
+/// GPIOA pin of the alarm output
+#[cfg(feature = "anchor-alarm")]
+const ALARM_PIN: u32 = 8;
+
+#[cfg(feature = "anchor-alarm")]
+const _: () = assert!(
+    !crate::board::uses(ALARM_PIN),
+    "anchor-alarm pin used by the board"
+);
+
+/// Longitude span of a full turn in 1e-7 degrees
+const FULL_TURN: i64 = 3_600_000_000;
+
+/// Stored anchor position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    /// In 1e-7 degrees
+    pub lat: i32,
+    pub lon: i32,
+    pub radius_m: u32,
+}
+
+/// Alarm raised or cleared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub raised: bool,
+    /// Distance of the mean position from the anchor
+    pub distance_m: f32,
+    pub radius_m: u32,
+}
+
+pub struct AnchorWatch {
+    anchor: Option<Anchor>,
+    /// Latest offsets of fixes from the anchor in 1e-7 degrees
+    offsets: Deque<(i64, i64), AVERAGE_FIXES>,
+    /// Distance of the mean of a full set of offsets
+    distance_m: Option<f32>,
+    raised: bool,
+}
+
+impl AnchorWatch {
+    pub const fn new() -> Self {
+        Self {
+            anchor: None,
+            offsets: Deque::new(),
+            distance_m: None,
+            raised: false,
+        }
+    }
+
+    pub fn anchor(&self) -> Option<Anchor> {
+        self.anchor
+    }
+
+    /// Distance of the mean position from the anchor, once enough fixes were averaged
+    pub fn distance_m(&self) -> Option<f32> {
+        self.distance_m
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised
+    }
+
+    /// Watch `anchor`, or stop watching. The alarm starts cleared.
+    pub fn set(&mut self, anchor: Option<Anchor>) {
+        *self = Self {
+            anchor,
+            ..Self::new()
+        };
+    }
+
+    /// Account for a completed fix, returning the change of the alarm if any
+    pub fn update(&mut self, fix: &GpsFix) -> Option<Event> {
+        let anchor = self.anchor?;
+        if !fix.is_valid() {
+            return None;
+        }
+        if self.offsets.is_full() {
+            self.offsets.pop_front();
+        }
+        // Shortest way around, across the antimeridian if need be
+        let dlon = (i64::from(fix.lon) - i64::from(anchor.lon) + FULL_TURN / 2)
+            .rem_euclid(FULL_TURN)
+            - FULL_TURN / 2;
+        let _ = self
+            .offsets
+            .push_back((i64::from(fix.lat) - i64::from(anchor.lat), dlon));
+        if !self.offsets.is_full() {
+            return None;
+        }
+        let (sum_lat, sum_lon) = self.offsets.iter().fold((0, 0), |(lat, lon), offset| {
+            (lat + offset.0, lon + offset.1)
+        });
+        let count = AVERAGE_FIXES as i64;
+        // Back within ±180°, haversine only depends on the difference modulo a full turn
+        let mean_lon = (i64::from(anchor.lon) + sum_lon / count + FULL_TURN / 2)
+            .rem_euclid(FULL_TURN)
+            - FULL_TURN / 2;
+        let mean = (
+            (i64::from(anchor.lat) + sum_lat / count) as i32,
+            mean_lon as i32,
+        );
+        let distance_m = nav::haversine_m((anchor.lat, anchor.lon), mean);
+        self.distance_m = Some(distance_m);
+        let change = if self.raised {
+            distance_m <= anchor.radius_m.saturating_sub(HYSTERESIS_M) as f32
+        } else {
+            distance_m > anchor.radius_m as f32
+        };
+        if !change {
+            return None;
+        }
+        self.raised = !self.raised;
+        Some(Event {
+            raised: self.raised,
+            distance_m,
+            radius_m: anchor.radius_m,
+        })
+    }
+}
+
+impl Default for AnchorWatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the `$PBRIDGE,ALARM,ANCHOR` sentence including line ending, distance in whole metres
+pub fn write_sentence(out: &mut impl Write, event: &Event) -> fmt::Result {
+    let mut body = String::<48>::new();
+    write!(
+        body,
+        "PBRIDGE,ALARM,ANCHOR,{},{},{}",
+        if event.raised { "ON" } else { "OFF" },
+        libm::roundf(event.distance_m) as u32,
+        event.radius_m
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Alarm output on PA8, configured as push-pull output low. GPIOA clock must be enabled.
+#[cfg(feature = "anchor-alarm")]
+pub fn init_alarm(gpioa: &crate::chip::pac::GPIOA) {
+    set_alarm(false);
+    // SAFETY: 0b01 is output mode, push-pull by default, other pins are left unchanged
+    gpioa.moder.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b11 << (2 * ALARM_PIN)) | 0b01 << (2 * ALARM_PIN))
+    });
+}
+
+/// Drive the alarm output
+#[cfg(feature = "anchor-alarm")]
+pub fn set_alarm(on: bool) {
+    // SAFETY: BSRR writes are atomic and only touch PA8, other GPIOA pins are left to their users
+    let gpioa = unsafe { &*crate::chip::pac::GPIOA::ptr() };
+    // Lower half of BSRR sets pins, upper half resets them
+    let bit = if on { 1 } else { 1 << 16 } << ALARM_PIN;
+    // SAFETY: zero bits have no effect
+    gpioa.bsrr.write(|w| unsafe { w.bits(bit) });
+}
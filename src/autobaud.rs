@@ -0,0 +1,112 @@
+//! Recovery of the GPS baud, for a GPS that was reconfigured behind the bridge's back, e.g. by
+//! a UBX-CFG-PRT saved to its battery backed RAM or sent through the raw bridge.
+//!
+//! While the GPS is on, [`Autobaud::on_tick`] runs on every SysTick [`timer`](crate::timer)
+//! tick. Without a sentence passing its checksum for [`SILENCE_MS`], USART1 tries the rates of
+//! [`PROBE_BAUDS`] in turn, each for [`DWELL_MS`], skipping the one that stopped delivering.
+//! The first sentence passing its checksum locks onto the rate being tried, which becomes the
+//! GPS baud and is reported to the host as `$PBRIDGE,GPSBAUD,<baud>*hh`. Both times are
+//! extended by the update period in power save mode.
+//!
+//! Probing doesn't reach the [`crate::gps_ctrl`] data timeout with a full round, so the GPS is
+//! only power cycled once no rate worked. Turning the GPS off or the raw bridge, where the host
+//! talks to the GPS itself, ends probing and returns to the GPS baud.
+
+use crate::baud;
+use crate::bridge;
+use crate::clocks;
+use crate::nmea;
+use crate::psm;
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// Rates tried, the NMEA standard rate, the GP-735T default and common faster ones
+pub const PROBE_BAUDS: [u32; 4] = [4800, 9600, 38_400, 115_200];
+
+/// Time without a valid sentence before probing starts
+pub const SILENCE_MS: u32 = 2_000;
+
+/// Time each rate is tried, two output periods at 1Hz so a whole sentence is received at least
+/// once
+pub const DWELL_MS: u32 = 2_000;
+
+pub struct Autobaud {
+    /// GPS power seen at the last tick
+    powered: bool,
+    last_sentence: u32,
+    /// Index in [`PROBE_BAUDS`] of the rate being tried and the time it was switched to
+    probe: Option<(usize, u32)>,
+    /// GPS baud that stopped delivering, not tried
+    failed: u32,
+}
+
+impl Autobaud {
+    pub const fn new() -> Self {
+        Self {
+            powered: false,
+            last_sentence: 0,
+            probe: None,
+            failed: 0,
+        }
+    }
+
+    /// Rate USART1 has to run at while probing
+    pub fn probe_baud(&self) -> Option<u32> {
+        self.probe.map(|(index, _)| PROBE_BAUDS[index])
+    }
+
+    /// Check the GPS at `now` milliseconds, see [`crate::timer::now_ms`], with USART1 set to
+    /// `gps_baud` outside probing. True when USART1 has to switch baud.
+    pub fn on_tick(&mut self, now: u32, gps_power: bool, gps_baud: u32) -> bool {
+        if !gps_power || bridge::is_raw() {
+            self.powered = false;
+            return self.probe.take().is_some();
+        }
+        if !self.powered {
+            self.powered = true;
+            self.last_sentence = now;
+        }
+        let patience = psm::update_period_ms();
+        let next = match self.probe {
+            None if now.wrapping_sub(self.last_sentence) < SILENCE_MS + patience => return false,
+            None => {
+                self.failed = gps_baud;
+                0
+            }
+            Some((_, since)) if now.wrapping_sub(since) < DWELL_MS + patience => return false,
+            Some((index, _)) => index + 1,
+        };
+        // Starts over after the last rate, rates the USART1 clock can't reach are skipped
+        let index = (next..next + PROBE_BAUDS.len())
+            .map(|index| index % PROBE_BAUDS.len())
+            .find(|&index| {
+                let baud = PROBE_BAUDS[index];
+                baud != self.failed && baud::divider(clocks::PCLK2_HZ, baud).is_ok()
+            });
+        let switch = index.map(|index| PROBE_BAUDS[index]) != self.probe_baud();
+        self.probe = index.map(|index| (index, now));
+        switch
+    }
+
+    /// Account for a sentence passing its checksum at `now`, returning the rate locked onto if
+    /// probing
+    pub fn on_sentence(&mut self, now: u32) -> Option<u32> {
+        self.last_sentence = now;
+        let baud = self.probe_baud();
+        self.probe = None;
+        baud
+    }
+}
+
+impl Default for Autobaud {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the `$PBRIDGE,GPSBAUD,<baud>*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, baud: u32) -> fmt::Result {
+    let mut body = String::<32>::new();
+    write!(body, "PBRIDGE,GPSBAUD,{}", baud)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
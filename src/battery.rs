@@ -0,0 +1,103 @@
+//! Battery monitoring: the last [`crate::adc`] reading, reported in `$PBRIDGE,STATUS` and by
+//! `BATTERY`, and the low battery threshold set by `BATTERY <mv>`.
+//!
+//! The battery is sampled every [`SAMPLE_PERIOD_S`] from the RTC wakeup, so also while the MCU
+//! stops. Below the threshold the GPS is turned off and `$PBRIDGE,WARN,LOW_BATTERY*hh` is sent
+//! once. The GPS stays off, a [`crate::schedule`] window included, until the battery recovers
+//! [`HYSTERESIS_MV`] above the threshold, when the GPS is turned back on unless a schedule runs,
+//! which turns it on at its next window. `PWR ON` and the button still override it.
+
+use crate::adc::Readings;
+use crate::nmea;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Seconds between battery samples
+pub const SAMPLE_PERIOD_S: u32 = 10;
+
+/// Lowest and highest threshold accepted, the VBAT pin range
+pub const MIN_THRESHOLD_MV: u16 = 1800;
+pub const MAX_THRESHOLD_MV: u16 = 3600;
+
+/// Rise above the threshold needed to leave low battery, so the load drop of turning the GPS off
+/// doesn't turn it back on
+pub const HYSTERESIS_MV: u16 = 100;
+
+/// Last battery and VDDA readings, 0 before the first sample
+static VBAT_MV: AtomicU16 = AtomicU16::new(0);
+static VDDA_MV: AtomicU16 = AtomicU16::new(0);
+
+/// Low battery threshold, 0 disables it
+static THRESHOLD_MV: AtomicU16 = AtomicU16::new(0);
+
+/// Last battery voltage, `None` before the first sample
+pub fn vbat_mv() -> Option<u16> {
+    Some(VBAT_MV.load(Ordering::Relaxed)).filter(|&mv| mv != 0)
+}
+
+/// Last VDDA, `None` before the first sample
+pub fn vdda_mv() -> Option<u16> {
+    Some(VDDA_MV.load(Ordering::Relaxed)).filter(|&mv| mv != 0)
+}
+
+pub fn threshold_mv() -> Option<u16> {
+    Some(THRESHOLD_MV.load(Ordering::Relaxed)).filter(|&mv| mv != 0)
+}
+
+/// Set or disable the low battery threshold, checked at the next sample
+pub fn set_threshold(threshold_mv: Option<u16>) {
+    THRESHOLD_MV.store(threshold_mv.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Change seen by [`Monitor::on_sample`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The battery fell below the threshold, the GPS has to be turned off
+    Low,
+    /// The battery recovered above the threshold and hysteresis
+    Recovered,
+}
+
+pub struct Monitor {
+    low: bool,
+}
+
+impl Monitor {
+    pub const fn new() -> Self {
+        Self { low: false }
+    }
+
+    /// The battery is below the threshold, the GPS is kept off
+    pub fn is_low(&self) -> bool {
+        self.low
+    }
+
+    /// Record a sample and compare it with the threshold. Disabling the threshold leaves low
+    /// battery.
+    pub fn on_sample(&mut self, readings: Readings) -> Option<Event> {
+        VBAT_MV.store(readings.vbat_mv, Ordering::Relaxed);
+        VDDA_MV.store(readings.vdda_mv, Ordering::Relaxed);
+        let low = match threshold_mv() {
+            None => false,
+            Some(threshold) if self.low => readings.vbat_mv < threshold + HYSTERESIS_MV,
+            Some(threshold) => readings.vbat_mv < threshold,
+        };
+        if low == self.low {
+            return None;
+        }
+        self.low = low;
+        Some(if low { Event::Low } else { Event::Recovered })
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the `$PBRIDGE,WARN,LOW_BATTERY*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write) -> fmt::Result {
+    const BODY: &str = "PBRIDGE,WARN,LOW_BATTERY";
+    write!(out, "${}*{:02X}\r\n", BODY, nmea::checksum(BODY.as_bytes()))
+}
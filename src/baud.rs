@@ -0,0 +1,138 @@
+//! UART baud rates. BRR is computed from the USART kernel clock with oversampling by 8, which
+//! reaches higher rates than the reset default of 16 at the same clock.
+//! The GPS baud is changed with UBX-CFG-PRT. With the `host-autobaud` feature the host baud is
+//! detected by the USART from a `U` sync byte, see [`start_detection`].
+
+use crate::chip::pac::usart1;
+use crate::defaults;
+use crate::ubx;
+
+/// Baud of the GPS at power on, the GP-735T factory default of 9600 unless `BRIDGE_BAUD_GPS`
+/// was set at build time
+pub const GPS_DEFAULT: u32 = defaults::GPS_BAUD;
+
+/// Host baud at reset, 115200 leaves headroom over the GPS output. `BRIDGE_BAUD_HOST` at build
+/// time.
+pub const HOST_DEFAULT: u32 = defaults::HOST_BAUD;
+
+/// Rates accepted by the `BAUD` command
+pub const SUPPORTED: [u32; 8] = [
+    9600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 921_600,
+];
+
+const _: () = assert!(
+    is_supported(GPS_DEFAULT) && is_supported(HOST_DEFAULT),
+    "BRIDGE_BAUD_GPS and BRIDGE_BAUD_HOST must be supported rates"
+);
+
+/// Largest deviation from the requested baud in tenths of a percent
+const MAX_ERROR_PERMILLE: u32 = 10;
+
+pub const UBX_ID_CFG_PRT: u8 = 0x00;
+
+/// Largest deviation of a detected baud from a supported rate in tenths of a percent, the
+/// measurement is off by up to a kernel clock cycle over two bits
+const MAX_DETECTION_ERROR_PERMILLE: u32 = 50;
+
+/// Baud can't be reached within 1% from the USART clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported;
+
+const fn is_supported(baud: u32) -> bool {
+    let mut i = 0;
+    while i < SUPPORTED.len() {
+        if SUPPORTED[i] == baud {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// BRR value for `baud` from a `clock_hz` kernel clock with OVER8 set
+pub fn divider(clock_hz: u32, baud: u32) -> Result<u16, Unsupported> {
+    if baud == 0 {
+        return Err(Unsupported);
+    }
+    let usartdiv = (2 * clock_hz + baud / 2) / baud;
+    if !(16..=0xFFFF).contains(&usartdiv) {
+        return Err(Unsupported);
+    }
+    let actual = 2 * clock_hz / usartdiv;
+    if actual.abs_diff(baud) * 1000 / baud > MAX_ERROR_PERMILLE {
+        return Err(Unsupported);
+    }
+    // BRR[3] must be 0 and BRR[2:0] is USARTDIV[3:0] shifted right, see reference manual 38.5.4
+    Ok(((usartdiv & !0xF) | ((usartdiv & 0xF) >> 1)) as u16)
+}
+
+/// Program BRR and oversampling for `baud`. USART must be disabled.
+pub fn set(usart: &usart1::RegisterBlock, clock_hz: u32, baud: u32) -> Result<(), Unsupported> {
+    let brr = divider(clock_hz, baud)?;
+    usart.cr1.modify(|_, w| w.over8().set_bit());
+    usart.brr.write(|w| w.brr().bits(brr));
+    Ok(())
+}
+
+/// State of the baud detection by the USART
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// No character received since detection was armed
+    Pending,
+    /// Supported rate closest to the one measured
+    Locked(u32),
+    /// The character didn't start with bits 1 and 0 or gave no supported rate
+    Failed,
+}
+
+/// Arm auto baud rate detection on the next received character, measured from the falling edge
+/// of its start bit to the next one. The character must start with bits 1 and 0, like the `U`
+/// sync byte. USART must be disabled.
+pub fn start_detection(usart: &usart1::RegisterBlock) {
+    usart.cr2.modify(|_, w| w.abrmod().edge().abren().set_bit());
+}
+
+/// Arm detection again on the next received character, while the USART is enabled
+pub fn restart_detection(usart: &usart1::RegisterBlock) {
+    usart.rqr.write(|w| w.abrrq().set_bit());
+}
+
+/// Result of the detection armed last, the USART runs at the measured baud once locked
+pub fn detection(usart: &usart1::RegisterBlock, clock_hz: u32) -> Detection {
+    let isr = usart.isr.read();
+    if isr.abre().bit_is_set() {
+        return Detection::Failed;
+    }
+    if isr.abrf().bit_is_clear() {
+        return Detection::Pending;
+    }
+    // Inverse of the BRR layout with OVER8, see `divider`
+    let brr = u32::from(usart.brr.read().brr().bits());
+    let usartdiv = (brr & !0xF) | (brr & 0x7) << 1;
+    if usartdiv == 0 {
+        return Detection::Failed;
+    }
+    let measured = 2 * clock_hz / usartdiv;
+    SUPPORTED
+        .iter()
+        .copied()
+        .find(|&baud| measured.abs_diff(baud) * 1000 / baud <= MAX_DETECTION_ERROR_PERMILLE)
+        .map_or(Detection::Failed, Detection::Locked)
+}
+
+/// UBX-CFG-PRT frame setting the GPS UART to `baud`, 8N1, UBX and NMEA in and out
+pub fn cfg_prt(baud: u32) -> ubx::Frame {
+    let mode = 0x0000_08D0u32.to_le_bytes();
+    let baud = baud.to_le_bytes();
+    #[rustfmt::skip]
+    let payload = [
+        1, 0, 0, 0, // UART1, reserved, TX ready disabled
+        mode[0], mode[1], mode[2], mode[3],
+        baud[0], baud[1], baud[2], baud[3],
+        0x03, 0x00, // UBX and NMEA in
+        0x03, 0x00, // UBX and NMEA out
+        0, 0, 0, 0, // flags, reserved
+    ];
+    // Payload is well below the maximum
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_PRT, &payload).unwrap_or_default()
+}
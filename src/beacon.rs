@@ -0,0 +1,171 @@
+//! Position beacon on a LoRa radio, enabled by the `lora` feature, turning the bridge into a
+//! simple GPS tracker. See [`crate::lora`] for the radio and its pins.
+//!
+//! Every `LORA` interval, 60 seconds by default, the latest fix passing the [`crate::quality`]
+//! gate is sent as one packet: [`RECORD_FIX`], the [`crate::version::short_id`] of the bridge as
+//! a little endian u32 and the [`FixRecord`] of `MODE BIN`, [`PAYLOAD_LEN`] bytes in all. No
+//! packet is sent without such a fix. The carrier is `BRIDGE_LORA_FREQUENCY_KHZ`, 868.1MHz by
+//! default, and the spreading factor 9, both changed by `LORA FREQ` and `LORA SF`, none of them
+//! saved.
+//!
+//! The next packet starts no sooner than 100 times the time on air of the last one after it, so
+//! the beacon keeps to the 1% duty cycle, [`DUTY_CYCLE_PERCENT`], of most of the European 868MHz
+//! band, e.g. 27 seconds after a packet at SF9 and 3.3 minutes at SF12. A shorter interval is
+//! stretched to it. SysTick starts packets and polls for their end, so none are sent while the
+//! MCU is in Stop mode.
+
+use crate::defaults;
+use crate::fix::GpsFix;
+use crate::lora::{self, Radio};
+use crate::protocol::{self, FixRecord};
+use crate::quality;
+use crate::version;
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering};
+
+/// Record type of the packet, as in binary output
+pub const RECORD_FIX: u8 = protocol::RECORD_FIX;
+
+/// Bytes of a packet
+pub const PAYLOAD_LEN: usize = 5 + FixRecord::LEN;
+
+/// Carrier frequency at boot, `BRIDGE_LORA_FREQUENCY_KHZ`
+pub const DEFAULT_FREQUENCY_KHZ: u32 = defaults::LORA_FREQUENCY_KHZ;
+
+/// Spreading factor at boot
+pub const DEFAULT_SF: u8 = 9;
+
+/// Interval at boot
+pub const DEFAULT_INTERVAL_S: u16 = 60;
+
+/// Shortest interval accepted
+pub const MIN_INTERVAL_S: u16 = 10;
+
+/// Longest interval accepted
+pub const MAX_INTERVAL_S: u16 = 3600;
+
+/// Share of time the radio may transmit
+pub const DUTY_CYCLE_PERCENT: u32 = 1;
+
+/// A packet not done after twice its time on air and this is given up
+const TIMEOUT_MARGIN_MS: u32 = 100;
+
+static FREQUENCY_KHZ: AtomicU32 = AtomicU32::new(DEFAULT_FREQUENCY_KHZ);
+static SF: AtomicU8 = AtomicU8::new(DEFAULT_SF);
+
+/// Seconds between packets, 0 disables them
+static INTERVAL_S: AtomicU16 = AtomicU16::new(DEFAULT_INTERVAL_S);
+
+/// Packets sent and given up since boot
+static SENT: AtomicU32 = AtomicU32::new(0);
+static FAILED: AtomicU32 = AtomicU32::new(0);
+
+pub fn frequency_khz() -> u32 {
+    FREQUENCY_KHZ.load(Ordering::Relaxed)
+}
+
+/// Change the carrier frequency from the next packet
+pub fn set_frequency(frequency_khz: u32) {
+    FREQUENCY_KHZ.store(
+        frequency_khz.clamp(lora::MIN_FREQUENCY_KHZ, lora::MAX_FREQUENCY_KHZ),
+        Ordering::Relaxed,
+    );
+}
+
+pub fn sf() -> u8 {
+    SF.load(Ordering::Relaxed)
+}
+
+/// Change the spreading factor from the next packet
+pub fn set_sf(sf: u8) {
+    SF.store(sf.clamp(lora::MIN_SF, lora::MAX_SF), Ordering::Relaxed);
+}
+
+pub fn interval_s() -> u16 {
+    INTERVAL_S.load(Ordering::Relaxed)
+}
+
+/// Change the interval, the next packet follows `interval_s` after the last one
+pub fn set_interval(interval_s: u16) {
+    INTERVAL_S.store(interval_s, Ordering::Relaxed);
+}
+
+/// Packets sent since boot
+pub fn sent() -> u32 {
+    SENT.load(Ordering::Relaxed)
+}
+
+/// Packets given up since boot, the radio didn't report them sent in time
+pub fn failed() -> u32 {
+    FAILED.load(Ordering::Relaxed)
+}
+
+/// Packet carrying `fix`
+pub fn payload(fix: &GpsFix) -> [u8; PAYLOAD_LEN] {
+    let mut payload = [0; PAYLOAD_LEN];
+    payload[0] = RECORD_FIX;
+    payload[1..5].copy_from_slice(&version::short_id().to_le_bytes());
+    payload[5..].copy_from_slice(&fix.record().to_bytes());
+    payload
+}
+
+/// Wait after a packet of `airtime_ms` before the next one, the longer of the interval and the
+/// duty cycle's silence
+pub fn wait_ms(interval_s: u16, airtime_ms: u32) -> u32 {
+    (u32::from(interval_s) * 1000).max(airtime_ms * (100 / DUTY_CYCLE_PERCENT))
+}
+
+/// The radio and the timing of its packets
+pub struct Beacon {
+    radio: Radio,
+    /// [`crate::timer::now_ms`] of the start of the last packet
+    last_ms: u32,
+    /// Time on air of the last packet
+    airtime_ms: u32,
+    /// The last packet hasn't been reported sent yet
+    transmitting: bool,
+}
+
+impl Beacon {
+    pub fn new(radio: Radio) -> Self {
+        Self {
+            radio,
+            last_ms: 0,
+            airtime_ms: 0,
+            transmitting: false,
+        }
+    }
+
+    /// Called every SysTick tick at `now_ms`: finish the packet being sent, or send `fix` once
+    /// the interval and the duty cycle allow
+    pub fn on_tick(&mut self, now_ms: u32, fix: &GpsFix) {
+        if !lora::is_present() {
+            return;
+        }
+        let elapsed_ms = now_ms.wrapping_sub(self.last_ms);
+        if self.transmitting {
+            if self.radio.is_done() {
+                SENT.fetch_add(1, Ordering::Relaxed);
+            } else if elapsed_ms > 2 * self.airtime_ms + TIMEOUT_MARGIN_MS {
+                FAILED.fetch_add(1, Ordering::Relaxed);
+            } else {
+                return;
+            }
+            self.radio.sleep();
+            self.transmitting = false;
+            return;
+        }
+        let interval_s = interval_s();
+        if interval_s == 0
+            || elapsed_ms < wait_ms(interval_s, self.airtime_ms)
+            || !fix.is_valid()
+            || !quality::gate().passes(fix)
+        {
+            return;
+        }
+        let sf = sf();
+        self.radio.transmit(frequency_khz(), sf, &payload(fix));
+        self.last_ms = now_ms;
+        self.airtime_ms = lora::airtime_us(sf, PAYLOAD_LEN).div_ceil(1000);
+        self.transmitting = true;
+    }
+}
@@ -0,0 +1,193 @@
+//! Bluetooth serial module on LPUART1, enabled by the `ble` feature, so a phone receives NMEA
+//! without a cable. `ROUTE` selects what is sent to it, see [`crate::router`], and commands from
+//! the phone are run like those of the host, see [`crate::inbox`].
+//!
+//! LPUART1 sends on PC1 and receives on PC0, alternate function 8, wired to the RX and TX of an
+//! HM-10 BLE or HC-05 classic Bluetooth module at [`BAUD`], their default. The L432 has no other
+//! pins for LPUART1 than those of the host, so a chip-l452 or chip-l476 with port C is needed.
+//! LPUART1 runs from HSI16, so [`BAUD`] can be reached from any system clock, and a command
+//! from the phone wakes the MCU from Stop mode like one on USART2.
+//!
+//! At boot the module is sent `AT` and `AT+NAME` with [`NAME`], HM-10 commands answered with
+//! `OK` within [`REPLY_TIMEOUT_MS`] while no phone is connected. An HC-05 in data mode doesn't
+//! answer and keeps its own name, sentences are sent to it all the same.
+//!
+//! Lines are queued whole in a [`QUEUE_LEN`] byte queue drained by the LPUART1 interrupt, a
+//! report or a response going ahead of the sentences queued, as at [`BAUD`] 512 bytes of them
+//! take half a second. A line that doesn't fit, e.g. while a slow module is busy, is handled by
+//! the `OVERFLOW` policy of the module, see [`crate::linequeue`].
+
+#[cfg(feature = "chip-l432")]
+compile_error!("feature `ble` needs LPUART1 on PC0 and PC1, chip-l432 has no port C for it");
+
+use crate::chip::pac::{GPIOC, LPUART1, RCC};
+use crate::clocks;
+use crate::cmd::Error;
+use crate::linequeue::LineQueue;
+use crate::router::{Class, Sink};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Baud of the module
+pub const BAUD: u32 = 9600;
+
+/// Name the module advertises
+pub const NAME: &str = "GPS-BRIDGE";
+
+/// Bytes of sentences waiting for the module
+pub const QUEUE_LEN: usize = 512;
+
+/// Time the module is given to answer an AT command
+pub const REPLY_TIMEOUT_MS: u32 = 500;
+
+/// Kernel clock of LPUART1
+const CLOCK_HZ: u32 = 16_000_000;
+
+/// LPUART BRR is 256 times the kernel clock over the baud
+const BRR: u32 = ((CLOCK_HZ as u64 * 256 + BAUD as u64 / 2) / BAUD as u64) as u32;
+
+const _: () = assert!(BRR >= 0x300 && BRR <= 0xF_FFFF, "BAUD out of LPUART1 range");
+
+/// Polls of the receiver per millisecond while waiting for an answer
+const POLLS_PER_MS: u32 = 100;
+
+/// The module answered the AT commands at boot
+static ANSWERED: AtomicBool = AtomicBool::new(false);
+
+/// The module answered `AT` at boot, an HM-10 while no phone is connected
+pub fn answered() -> bool {
+    ANSWERED.load(Ordering::Relaxed)
+}
+
+/// LPUART1 and the sentences queued for it
+pub struct Ble {
+    lpuart1: LPUART1,
+    queue: LineQueue<QUEUE_LEN>,
+}
+
+impl Ble {
+    /// Route PC0 and PC1 to LPUART1 at [`BAUD`] and set up the module, blocking for up to
+    /// twice [`REPLY_TIMEOUT_MS`]. GPIOC, LPUART1 and HSI16 clocks are enabled here.
+    pub fn new(rcc: &RCC, gpioc: &GPIOC, lpuart1: LPUART1) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.gpiocen().set_bit());
+        rcc.apb1enr2.modify(|_, w| w.lpuart1en().set_bit());
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        rcc.ccipr.modify(|_, w| w.lpuart1sel().hsi16());
+
+        gpioc.afrl.modify(|_, w| w.afrl0().af8().afrl1().af8());
+        gpioc
+            .moder
+            .modify(|_, w| w.moder0().alternate().moder1().alternate());
+
+        // SAFETY: in range, checked at compile time
+        lpuart1.brr.write(|w| unsafe { w.bits(BRR) });
+        lpuart1.cr1.write(|w| {
+            w.te()
+                .set_bit()
+                .re()
+                .set_bit()
+                .uesm()
+                .set_bit()
+                .ue()
+                .set_bit()
+        });
+
+        let mut ble = Self {
+            lpuart1,
+            queue: LineQueue::new(Sink::Ble),
+        };
+        let mut name = [0; 7 + NAME.len()];
+        name[..7].copy_from_slice(b"AT+NAME");
+        name[7..].copy_from_slice(NAME.as_bytes());
+        let answered = ble.command(b"AT") && ble.command(&name);
+        ANSWERED.store(answered, Ordering::Relaxed);
+        // The answers have been read, what follows comes from the phone
+        ble.lpuart1.icr.write(|w| w.orecf().set_bit());
+        ble.lpuart1.cr1.modify(|_, w| w.rxneie().set_bit());
+        ble
+    }
+
+    /// Queue `line` of `class` whole by the module's overflow policy
+    pub fn write(&mut self, class: Class, line: &[u8]) {
+        self.queue.push(class, line);
+        if !self.queue.is_empty() {
+            self.lpuart1.cr1.modify(|_, w| w.txeie().set_bit());
+        }
+    }
+
+    /// Called by SysTick at `now_ms`, see [`LineQueue::on_tick`]
+    pub fn on_tick(&mut self, now_ms: u32) {
+        self.queue.on_tick(now_ms);
+    }
+
+    /// Nothing waits to be sent
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Called on the LPUART1 interrupt: send the next queued byte, stop interrupting once the
+    /// queue is empty and return the byte received from the phone, [`Error::Framing`] for one
+    /// with a framing or noise error or following an overrun
+    pub fn on_interrupt(&mut self) -> Option<Result<u8, Error>> {
+        let isr = self.lpuart1.isr.read();
+        // A line missing a byte lost to an overrun can't be trusted either
+        let faulty = isr.ore().bit_is_set() || isr.fe().bit_is_set() || isr.nf().bit_is_set();
+        if faulty {
+            self.lpuart1
+                .icr
+                .write(|w| w.orecf().set_bit().fecf().set_bit().ncf().set_bit());
+        }
+        let received = isr.rxne().bit_is_set().then(|| {
+            let byte = self.lpuart1.rdr.read().bits() as u8;
+            if faulty {
+                Err(Error::Framing)
+            } else {
+                Ok(byte)
+            }
+        });
+        send_next(&self.lpuart1, &mut self.queue);
+        if self.queue.is_empty() {
+            self.lpuart1.cr1.modify(|_, w| w.txeie().clear_bit());
+        }
+        received
+    }
+
+    /// Send an AT command and wait for an answer starting with `OK`
+    fn command(&mut self, command: &[u8]) -> bool {
+        // Drop anything received earlier, e.g. noise while the module started
+        while self.lpuart1.isr.read().rxne().bit_is_set() {
+            let _ = self.lpuart1.rdr.read();
+        }
+        self.lpuart1.icr.write(|w| w.orecf().set_bit());
+        for &byte in command {
+            while self.lpuart1.isr.read().txe().bit_is_clear() {}
+            // SAFETY: any byte can be sent
+            self.lpuart1
+                .tdr
+                .write(|w| unsafe { w.bits(u32::from(byte)) });
+        }
+        let mut answer = [0; 2];
+        let mut len = 0;
+        for _ in 0..REPLY_TIMEOUT_MS * POLLS_PER_MS {
+            if self.lpuart1.isr.read().rxne().bit_is_set() {
+                answer[len] = self.lpuart1.rdr.read().bits() as u8;
+                len += 1;
+                if len == answer.len() {
+                    return answer == *b"OK";
+                }
+            }
+            cortex_m::asm::delay(clocks::SYSCLK_HZ / 1000 / POLLS_PER_MS);
+        }
+        false
+    }
+}
+
+/// Send the next queued byte if the transmitter is empty
+fn send_next(lpuart1: &LPUART1, queue: &mut LineQueue<QUEUE_LEN>) {
+    if lpuart1.isr.read().txe().bit_is_set() {
+        if let Some(byte) = queue.pop() {
+            // SAFETY: any byte can be sent
+            lpuart1.tdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+        }
+    }
+}
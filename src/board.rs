@@ -0,0 +1,209 @@
+//! Pinmaps of the supported boards, selected with a `board-*` feature so different wiring doesn't
+//! need a fork. `board-l432kc-nucleo` is the default, for another board build with
+//! `--no-default-features --features board-custom` after editing [`PINS`] in the `custom` module.
+//!
+//! All pins are on GPIOA. The alternate function numbers are those of the datasheet's table for
+//! the USART1 and USART2 signals and TIM2 CH1, the peripherals the firmware uses. Flow control,
+//! USB, CAN, the SD card, I2C1 and the geofence, speed and anchor alarms keep their fixed pins.
+//! The RS-485 driver enable is only routed with the `rs485` feature. The `spi-slave` feature takes
+//! PA4 for SPI3 NSS, the LED moves elsewhere.
+
+use crate::chip::pac::GPIOA;
+
+#[cfg(all(feature = "board-l432kc-nucleo", feature = "board-custom"))]
+compile_error!("select one board feature, board-custom needs --no-default-features");
+
+#[cfg(not(any(feature = "board-l432kc-nucleo", feature = "board-custom")))]
+compile_error!("select a board feature, board-l432kc-nucleo or board-custom");
+
+/// GPIOA pin routed to a peripheral by its alternate function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AfPin {
+    pub pin: u32,
+    pub af: u32,
+}
+
+/// Driver enable of an RS-485 transceiver on the host link, see [`crate::rs485`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverEnable {
+    /// USART2_DE driven by the USART
+    Usart(AfPin),
+    /// Output driven around DMA transfers
+    Gpio(u32),
+}
+
+impl DriverEnable {
+    pub const fn pin(&self) -> u32 {
+        match *self {
+            DriverEnable::Usart(pin) => pin.pin,
+            DriverEnable::Gpio(pin) => pin,
+        }
+    }
+}
+
+/// GPIOA pins of a board
+#[derive(Debug, Clone, Copy)]
+pub struct Pinmap {
+    /// USART1 TX and RX, wired to the GPS RX and TX
+    pub gps_tx: AfPin,
+    pub gps_rx: AfPin,
+    /// USART2 TX and RX, wired to the host UART adaptor
+    pub host_tx: AfPin,
+    pub host_rx: AfPin,
+    /// TIM2 CH1 capturing the GPS timepulse
+    pub pps: AfPin,
+    /// Output switching the GPS supply
+    pub gps_power: u32,
+    /// Status LED of the `indicator` feature
+    pub led: u32,
+    /// Push-button of the `button` feature, 5 to 9 to raise EXTI9_5
+    pub button: u32,
+    /// RS-485 driver enable of the `rs485` feature
+    pub host_de: DriverEnable,
+}
+
+#[cfg(feature = "board-l432kc-nucleo")]
+mod nucleo {
+    use super::{AfPin, DriverEnable, Pinmap};
+
+    /// NUCLEO-L432KC: GPS on D1/D0, host adaptor on A7/A2, PPS on A4. GPS power is on D2,
+    /// PA12 is USB DP with the `usb` feature and CAN_TX with `can`, D9 takes over. The LED is on
+    /// A3, A5 with the `spi-slave` feature.
+    pub const PINS: Pinmap = Pinmap {
+        gps_tx: AfPin { pin: 9, af: 7 },
+        gps_rx: AfPin { pin: 10, af: 7 },
+        host_tx: AfPin { pin: 2, af: 7 },
+        host_rx: AfPin { pin: 3, af: 7 },
+        pps: AfPin { pin: 5, af: 1 },
+        gps_power: if cfg!(any(feature = "usb", feature = "can")) {
+            8
+        } else {
+            12
+        },
+        led: if cfg!(feature = "spi-slave") { 6 } else { 4 },
+        // A6
+        button: 7,
+        // A1, USART2_DE
+        host_de: DriverEnable::Usart(AfPin { pin: 1, af: 7 }),
+    };
+}
+
+#[cfg(feature = "board-custom")]
+mod custom {
+    use super::{AfPin, DriverEnable, Pinmap};
+
+    /// Edit for your wiring. This example takes the host RX from PA15, the ST-LINK virtual COM
+    /// port on the Nucleo, and the PPS input on PA0, which rules out the `flow-control` feature.
+    pub const PINS: Pinmap = Pinmap {
+        gps_tx: AfPin { pin: 9, af: 7 },
+        gps_rx: AfPin { pin: 10, af: 7 },
+        host_tx: AfPin { pin: 2, af: 7 },
+        host_rx: AfPin { pin: 15, af: 3 },
+        pps: AfPin { pin: 0, af: 1 },
+        gps_power: 8,
+        led: if cfg!(feature = "spi-slave") { 7 } else { 4 },
+        button: 6,
+        host_de: DriverEnable::Gpio(5),
+    };
+}
+
+#[cfg(feature = "board-custom")]
+pub use custom::PINS;
+#[cfg(all(feature = "board-l432kc-nucleo", not(feature = "board-custom")))]
+pub use nucleo::PINS;
+
+const _: () = {
+    let pins = [
+        PINS.gps_tx.pin,
+        PINS.gps_rx.pin,
+        PINS.host_tx.pin,
+        PINS.host_rx.pin,
+        PINS.pps.pin,
+        PINS.gps_power,
+        PINS.led,
+        PINS.button,
+    ];
+    let mut i = 0;
+    while i < pins.len() {
+        assert!(pins[i] < 16, "GPIOA has 16 pins");
+        let mut j = i + 1;
+        while j < pins.len() {
+            assert!(pins[i] != pins[j], "pin used twice");
+            j += 1;
+        }
+        i += 1;
+    }
+    assert!(
+        PINS.button >= 5 && PINS.button <= 9,
+        "button must raise EXTI9_5"
+    );
+};
+
+#[cfg(feature = "rs485")]
+const _: () = assert!(
+    PINS.host_de.pin() < 16 && !uses(PINS.host_de.pin()),
+    "RS-485 DE pin used twice"
+);
+
+#[cfg(feature = "spi-slave")]
+const _: () = assert!(!uses(crate::spi_slave::NSS.pin), "SPI NSS pin used twice");
+
+#[cfg(feature = "can")]
+const _: () = assert!(
+    !uses(crate::can::PINS[0].pin) && !uses(crate::can::PINS[1].pin),
+    "CAN pin used twice"
+);
+
+/// `pin` is in the pinmap, for features with fixed pins to check against
+pub const fn uses(pin: u32) -> bool {
+    let pins = [
+        PINS.gps_tx.pin,
+        PINS.gps_rx.pin,
+        PINS.host_tx.pin,
+        PINS.host_rx.pin,
+        PINS.pps.pin,
+        PINS.gps_power,
+        PINS.led,
+        PINS.button,
+    ];
+    let mut i = 0;
+    while i < pins.len() {
+        if pins[i] == pin {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Route the UART and PPS pins to their peripherals, the UART pins at very high speed.
+/// GPIOA clock must be enabled.
+pub fn init(gpioa: &GPIOA) {
+    for pin in [PINS.gps_tx, PINS.gps_rx, PINS.host_tx, PINS.host_rx] {
+        set_alternate(gpioa, pin);
+        // SAFETY: 0b11 is very high speed, other pins are left unchanged
+        gpioa
+            .ospeedr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b11 << (2 * pin.pin)) });
+    }
+    set_alternate(gpioa, PINS.pps);
+}
+
+/// Put a pin in alternate function mode
+pub fn set_alternate(gpioa: &GPIOA, pin: AfPin) {
+    let shift = 4 * (pin.pin % 8);
+    // SAFETY: AF0 to AF15 are all valid, other pins are left unchanged
+    if pin.pin < 8 {
+        gpioa
+            .afrl
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0xF << shift) | pin.af << shift) });
+    } else {
+        gpioa
+            .afrh
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0xF << shift) | pin.af << shift) });
+    }
+    // SAFETY: 0b10 is alternate function mode, other pins are left unchanged
+    gpioa.moder.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b11 << (2 * pin.pin)) | 0b10 << (2 * pin.pin))
+    });
+}
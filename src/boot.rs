@@ -0,0 +1,143 @@
+//! A/B firmware images in the two flash banks of the L476 with rollback, the `ab-boot` feature.
+//!
+//! The running image is always mapped at 0x0800_0000 and the other bank at 0x0808_0000, where
+//! `DFU` writes the update, see [`crate::dfu`]. `BOOT SWAP` checks the other bank holds a vector
+//! table, starts a trial and flips the BFB2 option bit, so the option byte loader resets into the
+//! system bootloader, which boots bank 2 when BFB2 is set and bank 1 otherwise.
+//!
+//! A trial counts boots in an RTC backup register. The new image marks itself healthy after
+//! [`HEALTHY_AFTER_S`] seconds without a reset, counted by the RTC wakeup. Once it has booted
+//! [`MAX_ATTEMPTS`] times without, e.g. reset by the IWDG each time, [`on_reset`] flips BFB2 back
+//! and the previous image boots again. An image built without `ab-boot` never marks itself
+//! healthy but doesn't count its boots either, so it is kept.
+//!
+//! `memory/l476-ab.x` limits an image to the part of a bank below the configuration and flash
+//! log pages, which stay with the bank mapped second. Each image therefore keeps its own
+//! settings and log, saved in the other bank.
+
+#[cfg(not(feature = "chip-l476"))]
+compile_error!("feature `ab-boot` requires the dual bank chip-l476");
+
+use crate::chip::pac::{Peripherals, FLASH, RTC, SYSCFG};
+use crate::flash;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Boots of a trial image without marking itself healthy before rolling back
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Seconds an image has to run to be marked healthy
+pub const HEALTHY_AFTER_S: u32 = 60;
+
+/// RTC backup register holding the trial, after the one of [`crate::dfu`]
+const BACKUP_REGISTER: usize = 1;
+
+/// Upper bits of [`BACKUP_REGISTER`] during a trial, the lower byte counts boots
+const TRIAL_MAGIC: u32 = 0xAB7E_5700;
+
+/// Start of the bank mapped second
+const OTHER_BANK: u32 = 0x0808_0000;
+
+/// SRAM1 range of an initial stack pointer
+const RAM: core::ops::RangeInclusive<u32> = 0x2000_0000..=0x2001_8000;
+
+/// `BOOT SWAP` waits for its response to be sent
+static SWAP_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Seconds since reset, counted by [`on_wakeup`]
+static UPTIME_S: AtomicU32 = AtomicU32::new(0);
+
+/// Bank the running image was booted from, 1 or 2
+pub fn bank() -> u8 {
+    // SAFETY: read-only access, SYSCFG is otherwise unused
+    let syscfg = unsafe { &*SYSCFG::ptr() };
+    if syscfg.memrmp.read().fb_mode().bit_is_set() {
+        2
+    } else {
+        1
+    }
+}
+
+/// Boots of the running image if it is on trial
+pub fn trial_attempts() -> Option<u32> {
+    let value = backup_register().read().bits();
+    (value & !0xFF == TRIAL_MAGIC).then_some(value & 0xFF)
+}
+
+/// The bank mapped second starts with a vector table of an image linked at 0x0800_0000
+pub fn other_bank_valid() -> bool {
+    let words = flash::read(OTHER_BANK, 8);
+    let stack = u32::from_le_bytes([words[0], words[1], words[2], words[3]]);
+    let reset = u32::from_le_bytes([words[4], words[5], words[6], words[7]]);
+    RAM.contains(&stack) && (flash::BASE..OTHER_BANK).contains(&reset) && reset & 1 == 1
+}
+
+/// Start a trial of the other bank, booted by [`swap`] once idle has sent the response. The
+/// backup domain is writable since [`crate::rtc::Rtc::new`].
+pub fn request_swap() {
+    set_backup_register(TRIAL_MAGIC);
+    SWAP_PENDING.store(true, Ordering::Relaxed);
+}
+
+pub fn is_swap_pending() -> bool {
+    SWAP_PENDING.load(Ordering::Relaxed)
+}
+
+/// Count a boot of a trial image and roll back once it has used up its attempts. Must be called
+/// after [`crate::rtc::Rtc::new`]. Doesn't return when rolling back.
+pub fn on_reset(flash: &FLASH) {
+    let Some(attempts) = trial_attempts() else {
+        return;
+    };
+    if attempts < MAX_ATTEMPTS {
+        set_backup_register(TRIAL_MAGIC | (attempts + 1));
+        return;
+    }
+    set_backup_register(0);
+    boot_other_bank(flash);
+}
+
+/// Count `elapsed_s` seconds of uptime and end a trial once the image has run
+/// [`HEALTHY_AFTER_S`]
+pub fn on_wakeup(elapsed_s: u32) {
+    let uptime_s = UPTIME_S.load(Ordering::Relaxed).saturating_add(elapsed_s);
+    UPTIME_S.store(uptime_s, Ordering::Relaxed);
+    if uptime_s >= HEALTHY_AFTER_S && trial_attempts().is_some() {
+        set_backup_register(0);
+    }
+}
+
+/// Boot the other bank, doesn't return
+pub fn swap() -> ! {
+    // SAFETY: called from idle once no task is using the flash, nothing runs after it
+    let flash = unsafe { Peripherals::steal() }.FLASH;
+    boot_other_bank(&flash);
+    unreachable!()
+}
+
+/// Program BFB2 to select the bank not running and load the option bytes, which resets the MCU
+fn boot_other_bank(flash: &FLASH) {
+    let bfb2 = bank() == 1;
+    flash::unlocked(flash, |flash| {
+        flash::unlock_options(flash);
+        while flash.sr.read().bsy().bit_is_set() {}
+        flash.optr.modify(|_, w| w.bfb2().bit(bfb2));
+        flash.cr.modify(|_, w| w.optstrt().set_bit());
+        while flash.sr.read().bsy().bit_is_set() {}
+        flash.cr.modify(|_, w| w.obl_launch().set_bit());
+        // The option byte loader resets the MCU
+        loop {
+            cortex_m::asm::nop();
+        }
+    })
+}
+
+fn backup_register() -> &'static crate::chip::pac::rtc::BKPR {
+    // SAFETY: the backup registers are not used by the RTC driver
+    let rtc = unsafe { &*RTC::ptr() };
+    &rtc.bkpr[BACKUP_REGISTER]
+}
+
+fn set_backup_register(value: u32) {
+    // SAFETY: any value is valid
+    backup_register().write(|w| unsafe { w.bits(value) });
+}
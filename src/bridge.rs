@@ -0,0 +1,117 @@
+//! Transparent bridge between the host and the GPS, entered with `BRIDGE RAW` so tools such as
+//! u-center can talk to the GP-735T directly through the board.
+//!
+//! In raw mode every byte received on USART2 is queued for USART1, and every byte received from
+//! the GPS is sent to the host unchanged instead of the sentences passing the filter. Commands
+//! aren't parsed until the host sends the escape sequence, which is still passed on to the GPS.
+//! Sentences from the GPS keep updating the fix.
+//!
+//! The escape sequence is `+++` with at least [`GUARD_MS`] of silence before and after it and
+//! less than that between the pluses, so pluses within binary UBX data don't end raw mode. The
+//! guard times are measured with the SysTick [`timer`]: a one-shot timer started by the last plus
+//! pends USART2 once the trailing guard time has passed, which leaves raw mode and answers `OK`.
+//!
+//! The bridge doesn't follow baud changes the host tool sends to the GPS, change them with `BAUD`
+//! before entering raw mode.
+
+use crate::chip::pac::Interrupt;
+use crate::timer::{self, TimerId};
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::peripheral::NVIC;
+
+/// Bytes of the escape sequence, all `+`
+pub const ESCAPE_LEN: u8 = 3;
+
+/// Silence required before and after the escape sequence, and the longest gap within it
+pub const GUARD_MS: u32 = 1000;
+
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// The trailing guard time of an escape sequence has passed
+static ESCAPED: AtomicBool = AtomicBool::new(false);
+
+/// Bytes are passed through between the host and the GPS
+pub fn is_raw() -> bool {
+    RAW.load(Ordering::Relaxed)
+}
+
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Timer callback ending the trailing guard time
+fn guard_elapsed() {
+    ESCAPED.store(true, Ordering::Relaxed);
+    NVIC::pend(Interrupt::USART2);
+}
+
+/// Finds the escape sequence in the bytes received from the host
+pub struct Escape {
+    /// Pluses of the sequence received so far
+    pluses: u8,
+    /// [`timer::now_ms`] of the last byte received
+    last_byte: u32,
+    /// Timer of the trailing guard time
+    guard: Option<TimerId>,
+}
+
+impl Escape {
+    pub const fn new() -> Self {
+        Self {
+            pluses: 0,
+            last_byte: 0,
+            guard: None,
+        }
+    }
+
+    /// Start over as raw mode is entered, the guard time before the sequence starts now
+    pub fn reset(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            timer::cancel(guard);
+        }
+        self.pluses = 0;
+        self.last_byte = timer::now_ms();
+    }
+
+    /// Checked on every USART2 interrupt, true once the escape sequence completed with its
+    /// trailing guard time
+    pub fn take_escaped(&mut self) -> bool {
+        if !ESCAPED.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        // The one-shot timer has freed its entry
+        self.guard = None;
+        true
+    }
+
+    /// Feed a byte received in raw mode at `now` milliseconds. The last plus of the sequence
+    /// starts the trailing guard time, any byte within it cancels the escape.
+    pub fn push(&mut self, byte: u8, now: u32) {
+        // The guard timer is still running, it would have been taken first otherwise
+        if let Some(guard) = self.guard.take() {
+            timer::cancel(guard);
+        }
+        let gap = now.wrapping_sub(self.last_byte);
+        self.last_byte = now;
+        let continues = match self.pluses {
+            0 => gap >= GUARD_MS,
+            _ => gap < GUARD_MS,
+        };
+        if byte != b'+' || !continues {
+            self.pluses = 0;
+            return;
+        }
+        self.pluses += 1;
+        if self.pluses == ESCAPE_LEN {
+            self.pluses = 0;
+            // Without a free timer the sequence is ignored and has to be sent again
+            self.guard = timer::start_oneshot(GUARD_MS, guard_elapsed).ok();
+        }
+    }
+}
+
+impl Default for Escape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,100 @@
+//! Push-button for use without a host, enabled by the `button` feature. A short press toggles GPS
+//! power like the host's `0`/`1` bytes, a press of [`LONG_PRESS_US`] or more toggles SD card
+//! logging with the `sd-log` feature.
+//!
+//! The button connects [`BUTTON_PIN`] of GPIOA to ground, pulled up internally. Both edges raise
+//! EXTI9_5, which limits the pin to PA5 to PA9, see [`board`](crate::board). An edge within
+//! [`DEBOUNCE_US`] of the last accepted one is contact bounce and ignored, timed by TIM2.
+//! TIM2 halts in Stop mode, so idle only sleeps while the button is held.
+
+use crate::board;
+use crate::chip::pac::{EXTI, GPIOA};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// GPIOA pin of the button, PA7 is A6 on the Nucleo
+pub const BUTTON_PIN: u32 = board::PINS.button;
+
+/// Time after an accepted edge during which the contacts settle
+pub const DEBOUNCE_US: u64 = 20_000;
+
+/// Shortest long press
+pub const LONG_PRESS_US: u64 = 1_000_000;
+
+static HELD: AtomicBool = AtomicBool::new(false);
+
+/// The button is held down, idle must not enter Stop mode
+pub fn is_held() -> bool {
+    HELD.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+}
+
+pub struct Button {
+    /// Time of the accepted press edge while held
+    pressed_at: Option<u64>,
+    /// Time of the last accepted edge
+    last_edge: u64,
+}
+
+impl Button {
+    /// Configure the pin as input with pull-up and unmask its EXTI line on both edges.
+    /// GPIOA clock must be enabled. GPIOA is the EXTI source of every line after reset.
+    pub fn new(gpioa: &GPIOA, exti: &EXTI) -> Self {
+        // SAFETY: only the two bits of the pin are changed, 0b00 is input mode and 0b01 pull-up
+        gpioa
+            .moder
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << (2 * BUTTON_PIN))) });
+        gpioa.pupdr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << (2 * BUTTON_PIN)) | 0b01 << (2 * BUTTON_PIN))
+        });
+        // SAFETY: only the bit of the pin's line is set
+        exti.ftsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << BUTTON_PIN) });
+        exti.rtsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << BUTTON_PIN) });
+        exti.imr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << BUTTON_PIN) });
+        Self {
+            pressed_at: None,
+            last_edge: 0,
+        }
+    }
+
+    /// Handle the EXTI interrupt at `now` microseconds, returning a press once released.
+    /// The pin level is read rather than the edge direction, so an edge ignored as bounce is
+    /// made up for by the next one.
+    pub fn on_interrupt(&mut self, now: u64) -> Option<Press> {
+        // SAFETY: PR1 bits are cleared by writing one, other lines are unaffected
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.pr1.write(|w| unsafe { w.bits(1 << BUTTON_PIN) });
+        if now.wrapping_sub(self.last_edge) < DEBOUNCE_US {
+            return None;
+        }
+        // SAFETY: reading the input data register has no side effects
+        let gpioa = unsafe { &*GPIOA::ptr() };
+        let down = gpioa.idr.read().bits() & 1 << BUTTON_PIN == 0;
+        let press = match (self.pressed_at, down) {
+            (None, true) => {
+                self.pressed_at = Some(now);
+                None
+            }
+            (Some(pressed_at), false) => {
+                self.pressed_at = None;
+                Some(if now - pressed_at >= LONG_PRESS_US {
+                    Press::Long
+                } else {
+                    Press::Short
+                })
+            }
+            // Bounced back before the interrupt ran
+            _ => return None,
+        };
+        self.last_edge = now;
+        HELD.store(down, Ordering::Relaxed);
+        press
+    }
+}
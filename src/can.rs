@@ -0,0 +1,215 @@
+//! Position, COG/SOG and time broadcast on CAN, enabled by the `can` feature, to feed a marine or
+//! vehicle network.
+//!
+//! bxCAN sends on PA12 (D2) and receives on PA11 (D10), alternate function 9, through a
+//! transceiver such as an SN65HVD230. They are the USB pins, so `usb` can't be combined with it,
+//! and GPS power moves to D9 as with `usb`. The bit rate is `BRIDGE_CAN_BITRATE` at build time,
+//! 250 kbit/s by default as on NMEA 2000, sampled at 87.5% of the bit.
+//!
+//! Every `CAN` period, 1 second by default, the latest fix is sent as PGNs 129025, 129026 and
+//! 126992 from source address `BRIDGE_CAN_ADDRESS`, see [`crate::n2k`] for the frames, with not
+//! available values while there is no fix. Frames are only queued in the three transmit
+//! mailboxes, a period finding them full because nothing acknowledges, e.g. without another node
+//! on the bus, skips its frames and counts them. Nothing is received.
+
+#[cfg(feature = "usb")]
+compile_error!("feature `can` takes PA11 and PA12 from `usb`");
+
+use crate::board::{self, AfPin};
+use crate::chip::pac::{CAN1, GPIOA, RCC};
+use crate::clocks;
+use crate::defaults;
+use crate::fix::GpsFix;
+use crate::n2k::{self, Frame};
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+/// Bit rate, `BRIDGE_CAN_BITRATE`
+pub const BITRATE: u32 = defaults::CAN_BITRATE;
+
+/// Source address of the frames, `BRIDGE_CAN_ADDRESS`
+pub const ADDRESS: u8 = defaults::CAN_ADDRESS;
+
+/// Broadcast period at boot
+pub const DEFAULT_PERIOD_MS: u16 = 1000;
+
+/// Shortest broadcast period accepted, three frames per period leave the bus mostly free
+pub const MIN_PERIOD_MS: u16 = 100;
+
+/// Longest broadcast period accepted
+pub const MAX_PERIOD_MS: u16 = 60_000;
+
+/// CAN_TX and CAN_RX
+pub const PINS: [AfPin; 2] = [AfPin { pin: 12, af: 9 }, AfPin { pin: 11, af: 9 }];
+
+/// Bit timing for [`BITRATE`] from PCLK1
+const TIMING: Timing = timing(clocks::PCLK1_HZ, BITRATE);
+
+/// Milliseconds between broadcasts, 0 disables them
+static PERIOD_MS: AtomicU16 = AtomicU16::new(DEFAULT_PERIOD_MS);
+
+/// Frames skipped on full mailboxes since boot
+static SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+pub fn period_ms() -> u16 {
+    PERIOD_MS.load(Ordering::Relaxed)
+}
+
+/// Change the broadcast period, the next broadcast follows `period_ms` after the last one
+pub fn set_period(period_ms: u16) {
+    PERIOD_MS.store(period_ms, Ordering::Relaxed);
+}
+
+/// Frames skipped since boot
+pub fn skipped() -> u32 {
+    SKIPPED.load(Ordering::Relaxed)
+}
+
+/// Prescaler and segments in time quanta, as written to BTR less one
+struct Timing {
+    brp: u16,
+    ts1: u8,
+    ts2: u8,
+}
+
+/// Time quanta per bit tried, nearest to 16 first. TS1 takes at most 16 of them.
+const QUANTA: [u32; 13] = [16, 15, 17, 14, 18, 13, 19, 12, 20, 11, 10, 9, 8];
+
+/// Find the number of time quanta per bit dividing `pclk_hz` evenly with the sample point near
+/// 87.5%, failing the build if there is none
+const fn timing(pclk_hz: u32, bitrate: u32) -> Timing {
+    let mut i = 0;
+    while i < QUANTA.len() {
+        let quanta = QUANTA[i];
+        let per_bit = bitrate * quanta;
+        if pclk_hz.is_multiple_of(per_bit) && pclk_hz / per_bit <= 1024 {
+            let ts2 = (quanta + 4) / 8;
+            return Timing {
+                brp: (pclk_hz / per_bit - 1) as u16,
+                ts1: (quanta - 1 - ts2 - 1) as u8,
+                ts2: (ts2 - 1) as u8,
+            };
+        }
+        i += 1;
+    }
+    panic!("BRIDGE_CAN_BITRATE doesn't divide PCLK1 into 8 to 20 time quanta");
+}
+
+/// Frames skipped on full mailboxes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxesFull;
+
+/// CAN1 sending the fix every period
+pub struct Can {
+    can1: CAN1,
+    /// [`crate::timer::now_ms`] of the last broadcast
+    last_ms: u32,
+    /// Sequence ID of the next broadcast
+    sid: u8,
+}
+
+impl Can {
+    /// Route PA11 and PA12 to CAN1 and leave initialization mode with the bit timing of
+    /// [`BITRATE`]. The CAN1 clock is enabled here, GPIOA's must be.
+    pub fn new(rcc: &RCC, gpioa: &GPIOA, can1: CAN1) -> Self {
+        rcc.apb1enr1.modify(|_, w| w.can1en().set_bit());
+        for pin in PINS {
+            board::set_alternate(gpioa, pin);
+        }
+
+        can1.mcr
+            .modify(|_, w| w.sleep().clear_bit().inrq().set_bit());
+        while can1.msr.read().inak().bit_is_clear() {}
+        // SAFETY: the fields are in range, checked by `timing`
+        can1.btr.write(|w| unsafe {
+            w.brp()
+                .bits(TIMING.brp)
+                .ts1()
+                .bits(TIMING.ts1)
+                .ts2()
+                .bits(TIMING.ts2)
+                .sjw()
+                .bits(0)
+        });
+        // Recover from bus off by itself, send mailboxes in the order they were filled. Normal
+        // mode starts once 11 recessive bits are seen, without waiting here for a bus.
+        can1.mcr.modify(|_, w| {
+            w.abom()
+                .set_bit()
+                .txfp()
+                .set_bit()
+                .dbf()
+                .clear_bit()
+                .inrq()
+                .clear_bit()
+        });
+        Self {
+            can1,
+            last_ms: 0,
+            sid: 0,
+        }
+    }
+
+    /// Called every SysTick tick at `now_ms`: broadcast `fix` once the period has passed
+    pub fn on_tick(&mut self, now_ms: u32, fix: &GpsFix) {
+        let period_ms = period_ms();
+        if period_ms == 0 || now_ms.wrapping_sub(self.last_ms) < u32::from(period_ms) {
+            return;
+        }
+        self.last_ms = now_ms;
+        self.broadcast(fix);
+    }
+
+    /// Queue the frames of `fix`, skipping those that don't fit
+    fn broadcast(&mut self, fix: &GpsFix) {
+        let valid = fix.is_valid();
+        let frames = [
+            n2k::position(ADDRESS, valid.then_some((fix.lat, fix.lon))),
+            n2k::cog_sog(ADDRESS, self.sid, valid.then_some((fix.course, fix.speed))),
+            n2k::system_time(ADDRESS, self.sid, fix.date, fix.timestamp),
+        ];
+        for frame in &frames {
+            if self.transmit(frame).is_err() {
+                SKIPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sid = if self.sid >= n2k::MAX_SID {
+            0
+        } else {
+            self.sid + 1
+        };
+    }
+
+    /// Fill an empty transmit mailbox with `frame` and request its transmission
+    fn transmit(&mut self, frame: &Frame) -> Result<(), MailboxesFull> {
+        let tsr = self.can1.tsr.read();
+        let empty = [
+            tsr.tme0().bit_is_set(),
+            tsr.tme1().bit_is_set(),
+            tsr.tme2().bit_is_set(),
+        ];
+        let mailbox = empty.iter().position(|&empty| empty).ok_or(MailboxesFull)?;
+        let tx = &self.can1.tx[mailbox];
+        // SAFETY: any 8 byte payload and DLC 8 are valid
+        tx.tdtr.write(|w| unsafe { w.dlc().bits(8) });
+        tx.tdlr.write(|w| unsafe {
+            w.bits(u32::from_le_bytes([
+                frame.data[0],
+                frame.data[1],
+                frame.data[2],
+                frame.data[3],
+            ]))
+        });
+        tx.tdhr.write(|w| unsafe {
+            w.bits(u32::from_le_bytes([
+                frame.data[4],
+                frame.data[5],
+                frame.data[6],
+                frame.data[7],
+            ]))
+        });
+        // SAFETY: a 29-bit identifier with IDE set, TXRQ last starts the transmission
+        tx.tir
+            .write(|w| unsafe { w.bits(frame.id << 3 | 1 << 2 | 1) });
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! The supported STM32L4 parts, selected with a `chip-*` feature, `chip-l432` by default.
+//! Each selects its device crate as [`pac`], the flash geometry and the `memory/*.x` linker
+//! memory layout, see `build.rs`.
+//!
+//! The L432 and L452 share the `stm32l4x2` device crate. The L476 uses `stm32l4x6`, it has two
+//! flash banks and no USB device peripheral, so it can't be built with the `usb` feature.
+
+#[cfg(not(any(feature = "chip-l432", feature = "chip-l452", feature = "chip-l476")))]
+compile_error!("select a chip feature, chip-l432, chip-l452 or chip-l476");
+
+#[cfg(any(
+    all(feature = "chip-l432", feature = "chip-l452"),
+    all(feature = "chip-l432", feature = "chip-l476"),
+    all(feature = "chip-l452", feature = "chip-l476"),
+))]
+compile_error!("select one chip feature, others than chip-l432 need --no-default-features");
+
+#[cfg(all(feature = "chip-l476", feature = "usb"))]
+compile_error!("feature `usb` requires a chip with the USB device peripheral, not chip-l476");
+
+#[cfg(any(feature = "chip-l432", feature = "chip-l452"))]
+pub use stm32l4::stm32l4x2 as pac;
+#[cfg(all(
+    feature = "chip-l476",
+    not(any(feature = "chip-l432", feature = "chip-l452"))
+))]
+pub use stm32l4::stm32l4x6 as pac;
+
+/// Flash pages of 2KB, 256KB, 512KB and 1MB
+pub const FLASH_PAGES: usize = if cfg!(feature = "chip-l476") {
+    512
+} else if cfg!(feature = "chip-l452") {
+    256
+} else {
+    128
+};
+
+/// Pages of a flash bank, page numbers restart in the second bank of the L476
+pub const FLASH_BANK_PAGES: usize = if cfg!(feature = "chip-l476") {
+    256
+} else {
+    FLASH_PAGES
+};
+
+/// Temperature of the second temperature sensor calibration point, TS_CAL2
+pub const TS_CAL2_C: i32 = if cfg!(feature = "chip-l476") {
+    110
+} else {
+    130
+};
@@ -0,0 +1,107 @@
+//! System clock configuration, selected by Cargo feature:
+//!
+//! - default: MSI at 4MHz, the reset configuration
+//! - `clock-msi-48`: MSI at 48MHz, trimmed by the LSE once it runs
+//! - `clock-pll-80`: HSI16 through the main PLL at 80MHz
+//!
+//! AHB and both APB buses run at SYSCLK. USART2 is clocked from HSI16 regardless, see
+//! [`crate::power`]. Above 26MHz the core must stay in voltage range 1.
+
+use crate::chip::pac::{rcc, FLASH, RCC};
+
+#[cfg(all(feature = "clock-msi-48", feature = "clock-pll-80"))]
+compile_error!("features `clock-msi-48` and `clock-pll-80` are mutually exclusive");
+
+pub const SYSCLK_HZ: u32 = if cfg!(feature = "clock-pll-80") {
+    80_000_000
+} else if cfg!(feature = "clock-msi-48") {
+    48_000_000
+} else {
+    4_000_000
+};
+
+/// APB1 clock of TIM2 and USART2 registers
+pub const PCLK1_HZ: u32 = SYSCLK_HZ;
+
+/// APB2 clock of USART1
+pub const PCLK2_HZ: u32 = SYSCLK_HZ;
+
+/// Highest SYSCLK in voltage range 2
+pub const RANGE2_MAX_HZ: u32 = 26_000_000;
+
+/// Flash wait states in voltage range 1, reference manual table 9
+const fn wait_states(hz: u32) -> u8 {
+    match hz {
+        0..=16_000_000 => 0,
+        16_000_001..=32_000_000 => 1,
+        32_000_001..=48_000_000 => 2,
+        48_000_001..=64_000_000 => 3,
+        _ => 4,
+    }
+}
+
+/// Switch SYSCLK to the selected source. Must be called in voltage range 1, the reset default,
+/// before peripherals depending on bus clocks are configured, and after starting the LSE for
+/// MSI to lock to it.
+pub fn init(rcc: &RCC, flash: &FLASH) {
+    // Wait states must be raised before the clock
+    let latency = wait_states(SYSCLK_HZ);
+    // SAFETY: 0 to 4 wait states are valid
+    flash
+        .acr
+        .modify(|_, w| unsafe { w.latency().bits(latency) });
+    while flash.acr.read().latency().bits() != latency {}
+    // SAFETY: 0 divides AHB, APB1 and APB2 by 1
+    rcc.cfgr
+        .modify(|_, w| unsafe { w.hpre().bits(0).ppre1().bits(0).ppre2().bits(0) });
+    switch(rcc);
+}
+
+/// Restore SYSCLK after Stop mode, which wakes up on MSI with the PLL off
+pub fn restore() {
+    if cfg!(feature = "clock-pll-80") {
+        // SAFETY: RCC is only configured by init and here, with the core otherwise idle
+        switch(unsafe { &*RCC::ptr() });
+    }
+}
+
+#[cfg(not(any(feature = "clock-msi-48", feature = "clock-pll-80")))]
+fn switch(_: &rcc::RegisterBlock) {}
+
+#[cfg(feature = "clock-msi-48")]
+fn switch(rcc: &rcc::RegisterBlock) {
+    // MSIRANGE in CR takes effect while MSI is ready, which it is as the current SYSCLK
+    rcc.cr
+        .modify(|_, w| w.msirange().range48m().msirgsel().set_bit());
+    while rcc.cr.read().msirdy().bit_is_clear() {}
+    if rcc.bdcr.read().lserdy().bit_is_set() {
+        rcc.cr.modify(|_, w| w.msipllen().set_bit());
+    }
+}
+
+#[cfg(feature = "clock-pll-80")]
+fn switch(rcc: &rcc::RegisterBlock) {
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+    rcc.cr.modify(|_, w| w.pllon().clear_bit());
+    while rcc.cr.read().pllrdy().bit_is_set() {}
+    // 16MHz / M 1 * N 10 = 160MHz VCO, / R 2 = 80MHz
+    // SAFETY: PLLSRC 2 is HSI16, PLLM 0 divides by 1, PLLR 0 divides by 2
+    rcc.pllcfgr.write(|w| unsafe {
+        w.pllsrc()
+            .bits(0b10)
+            .pllm()
+            .bits(0)
+            .plln()
+            .bits(10)
+            .pllr()
+            .bits(0)
+            .pllren()
+            .set_bit()
+    });
+    rcc.cr.modify(|_, w| w.pllon().set_bit());
+    while rcc.cr.read().pllrdy().bit_is_clear() {}
+    // SAFETY: 0b11 selects PLL as SYSCLK
+    rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(0b11) });
+    while rcc.cfgr.read().sws().bits() != 0b11 {}
+}
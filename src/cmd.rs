@@ -0,0 +1,1704 @@
+//! Line based command protocol on the host UART, and on USB and the Bluetooth module as built in,
+//! see [`crate::inbox`].
+//! Commands are words separated by spaces and terminated by CR or LF, case insensitive:
+//!
+//! - `PWR ON|OFF` turns the GPS on/off
+//! - `STATUS` reports GPS power, navigation rate and the latest fix
+//! - `RATE 1|5|10` sets the GPS navigation rate in Hz
+//! - `PSM ON|OFF|CYCLIC <s>` switches the GPS power save mode, `CYCLIC` with an update period of
+//!   1 to 3600 seconds, see [`crate::psm`]
+//! - `GNSS [<systems>]` reports or selects the satellite systems, `<systems>` is a comma
+//!   separated list of `GPS` or `GLONASS` and optionally `SBAS` and `QZSS`, see [`crate::gnss`]
+//! - `SRC [NMEA|UBX]` reports or selects whether fixes come from NMEA sentences or UBX-NAV-PVT
+//!   messages of the GPS, see [`crate::source`]
+//! - `AID` enters aiding mode for UBX aiding frames from the host, `AID END` leaves it,
+//!   see [`crate::aid`]
+//! - `BRIDGE RAW` passes bytes through between the host and the GPS until the host sends `+++`
+//!   between guard times, see [`crate::bridge`]
+//! - `UBX <class> <id> [<payload>]` sends a UBX frame to the GPS, arguments in hex
+//! - `PUBX RATE <type> <n>` outputs sentence `<type>` every `n` epochs on the GPS UART, 0 turns
+//!   it off, and `PUBX BAUD <rate>` sets the GPS baud, as `$PUBX` sentences, see [`crate::pubx`]
+//! - `BAUD [GPS] <rate>` sets the host or GPS baud, see [`crate::baud::SUPPORTED`]
+//! - `TIME?` reports the UTC date and time of the GPS disciplined RTC
+//! - `VERSION?` reports the firmware name and version, git commit and unique device ID, see
+//!   [`crate::version`]
+//! - `FILTER [[+|-]<types>|ALL|NONE]` selects the sentence types forwarded to the host,
+//!   `<types>` is a comma separated list of `GGA`, `RMC`, `GSA`, `GSV`, `VTG`, `GLL`, `OTHER`
+//!   and `POS`, see [`crate::pos`]. `+` adds to and `-` removes from the current selection, no
+//!   argument reports it.
+//! - `PASSTHRU [RAW|VALID]` reports or selects whether sentences failing their checksum are
+//!   forwarded, `VALID` drops them and is the default
+//! - `DECIMATE [<n>|OFF]` reports or sets the decimation of forwarded sentences, one of every `n`
+//!   of each type, 1 to 3600, see [`crate::decimate`]
+//! - `MODE [NMEA|BIN]` reports or selects the output, sentences or binary fix records, see
+//!   [`crate::protocol`]
+//! - `UNITS [MPS|KMH|MPH|KNOTS|M|FT]` reports or selects the speed or altitude unit of `STATUS`
+//!   and binary fix records, see [`crate::units`]
+//! - `QUALITY [FIX 2D|3D|SATS <n>|HDOP <hdop>]` reports or sets the minimum fix type, satellites
+//!   and the worst HDOP of fixes sent in `MODE BIN`, counted by the trip or logged, see
+//!   [`crate::quality`]
+//! - `SMOOTH [ON <alpha>|OFF]` reports or sets the position and speed smoothing of the display
+//!   and the geofence, `alpha` from 0.01 to 1, see [`crate::smoothing`]
+//! - `SPEEDALARM [<kmh>|OFF]` reports or sets the speed alarm limit, 1 to 999 km/h, see
+//!   [`crate::speed_alarm`]
+//! - `ANCHOR [SET [<radius_m>]|OFF]` reports the anchor watch, or starts it at the current
+//!   position with a radius of 1 to 10000 metres, 30 by default, see [`crate::anchor`]
+//! - `SCHEDULE [<on_s> <off_s>|OFF]` reports or sets GPS duty cycling, on for up to `on_s`
+//!   seconds until a fix passes `QUALITY`, 10 to 3600, then off for `off_s` seconds, 1 to 86400,
+//!   see [`crate::schedule`]
+//! - `BATTERY [<mv>|OFF]` reports the battery voltage and VDDA, or sets the low battery threshold
+//!   below which the GPS is turned off, 1800 to 3600 mV, see [`crate::battery`]
+//! - `FIXTIMEOUT [<s>|OFF]` reports or sets the time without a fix before the GPS is power
+//!   cycled, a multiple of 10 from 60 to 2550 seconds, see [`crate::gps_ctrl`]
+//! - `REPORT [<s>|OFF]` reports or sets the period of the `$PBRIDGE,STATUS` sentence, 1 to 3600
+//!   seconds, see [`crate::report`]
+//! - `SAVE` stores baud rates, filter, navigation rate, GPS power, geofence zones and the fix
+//!   timeout for the next boot
+//! - `DFU` resets into the STM32 system bootloader to update the firmware over USART2, see
+//!   [`crate::dfu`]
+//! - `STATS [CLR]` reports UART error, dropped byte, sentence and checksum error counters, the
+//!   queue high-water marks, the stack never used, see [`crate::stack`], the CPU load, see
+//!   [`crate::load`], and the lines dropped by each sink, `CLR` resets all but the stack
+//! - `TTFF?` reports the number of GPS starts timed and the minimum, average and maximum time to
+//!   first fix in milliseconds, see [`crate::ttff`]
+//! - `BATCH [<bytes>|OFF]` reports or sets the bytes of forwarded sentences collected before a
+//!   host transfer starts, see [`crate::dma`]
+//! - `SATS?` reports the satellites in view, one `SAT` line each followed by the count, see
+//!   [`crate::sky`]
+//! - `TRIP?` reports distance, trip and moving time, average and maximum speed of the trip,
+//!   `TRIP RESET` starts a new one
+//! - `ZONE <id> [<lat> <lon> <radius_m>]` reports or sets a geofence zone, coordinates in decimal
+//!   degrees. Setting the zone after the last one adds it. `ZONE CLR` removes all zones.
+//! - `LOG ON [CSV|GPX]|OFF` starts or stops logging fixes to the SD card, CSV by default,
+//!   with the `sd-log` feature
+//! - `DUMP [NMEA|CSV]` sends the flash track log, NMEA by default, and `ERASE` clears it,
+//!   with the `flash-log` feature
+//! - `BOOT [SWAP]` reports the running flash bank and its trial, or boots the other bank on
+//!   trial, with the `ab-boot` feature, see [`crate::boot`]
+//! - `RS485 [<address>|OFF]` reports the RS-485 driver enable and bus address, or sets or clears
+//!   the address, with the `rs485` feature, see [`crate::rs485`]
+//! - `POLL [ON|OFF]` reports polled mode and the fixes kept, or enters or leaves it, with the
+//!   `rs485` feature and a bus address, see [`crate::poll`]
+//! - `CAN [<ms>|OFF]` reports or sets the period of the CAN broadcast, 100 to 60000 ms, with the
+//!   `can` feature, see [`crate::can`]
+//! - `LOG EVENTS [<n>|CLR]` lists the latest events logged in the EEPROM, or hides those logged
+//!   so far, with the `eeprom` feature, see [`crate::eventlog`]
+//! - `SPI [FIX|NMEA|ALL]` reports or selects what is streamed from the SPI slave port, with the
+//!   `spi-slave` feature, see [`crate::spi_slave`]
+//! - `ROUTE [[GPS|SYNTH|REPORT] <sinks>]` reports or selects the sinks of forwarded sentences,
+//!   synthesized sentences or reports, a comma separated list of `HOST`, `USB` and `BLE` as
+//!   built in or `NONE`, GPS and SYNTH alike without a source, see [`crate::router`]
+//! - `OVERFLOW [<sink> NEWEST|OLDEST|BLOCK <ms>]` reports or selects what a sink does with a
+//!   line that doesn't fit its queue, drop it, drop the oldest lines or hold it up to 100 ms, see
+//!   [`crate::router`]
+//! - `SELFTEST` checks the GPS UART in loopback, a queue, a scratch flash page, the RTC and the
+//!   GPS power pin, reporting each as passed, failed or skipped, see [`crate::selftest`]
+//! - `LORA [<s>|OFF|FREQ <mhz>|SF <n>]` reports the LoRa beacon, or sets its interval, 10 to 3600
+//!   seconds, its carrier frequency or its spreading factor, 7 to 12, with the `lora` feature,
+//!   see [`crate::beacon`]
+//! - `PROFILE?` reports the average and maximum duration of the USART and DMA interrupt
+//!   handlers in microseconds, with the `profiling` feature, see [`crate::profile`]
+//!
+//! Every command is answered with one line, `OK`, `ERR <reason>` or the requested data.
+//! UBX acknowledgements from the GPS are reported as `UBX ACK|NAK <class> <id>` when received.
+
+use crate::anchor::{self, Anchor, AnchorWatch};
+use crate::battery::{self, Monitor};
+use crate::baud;
+#[cfg(feature = "lora")]
+use crate::beacon;
+#[cfg(feature = "ble")]
+use crate::ble;
+#[cfg(feature = "rs485")]
+use crate::board::{DriverEnable, PINS};
+#[cfg(feature = "ab-boot")]
+use crate::boot;
+#[cfg(feature = "can")]
+use crate::can;
+use crate::config::{self, Zone, MAX_ZONES};
+use crate::decimate;
+use crate::dma::TX_BUFFER_SIZE;
+#[cfg(feature = "eeprom")]
+use crate::eventlog::{self, Entry};
+use crate::filter::{Filter, Output, Passthru};
+use crate::fix::GpsFix;
+#[cfg(feature = "flash-log")]
+use crate::flashlog;
+use crate::geo::{AltitudeUnit, SpeedUnit};
+use crate::gnss::{self, Selection};
+use crate::gps_ctrl;
+#[cfg(feature = "lora")]
+use crate::lora;
+use crate::nav::Trip;
+use crate::nmea::{self, Date, FixType, SentenceType, Time};
+#[cfg(feature = "profiling")]
+use crate::profile::{self, Handler};
+use crate::psm;
+use crate::quality::{self, Gate};
+use crate::rate::{self, Profile};
+use crate::report;
+use crate::router::{self, Overflow, Sink, Sinks};
+#[cfg(feature = "rs485")]
+use crate::rs485;
+use crate::schedule::{self, Schedule, Windows};
+#[cfg(feature = "sd-log")]
+use crate::sdlog;
+use crate::selftest::Results;
+use crate::sky::{Satellite, Sky};
+use crate::smoothing::Smoother;
+use crate::source::Source;
+use crate::speed_alarm;
+#[cfg(feature = "spi-slave")]
+use crate::spi_slave::{self, Content};
+use crate::stats::Stats;
+use crate::ubx::Ack;
+use crate::units;
+use crate::version;
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+/// Longest command line accepted, excluding line ending
+pub const MAX_LINE_LEN: usize = 64;
+
+/// Longest response line sent, including line ending, room for `STATS` with every sink
+pub const MAX_RESPONSE_LEN: usize = 160;
+
+/// Longest UBX payload that fits in a command line
+pub const MAX_UBX_PAYLOAD_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Line exceeded [`MAX_LINE_LEN`]
+    TooLong,
+    /// First word is not a command
+    Unknown,
+    /// Missing or invalid argument
+    Argument,
+    /// Output queue has no room, try again later
+    Busy,
+    /// A byte of the line was received with a framing error
+    Framing,
+    /// Writing the configuration to flash failed
+    Flash,
+    /// The EEPROM didn't answer or writing it failed
+    Eeprom,
+    /// The command needs a valid fix
+    NoFix,
+    /// The other flash bank holds no firmware image
+    NoImage,
+    /// The bridge has no RS-485 bus address
+    NoAddress,
+    /// No LoRa radio answered at boot
+    NoRadio,
+    /// The command can only be sent on USART2
+    HostOnly,
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::TooLong => "TOO_LONG",
+            Error::Unknown => "UNKNOWN",
+            Error::Argument => "ARGUMENT",
+            Error::Busy => "BUSY",
+            Error::Framing => "FRAMING",
+            Error::Flash => "FLASH",
+            Error::Eeprom => "EEPROM",
+            Error::NoFix => "NOFIX",
+            Error::NoImage => "NOIMAGE",
+            Error::NoAddress => "NOADDR",
+            Error::NoRadio => "NORADIO",
+            Error::HostOnly => "HOSTONLY",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Turn GPS on if true, off if false
+    Power(bool),
+    Status,
+    /// Switch to a navigation rate profile
+    Rate(&'static Profile),
+    /// Switch the GPS power save mode
+    Psm(psm::Mode),
+    /// Report the satellite systems, or select them
+    Gnss(Option<Selection>),
+    /// Report the source of fixes, or switch the GPS to it
+    Src(Option<Source>),
+    /// Enter aiding mode if true, leave it if false
+    Aid(bool),
+    /// Pass bytes through between the host and the GPS
+    Bridge,
+    /// Send a raw UBX frame to the GPS
+    Ubx {
+        class: u8,
+        id: u8,
+        payload: Vec<u8, MAX_UBX_PAYLOAD_LEN>,
+    },
+    /// Send a `$PUBX` configuration sentence to the GPS
+    Pubx(Pubx),
+    /// Switch a UART to another baud
+    Baud {
+        port: Port,
+        baud: u32,
+    },
+    /// Report the RTC date and time
+    Time,
+    /// Report the firmware version
+    Version,
+    /// Report or change the forwarded sentence types
+    Filter(FilterChange),
+    /// Report the checksum handling, or change it
+    Passthru(Option<Passthru>),
+    /// Report the decimation factor, or set it, 1 forwards every sentence
+    Decimate(Option<u16>),
+    /// Report the output, or select it
+    Mode(Option<Output>),
+    /// Report the units, or select one
+    Units(Option<UnitsChange>),
+    /// Report the status sentence period, or set it in seconds, 0 disables it
+    Report(Option<u16>),
+    /// Report the host transfer batch threshold, or set it in bytes, 0 disables it
+    Batch(Option<u16>),
+    /// Report the counters, or reset them if true
+    Stats(bool),
+    /// Report the times to first fix
+    Ttff,
+    /// Persist the current settings
+    Save,
+    /// Reset into the system bootloader
+    Dfu,
+    /// Report, set or remove geofence zones
+    Zone(ZoneChange),
+    /// Report the satellites in view
+    Sats,
+    /// Report the trip statistics, or reset them if true
+    Trip(bool),
+    /// Report the quality gate, or change one of its limits
+    Quality(Option<QualityChange>),
+    /// Report the smoothing, or set its alpha in hundredths, 0 disables it
+    Smooth(Option<u8>),
+    /// Report the speed alarm limit, or set it in km/h, 0 disables it
+    SpeedAlarm(Option<u16>),
+    /// Report the anchor watch, start it at the current position or stop it
+    Anchor(AnchorChange),
+    /// Report or change the GPS duty cycle
+    Schedule(ScheduleChange),
+    /// Report the battery, or set the low battery threshold in millivolts, 0 disables it
+    Battery(Option<u16>),
+    /// Report the fix timeout, or set it in seconds, 0 disables it
+    FixTimeout(Option<u16>),
+    /// Start logging to the SD card in a format, or stop if `None`
+    #[cfg(feature = "sd-log")]
+    Log(Option<sdlog::Format>),
+    /// Send the flash track log to the host
+    #[cfg(feature = "flash-log")]
+    Dump(flashlog::Format),
+    /// Clear the flash track log
+    #[cfg(feature = "flash-log")]
+    Erase,
+    /// Report the running bank, or boot the other one on trial if true
+    #[cfg(feature = "ab-boot")]
+    Boot(bool),
+    /// Report the RS-485 settings, or set the bus address, 0 clears it
+    #[cfg(feature = "rs485")]
+    Rs485(Option<u8>),
+    /// Report polled mode, or enter or leave it
+    #[cfg(feature = "rs485")]
+    Poll(Option<bool>),
+    /// Report the CAN broadcast period, or set it in milliseconds, 0 disables it
+    #[cfg(feature = "can")]
+    Can(Option<u16>),
+    /// List the latest events, or log the marker hiding them with `None`
+    #[cfg(feature = "eeprom")]
+    Events(Option<u8>),
+    /// Report the SPI stream, or select its content
+    #[cfg(feature = "spi-slave")]
+    Spi(Option<Content>),
+    /// Report where each source goes, or route one
+    Route(RouteChange),
+    /// Report the overflow policies, or set that of a sink
+    Overflow(Option<(Sink, Overflow)>),
+    /// Run the self-test
+    SelfTest,
+    /// Report the LoRa beacon, or change a setting
+    #[cfg(feature = "lora")]
+    Lora(LoraChange),
+    /// Report the durations of the interrupt handlers
+    #[cfg(feature = "profiling")]
+    Profile,
+}
+
+/// Argument of the `PUBX` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pubx {
+    /// Output `sentence` every `rate` epochs, 0 disables it
+    Rate { sentence: SentenceType, rate: u8 },
+    /// Switch the GPS UART to `baud`
+    Baud(u32),
+}
+
+/// UART of the `BAUD` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    Host,
+    Gps,
+}
+
+/// Argument of the `UNITS` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitsChange {
+    Speed(SpeedUnit),
+    Altitude(AltitudeUnit),
+}
+
+/// Arguments of the `QUALITY` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityChange {
+    /// `Fix2D` or `Fix3D`
+    Fix(FixType),
+    Sats(u8),
+    /// In hundredths
+    Hdop(u16),
+}
+
+impl QualityChange {
+    pub fn apply(&self, gate: &mut Gate) {
+        match *self {
+            QualityChange::Fix(fix_type) => gate.min_fix = fix_type,
+            QualityChange::Sats(sats) => gate.min_sats = sats,
+            QualityChange::Hdop(hdop) => gate.max_hdop = hdop,
+        }
+    }
+}
+
+/// Arguments of the `ANCHOR` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorChange {
+    Query,
+    /// Anchor at the current position with a radius in metres
+    Set(u32),
+    Off,
+}
+
+/// Argument of the `SCHEDULE` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleChange {
+    Query,
+    Set(Windows),
+    Off,
+}
+
+/// Argument of the `LORA` command
+#[cfg(feature = "lora")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoraChange {
+    Query,
+    /// Seconds between packets, 0 disables them
+    Interval(u16),
+    FrequencyKhz(u32),
+    Sf(u8),
+}
+
+/// Argument of the `ROUTE` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteChange {
+    Query,
+    Set(router::Source, Sinks),
+    /// Both sources of sentences, GPS and SYNTH
+    Sentences(Sinks),
+}
+
+/// Argument of the `FILTER` command, masks as in [`Filter::from_mask`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterChange {
+    Query,
+    Set(u8),
+    Enable(u8),
+    Disable(u8),
+}
+
+/// Argument of the `ZONE` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneChange {
+    Query(usize),
+    Set(usize, Zone),
+    Clear,
+}
+
+impl Command {
+    pub fn parse(line: &[u8]) -> Result<Self, Error> {
+        let mut words = line
+            .split(|&byte| byte == b' ')
+            .filter(|word| !word.is_empty());
+        let name = words.next().ok_or(Error::Unknown)?;
+        let command = if name.eq_ignore_ascii_case(b"PWR") {
+            Command::Power(on_off(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"STATUS") {
+            Command::Status
+        } else if name.eq_ignore_ascii_case(b"RATE") {
+            let hz = decimal(words.next())?;
+            let profile = u8::try_from(hz)
+                .ok()
+                .and_then(rate::profile)
+                .ok_or(Error::Argument)?;
+            Command::Rate(profile)
+        } else if name.eq_ignore_ascii_case(b"PSM") {
+            Command::Psm(psm_mode(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"GNSS") {
+            Command::Gnss(words.next().map(gnss_selection).transpose()?)
+        } else if name.eq_ignore_ascii_case(b"SRC") {
+            Command::Src(match words.next() {
+                None => None,
+                Some(word) if word.eq_ignore_ascii_case(b"NMEA") => Some(Source::Nmea),
+                Some(word) if word.eq_ignore_ascii_case(b"UBX") => Some(Source::Ubx),
+                Some(_) => return Err(Error::Argument),
+            })
+        } else if name.eq_ignore_ascii_case(b"AID") {
+            match words.next() {
+                None => Command::Aid(true),
+                Some(word) if word.eq_ignore_ascii_case(b"END") => Command::Aid(false),
+                Some(_) => return Err(Error::Argument),
+            }
+        } else if name.eq_ignore_ascii_case(b"BRIDGE") {
+            match words.next() {
+                Some(word) if word.eq_ignore_ascii_case(b"RAW") => Command::Bridge,
+                _ => return Err(Error::Argument),
+            }
+        } else if name.eq_ignore_ascii_case(b"UBX") {
+            let class = hex_byte(words.next())?;
+            let id = hex_byte(words.next())?;
+            let payload = match words.next() {
+                Some(word) => hex_bytes(word)?,
+                None => Vec::new(),
+            };
+            Command::Ubx { class, id, payload }
+        } else if name.eq_ignore_ascii_case(b"PUBX") {
+            Command::Pubx(pubx(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"BAUD") {
+            let mut word = words.next();
+            let port = match word {
+                Some(port) if port.eq_ignore_ascii_case(b"GPS") => {
+                    word = words.next();
+                    Port::Gps
+                }
+                _ => Port::Host,
+            };
+            let baud = decimal(word)?;
+            if !baud::SUPPORTED.contains(&baud) {
+                return Err(Error::Argument);
+            }
+            Command::Baud { port, baud }
+        } else if name.eq_ignore_ascii_case(b"TIME?") {
+            Command::Time
+        } else if name.eq_ignore_ascii_case(b"VERSION?") {
+            Command::Version
+        } else if name.eq_ignore_ascii_case(b"FILTER") {
+            Command::Filter(filter_change(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"PASSTHRU") {
+            Command::Passthru(match words.next() {
+                None => None,
+                Some(word) if word.eq_ignore_ascii_case(b"RAW") => Some(Passthru::Raw),
+                Some(word) if word.eq_ignore_ascii_case(b"VALID") => Some(Passthru::Valid),
+                Some(_) => return Err(Error::Argument),
+            })
+        } else if name.eq_ignore_ascii_case(b"DECIMATE") {
+            Command::Decimate(decimation(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"MODE") {
+            Command::Mode(match words.next() {
+                None => None,
+                Some(word) if word.eq_ignore_ascii_case(b"NMEA") => Some(Output::Nmea),
+                Some(word) if word.eq_ignore_ascii_case(b"BIN") => Some(Output::Binary),
+                Some(_) => return Err(Error::Argument),
+            })
+        } else if name.eq_ignore_ascii_case(b"UNITS") {
+            Command::Units(match words.next() {
+                None => None,
+                Some(word) => Some(units_change(word)?),
+            })
+        } else if name.eq_ignore_ascii_case(b"REPORT") {
+            Command::Report(report_period(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"BATCH") {
+            Command::Batch(batch_threshold(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"SAVE") {
+            Command::Save
+        } else if name.eq_ignore_ascii_case(b"DFU") {
+            Command::Dfu
+        } else if name.eq_ignore_ascii_case(b"STATS") {
+            match words.next() {
+                None => Command::Stats(false),
+                Some(word) if word.eq_ignore_ascii_case(b"CLR") => Command::Stats(true),
+                Some(_) => return Err(Error::Argument),
+            }
+        } else if name.eq_ignore_ascii_case(b"SATS?") {
+            Command::Sats
+        } else if name.eq_ignore_ascii_case(b"TTFF?") {
+            Command::Ttff
+        } else if name.eq_ignore_ascii_case(b"TRIP?") {
+            Command::Trip(false)
+        } else if name.eq_ignore_ascii_case(b"TRIP") {
+            match words.next() {
+                Some(word) if word.eq_ignore_ascii_case(b"RESET") => Command::Trip(true),
+                _ => return Err(Error::Argument),
+            }
+        } else if name.eq_ignore_ascii_case(b"ZONE") {
+            Command::Zone(zone_change(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"QUALITY") {
+            Command::Quality(match words.next() {
+                None => None,
+                Some(word) => Some(quality_change(word, words.next())?),
+            })
+        } else if name.eq_ignore_ascii_case(b"SMOOTH") {
+            Command::Smooth(smooth_alpha(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"SPEEDALARM") {
+            Command::SpeedAlarm(speed_limit(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"ANCHOR") {
+            Command::Anchor(anchor_change(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"SCHEDULE") {
+            Command::Schedule(schedule_change(&mut words)?)
+        } else if name.eq_ignore_ascii_case(b"BATTERY") {
+            Command::Battery(battery_threshold(words.next())?)
+        } else if name.eq_ignore_ascii_case(b"FIXTIMEOUT") {
+            Command::FixTimeout(fix_timeout(words.next())?)
+        } else {
+            feature_command(name, &mut words)?
+        };
+        // Trailing arguments are a typo rather than something to silently ignore
+        match words.next() {
+            Some(_) => Err(Error::Argument),
+            None => Ok(command),
+        }
+    }
+
+    /// The command streams bytes on USART2 and can't be run from another port
+    pub fn is_host_only(&self) -> bool {
+        match self {
+            Command::Aid(true) | Command::Bridge => true,
+            #[cfg(feature = "flash-log")]
+            Command::Dump(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Parse a speed or altitude unit name
+fn units_change(word: &[u8]) -> Result<UnitsChange, Error> {
+    SpeedUnit::from_name(word)
+        .map(UnitsChange::Speed)
+        .or_else(|| AltitudeUnit::from_name(word).map(UnitsChange::Altitude))
+        .ok_or(Error::Argument)
+}
+
+/// Parse `ON` or `OFF`
+fn on_off(word: Option<&[u8]>) -> Result<bool, Error> {
+    match word {
+        Some(word) if word.eq_ignore_ascii_case(b"ON") => Ok(true),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(false),
+        _ => Err(Error::Argument),
+    }
+}
+
+/// `LOG ON [CSV|GPX]` or `LOG OFF`, `None` stopping the SD card log
+#[cfg(feature = "sd-log")]
+fn track_format(on: Option<&[u8]>, format: Option<&[u8]>) -> Result<Option<sdlog::Format>, Error> {
+    let on = on_off(on)?;
+    Ok(match format {
+        None if on => Some(sdlog::Format::Csv),
+        None => None,
+        Some(word) if on && word.eq_ignore_ascii_case(b"CSV") => Some(sdlog::Format::Csv),
+        Some(word) if on && word.eq_ignore_ascii_case(b"GPX") => Some(sdlog::Format::Gpx),
+        Some(_) => return Err(Error::Argument),
+    })
+}
+
+/// Count of `LOG EVENTS [<n>]`, or `None` for `LOG EVENTS CLR`
+#[cfg(feature = "eeprom")]
+fn event_count(word: Option<&[u8]>) -> Result<Option<u8>, Error> {
+    match word {
+        None => Ok(Some(eventlog::DEFAULT_LISTED)),
+        Some(word) if word.eq_ignore_ascii_case(b"CLR") => Ok(None),
+        word => u8::try_from(decimal(word)?)
+            .ok()
+            .filter(|count| (1..=eventlog::MAX_LISTED).contains(count))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the commands of optional features, unknown without them
+#[cfg_attr(
+    not(any(feature = "sd-log", feature = "flash-log")),
+    allow(unused_variables)
+)]
+fn feature_command<'a>(
+    name: &[u8],
+    words: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<Command, Error> {
+    #[cfg(any(feature = "sd-log", feature = "eeprom"))]
+    if name.eq_ignore_ascii_case(b"LOG") {
+        let word = words.next();
+        #[cfg(feature = "eeprom")]
+        if word.is_some_and(|word| word.eq_ignore_ascii_case(b"EVENTS")) {
+            return event_count(words.next()).map(Command::Events);
+        }
+        #[cfg(feature = "sd-log")]
+        return track_format(word, words.next()).map(Command::Log);
+        #[cfg(not(feature = "sd-log"))]
+        return Err(Error::Argument);
+    }
+    #[cfg(feature = "flash-log")]
+    if name.eq_ignore_ascii_case(b"DUMP") {
+        let format = match words.next() {
+            None => flashlog::Format::Nmea,
+            Some(word) if word.eq_ignore_ascii_case(b"NMEA") => flashlog::Format::Nmea,
+            Some(word) if word.eq_ignore_ascii_case(b"CSV") => flashlog::Format::Csv,
+            Some(_) => return Err(Error::Argument),
+        };
+        return Ok(Command::Dump(format));
+    }
+    #[cfg(feature = "flash-log")]
+    if name.eq_ignore_ascii_case(b"ERASE") {
+        return Ok(Command::Erase);
+    }
+    #[cfg(feature = "ab-boot")]
+    if name.eq_ignore_ascii_case(b"BOOT") {
+        return match words.next() {
+            None => Ok(Command::Boot(false)),
+            Some(word) if word.eq_ignore_ascii_case(b"SWAP") => Ok(Command::Boot(true)),
+            Some(_) => Err(Error::Argument),
+        };
+    }
+    #[cfg(feature = "rs485")]
+    if name.eq_ignore_ascii_case(b"RS485") {
+        return rs485_address(words.next()).map(Command::Rs485);
+    }
+    #[cfg(feature = "rs485")]
+    if name.eq_ignore_ascii_case(b"POLL") {
+        return match words.next() {
+            None => Ok(Command::Poll(None)),
+            word => on_off(word).map(|on| Command::Poll(Some(on))),
+        };
+    }
+    #[cfg(feature = "can")]
+    if name.eq_ignore_ascii_case(b"CAN") {
+        return can_period(words.next()).map(Command::Can);
+    }
+    #[cfg(feature = "spi-slave")]
+    if name.eq_ignore_ascii_case(b"SPI") {
+        return Ok(Command::Spi(match words.next() {
+            None => None,
+            Some(word) if word.eq_ignore_ascii_case(b"FIX") => Some(Content::Fix),
+            Some(word) if word.eq_ignore_ascii_case(b"NMEA") => Some(Content::Nmea),
+            Some(word) if word.eq_ignore_ascii_case(b"ALL") => Some(Content::All),
+            Some(_) => return Err(Error::Argument),
+        }));
+    }
+    if name.eq_ignore_ascii_case(b"ROUTE") {
+        return route_change(words).map(Command::Route);
+    }
+    if name.eq_ignore_ascii_case(b"OVERFLOW") {
+        return overflow(words).map(Command::Overflow);
+    }
+    if name.eq_ignore_ascii_case(b"SELFTEST") {
+        return Ok(Command::SelfTest);
+    }
+    #[cfg(feature = "lora")]
+    if name.eq_ignore_ascii_case(b"LORA") {
+        return lora_change(words).map(Command::Lora);
+    }
+    #[cfg(feature = "profiling")]
+    if name.eq_ignore_ascii_case(b"PROFILE?") {
+        return Ok(Command::Profile);
+    }
+    Err(Error::Unknown)
+}
+
+/// Parse the `FILTER` argument
+fn filter_change(word: Option<&[u8]>) -> Result<FilterChange, Error> {
+    let word = match word {
+        Some(word) => word,
+        None => return Ok(FilterChange::Query),
+    };
+    if word.eq_ignore_ascii_case(b"ALL") {
+        return Ok(FilterChange::Set(Filter::all().mask()));
+    }
+    if word.eq_ignore_ascii_case(b"NONE") {
+        return Ok(FilterChange::Set(0));
+    }
+    match word.split_first() {
+        Some((b'+', types)) => sentence_types(types).map(FilterChange::Enable),
+        Some((b'-', types)) => sentence_types(types).map(FilterChange::Disable),
+        _ => sentence_types(word).map(FilterChange::Set),
+    }
+}
+
+/// Parse the `ZONE` arguments
+fn zone_change<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<ZoneChange, Error> {
+    let word = words.next().ok_or(Error::Argument)?;
+    if word.eq_ignore_ascii_case(b"CLR") {
+        return Ok(ZoneChange::Clear);
+    }
+    let id = decimal(Some(word))? as usize;
+    if id >= MAX_ZONES {
+        return Err(Error::Argument);
+    }
+    let Some(lat) = words.next() else {
+        return Ok(ZoneChange::Query(id));
+    };
+    let zone = Zone {
+        lat: degrees(lat, 90)?,
+        lon: degrees(words.next().ok_or(Error::Argument)?, 180)?,
+        radius_m: decimal(words.next())?,
+    };
+    if zone.radius_m == 0 {
+        return Err(Error::Argument);
+    }
+    Ok(ZoneChange::Set(id, zone))
+}
+
+/// Parse the `PSM` arguments
+fn psm_mode<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<psm::Mode, Error> {
+    let word = words.next().ok_or(Error::Argument)?;
+    let update_period_s = if word.eq_ignore_ascii_case(b"OFF") {
+        return Ok(psm::Mode::Off);
+    } else if word.eq_ignore_ascii_case(b"ON") {
+        psm::DEFAULT_UPDATE_PERIOD_S
+    } else if word.eq_ignore_ascii_case(b"CYCLIC") {
+        decimal(words.next())?
+    } else {
+        return Err(Error::Argument);
+    };
+    if !(1..=psm::MAX_UPDATE_PERIOD_S).contains(&update_period_s) {
+        return Err(Error::Argument);
+    }
+    Ok(psm::Mode::On { update_period_s })
+}
+
+/// Parse the `GNSS` argument, a comma separated list of systems
+fn gnss_selection(word: &[u8]) -> Result<Selection, Error> {
+    let mut systems: Vec<gnss::System, 4> = Vec::new();
+    for name in word.split(|&byte| byte == b',') {
+        let system = gnss::System::from_name(name).ok_or(Error::Argument)?;
+        systems.push(system).map_err(|_| Error::Argument)?;
+    }
+    Selection::new(systems).ok_or(Error::Argument)
+}
+
+/// Parse the `QUALITY` arguments, a limit and its value
+fn quality_change(name: &[u8], value: Option<&[u8]>) -> Result<QualityChange, Error> {
+    let value = value.ok_or(Error::Argument)?;
+    if name.eq_ignore_ascii_case(b"FIX") {
+        if value.eq_ignore_ascii_case(b"2D") {
+            Ok(QualityChange::Fix(FixType::Fix2D))
+        } else if value.eq_ignore_ascii_case(b"3D") {
+            Ok(QualityChange::Fix(FixType::Fix3D))
+        } else {
+            Err(Error::Argument)
+        }
+    } else if name.eq_ignore_ascii_case(b"SATS") {
+        u8::try_from(decimal(Some(value))?)
+            .ok()
+            .filter(|&sats| sats <= quality::MAX_SATS)
+            .map(QualityChange::Sats)
+            .ok_or(Error::Argument)
+    } else if name.eq_ignore_ascii_case(b"HDOP") {
+        nmea::fixed(value, 2)
+            .ok()
+            .flatten()
+            .filter(|hdop| (1..=i64::from(quality::MAX_HDOP)).contains(hdop))
+            .map(|hdop| QualityChange::Hdop(hdop as u16))
+            .ok_or(Error::Argument)
+    } else {
+        Err(Error::Argument)
+    }
+}
+
+/// Parse the `SMOOTH` arguments into alpha in hundredths
+fn smooth_alpha<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<Option<u8>, Error> {
+    let Some(word) = words.next() else {
+        return Ok(None);
+    };
+    if word.eq_ignore_ascii_case(b"OFF") {
+        return Ok(Some(0));
+    }
+    if !word.eq_ignore_ascii_case(b"ON") {
+        return Err(Error::Argument);
+    }
+    nmea::fixed(words.next().ok_or(Error::Argument)?, 2)
+        .ok()
+        .flatten()
+        .filter(|alpha| (1..=i64::from(Smoother::MAX_ALPHA)).contains(alpha))
+        .map(|alpha| Some(alpha as u8))
+        .ok_or(Error::Argument)
+}
+
+/// Parse the `SPEEDALARM` argument
+fn speed_limit(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|kmh| (1..=speed_alarm::MAX_LIMIT_KMH).contains(kmh))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `ANCHOR` arguments
+fn anchor_change<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<AnchorChange, Error> {
+    match words.next() {
+        None => Ok(AnchorChange::Query),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(AnchorChange::Off),
+        Some(word) if word.eq_ignore_ascii_case(b"SET") => {
+            let radius_m = match words.next() {
+                None => anchor::DEFAULT_RADIUS_M,
+                word => decimal(word)?,
+            };
+            if !(1..=anchor::MAX_RADIUS_M).contains(&radius_m) {
+                return Err(Error::Argument);
+            }
+            Ok(AnchorChange::Set(radius_m))
+        }
+        Some(_) => Err(Error::Argument),
+    }
+}
+
+/// Parse the `SCHEDULE` arguments
+fn schedule_change<'a>(
+    words: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<ScheduleChange, Error> {
+    let on_s = match words.next() {
+        None => return Ok(ScheduleChange::Query),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => return Ok(ScheduleChange::Off),
+        word => decimal(word)?,
+    };
+    let off_s = decimal(words.next())?;
+    if !(schedule::MIN_ON_S..=schedule::MAX_ON_S).contains(&on_s)
+        || !(1..=schedule::MAX_OFF_S).contains(&off_s)
+    {
+        return Err(Error::Argument);
+    }
+    Ok(ScheduleChange::Set(Windows { on_s, off_s }))
+}
+
+/// Parse the `BATTERY` argument
+fn battery_threshold(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|mv| (battery::MIN_THRESHOLD_MV..=battery::MAX_THRESHOLD_MV).contains(mv))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `FIXTIMEOUT` argument
+fn fix_timeout(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => {
+            let seconds = decimal(word)?;
+            u16::try_from(seconds)
+                .ok()
+                .filter(|seconds| {
+                    (gps_ctrl::MIN_FIX_TIMEOUT_S..=config::MAX_FIX_TIMEOUT_S).contains(seconds)
+                        && seconds % config::FIX_TIMEOUT_UNIT_S == 0
+                })
+                .map(Some)
+                .ok_or(Error::Argument)
+        }
+    }
+}
+
+/// Parse the `DECIMATE` argument
+fn decimation(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(1)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|factor| (1..=decimate::MAX_FACTOR).contains(factor))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `REPORT` argument
+fn report_period(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|seconds| (1..=report::MAX_PERIOD_S).contains(seconds))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `BATCH` argument
+fn batch_threshold(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|&bytes| (1..=TX_BUFFER_SIZE).contains(&usize::from(bytes)))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `CAN` argument, `Some(0)` for `OFF`
+#[cfg(feature = "can")]
+fn can_period(word: Option<&[u8]>) -> Result<Option<u16>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|ms| (can::MIN_PERIOD_MS..=can::MAX_PERIOD_MS).contains(ms))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `ROUTE` arguments, a source followed by its sinks, or the sinks of both sources of
+/// sentences
+fn route_change<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<RouteChange, Error> {
+    let Some(word) = words.next() else {
+        return Ok(RouteChange::Query);
+    };
+    let sinks = |word: Option<&[u8]>| word.and_then(Sinks::parse).ok_or(Error::Argument);
+    match router::Source::from_name(word) {
+        Some(source) => Ok(RouteChange::Set(source, sinks(words.next())?)),
+        None => Ok(RouteChange::Sentences(sinks(Some(word))?)),
+    }
+}
+
+/// Parse the `OVERFLOW` arguments, a sink and its policy
+fn overflow<'a>(
+    words: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<Option<(Sink, Overflow)>, Error> {
+    let Some(word) = words.next() else {
+        return Ok(None);
+    };
+    let sink = Sink::from_name(word).ok_or(Error::Argument)?;
+    let overflow = match words.next() {
+        Some(word) if word.eq_ignore_ascii_case(b"NEWEST") => Overflow::DropNewest,
+        Some(word) if word.eq_ignore_ascii_case(b"OLDEST") => Overflow::DropOldest,
+        Some(word) if word.eq_ignore_ascii_case(b"BLOCK") => u16::try_from(decimal(words.next())?)
+            .ok()
+            .filter(|ms| (1..=Overflow::MAX_BLOCK_MS).contains(ms))
+            .map(Overflow::Block)
+            .ok_or(Error::Argument)?,
+        _ => return Err(Error::Argument),
+    };
+    Ok(Some((sink, overflow)))
+}
+
+/// Parse the `LORA` arguments, frequencies in MHz with up to 3 decimals
+#[cfg(feature = "lora")]
+fn lora_change<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<LoraChange, Error> {
+    match words.next() {
+        None => Ok(LoraChange::Query),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(LoraChange::Interval(0)),
+        Some(word) if word.eq_ignore_ascii_case(b"FREQ") => {
+            nmea::fixed(words.next().ok_or(Error::Argument)?, 3)
+                .ok()
+                .flatten()
+                .and_then(|khz| u32::try_from(khz).ok())
+                .filter(|khz| (lora::MIN_FREQUENCY_KHZ..=lora::MAX_FREQUENCY_KHZ).contains(khz))
+                .map(LoraChange::FrequencyKhz)
+                .ok_or(Error::Argument)
+        }
+        Some(word) if word.eq_ignore_ascii_case(b"SF") => u8::try_from(decimal(words.next())?)
+            .ok()
+            .filter(|sf| (lora::MIN_SF..=lora::MAX_SF).contains(sf))
+            .map(LoraChange::Sf)
+            .ok_or(Error::Argument),
+        word => u16::try_from(decimal(word)?)
+            .ok()
+            .filter(|s| (beacon::MIN_INTERVAL_S..=beacon::MAX_INTERVAL_S).contains(s))
+            .map(LoraChange::Interval)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `RS485` argument, `Some(0)` for `OFF`
+#[cfg(feature = "rs485")]
+fn rs485_address(word: Option<&[u8]>) -> Result<Option<u8>, Error> {
+    match word {
+        None => Ok(None),
+        Some(word) if word.eq_ignore_ascii_case(b"OFF") => Ok(Some(0)),
+        word => u8::try_from(decimal(word)?)
+            .ok()
+            .filter(|address| (1..=rs485::MAX_ADDRESS).contains(address))
+            .map(Some)
+            .ok_or(Error::Argument),
+    }
+}
+
+/// Parse the `PUBX` arguments
+fn pubx<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<Pubx, Error> {
+    match words.next() {
+        Some(word) if word.eq_ignore_ascii_case(b"RATE") => {
+            let sentence = words
+                .next()
+                .and_then(SentenceType::from_name)
+                .filter(|&sentence| !matches!(sentence, SentenceType::Other | SentenceType::Pos))
+                .ok_or(Error::Argument)?;
+            let rate = u8::try_from(decimal(words.next())?).map_err(|_| Error::Argument)?;
+            Ok(Pubx::Rate { sentence, rate })
+        }
+        Some(word) if word.eq_ignore_ascii_case(b"BAUD") => {
+            let baud = decimal(words.next())?;
+            if !baud::SUPPORTED.contains(&baud) {
+                return Err(Error::Argument);
+            }
+            Ok(Pubx::Baud(baud))
+        }
+        _ => Err(Error::Argument),
+    }
+}
+
+/// Parse decimal degrees up to `max` either way into 1e-7 degrees
+fn degrees(word: &[u8], max: i64) -> Result<i32, Error> {
+    nmea::fixed(word, 7)
+        .ok()
+        .flatten()
+        .filter(|value| value.abs() <= max * 10_000_000)
+        .map(|value| value as i32)
+        .ok_or(Error::Argument)
+}
+
+/// Parse a comma separated list of sentence type names into a filter mask
+fn sentence_types(word: &[u8]) -> Result<u8, Error> {
+    word.split(|&byte| byte == b',').try_fold(0, |mask, name| {
+        SentenceType::from_name(name)
+            .map(|sentence_type| mask | Filter::bit(sentence_type))
+            .ok_or(Error::Argument)
+    })
+}
+
+/// Parse a decimal number
+fn decimal(word: Option<&[u8]>) -> Result<u32, Error> {
+    word.and_then(|word| core::str::from_utf8(word).ok())
+        .and_then(|word| word.parse().ok())
+        .ok_or(Error::Argument)
+}
+
+/// Parse a two digit hex byte
+fn hex_byte(word: Option<&[u8]>) -> Result<u8, Error> {
+    match word {
+        Some(word) if word.len() == 2 => hex_bytes::<1>(word).map(|bytes| bytes[0]),
+        _ => Err(Error::Argument),
+    }
+}
+
+/// Parse a string of hex digit pairs
+fn hex_bytes<const N: usize>(word: &[u8]) -> Result<Vec<u8, N>, Error> {
+    if !word.len().is_multiple_of(2) {
+        return Err(Error::Argument);
+    }
+    let mut bytes = Vec::new();
+    for pair in word.chunks(2) {
+        let digits = core::str::from_utf8(pair).map_err(|_| Error::Argument)?;
+        let byte = u8::from_str_radix(digits, 16).map_err(|_| Error::Argument)?;
+        bytes.push(byte).map_err(|_| Error::Argument)?;
+    }
+    Ok(bytes)
+}
+
+/// Collects received bytes into command lines
+pub struct LineBuffer {
+    buffer: Vec<u8, MAX_LINE_LEN>,
+    /// Error reported at the next line ending instead of the line, such as more than
+    /// [`MAX_LINE_LEN`] bytes received
+    error: Option<Error>,
+    /// Buffer holds the line last returned by [`LineBuffer::push`]
+    complete: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            error: None,
+            complete: false,
+        }
+    }
+
+    /// No bytes received since the last line ending
+    pub fn is_empty(&self) -> bool {
+        self.complete || (self.buffer.is_empty() && self.error.is_none())
+    }
+
+    /// Feed one received byte. Returns the line without its ending once CR or LF is received,
+    /// empty lines are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], Error>> {
+        // The previous line was borrowed by the caller until now
+        if self.complete {
+            self.buffer.clear();
+            self.complete = false;
+        }
+        match byte {
+            b'\r' | b'\n' if self.error.is_some() => {
+                self.buffer.clear();
+                self.error.take().map(Err)
+            }
+            b'\r' | b'\n' if self.buffer.is_empty() => None,
+            b'\r' | b'\n' => {
+                self.complete = true;
+                Some(Ok(&self.buffer))
+            }
+            _ => {
+                if self.buffer.push(byte).is_err() {
+                    self.reject(Error::TooLong);
+                }
+                None
+            }
+        }
+    }
+
+    /// Reject the line being received, the next line ending returns `error` unless another
+    /// error came first
+    pub fn reject(&mut self, error: Error) {
+        if self.complete {
+            self.buffer.clear();
+            self.complete = false;
+        }
+        self.error.get_or_insert(error);
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fixed point number displayed with `decimals` fractional digits
+pub struct Decimal(pub i64, pub u32);
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Decimal(value, decimals) = *self;
+        let sign = if value < 0 { "-" } else { "" };
+        let value = value.unsigned_abs();
+        if decimals == 0 {
+            return write!(f, "{}{}", sign, value);
+        }
+        let scale = 10u64.pow(decimals);
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            value / scale,
+            value % scale,
+            width = decimals as usize
+        )
+    }
+}
+
+/// Append CRLF to a response, cutting it short if there is no room left
+pub fn terminate(response: &mut String<MAX_RESPONSE_LEN>) {
+    if response.push_str("\r\n").is_err() {
+        response.truncate(MAX_RESPONSE_LEN - 2);
+        let _ = response.push_str("\r\n");
+    }
+}
+
+/// Write the `STATUS` response, altitude and speed in the units selected by `UNITS`
+pub fn write_status(
+    out: &mut impl Write,
+    gps_power: bool,
+    rate: &Profile,
+    fix: &GpsFix,
+) -> fmt::Result {
+    let fix_type = match fix.fix_type {
+        FixType::None => "NONE",
+        FixType::Fix2D => "2D",
+        FixType::Fix3D => "3D",
+    };
+    write!(
+        out,
+        "STATUS PWR={} RATE={} FIX={} SATS={} HDOP={}",
+        if gps_power { "ON" } else { "OFF" },
+        rate.hz,
+        fix_type,
+        fix.sats,
+        Decimal(fix.hdop.into(), 2)
+    )?;
+    if fix.is_valid() {
+        let speed_unit = units::speed_unit();
+        let altitude_unit = units::altitude_unit();
+        write!(
+            out,
+            " LAT={} LON={} ALT={}{} SPEED={}{}",
+            Decimal(fix.lat.into(), 7),
+            Decimal(fix.lon.into(), 7),
+            Decimal(altitude_unit.convert(fix.altitude).into(), 3),
+            altitude_unit.name(),
+            Decimal(speed_unit.convert(fix.speed).into(), 3),
+            speed_unit.name()
+        )?;
+    }
+    Ok(())
+}
+
+/// Write a `SATS?` line of one satellite:
+/// `SAT <talker> <prn> <elevation> <azimuth> <snr> USED|-`, unknown values as `-`
+pub fn write_satellite(out: &mut impl Write, satellite: &Satellite) -> fmt::Result {
+    write!(
+        out,
+        "SAT {}{} {}",
+        char::from(satellite.talker[0]),
+        char::from(satellite.talker[1]),
+        satellite.prn
+    )?;
+    for value in [
+        satellite.elevation.map(u16::from),
+        satellite.azimuth,
+        satellite.snr.map(u16::from),
+    ] {
+        match value {
+            Some(value) => write!(out, " {}", value)?,
+            None => out.write_str(" -")?,
+        }
+    }
+    out.write_str(if satellite.used { " USED" } else { " -" })
+}
+
+/// Write the `SATS?` response ending the satellite lines, `SATS <in_view> <used>`
+pub fn write_sats(out: &mut impl Write, sky: &Sky) -> fmt::Result {
+    write!(out, "SATS {} {}", sky.satellites().len(), sky.used_count())
+}
+
+/// Write the `VERSION?` response, package name and version of the firmware, the git commit it was
+/// built from, the unique device ID and its abbreviation of `$PBRIDGE,STATUS`
+pub fn write_version(out: &mut impl Write) -> fmt::Result {
+    write!(
+        out,
+        "VERSION {} {} GIT={} UID=",
+        env!("CARGO_PKG_NAME"),
+        version::VERSION,
+        version::GIT_HASH
+    )?;
+    version::write_uid(out)?;
+    write!(out, " ID={:08X}", version::short_id())
+}
+
+/// Write the `TIME?` response, an ISO 8601 UTC timestamp or `NONE` before the RTC is set
+pub fn write_time(out: &mut impl Write, now: Option<(Date, Time)>) -> fmt::Result {
+    match now {
+        Some((date, time)) => write!(
+            out,
+            "TIME {:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            date.year, date.month, date.day, time.hour, time.minute, time.second, time.millisecond
+        ),
+        None => out.write_str("TIME NONE"),
+    }
+}
+
+/// Write the `FILTER` response
+pub fn write_filter(out: &mut impl Write, filter: &Filter) -> fmt::Result {
+    out.write_str("FILTER ")?;
+    filter.write_types(out)
+}
+
+/// Write the `PASSTHRU` response
+pub fn write_passthru(out: &mut impl Write, passthru: Passthru) -> fmt::Result {
+    write!(out, "PASSTHRU {}", passthru.as_str())
+}
+
+/// Write the `MODE` response
+pub fn write_mode(out: &mut impl Write, output: Output) -> fmt::Result {
+    write!(out, "MODE {}", output.as_str())
+}
+
+/// Write the `UNITS` response, speed unit then altitude unit
+pub fn write_units(out: &mut impl Write, speed: SpeedUnit, altitude: AltitudeUnit) -> fmt::Result {
+    write!(out, "UNITS {} {}", speed.name(), altitude.name())
+}
+
+/// Write the `SRC` response
+pub fn write_src(out: &mut impl Write, source: Source) -> fmt::Result {
+    write!(out, "SRC {}", source.as_str())
+}
+
+/// Write the `GNSS` response
+pub fn write_gnss(out: &mut impl Write, selection: &Selection) -> fmt::Result {
+    out.write_str("GNSS ")?;
+    selection.write_systems(out)
+}
+
+/// Write the acknowledgement of a `GNSS` selection, `GNSS ACK|NAK <systems>`
+pub fn write_gnss_ack(out: &mut impl Write, selection: &Selection, accepted: bool) -> fmt::Result {
+    let result = if accepted { "ACK" } else { "NAK" };
+    write!(out, "GNSS {} ", result)?;
+    selection.write_systems(out)
+}
+
+/// Write the `STATS` response with the `stack_free` bytes of [`crate::stack::free`] and the
+/// `load` of the last window in permille, followed by the lines dropped by each sink built in
+pub fn write_stats(
+    out: &mut impl Write,
+    stats: &Stats,
+    stack_free: usize,
+    load: u32,
+) -> fmt::Result {
+    write!(
+        out,
+        "STATS ORE={} FE={} NE={} DROP={} NMEA={} BAD={} CKSUM={} HOSTQ={} GPSQ={} RX={} STACK={} \
+         LOAD={} LOADMAX={}",
+        stats.overruns.get(),
+        stats.framing_errors.get(),
+        stats.noise_errors.get(),
+        stats.dropped_bytes.get(),
+        stats.sentences.get(),
+        stats.invalid_sentences.get(),
+        stats.checksum_errors.get(),
+        stats.host_tx_peak.get(),
+        stats.gps_tx_peak.get(),
+        stats.gps_rx_peak.get(),
+        stack_free,
+        Decimal(load.into(), 1),
+        Decimal(stats.cpu_load_peak.get().into(), 1)
+    )?;
+    for sink in Sink::ALL.into_iter().filter(Sink::is_built) {
+        write!(
+            out,
+            " {}_DROPPED={}",
+            sink.as_str(),
+            stats.dropped_lines[sink as usize].get()
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the `TTFF?` response, `TTFF N=0` before the first fix is timed
+pub fn write_ttff(out: &mut impl Write, stats: &Stats) -> fmt::Result {
+    write!(out, "TTFF N={}", stats.ttff.count())?;
+    match stats.ttff.summary() {
+        Some((min, avg, max)) => write!(out, " MIN={} AVG={} MAX={}", min, avg, max),
+        None => Ok(()),
+    }
+}
+
+/// Write the `TRIP?` response, distance in metres, times in seconds and speeds in m/s
+pub fn write_trip(out: &mut impl Write, trip: &Trip) -> fmt::Result {
+    write!(
+        out,
+        "TRIP DIST={} TIME={} MOVING={} AVG={} MAX={}",
+        Decimal((trip.distance_m() * 10.0) as i64, 1),
+        trip.elapsed_s(),
+        trip.moving_s(),
+        Decimal(trip.average_speed().into(), 3),
+        Decimal(trip.max_speed().into(), 3)
+    )
+}
+
+/// Write the `ZONE` response for zone `id`, `NONE` if it isn't defined
+pub fn write_zone(out: &mut impl Write, id: usize, zone: Option<&Zone>) -> fmt::Result {
+    match zone {
+        Some(zone) => write!(
+            out,
+            "ZONE {} {} {} {}",
+            id,
+            Decimal(zone.lat.into(), 7),
+            Decimal(zone.lon.into(), 7),
+            zone.radius_m
+        ),
+        None => write!(out, "ZONE {} NONE", id),
+    }
+}
+
+/// Write the `QUALITY` response, e.g. `QUALITY FIX=2D SATS=4 HDOP=5.00`
+pub fn write_quality(out: &mut impl Write, gate: &Gate) -> fmt::Result {
+    write!(
+        out,
+        "QUALITY FIX={} SATS={} HDOP={}",
+        if gate.min_fix == FixType::Fix3D {
+            "3D"
+        } else {
+            "2D"
+        },
+        gate.min_sats,
+        Decimal(gate.max_hdop.into(), 2)
+    )
+}
+
+/// Write the `SMOOTH` response, alpha in hundredths
+pub fn write_smooth(out: &mut impl Write, alpha: u8) -> fmt::Result {
+    match alpha {
+        0 => out.write_str("SMOOTH OFF"),
+        alpha => write!(out, "SMOOTH ON {}", Decimal(alpha.into(), 2)),
+    }
+}
+
+/// Write the `SPEEDALARM` response
+pub fn write_speed_alarm(out: &mut impl Write, limit_kmh: u16) -> fmt::Result {
+    match limit_kmh {
+        0 => out.write_str("SPEEDALARM OFF"),
+        kmh => write!(out, "SPEEDALARM {}", kmh),
+    }
+}
+
+/// Write the `ANCHOR` response: `ANCHOR <lat> <lon> <radius_m> <distance_m> OK|DRAG`, the
+/// distance `-` until enough fixes were averaged, or `ANCHOR OFF`
+pub fn write_anchor(out: &mut impl Write, watch: &AnchorWatch) -> fmt::Result {
+    let Some(Anchor { lat, lon, radius_m }) = watch.anchor() else {
+        return out.write_str("ANCHOR OFF");
+    };
+    write!(
+        out,
+        "ANCHOR {} {} {} ",
+        Decimal(lat.into(), 7),
+        Decimal(lon.into(), 7),
+        radius_m
+    )?;
+    match watch.distance_m() {
+        Some(distance_m) => write!(out, "{}", Decimal((distance_m * 10.0) as i64, 1))?,
+        None => out.write_str("-")?,
+    }
+    out.write_str(if watch.is_raised() { " DRAG" } else { " OK" })
+}
+
+/// Write the `SCHEDULE` response, `WINDOW` while the GPS is on waiting for a fix and `SLEEP`
+/// while it is off
+pub fn write_schedule(out: &mut impl Write, schedule: &Schedule) -> fmt::Result {
+    let Some(Windows { on_s, off_s }) = schedule.windows() else {
+        return out.write_str("SCHEDULE OFF");
+    };
+    let phase = if schedule.is_on() { "WINDOW" } else { "SLEEP" };
+    write!(out, "SCHEDULE {} {} {}", on_s, off_s, phase)
+}
+
+/// Write the `BATTERY` response, e.g. `BATTERY VBAT=3712 VDDA=3301 MIN=3300 OK`, `-` before the
+/// first sample and `LOW` once below the threshold
+pub fn write_battery(out: &mut impl Write, monitor: &Monitor) -> fmt::Result {
+    out.write_str("BATTERY VBAT=")?;
+    write_millivolts(out, battery::vbat_mv())?;
+    out.write_str(" VDDA=")?;
+    write_millivolts(out, battery::vdda_mv())?;
+    match battery::threshold_mv() {
+        Some(mv) => write!(out, " MIN={}", mv)?,
+        None => out.write_str(" MIN=OFF")?,
+    }
+    out.write_str(if monitor.is_low() { " LOW" } else { " OK" })
+}
+
+fn write_millivolts(out: &mut impl Write, mv: Option<u16>) -> fmt::Result {
+    match mv {
+        Some(mv) => write!(out, "{}", mv),
+        None => out.write_str("-"),
+    }
+}
+
+/// Write the `BOOT` response, e.g. `BOOT BANK=2 TRIAL=1` during the first boot of a trial and
+/// `BOOT BANK=2 OK` once healthy
+#[cfg(feature = "ab-boot")]
+pub fn write_boot(out: &mut impl Write) -> fmt::Result {
+    write!(out, "BOOT BANK={}", boot::bank())?;
+    match boot::trial_attempts() {
+        Some(attempts) => write!(out, " TRIAL={}", attempts),
+        None => out.write_str(" OK"),
+    }
+}
+
+/// Write the `RS485` response, e.g. `RS485 ADDR=3 DE=USART ASSERT=10 DEASSERT=10`
+#[cfg(feature = "rs485")]
+pub fn write_rs485(out: &mut impl Write) -> fmt::Result {
+    match rs485::address() {
+        Some(address) => write!(out, "RS485 ADDR={}", address)?,
+        None => out.write_str("RS485 ADDR=OFF")?,
+    }
+    let de = match PINS.host_de {
+        DriverEnable::Usart(_) => "USART",
+        DriverEnable::Gpio(_) => "GPIO",
+    };
+    write!(
+        out,
+        " DE={} ASSERT={} DEASSERT={}",
+        de,
+        rs485::ASSERT_US,
+        rs485::DEASSERT_US
+    )
+}
+
+/// Write the `POLL` response, `POLL ON KEPT=<fixes>` or `POLL OFF`
+#[cfg(feature = "rs485")]
+pub fn write_poll(out: &mut impl Write, kept: usize) -> fmt::Result {
+    match rs485::is_polled() {
+        true => write!(out, "POLL ON KEPT={}", kept),
+        false => out.write_str("POLL OFF"),
+    }
+}
+
+/// Write the `CAN` response, e.g. `CAN 1000 SKIPPED=0` or `CAN OFF SKIPPED=0`
+#[cfg(feature = "can")]
+pub fn write_can(out: &mut impl Write) -> fmt::Result {
+    match can::period_ms() {
+        0 => out.write_str("CAN OFF")?,
+        ms => write!(out, "CAN {}", ms)?,
+    }
+    write!(out, " SKIPPED={}", can::skipped())
+}
+
+/// Write the `ROUTE` response, e.g. `ROUTE GPS=HOST,USB SYNTH=HOST,USB REPORT=HOST`, with
+/// `MODULE=OK` or `MODULE=NONE` whether the Bluetooth module answered at boot
+pub fn write_route(out: &mut impl Write) -> fmt::Result {
+    out.write_str("ROUTE")?;
+    for source in router::Source::ALL {
+        write!(out, " {}=", source.as_str())?;
+        router::route(source).write(out)?;
+    }
+    #[cfg(feature = "ble")]
+    write!(
+        out,
+        " MODULE={}",
+        if ble::answered() { "OK" } else { "NONE" }
+    )?;
+    Ok(())
+}
+
+/// Write the `OVERFLOW` response for the sinks built in, e.g.
+/// `OVERFLOW HOST=NEWEST USB=OLDEST BLE=BLOCK:50`
+pub fn write_overflow(out: &mut impl Write) -> fmt::Result {
+    out.write_str("OVERFLOW")?;
+    for sink in Sink::ALL.into_iter().filter(Sink::is_built) {
+        write!(out, " {}=", sink.as_str())?;
+        router::overflow(sink).write(out)?;
+    }
+    Ok(())
+}
+
+/// Write the `SELFTEST` response, e.g.
+/// `SELFTEST UART=PASS QUEUE=PASS FLASH=PASS RTC=PASS POWER=SKIP`
+pub fn write_selftest(out: &mut impl Write, results: &Results) -> fmt::Result {
+    out.write_str("SELFTEST")?;
+    for (name, outcome) in results.items() {
+        write!(out, " {}={}", name, outcome.as_str())?;
+    }
+    Ok(())
+}
+
+/// Write the `PROFILE?` response, the average and maximum duration of each handler built in in
+/// microseconds, or `NONE` before it first ran, e.g.
+/// `PROFILE USART1=3.1/25.0 USART2=1.2/8.8 DMA1_CH5=6.0/30.2 DMA1_CH7=0.9/2.1`
+#[cfg(feature = "profiling")]
+pub fn write_profile(out: &mut impl Write) -> fmt::Result {
+    out.write_str("PROFILE")?;
+    for handler in Handler::ALL.into_iter().filter(Handler::is_built) {
+        write!(out, " {}=", handler.as_str())?;
+        match profile::summary(handler) {
+            Some((_, average, max)) => write!(
+                out,
+                "{}/{}",
+                Decimal(profile::tenths_of_us(average) as i64, 1),
+                Decimal(profile::tenths_of_us(max) as i64, 1)
+            )?,
+            None => out.write_str("NONE")?,
+        }
+    }
+    Ok(())
+}
+
+/// Write the `LORA` response, e.g. `LORA 60 FREQ=868.100 SF=9 SENT=12 FAILED=0` or
+/// `LORA OFF FREQ=868.100 SF=9 SENT=12 FAILED=0`
+#[cfg(feature = "lora")]
+pub fn write_lora(out: &mut impl Write) -> fmt::Result {
+    match beacon::interval_s() {
+        0 => out.write_str("LORA OFF")?,
+        s => write!(out, "LORA {}", s)?,
+    }
+    write!(
+        out,
+        " FREQ={} SF={} SENT={} FAILED={}",
+        Decimal(i64::from(beacon::frequency_khz()), 3),
+        beacon::sf(),
+        beacon::sent(),
+        beacon::failed()
+    )
+}
+
+/// Write an `EVENT <seq> <time>|NONE <event>` line of the `LOG EVENTS` response, e.g.
+/// `EVENT 12 2025-03-01T10:15:00Z RESET BOR`
+#[cfg(feature = "eeprom")]
+pub fn write_event(out: &mut impl Write, entry: &Entry) -> fmt::Result {
+    write!(out, "EVENT {} ", entry.seq)?;
+    match entry.time {
+        Some((date, time)) => write!(
+            out,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z ",
+            date.year, date.month, date.day, time.hour, time.minute, time.second
+        )?,
+        None => out.write_str("NONE ")?,
+    }
+    entry.event.write(out)
+}
+
+/// Write the `EVENTS <listed>` line ending the `LOG EVENTS` response
+#[cfg(feature = "eeprom")]
+pub fn write_events(out: &mut impl Write, listed: usize) -> fmt::Result {
+    write!(out, "EVENTS {}", listed)
+}
+
+/// Write the `SPI` response, e.g. `SPI ALL QUEUED=3 DROPPED=0`
+#[cfg(feature = "spi-slave")]
+pub fn write_spi(out: &mut impl Write, queued: u32, dropped: u32) -> fmt::Result {
+    write!(
+        out,
+        "SPI {} QUEUED={} DROPPED={}",
+        spi_slave::content().as_str(),
+        queued,
+        dropped
+    )
+}
+
+/// Write the `FIXTIMEOUT` response
+pub fn write_fix_timeout(out: &mut impl Write, fix_timeout_s: u16) -> fmt::Result {
+    match fix_timeout_s {
+        0 => out.write_str("FIXTIMEOUT OFF"),
+        seconds => write!(out, "FIXTIMEOUT {}", seconds),
+    }
+}
+
+/// Write the `DECIMATE` response
+pub fn write_decimation(out: &mut impl Write, factor: u16) -> fmt::Result {
+    match factor {
+        1 => out.write_str("DECIMATE OFF"),
+        factor => write!(out, "DECIMATE {}", factor),
+    }
+}
+
+/// Write the `REPORT` response
+pub fn write_report_period(out: &mut impl Write, period_s: u16) -> fmt::Result {
+    match period_s {
+        0 => out.write_str("REPORT OFF"),
+        seconds => write!(out, "REPORT {}", seconds),
+    }
+}
+
+/// Write the `BATCH` response
+pub fn write_batch(out: &mut impl Write, threshold: usize) -> fmt::Result {
+    match threshold {
+        0 => out.write_str("BATCH OFF"),
+        bytes => write!(out, "BATCH {}", bytes),
+    }
+}
+
+/// Write the report of a UBX acknowledgement received from the GPS
+pub fn write_ack(out: &mut impl Write, ack: &Ack) -> fmt::Result {
+    let (result, class, id) = match *ack {
+        Ack::Ack { class, id } => ("ACK", class, id),
+        Ack::Nak { class, id } => ("NAK", class, id),
+    };
+    write!(out, "UBX {} {:02X} {:02X}", result, class, id)
+}
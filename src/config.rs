@@ -0,0 +1,333 @@
+//! Settings persisted in the last two flash pages, loaded at boot and written by the `SAVE`
+//! command. With the `eeprom` feature they are kept in an external EEPROM instead, see
+//! [`Storage`].
+//!
+//! Each setting is a key-value record of [`kv`], appended only when `SAVE` changes it, so
+//! saving a new baud rate writes 8 bytes and a flash page lasts hundreds of saves before it is
+//! compacted into the other one. A value cut short by a reset fails its CRC and the one before
+//! it stays in effect, a stored value this firmware doesn't support loads the default.
+//!
+//! Earlier firmware kept a whole [`RECORD_LEN`] byte record per save in slots from the start of
+//! the second page, or of the EEPROM. The latest is loaded until the first `SAVE`, or the first
+//! position saved for a hot start, see `crate::hotstart`, which saves it as records.
+
+use crate::baud;
+use crate::chip::pac::FLASH;
+use crate::filter::Filter;
+use crate::flash::{self, Flash};
+use crate::gps_ctrl;
+#[cfg(feature = "hot-start")]
+use crate::hotstart::Seed;
+use crate::kv::{self, MAX_VALUE};
+use crate::rate::{self, Profile};
+use crate::storage::{self, Error, Partition, Storage};
+use core::ops::Range;
+use heapless::Vec;
+
+/// First of the two flash pages kept out of the firmware image by `memory/*.x`
+pub const FIRST_PAGE: usize = flash::PAGES - 2;
+pub const PAGES: usize = 2;
+
+/// Flash pages reserved for the track log before the configuration pages, built in or not, see
+/// `flashlog`
+pub const LOG_PAGES: usize = 32;
+
+/// Circular zones stored for the geofence
+pub const MAX_ZONES: usize = 4;
+
+/// Resolution of the fix timeout, which is stored in a byte
+pub const FIX_TIMEOUT_UNIT_S: u16 = 10;
+
+/// Longest fix timeout that can be stored
+pub const MAX_FIX_TIMEOUT_S: u16 = 255 * FIX_TIMEOUT_UNIT_S;
+
+/// Keys of the settings
+const KEY_HOST_BAUD: u8 = 0;
+const KEY_GPS_BAUD: u8 = 1;
+const KEY_FILTER: u8 = 2;
+const KEY_RATE: u8 = 3;
+const KEY_GPS_POWER: u8 = 4;
+const KEY_ZONES: u8 = 5;
+const KEY_FIX_TIMEOUT: u8 = 6;
+/// Position and time of the last fix, see [`crate::hotstart`]
+#[cfg(feature = "hot-start")]
+const KEY_HOT_START: u8 = 7;
+
+/// Bytes of a stored zone: latitude, longitude and radius
+const ZONE_LEN: usize = 12;
+
+const _: () = assert!(MAX_ZONES * ZONE_LEN <= MAX_VALUE);
+
+/// Bytes per record of earlier firmware
+pub const RECORD_LEN: usize = 72;
+
+/// Identifies records of earlier firmware, and their layout
+const RECORD_MAGIC: u16 = 0xC0F1;
+const RECORD_VERSION: u8 = 2;
+
+/// Version without the fix timeout, which loads with the default
+const RECORD_VERSION_1: u8 = 1;
+
+/// The CRC of a record, which covers the bytes before it, and a reserved word
+const RECORD_CRC: Range<usize> = 64..68;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zone {
+    /// Center in 1e-7 degrees
+    pub lat: i32,
+    pub lon: i32,
+    pub radius_m: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub host_baud: u32,
+    pub gps_baud: u32,
+    pub filter: Filter,
+    pub rate: &'static Profile,
+    /// GPS turned on at boot
+    pub gps_power: bool,
+    pub zones: Vec<Zone, MAX_ZONES>,
+    /// Time without a fix before the GPS is power cycled, 0 disables it, see [`gps_ctrl`]
+    pub fix_timeout_s: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host_baud: baud::HOST_DEFAULT,
+            gps_baud: baud::GPS_DEFAULT,
+            filter: Filter::DEFAULT,
+            rate: rate::DEFAULT,
+            gps_power: false,
+            zones: Vec::new(),
+            fix_timeout_s: gps_ctrl::DEFAULT_FIX_TIMEOUT_S,
+        }
+    }
+}
+
+impl Config {
+    /// Pass the value of every setting to `f`, with its key
+    fn encode(&self, mut f: impl FnMut(u8, &[u8]) -> Result<(), Error>) -> Result<(), Error> {
+        f(KEY_HOST_BAUD, &self.host_baud.to_le_bytes())?;
+        f(KEY_GPS_BAUD, &self.gps_baud.to_le_bytes())?;
+        f(KEY_FILTER, &[self.filter.mask()])?;
+        f(KEY_RATE, &[self.rate.hz])?;
+        f(KEY_GPS_POWER, &[u8::from(self.gps_power)])?;
+        f(
+            KEY_FIX_TIMEOUT,
+            &[(self.fix_timeout_s / FIX_TIMEOUT_UNIT_S) as u8],
+        )?;
+        let mut zones = [0; MAX_ZONES * ZONE_LEN];
+        for (zone, bytes) in self.zones.iter().zip(zones.chunks_exact_mut(ZONE_LEN)) {
+            bytes[0..4].copy_from_slice(&zone.lat.to_le_bytes());
+            bytes[4..8].copy_from_slice(&zone.lon.to_le_bytes());
+            bytes[8..12].copy_from_slice(&zone.radius_m.to_le_bytes());
+        }
+        f(KEY_ZONES, &zones[..self.zones.len() * ZONE_LEN])
+    }
+
+    /// Apply the stored `value` of `key`, unless it is damaged or this firmware doesn't support
+    /// it
+    fn decode(&mut self, key: u8, value: &[u8]) {
+        let u32_at = |offset: usize| {
+            let bytes = value.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let byte = match value {
+            [byte] => Some(*byte),
+            _ => None,
+        };
+        match key {
+            KEY_HOST_BAUD | KEY_GPS_BAUD => {
+                let Some(baud) = u32_at(0).filter(|baud| baud::SUPPORTED.contains(baud)) else {
+                    return;
+                };
+                match key {
+                    KEY_HOST_BAUD => self.host_baud = baud,
+                    _ => self.gps_baud = baud,
+                }
+            }
+            KEY_FILTER => {
+                if let Some(mask) = byte {
+                    self.filter = Filter::from_mask(mask);
+                }
+            }
+            KEY_RATE => {
+                if let Some(profile) = byte.and_then(rate::profile) {
+                    self.rate = profile;
+                }
+            }
+            KEY_GPS_POWER => {
+                if let Some(power) = byte {
+                    self.gps_power = power != 0;
+                }
+            }
+            KEY_FIX_TIMEOUT => {
+                if let Some(units) = byte {
+                    self.fix_timeout_s = u16::from(units) * FIX_TIMEOUT_UNIT_S;
+                }
+            }
+            KEY_ZONES
+                if value.len().is_multiple_of(ZONE_LEN) && value.len() <= MAX_ZONES * ZONE_LEN =>
+            {
+                let zones = (0..value.len())
+                    .step_by(ZONE_LEN)
+                    .map(|offset| {
+                        Some(Zone {
+                            lat: u32_at(offset)? as i32,
+                            lon: u32_at(offset + 4)? as i32,
+                            radius_m: u32_at(offset + 8)?,
+                        })
+                    })
+                    .collect::<Option<_>>();
+                if let Some(zones) = zones {
+                    self.zones = zones;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Decode a whole configuration record of earlier firmware, `None` if it is damaged or
+    /// holds values this firmware doesn't support
+    fn decode_record(record: &[u8]) -> Option<Self> {
+        let u32_at = |offset: usize| {
+            let bytes = record.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        if record.len() != RECORD_LEN
+            || record[0..2] != RECORD_MAGIC.to_le_bytes()
+            || (record[2] != RECORD_VERSION && record[2] != RECORD_VERSION_1)
+            || !storage::is_sealed(record, RECORD_CRC)
+        {
+            return None;
+        }
+        let host_baud = u32_at(4)?;
+        let gps_baud = u32_at(8)?;
+        if !baud::SUPPORTED.contains(&host_baud) || !baud::SUPPORTED.contains(&gps_baud) {
+            return None;
+        }
+        let count = usize::from(record[14]);
+        if count > MAX_ZONES {
+            return None;
+        }
+        let zones = (0..count)
+            .map(|i| {
+                let offset = 16 + i * 12;
+                Some(Zone {
+                    lat: u32_at(offset)? as i32,
+                    lon: u32_at(offset + 4)? as i32,
+                    radius_m: u32_at(offset + 8)?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let fix_timeout_s = if record[2] == RECORD_VERSION_1 {
+            gps_ctrl::DEFAULT_FIX_TIMEOUT_S
+        } else {
+            u16::from(record[15]) * FIX_TIMEOUT_UNIT_S
+        };
+        Some(Self {
+            host_baud,
+            gps_baud,
+            filter: Filter::from_mask(record[12]),
+            rate: rate::profile(record[13])?,
+            gps_power: record[3] != 0,
+            zones,
+            fix_timeout_s,
+        })
+    }
+}
+
+/// The configuration pages of internal flash
+pub fn flash_pages(flash: &FLASH) -> Partition<Flash<'_>> {
+    Partition::new(Flash(flash), FIRST_PAGE, PAGES)
+}
+
+/// Settings in the key-value records of a [`Storage`]
+pub struct Store {
+    values: kv::Store,
+}
+
+impl Store {
+    pub fn new(storage: &mut impl Storage) -> Self {
+        Self {
+            values: kv::Store::new(storage),
+        }
+    }
+
+    /// Saved configuration, settings that were never saved have their default
+    pub fn load(&self, storage: &mut impl Storage) -> Option<Config> {
+        if self.values.is_blank() {
+            return load_record(storage);
+        }
+        let mut config = Config::default();
+        let mut buffer = [0; MAX_VALUE];
+        for key in [
+            KEY_HOST_BAUD,
+            KEY_GPS_BAUD,
+            KEY_FILTER,
+            KEY_RATE,
+            KEY_GPS_POWER,
+            KEY_ZONES,
+            KEY_FIX_TIMEOUT,
+        ] {
+            if let Ok(Some(value)) = self.values.get(storage, key, &mut buffer) {
+                config.decode(key, value);
+            }
+        }
+        Some(config)
+    }
+
+    /// The storage needs [`Store::prepare`]
+    pub fn needs_erase(&self) -> bool {
+        self.values.needs_erase()
+    }
+
+    /// Erase a block of the storage ahead of the next compaction, see [`kv::Store::prepare`]
+    pub fn prepare(&mut self, storage: &mut impl Storage) -> Result<(), Error> {
+        self.values.prepare(storage)
+    }
+
+    /// Append the settings of `config` that changed since the last save
+    pub fn save(&mut self, storage: &mut impl Storage, config: &Config) -> Result<(), Error> {
+        config.encode(|key, value| self.values.set(storage, key, value))
+    }
+
+    /// Saved position and time of the last fix
+    #[cfg(feature = "hot-start")]
+    pub fn hot_start(&self, storage: &mut impl Storage) -> Option<Seed> {
+        let mut buffer = [0; MAX_VALUE];
+        let value = self
+            .values
+            .get(storage, KEY_HOT_START, &mut buffer)
+            .ok()
+            .flatten()?;
+        Seed::decode(value)
+    }
+
+    /// Save the position and time of the last fix. A configuration of earlier firmware is
+    /// saved first, as it is no longer loaded once there are values.
+    #[cfg(feature = "hot-start")]
+    pub fn save_hot_start(&mut self, storage: &mut impl Storage, seed: &Seed) -> Result<(), Error> {
+        if self.values.is_blank() {
+            if let Some(config) = load_record(storage) {
+                self.save(storage, &config)?;
+            }
+        }
+        self.values.set(storage, KEY_HOT_START, &seed.encode())
+    }
+}
+
+/// Latest record of earlier firmware, in the slots from the start of the storage or of its
+/// second half
+fn load_record(storage: &mut impl Storage) -> Option<Config> {
+    let capacity = storage.capacity();
+    [capacity / 2, 0].into_iter().find_map(|start| {
+        (0..(capacity - start) / RECORD_LEN).rev().find_map(|slot| {
+            let mut record = [0; RECORD_LEN];
+            storage.read(start + slot * RECORD_LEN, &mut record).ok()?;
+            Config::decode_record(&record)
+        })
+    })
+}
@@ -0,0 +1,80 @@
+//! Decimation of the sentences forwarded to the host, set by `DECIMATE`.
+//!
+//! Of the sentences of each type passing the filter only every `n`th is forwarded, counted per
+//! type, so a GPS running at 10Hz for the smoothing and the alarms feeds a telemetry link at 1Hz
+//! with `DECIMATE 10`. The parts of a GSV group follow its first part, so the satellites in view
+//! are sent as whole groups. Sentences synthesized from UBX-NAV-PVT are decimated the same way,
+//! binary fix records, dumps and the SPI stream aren't. The factor isn't saved.
+
+use crate::nmea::SentenceType;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Largest factor, an hour of sentences at 1Hz
+pub const MAX_FACTOR: u16 = 3600;
+
+/// Every sentence is forwarded
+const OFF: u16 = 1;
+
+static FACTOR: AtomicU16 = AtomicU16::new(OFF);
+
+/// One of this many sentences of a type is forwarded
+pub fn factor() -> u16 {
+    FACTOR.load(Ordering::Relaxed)
+}
+
+/// Change the factor, 1 forwards every sentence. The next sentence of each type is forwarded.
+pub fn set_factor(factor: u16) {
+    FACTOR.store(factor.clamp(OFF, MAX_FACTOR), Ordering::Relaxed);
+}
+
+/// Sentences of each type since the last forwarded one
+pub struct Decimator {
+    counts: [u16; SentenceType::ALL.len()],
+    /// The GSV group being received is forwarded
+    gsv_group: bool,
+    /// Factor the counts were taken with
+    factor: u16,
+}
+
+impl Decimator {
+    pub const fn new() -> Self {
+        Self {
+            counts: [0; SentenceType::ALL.len()],
+            gsv_group: true,
+            factor: OFF,
+        }
+    }
+
+    /// Count `sentence`, of `sentence_type`, returning whether it is forwarded
+    pub fn passes(&mut self, sentence_type: SentenceType, sentence: &[u8]) -> bool {
+        let factor = factor();
+        if factor != self.factor {
+            self.factor = factor;
+            self.counts = [0; SentenceType::ALL.len()];
+        }
+        if sentence_type == SentenceType::Gsv && !is_first_part(sentence) {
+            return self.gsv_group;
+        }
+        let count = &mut self.counts[sentence_type as usize];
+        let passes = *count == 0;
+        *count = (*count + 1) % factor;
+        if sentence_type == SentenceType::Gsv {
+            self.gsv_group = passes;
+        }
+        passes
+    }
+}
+
+impl Default for Decimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The message number of a GSV sentence is 1, or missing
+fn is_first_part(sentence: &[u8]) -> bool {
+    sentence
+        .split(|&byte| byte == b',')
+        .nth(2)
+        .is_none_or(|number| number == b"1")
+}
@@ -0,0 +1,20 @@
+//! Defaults of a deployment, set at build time from environment variables by `build.rs`:
+//!
+//! - `BRIDGE_BAUD_HOST`: host baud without a saved configuration, [`HOST_BAUD`]
+//! - `BRIDGE_BAUD_GPS`: baud of the GPS at power on, [`GPS_BAUD`]
+//! - `BRIDGE_BUFFER_SIZE`: size of each host TX buffer, [`TX_BUFFER_SIZE`]
+//! - `BRIDGE_RS485_ADDRESS`: bus address of the `rs485` feature, 0 for none,
+//!   [`RS485_ADDRESS`]
+//! - `BRIDGE_RS485_ASSERT_US`, `BRIDGE_RS485_DEASSERT_US`: RS-485 driver enable lead and hold
+//!   times, [`RS485_ASSERT_US`], [`RS485_DEASSERT_US`]
+//! - `BRIDGE_I2C_ADDRESS`: 7-bit address of the `i2c-slave` feature, [`I2C_ADDRESS`]
+//! - `BRIDGE_CAN_BITRATE`, `BRIDGE_CAN_ADDRESS`: bit rate and source address of the `can`
+//!   feature, [`CAN_BITRATE`], [`CAN_ADDRESS`]
+//! - `BRIDGE_LORA_FREQUENCY_KHZ`: carrier frequency of the `lora` feature at boot,
+//!   [`LORA_FREQUENCY_KHZ`]
+//! - `BRIDGE_DEFAULT_FILTERS`: sentence types forwarded without a saved configuration, as in
+//!   `FILTER`, e.g. `GGA,RMC`, [`DEFAULT_FILTERS`]
+//!
+//! Unset variables keep the values documented in the README.
+
+include!(concat!(env!("OUT_DIR"), "/config_gen.rs"));
@@ -0,0 +1,75 @@
+//! Firmware update over USART2 through the STM32 system bootloader, entered by `DFU`.
+//!
+//! Jumping to the bootloader from the running firmware would hand it clocks, DMA, pending
+//! interrupts and a running IWDG, which can't be stopped. Instead `DFU` leaves a request in an RTC
+//! backup register and resets the MCU once its `OK` has been sent. [`take_request`] runs first in
+//! `init`, before anything is configured or the IWDG started, clears the request and jumps to the
+//! bootloader with the MCU in its reset state, so the bootloader is entered once and the next
+//! reset runs the firmware again.
+//!
+//! The bootloader speaks the AN3155 USART protocol on PA2/PA3, the host pins of the NUCLEO-L432KC
+//! pinmap, at up to 115200 baud with even parity, e.g.
+//! `stm32flash -b 115200 -w listen-gps.bin -v -g 0x08000000 /dev/ttyUSB0`. It also listens on
+//! USART1, the GPS power pin floats after reset, so a GPS switched on by a floating pin has to be
+//! quiet for the host to be heard first. A board wired to other host pins needs SWD.
+
+use crate::chip::pac::{PWR, RCC, RTC, SYSCFG};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// RTC backup register carrying the request through the reset, not used otherwise
+const BACKUP_REGISTER: usize = 0;
+
+/// Value of [`BACKUP_REGISTER`] asking for the bootloader
+const MAGIC: u32 = 0xB007_10AD;
+
+/// System memory, starting with the bootloader's vector table
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+
+/// SYSCFG_MEMRMP MEM_MODE value mapping system memory at address 0
+const MEM_MODE_SYSTEM: u8 = 0b001;
+
+/// A request is waiting for the response to be sent
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Ask for the bootloader, entered by [`reset`] once idle has sent the response. The backup
+/// domain is writable since [`crate::rtc::Rtc::new`].
+pub fn request() {
+    // SAFETY: the backup registers are not used by the RTC driver
+    let rtc = unsafe { &*RTC::ptr() };
+    // SAFETY: any value is valid
+    rtc.bkpr[BACKUP_REGISTER].write(|w| unsafe { w.bits(MAGIC) });
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+pub fn is_pending() -> bool {
+    PENDING.load(Ordering::Relaxed)
+}
+
+/// Reset into the bootloader
+pub fn reset() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Enter the bootloader if it was requested before the reset, clearing the request. Must be
+/// called first in `init` once the PWR and RTC APB clocks are enabled.
+pub fn take_request(rcc: &RCC, pwr: &PWR, rtc: &RTC, syscfg: &SYSCFG) {
+    if rtc.bkpr[BACKUP_REGISTER].read().bits() != MAGIC {
+        return;
+    }
+    pwr.cr1.modify(|_, w| w.dbp().set_bit());
+    // SAFETY: any value is valid
+    rtc.bkpr[BACKUP_REGISTER].write(|w| unsafe { w.bits(0) });
+    pwr.cr1.modify(|_, w| w.dbp().clear_bit());
+
+    rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+    // SAFETY: MEM_MODE_SYSTEM is a valid mapping
+    syscfg
+        .memrmp
+        .modify(|_, w| unsafe { w.mem_mode().bits(MEM_MODE_SYSTEM) });
+    // SAFETY: no interrupt is enabled in the NVIC or SysTick yet, the bootloader expects
+    // PRIMASK clear as after reset. System memory holds a valid vector table.
+    unsafe {
+        cortex_m::interrupt::enable();
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
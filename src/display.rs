@@ -0,0 +1,201 @@
+//! SSD1306 128x64 OLED status display on I2C1, enabled by the `display` feature.
+//!
+//! Shows GPS power, fix type, satellites, HDOP, position, speed and UTC time, alternating every
+//! few seconds with the SNR of the satellites in view once there are any. The RTC wakeup
+//! interrupt requests a refresh every second with [`request_refresh`], which idle carries out:
+//! drawing and the 400kHz I2C transfers block, so interrupts preempt them and the UART paths
+//! are never held up.
+//!
+//! The display is on I2C1, see [`crate::i2c`] for the pins.
+
+use crate::chip::pac::{GPIOB, I2C1, RCC};
+use crate::cmd::Decimal;
+use crate::fix::GpsFix;
+use crate::i2c::I2c;
+use crate::nmea::FixType;
+use crate::sky::{self, Satellite};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_graphics::mono_font::ascii::{FONT_4X6, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+use heapless::String;
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+
+/// Height of a text line in pixels
+const LINE_HEIGHT: i32 = 10;
+
+/// Refreshes, one a second, each page is shown for
+const PAGE_REFRESHES: u8 = 5;
+
+/// Width of a satellite's bar with the gap to the next, in pixels
+const BAR_SLOT: i32 = 10;
+
+/// Satellites the sky page has room for
+const MAX_BARS: usize = 12;
+
+/// Top and bottom of the bars, a PRN label of 6 pixels goes below
+const BAR_TOP: i32 = 12;
+const BAR_BOTTOM: i32 = 52;
+
+/// SNR in dBHz drawn as a full height bar
+const FULL_SNR: u8 = 50;
+
+static REFRESH: AtomicBool = AtomicBool::new(false);
+
+/// Have idle redraw the display
+pub fn request_refresh() {
+    REFRESH.store(true, Ordering::Relaxed);
+}
+
+/// What the display shows
+pub struct Status {
+    pub gps_power: bool,
+    pub fix: GpsFix,
+    pub satellites: sky::Table,
+}
+
+type Driver =
+    Ssd1306<I2CInterface<I2c>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
+
+pub struct Display {
+    driver: Driver,
+    /// The controller has been configured, repeated after every error
+    initialized: bool,
+    /// Counts refreshes to alternate the pages
+    refreshes: u8,
+}
+
+impl Display {
+    /// Configure I2C1, see [`I2c::new`]. The display itself is set up by the first refresh.
+    pub fn new(rcc: &RCC, gpiob: &GPIOB, i2c1: I2C1) -> Self {
+        let interface = I2CDisplayInterface::new(I2c::new(rcc, gpiob, i2c1));
+        let driver = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+            .into_buffered_graphics_mode();
+        Self {
+            driver,
+            initialized: false,
+            refreshes: 0,
+        }
+    }
+
+    /// Redraw if a refresh was requested, `status` is only called then
+    pub fn refresh(&mut self, status: impl FnOnce() -> Status) {
+        if REFRESH.swap(false, Ordering::Relaxed) && self.draw(&status()).is_err() {
+            // Try again from scratch next time, e.g. once a display is connected
+            self.initialized = false;
+        }
+    }
+
+    fn draw(&mut self, status: &Status) -> Result<(), display_interface::DisplayError> {
+        if !self.initialized {
+            self.driver.init()?;
+            self.initialized = true;
+        }
+        self.refreshes = (self.refreshes + 1) % (2 * PAGE_REFRESHES);
+        self.driver.clear_buffer();
+        if self.refreshes >= PAGE_REFRESHES && !status.satellites.is_empty() {
+            self.draw_sky(&status.satellites);
+        } else {
+            self.draw_status(status);
+        }
+        self.driver.flush()
+    }
+
+    fn draw_status(&mut self, status: &Status) {
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let mut y = 0;
+        for line in lines(status).iter() {
+            // Drawing into the buffer can't fail
+            let _ = Text::with_baseline(line, Point::new(0, y), style, Baseline::Top)
+                .draw(&mut self.driver);
+            y += LINE_HEIGHT;
+        }
+    }
+
+    /// A bar per satellite as high as its SNR, filled if used in the fix, over the last two
+    /// digits of its PRN
+    fn draw_sky(&mut self, satellites: &[Satellite]) {
+        let used = satellites.iter().filter(|satellite| satellite.used).count();
+        let mut title = String::<21>::new();
+        let _ = write!(title, "SATS {} USED {}", satellites.len(), used);
+        let _ = Text::with_baseline(
+            &title,
+            Point::zero(),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+            Baseline::Top,
+        )
+        .draw(&mut self.driver);
+
+        let label_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+        for (i, satellite) in satellites.iter().take(MAX_BARS).enumerate() {
+            let x = i as i32 * BAR_SLOT;
+            let snr = i32::from(satellite.snr.unwrap_or(0).min(FULL_SNR));
+            let height = snr * (BAR_BOTTOM - BAR_TOP) / i32::from(FULL_SNR);
+            if height > 0 {
+                let style = if satellite.used {
+                    PrimitiveStyle::with_fill(BinaryColor::On)
+                } else {
+                    PrimitiveStyle::with_stroke(BinaryColor::On, 1)
+                };
+                let _ = Rectangle::new(
+                    Point::new(x + 1, BAR_BOTTOM - height),
+                    Size::new(BAR_SLOT as u32 - 4, height as u32),
+                )
+                .into_styled(style)
+                .draw(&mut self.driver);
+            }
+            let mut label = String::<2>::new();
+            let _ = write!(label, "{:02}", satellite.prn % 100);
+            let _ = Text::with_baseline(
+                &label,
+                Point::new(x, BAR_BOTTOM + 2),
+                label_style,
+                Baseline::Top,
+            )
+            .draw(&mut self.driver);
+        }
+    }
+}
+
+/// Six lines of 21 characters fill the display
+fn lines(status: &Status) -> [String<21>; 6] {
+    let mut lines: [String<21>; 6] = Default::default();
+    let fix = &status.fix;
+    let fix_type = match fix.fix_type {
+        FixType::None => "NONE",
+        FixType::Fix2D => "2D",
+        FixType::Fix3D => "3D",
+    };
+    // Lines are cut short rather than overflow
+    let _ = write!(
+        lines[0],
+        "GPS {} FIX {}",
+        if status.gps_power { "ON" } else { "OFF" },
+        fix_type
+    );
+    let _ = write!(
+        lines[1],
+        "SATS {} HDOP {}",
+        fix.sats,
+        Decimal(fix.hdop.into(), 2)
+    );
+    if fix.is_valid() {
+        let _ = write!(lines[2], "LAT {}", Decimal(fix.lat.into(), 7));
+        let _ = write!(lines[3], "LON {}", Decimal(fix.lon.into(), 7));
+        let _ = write!(lines[4], "SPD {} M/S", Decimal(fix.speed.into(), 3));
+    }
+    if let Some(time) = fix.timestamp {
+        let _ = write!(
+            lines[5],
+            "{:02}:{:02}:{:02} UTC",
+            time.hour, time.minute, time.second
+        );
+    }
+    lines
+}
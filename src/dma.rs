@@ -0,0 +1,507 @@
+//! DMA1 channel 5 receives USART1 (GPS) into a circular buffer.
+//! The USART1 IDLE interrupt and the DMA half/full transfer interrupts flush the bytes
+//! received since the last flush, so the CPU handles NMEA chunks instead of single bytes.
+//!
+//! DMA1 channel 7 transmits USART2 (host) from a double buffer. Whole sentences are appended
+//! to one buffer while DMA sends the other; the transfer complete interrupt swaps them. With a
+//! batch threshold set, an idle channel only starts once that many bytes are queued or the
+//! queue is flushed, so a burst of sentences goes out in fewer transfers and interrupts.
+//!
+//! Lines routed to the host are kept in the filling buffer in order of their [`Class`], so a
+//! report or a response goes out with the next transfer ahead of the sentences queued before
+//! it. A line that doesn't fit the filling buffer is handled by the `OVERFLOW` policy of the
+//! host, see [`DoubleBufferTx::write_line`].
+//!
+//! With the `rs485` feature a transfer only starts while the host has selected the bridge, or
+//! given it a turn in polled mode, see [`crate::rs485`].
+
+use crate::chip::pac::{DMA1, USART1, USART2};
+use crate::defaults;
+use crate::router::{self, Class, Held, Overflow, Sink};
+#[cfg(feature = "rs485")]
+use crate::rs485;
+use core::sync::atomic::{compiler_fence, Ordering};
+use heapless::Vec;
+
+/// Capacity of the USART1 RX circular buffer. The window in use must hold more than the bytes
+/// that can arrive between two flushes; half transfer and transfer complete interrupts flush
+/// every half window.
+pub const RX_BUFFER_SIZE: usize = 1024;
+
+/// USART1 RX DMA channel writing into a circular buffer.
+///
+/// Only channel 5 registers and flags are touched, so the rest of DMA1 remains free for
+/// other channels.
+pub struct CircularRx {
+    buffer: &'static mut [u8; RX_BUFFER_SIZE],
+    /// Length of the part of the buffer DMA wraps around in
+    window: usize,
+    /// Index of the next byte not yet handed out by [`CircularRx::flush`]
+    read_index: usize,
+}
+
+impl CircularRx {
+    /// Configure DMA1 channel 5 to continuously copy USART1 RDR into the first `window` bytes
+    /// of `buffer` and enable half transfer and transfer complete interrupts.
+    /// DMA1 clock must be enabled.
+    pub fn new(
+        dma1: &DMA1,
+        usart1: &USART1,
+        buffer: &'static mut [u8; RX_BUFFER_SIZE],
+        window: usize,
+    ) -> Self {
+        let window = window.min(RX_BUFFER_SIZE);
+        // Channel 5 request 2 is USART1_RX, see reference manual table 41
+        dma1.cselr.modify(|_, w| w.c5s().map2());
+        dma1.cpar5
+            .write(|w| unsafe { w.pa().bits(usart1.rdr.as_ptr() as u32) });
+        dma1.cmar5
+            .write(|w| unsafe { w.ma().bits(buffer.as_mut_ptr() as u32) });
+        dma1.cndtr5.write(|w| w.ndt().bits(window as u16));
+        dma1.ccr5.write(|w| {
+            w.dir()
+                .from_peripheral()
+                .circ()
+                .enabled()
+                .minc()
+                .enabled()
+                .psize()
+                .bits8()
+                .msize()
+                .bits8()
+                .htie()
+                .enabled()
+                .tcie()
+                .enabled()
+                .en()
+                .enabled()
+        });
+
+        Self {
+            buffer,
+            window,
+            read_index: 0,
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Restart reception with a different window, bytes not yet flushed are lost
+    pub fn set_window(&mut self, window: usize) {
+        // SAFETY: channel 5 is owned by this struct
+        let dma1 = unsafe { &*DMA1::ptr() };
+        self.window = window.min(RX_BUFFER_SIZE);
+        // CNDTR can only be written while the channel is disabled
+        dma1.ccr5.modify(|_, w| w.en().disabled());
+        dma1.cndtr5.write(|w| w.ndt().bits(self.window as u16));
+        dma1.ccr5.modify(|_, w| w.en().enabled());
+        self.read_index = 0;
+    }
+
+    /// Clear the channel 5 half/full transfer flags from its interrupt handler
+    pub fn clear_interrupt_flags() {
+        // SAFETY: IFCR is write-1-to-clear so only channel 5 flags are affected
+        let dma1 = unsafe { &*DMA1::ptr() };
+        dma1.ifcr.write(|w| w.chtif5().set_bit().ctcif5().set_bit());
+    }
+
+    /// Pass every byte received since the last flush to `f`, oldest first.
+    pub fn flush(&mut self, mut f: impl FnMut(u8)) {
+        // SAFETY: CNDTR5 is only read here; no other code owns channel 5
+        let dma1 = unsafe { &*DMA1::ptr() };
+
+        // CNDTR counts down from the buffer size and reloads in circular mode
+        let write_index = self.window - dma1.cndtr5.read().ndt().bits() as usize;
+        // Make sure bytes written by DMA are not read before CNDTR
+        compiler_fence(Ordering::Acquire);
+
+        while self.read_index != write_index % self.window {
+            // SAFETY: DMA never writes behind write_index until it wraps around
+            let byte = unsafe { core::ptr::read_volatile(&self.buffer[self.read_index]) };
+            f(byte);
+            self.read_index = (self.read_index + 1) % self.window;
+        }
+    }
+}
+
+/// Capacity of each of the two USART2 TX buffers, `BRIDGE_BUFFER_SIZE` at build time.
+pub const TX_BUFFER_SIZE: usize = defaults::TX_BUFFER_SIZE;
+
+/// Most lines queued in the filling buffer, whatever room is left in it
+const MAX_LINES: usize = TX_BUFFER_SIZE / 8;
+
+/// Data doesn't fit in the TX buffer being filled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferFull;
+
+/// USART2 TX DMA channel sending from two alternating buffers.
+///
+/// Only channel 7 registers and flags are touched, so the rest of DMA1 remains free for
+/// other channels.
+pub struct DoubleBufferTx {
+    buffers: &'static mut [[u8; TX_BUFFER_SIZE]; 2],
+    /// Index of the buffer being filled by the CPU, the other one may be read by DMA
+    filling: usize,
+    /// Lines queued in the filling buffer
+    lines: Lines,
+    /// Line waiting for a transfer to complete with `OVERFLOW BLOCK`
+    held: Held,
+    /// DMA is transferring the other buffer
+    busy: bool,
+    /// Bytes queued before an idle channel starts without a flush
+    threshold: usize,
+}
+
+impl DoubleBufferTx {
+    /// Configure DMA1 channel 7 to copy memory into USART2 TDR and enable its transfer
+    /// complete interrupt. The channel is started on demand by [`DoubleBufferTx::write`].
+    /// DMA1 clock must be enabled.
+    pub fn new(
+        dma1: &DMA1,
+        usart2: &USART2,
+        buffers: &'static mut [[u8; TX_BUFFER_SIZE]; 2],
+    ) -> Self {
+        // Channel 7 request 2 is USART2_TX, see reference manual table 41
+        dma1.cselr.modify(|_, w| w.c7s().map2());
+        dma1.cpar7
+            .write(|w| unsafe { w.pa().bits(usart2.tdr.as_ptr() as u32) });
+        dma1.ccr7.write(|w| {
+            w.dir()
+                .from_memory()
+                .minc()
+                .enabled()
+                .psize()
+                .bits8()
+                .msize()
+                .bits8()
+                .tcie()
+                .enabled()
+        });
+
+        Self {
+            buffers,
+            filling: 0,
+            lines: Lines::new(),
+            held: Held::new(),
+            busy: false,
+            threshold: 0,
+        }
+    }
+
+    /// Queue `data` for transmission as a whole, as [`Class::Bulk`]. Nothing is queued and
+    /// `Err` is returned if it doesn't fit in the filling buffer, which keeps sentences intact.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), BufferFull> {
+        self.insert(Class::Bulk, data)
+    }
+
+    /// Queue a routed line of `class` as a whole, dropping the oldest queued lines of lower
+    /// classes to make room for it, then by the host's [`Overflow`] policy, counting what is
+    /// dropped. Lines in the transfer in progress can't be taken back. A blocked line is held
+    /// until a transfer completes, the lines of its class and lower ones routed meanwhile are
+    /// dropped, see [`Held`].
+    pub fn write_line(&mut self, class: Class, line: &[u8]) {
+        if line.len() > TX_BUFFER_SIZE || self.held.blocks(class) {
+            router::record_drop(Sink::Host, 1, line.len());
+            return;
+        }
+        if !self.lines.fits(line.len()) {
+            // An idle channel held back by the batch threshold makes room right away
+            self.flush();
+        }
+        let buffer = &mut self.buffers[self.filling];
+        for lower in class.lower() {
+            self.lines.drop_oldest(buffer, lower, line.len());
+        }
+        if !self.lines.fits(line.len()) {
+            match router::overflow(Sink::Host) {
+                Overflow::DropNewest => {}
+                Overflow::DropOldest => self.lines.drop_oldest(buffer, class, line.len()),
+                Overflow::Block(ms) => {
+                    if self.held.hold(class, line, ms) {
+                        return;
+                    }
+                }
+            }
+        }
+        if self.insert(class, line).is_err() {
+            router::record_drop(Sink::Host, 1, line.len());
+        }
+    }
+
+    /// Start sending what is queued if the channel is idle, regardless of the threshold
+    pub fn flush(&mut self) {
+        if !self.busy {
+            self.start();
+        }
+    }
+
+    /// Batch threshold in bytes, 0 starts on every write
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Set the batch threshold, up to the buffer size, 0 starts on every write. What is queued
+    /// is flushed.
+    pub fn set_threshold(&mut self, bytes: usize) {
+        self.threshold = bytes.min(TX_BUFFER_SIZE);
+        self.flush();
+    }
+
+    /// Bytes waiting for the next transfer
+    pub fn queued(&self) -> usize {
+        self.lines.len
+    }
+
+    /// Nothing queued or being transferred by DMA. The last byte may still be shifted out.
+    pub fn is_idle(&self) -> bool {
+        !self.busy && self.lines.len == 0
+    }
+
+    /// DMA is transferring a buffer
+    pub fn is_sending(&self) -> bool {
+        self.busy
+    }
+
+    /// Stop the transfer in progress and drop everything queued or held, for a port that can't
+    /// send. Returns the number of bytes dropped.
+    pub fn discard(&mut self) -> usize {
+        // SAFETY: only channel 7 is disabled, IFCR is write-1-to-clear so only its flags are
+        // affected
+        let dma1 = unsafe { &*DMA1::ptr() };
+        dma1.ccr7.modify(|_, w| w.en().disabled());
+        let untransferred = if self.busy {
+            dma1.cndtr7.read().ndt().bits() as usize
+        } else {
+            0
+        };
+        dma1.ifcr.write(|w| w.ctcif7().set_bit());
+        let dropped = self.lines.len + untransferred + self.held.release();
+        self.lines.clear();
+        self.busy = false;
+        dropped
+    }
+
+    /// Handle the channel 7 transfer complete interrupt: start sending whatever was queued
+    /// during the previous transfer, then queue the held line in the buffer freed.
+    pub fn on_transfer_complete(&mut self) {
+        // SAFETY: ISR is read only, IFCR is write-1-to-clear so only channel 7 flags are
+        // affected
+        let dma1 = unsafe { &*DMA1::ptr() };
+        // Cleared by a discard since
+        if dma1.isr.read().tcif7().bit_is_clear() {
+            return;
+        }
+        dma1.ifcr.write(|w| w.ctcif7().set_bit());
+        self.busy = false;
+        self.start();
+        if let Some((class, line)) = self.held.line() {
+            if self
+                .lines
+                .insert(&mut self.buffers[self.filling], class, line)
+                .is_ok()
+            {
+                self.held.release();
+                if !self.busy && self.lines.len >= self.threshold {
+                    self.start();
+                }
+            }
+        }
+    }
+
+    /// Called by SysTick at `now_ms`: drop the held line once its wait is over
+    pub fn on_tick(&mut self, now_ms: u32) {
+        self.held.on_tick(Sink::Host, now_ms);
+    }
+
+    /// Hand the filling buffer to DMA and start filling the other one.
+    fn start(&mut self) {
+        // Held until the host selects this bridge, or gives it a turn
+        #[cfg(feature = "rs485")]
+        {
+            if !rs485::may_transmit() {
+                return;
+            }
+            rs485::on_transfer_start();
+        }
+        if self.lines.len == 0 {
+            return;
+        }
+        #[cfg(feature = "rs485")]
+        rs485::before_transmit();
+        // SAFETY: channel 7 is disabled between transfers and only reconfigured here
+        let dma1 = unsafe { &*DMA1::ptr() };
+        dma1.ccr7.modify(|_, w| w.en().disabled());
+        dma1.cmar7
+            .write(|w| unsafe { w.ma().bits(self.buffers[self.filling].as_ptr() as u32) });
+        dma1.cndtr7.write(|w| w.ndt().bits(self.lines.len as u16));
+        // Make sure buffer contents are written before DMA starts reading
+        compiler_fence(Ordering::Release);
+        dma1.ccr7.modify(|_, w| w.en().enabled());
+
+        self.filling ^= 1;
+        self.lines.clear();
+        self.busy = true;
+    }
+
+    /// Queue `data` after the lines of `class` and those of higher classes, starting an idle
+    /// channel once the threshold is reached
+    fn insert(&mut self, class: Class, data: &[u8]) -> Result<(), BufferFull> {
+        self.lines
+            .insert(&mut self.buffers[self.filling], class, data)?;
+        if !self.busy && self.lines.len >= self.threshold {
+            self.start();
+        }
+        Ok(())
+    }
+}
+
+/// Lines queued in a TX buffer, in order of their [`Class`]. A line is what one write queued,
+/// sentences, responses and binary records alike.
+struct Lines {
+    /// Length of each line, in the order they are queued
+    lens: Vec<u16, MAX_LINES>,
+    /// Number of lines of each class
+    counts: [usize; Class::ALL.len()],
+    /// Bytes queued
+    len: usize,
+}
+
+impl Lines {
+    const fn new() -> Self {
+        Self {
+            lens: Vec::new(),
+            counts: [0; Class::ALL.len()],
+            len: 0,
+        }
+    }
+
+    /// A line of `len` bytes fits in the buffer
+    fn fits(&self, len: usize) -> bool {
+        self.len + len <= TX_BUFFER_SIZE && !self.lens.is_full()
+    }
+
+    /// Queue `data` in `buffer` after the lines of `class` and those of higher classes
+    fn insert(
+        &mut self,
+        buffer: &mut [u8; TX_BUFFER_SIZE],
+        class: Class,
+        data: &[u8],
+    ) -> Result<(), BufferFull> {
+        if !self.fits(data.len()) {
+            return Err(BufferFull);
+        }
+        let index = self.counts[..=class as usize].iter().sum();
+        let at = self.offset(index);
+        buffer.copy_within(at..self.len, at + data.len());
+        buffer[at..at + data.len()].copy_from_slice(data);
+        self.lens
+            .insert(index, data.len() as u16)
+            .map_err(|_| BufferFull)?;
+        self.counts[class as usize] += 1;
+        self.len += data.len();
+        Ok(())
+    }
+
+    /// Drop the oldest lines of `class` from `buffer`, counting them, until a line of `len`
+    /// bytes fits
+    fn drop_oldest(&mut self, buffer: &mut [u8; TX_BUFFER_SIZE], class: Class, len: usize) {
+        let index = self.counts[..class as usize].iter().sum();
+        let start = self.offset(index);
+        while !self.fits(len) && self.counts[class as usize] > 0 {
+            let dropped = usize::from(self.lens.remove(index));
+            buffer.copy_within(start + dropped..self.len, start);
+            self.len -= dropped;
+            self.counts[class as usize] -= 1;
+            router::record_drop(Sink::Host, 1, dropped);
+        }
+    }
+
+    /// Offset in the buffer of the line at `index`
+    fn offset(&self, index: usize) -> usize {
+        self.lens[..index].iter().map(|&len| usize::from(len)).sum()
+    }
+
+    fn clear(&mut self) {
+        self.lens.clear();
+        self.counts = [0; Class::ALL.len()];
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(lines: &mut Lines, buffer: &mut [u8; TX_BUFFER_SIZE], class: Class, line: &[u8]) {
+        lines.insert(buffer, class, line).unwrap();
+    }
+
+    #[test]
+    fn lines_are_queued_by_class() {
+        let mut buffer = [0; TX_BUFFER_SIZE];
+        let mut lines = Lines::new();
+        queue(&mut lines, &mut buffer, Class::Bulk, b"$GPGGA\r\n");
+        queue(&mut lines, &mut buffer, Class::Response, b"OK 1\r\n");
+        queue(&mut lines, &mut buffer, Class::Bulk, b"$GPRMC\r\n");
+        queue(&mut lines, &mut buffer, Class::Alarm, b"$PBRIDGE\r\n");
+        queue(&mut lines, &mut buffer, Class::Response, b"OK 2\r\n");
+        assert_eq!(
+            &buffer[..lines.len],
+            b"$PBRIDGE\r\nOK 1\r\nOK 2\r\n$GPGGA\r\n$GPRMC\r\n"
+        );
+        assert_eq!(lines.counts, [1, 2, 2]);
+    }
+
+    #[test]
+    fn oldest_lines_are_dropped_until_the_line_fits() {
+        let mut buffer = [0; TX_BUFFER_SIZE];
+        let mut lines = Lines::new();
+        queue(&mut lines, &mut buffer, Class::Response, b"OK\r\n");
+        // Lines of 16 bytes filling the rest of the buffer
+        let count = (TX_BUFFER_SIZE - 4) / 16;
+        for i in 0..count {
+            let line = format!("$GPGSV,{:04}*00\r\n", i);
+            queue(&mut lines, &mut buffer, Class::Bulk, line.as_bytes());
+        }
+        let room = TX_BUFFER_SIZE - lines.len;
+
+        // Dropping the first line makes room for 16 more bytes, the second for 32
+        lines.drop_oldest(&mut buffer, Class::Bulk, room + 20);
+        assert_eq!(lines.counts, [0, 1, count - 2]);
+        assert_eq!(lines.len, TX_BUFFER_SIZE - room - 32);
+        assert_eq!(&buffer[..4], b"OK\r\n");
+        assert_eq!(&buffer[4..20], b"$GPGSV,0002*00\r\n");
+        let last = format!("$GPGSV,{:04}*00\r\n", count - 1);
+        assert_eq!(&buffer[lines.len - 16..lines.len], last.as_bytes());
+    }
+
+    #[test]
+    fn only_lines_of_the_class_are_dropped() {
+        let mut buffer = [0; TX_BUFFER_SIZE];
+        let mut lines = Lines::new();
+        queue(&mut lines, &mut buffer, Class::Alarm, b"$PBRIDGE\r\n");
+        queue(&mut lines, &mut buffer, Class::Response, b"OK\r\n");
+        queue(&mut lines, &mut buffer, Class::Bulk, b"$GPGGA\r\n");
+        lines.drop_oldest(&mut buffer, Class::Response, TX_BUFFER_SIZE);
+        assert_eq!(&buffer[..lines.len], b"$PBRIDGE\r\n$GPGGA\r\n");
+        assert_eq!(lines.counts, [1, 0, 1]);
+        assert!(!lines.fits(TX_BUFFER_SIZE));
+    }
+
+    #[test]
+    fn line_table_limits_the_lines_queued() {
+        let mut buffer = [0; TX_BUFFER_SIZE];
+        let mut lines = Lines::new();
+        for _ in 0..MAX_LINES {
+            queue(&mut lines, &mut buffer, Class::Bulk, b"$");
+        }
+        assert_eq!(
+            lines.insert(&mut buffer, Class::Bulk, b"$"),
+            Err(BufferFull)
+        );
+        lines.drop_oldest(&mut buffer, Class::Bulk, 1);
+        assert_eq!(lines.counts[Class::Bulk as usize], MAX_LINES - 1);
+    }
+}
@@ -0,0 +1,165 @@
+//! External I2C EEPROM or FRAM holding the configuration and the event log, enabled by the
+//! `eeprom` feature, so frequent saves and events don't wear out internal flash.
+//!
+//! The device is a 24xx part with two address bytes and pages of at least [`PAGE_LEN`] bytes,
+//! a 24C32 or larger such as a 24LC64, or an FM24CL64 FRAM, answering at [`ADDRESS`] with A0 to
+//! A2 low on I2C1, see [`crate::i2c`] for the pins. The first [`SIZE`] bytes are used:
+//! the configuration records from 0, replacing the flash pages of [`crate::config`], and from
+//! [`LOG_OFFSET`] a ring of [`LOG_ENTRIES`] [`crate::eventlog`]. A
+//! missing device loads the default configuration and fails `SAVE` with `ERR EEPROM`.
+//!
+//! An EEPROM doesn't acknowledge while it programs a page, up to 5ms, so every transfer first
+//! polls the device until it does. A FRAM acknowledges right away. Writes are split at page
+//! boundaries, as a page write wraps around within its page. The device belongs to idle, which
+//! runs the commands using it for USART2, see [`crate::jobs`], so the polling holds no task off.
+
+#[cfg(feature = "display")]
+compile_error!("feature `eeprom` takes I2C1 and its pins from `display`");
+
+#[cfg(feature = "i2c-slave")]
+compile_error!("feature `eeprom` takes I2C1 and its pins from `i2c-slave`");
+
+use crate::chip::pac::{GPIOB, I2C1, RCC};
+use crate::eventlog::{self, Entry, Event, ENTRY_LEN};
+use crate::i2c::{Error, I2c};
+use crate::nmea::{Date, Time};
+use crate::storage::{self, Partition, Storage};
+use embedded_hal::i2c::I2c as _;
+use heapless::Vec;
+
+/// 7-bit address with A0 to A2 low
+pub const ADDRESS: u8 = 0x50;
+
+/// Bytes used, those of a 24C32
+pub const SIZE: usize = 4096;
+
+/// Smallest page of the supported parts
+pub const PAGE_LEN: usize = 32;
+
+/// Start of the event log, past the configuration records
+pub const LOG_OFFSET: usize = 1024;
+
+/// Entries of the event log
+pub const LOG_ENTRIES: usize = (SIZE - LOG_OFFSET) / ENTRY_LEN;
+
+/// Acknowledge polls before the device is given up, each an address byte at 400kHz, longer
+/// than a page write cycle
+const READY_POLLS: u32 = 500;
+
+const _: () = assert!(PAGE_LEN.is_multiple_of(ENTRY_LEN) && LOG_OFFSET.is_multiple_of(PAGE_LEN));
+
+/// The device as a [`Storage`] of [`SIZE`] bytes, erased a page at a time by writing all ones.
+/// Any byte can be written over, erased or not.
+pub struct Device(I2c);
+
+/// The device and the write position of its event log
+pub struct Eeprom {
+    device: Device,
+    log: eventlog::Log,
+}
+
+impl Eeprom {
+    /// Configure I2C1, see [`I2c::new`], and find the end of the event log
+    pub fn new(rcc: &RCC, gpiob: &GPIOB, i2c1: I2C1) -> Self {
+        let mut device = Device(I2c::new(rcc, gpiob, i2c1));
+        let log = eventlog::Log::new(&mut log_partition(&mut device));
+        Self { device, log }
+    }
+
+    /// The configuration records, two banks of 512 bytes, see [`crate::config::Store`]
+    pub fn config(&mut self) -> Partition<&mut Device> {
+        Partition::new(&mut self.device, 0, LOG_OFFSET / PAGE_LEN)
+    }
+
+    /// Log `event`, stamped with `time`, see [`eventlog::Log::append`]
+    pub fn append(
+        &mut self,
+        event: Event,
+        time: Option<(Date, Time)>,
+    ) -> Result<(), storage::Error> {
+        let log = &mut log_partition(&mut self.device);
+        self.log.append(log, event, time)
+    }
+
+    /// Up to `count` of the latest entries, see [`eventlog::Log::latest`]
+    pub fn latest(
+        &mut self,
+        count: u8,
+    ) -> Result<Vec<Entry, { eventlog::MAX_LISTED as usize }>, storage::Error> {
+        self.log.latest(&mut log_partition(&mut self.device), count)
+    }
+}
+
+impl Device {
+    /// Read `buffer.len()` bytes from `offset`
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        self.wait_ready()?;
+        self.0
+            .write_read(ADDRESS, &(offset as u16).to_be_bytes(), buffer)
+    }
+
+    /// Write `data` at `offset`, a page write per page touched
+    fn write_at(&mut self, mut offset: usize, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let len = data.len().min(PAGE_LEN - offset % PAGE_LEN);
+            let mut frame = [0; 2 + PAGE_LEN];
+            frame[..2].copy_from_slice(&(offset as u16).to_be_bytes());
+            frame[2..2 + len].copy_from_slice(&data[..len]);
+            self.wait_ready()?;
+            self.0.write(ADDRESS, &frame[..2 + len])?;
+            offset += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Poll the device until it acknowledges, the end of a write cycle
+    fn wait_ready(&mut self) -> Result<(), Error> {
+        for _ in 0..READY_POLLS {
+            match self.0.write(ADDRESS, &[]) {
+                Err(Error::Nack) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// `len` bytes at `offset` are within [`SIZE`]
+    fn check(offset: usize, len: usize) -> Result<(), storage::Error> {
+        match offset + len <= SIZE {
+            true => Ok(()),
+            false => Err(storage::Error),
+        }
+    }
+}
+
+impl Storage for Device {
+    const ERASE_SIZE: usize = PAGE_LEN;
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), storage::Error> {
+        Self::check(offset, buffer.len())?;
+        self.read_at(offset, buffer).map_err(|_| storage::Error)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), storage::Error> {
+        Self::check(offset, data.len())?;
+        self.write_at(offset, data).map_err(|_| storage::Error)
+    }
+
+    fn erase(&mut self, block: usize) -> Result<(), storage::Error> {
+        self.write(block * PAGE_LEN, &[0xFF; PAGE_LEN])
+    }
+}
+
+/// The event log, see [`eventlog::Log`]
+fn log_partition(device: &mut Device) -> Partition<&mut Device> {
+    Partition::new(
+        device,
+        LOG_OFFSET / PAGE_LEN,
+        (SIZE - LOG_OFFSET) / PAGE_LEN,
+    )
+}
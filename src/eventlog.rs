@@ -0,0 +1,382 @@
+//! Log of notable events kept in the external EEPROM, enabled by the `eeprom` feature: boots
+//! with their reset cause, GPS power on and off and power cycles by the supervisor, fixes
+//! acquired and lost, bursts of lost bytes, low battery and the geofence, speed and anchor
+//! alarms.
+//!
+//! Tasks [`record`] events into a short queue, which idle writes to the EEPROM stamped with the
+//! UTC time of the RTC, see [`crate::eeprom`]. Each is an [`ENTRY_LEN`] byte entry in a [`Log`]
+//! overwriting the oldest: a sequence number, the event, the date and time, all ones before the
+//! RTC is set, and the low half of a CRC-32 of the bytes before it, so a torn write is skipped.
+//!
+//! The [`Monitor`] on SysTick records the changes no other module reports. A fix has to be
+//! acquired or lost for [`FIX_HOLD_MS`] to count, so a fix flickering at the edge of coverage
+//! doesn't fill the log, and bytes lost to overruns or full queues are logged once the burst
+//! ends, as their count.
+//!
+//! `LOG EVENTS [<n>]` lists the latest entries, `LOG EVENTS CLR` logs a [`Event::Cleared`]
+//! marker the listing stops at instead of erasing the ring. Only idle touches the EEPROM, USART2
+//! hands it the listing, see [`crate::jobs`], so neither an entry written nor one read, each
+//! waiting up to 5ms for the write cycle before it, holds a task off. Every GPS power change and
+//! fix acquired or lost is an entry, so this matters.
+
+use crate::geofence::Transition;
+use crate::nmea::{Date, Time};
+use crate::reset::Cause;
+use crate::stats::STATS;
+use crate::storage::{self, Error, Storage};
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::ops::Range;
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{Deque, Vec};
+
+/// Bytes per entry, a divisor of the EEPROM page so an entry is written at once
+pub const ENTRY_LEN: usize = 16;
+
+/// Events waiting for idle, later ones are dropped once full
+pub const QUEUE_LEN: usize = 8;
+
+/// Entries listed by `LOG EVENTS` without a count
+pub const DEFAULT_LISTED: u8 = 8;
+
+/// Most entries listed at once, as many lines as the host TX buffers take
+pub const MAX_LISTED: u8 = 16;
+
+/// Bytes of the log read at once while looking for its end at boot
+const SCAN_CHUNK: usize = 8 * ENTRY_LEN;
+
+/// The CRC, the low half of a CRC-32 over the bytes before it
+const CRC: Range<usize> = ENTRY_LEN - 2..ENTRY_LEN;
+
+/// How long a fix has to be acquired or lost before it is logged
+pub const FIX_HOLD_MS: u32 = 5000;
+
+/// Period of the check for lost bytes, a burst ends with a period without any
+pub const OVERFLOW_CHECK_MS: u32 = 1000;
+
+/// Stored year of an unknown time
+const NO_TIME: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Boot, with the cause of the reset
+    Reset(Cause),
+    /// The supervisor turned the GPS off for lack of a fix, see [`crate::gps_ctrl`]
+    GpsTimeout,
+    /// The battery fell below its threshold, see [`crate::battery`]
+    LowBattery,
+    /// The speed alarm was raised if true, cleared if false
+    SpeedAlarm(bool),
+    /// The anchor alarm was raised if true, cleared if false
+    AnchorAlarm(bool),
+    Geofence {
+        zone: u8,
+        transition: Transition,
+    },
+    /// `LOG EVENTS CLR`, older entries aren't listed
+    Cleared,
+    /// The GPS was turned on if true, off if false, by any means
+    GpsPower(bool),
+    /// A valid fix was acquired if true, lost while the GPS is on if false
+    Fix(bool),
+    /// Bytes lost to overruns or full queues in a burst, see [`crate::stats`]
+    Overflow(u16),
+}
+
+impl Event {
+    /// Kind and two argument bytes
+    fn encode(&self) -> [u8; 3] {
+        match *self {
+            Event::Reset(cause) => [1, cause as u8, 0],
+            Event::GpsTimeout => [2, 0, 0],
+            Event::LowBattery => [3, 0, 0],
+            Event::SpeedAlarm(raised) => [4, u8::from(raised), 0],
+            Event::AnchorAlarm(raised) => [5, u8::from(raised), 0],
+            Event::Geofence { zone, transition } => {
+                [6, zone, u8::from(transition == Transition::Exit)]
+            }
+            Event::Cleared => [7, 0, 0],
+            Event::GpsPower(on) => [8, u8::from(on), 0],
+            Event::Fix(valid) => [9, u8::from(valid), 0],
+            Event::Overflow(bytes) => {
+                let [low, high] = bytes.to_le_bytes();
+                [10, low, high]
+            }
+        }
+    }
+
+    fn decode(bytes: [u8; 3]) -> Option<Self> {
+        let [kind, arg, arg2] = bytes;
+        Some(match kind {
+            1 => Event::Reset(Cause::from_code(arg)),
+            2 => Event::GpsTimeout,
+            3 => Event::LowBattery,
+            4 => Event::SpeedAlarm(arg != 0),
+            5 => Event::AnchorAlarm(arg != 0),
+            6 => Event::Geofence {
+                zone: arg,
+                transition: if arg2 == 0 {
+                    Transition::Enter
+                } else {
+                    Transition::Exit
+                },
+            },
+            7 => Event::Cleared,
+            8 => Event::GpsPower(arg != 0),
+            9 => Event::Fix(arg != 0),
+            10 => Event::Overflow(u16::from_le_bytes([arg, arg2])),
+            _ => return None,
+        })
+    }
+
+    /// Write the event as listed by `LOG EVENTS`, named as in the sentences reporting it, e.g.
+    /// `GEOFENCE 1 ENTER`
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        let on_off = |raised| if raised { "ON" } else { "OFF" };
+        match *self {
+            Event::Reset(cause) => write!(out, "RESET {}", cause.name()),
+            Event::GpsTimeout => out.write_str("GPS_TIMEOUT"),
+            Event::LowBattery => out.write_str("LOW_BATTERY"),
+            Event::SpeedAlarm(raised) => write!(out, "SPEED {}", on_off(raised)),
+            Event::AnchorAlarm(raised) => write!(out, "ANCHOR {}", on_off(raised)),
+            Event::Geofence { zone, transition } => {
+                write!(out, "GEOFENCE {} {}", zone, transition.name())
+            }
+            Event::Cleared => out.write_str("CLR"),
+            Event::GpsPower(on) => write!(out, "GPS {}", on_off(on)),
+            Event::Fix(true) => out.write_str("FIX ACQUIRED"),
+            Event::Fix(false) => out.write_str("FIX LOST"),
+            Event::Overflow(bytes) => write!(out, "OVERFLOW {}", bytes),
+        }
+    }
+}
+
+/// An event as logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Counts up from 0 over every entry written
+    pub seq: u32,
+    pub event: Event,
+    /// UTC when written, `None` before the RTC is set
+    pub time: Option<(Date, Time)>,
+}
+
+impl Entry {
+    pub fn encode(&self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0xFF; ENTRY_LEN];
+        bytes[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[4..7].copy_from_slice(&self.event.encode());
+        if let Some((date, time)) = self.time {
+            bytes[7..13].copy_from_slice(&[
+                date.year.saturating_sub(2000).min(99) as u8,
+                date.month,
+                date.day,
+                time.hour,
+                time.minute,
+                time.second,
+            ]);
+        }
+        storage::seal(&mut bytes, CRC);
+        bytes
+    }
+
+    /// Decode an entry, `None` if it is erased, torn or of an unknown kind
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ENTRY_LEN || !storage::is_sealed(bytes, CRC) {
+            return None;
+        }
+        let time = (bytes[7] != NO_TIME).then(|| {
+            let date = Date {
+                year: 2000 + u16::from(bytes[7]),
+                month: bytes[8],
+                day: bytes[9],
+            };
+            let time = Time {
+                hour: bytes[10],
+                minute: bytes[11],
+                second: bytes[12],
+                millisecond: 0,
+            };
+            (date, time)
+        });
+        Some(Self {
+            seq: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            event: Event::decode([bytes[4], bytes[5], bytes[6]])?,
+            time,
+        })
+    }
+}
+
+/// Write position in the ring of entries filling a storage. Entries are overwritten in place,
+/// which takes a storage written without erasing such as the EEPROM. The same storage is passed
+/// to every call.
+pub struct Log {
+    /// Entries of the storage
+    entries: usize,
+    /// Entry written next
+    next_entry: usize,
+    /// Sequence number of the entry written next
+    next_seq: u32,
+}
+
+impl Log {
+    /// Find the end of the log: the entry after the one with the highest sequence number
+    pub fn new(storage: &mut impl Storage) -> Self {
+        let mut log = Self {
+            entries: storage.capacity() / ENTRY_LEN,
+            next_entry: 0,
+            next_seq: 0,
+        };
+        let mut chunk = [0; SCAN_CHUNK];
+        for first in (0..log.entries).step_by(SCAN_CHUNK / ENTRY_LEN) {
+            let len = SCAN_CHUNK.min((log.entries - first) * ENTRY_LEN);
+            if storage.read(first * ENTRY_LEN, &mut chunk[..len]).is_err() {
+                break;
+            }
+            for (i, bytes) in chunk[..len].chunks_exact(ENTRY_LEN).enumerate() {
+                match Entry::decode(bytes) {
+                    Some(entry) if entry.seq >= log.next_seq => {
+                        log.next_entry = (first + i + 1) % log.entries;
+                        log.next_seq = entry.seq.wrapping_add(1);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        log
+    }
+
+    /// Log `event`, stamped with `time`. The entry is used up even if writing fails.
+    pub fn append(
+        &mut self,
+        storage: &mut impl Storage,
+        event: Event,
+        time: Option<(Date, Time)>,
+    ) -> Result<(), Error> {
+        let entry = Entry {
+            seq: self.next_seq,
+            event,
+            time,
+        };
+        let slot = self.next_entry;
+        self.next_entry = (self.next_entry + 1) % self.entries;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        storage.write_slot(slot, &entry.encode())
+    }
+
+    /// Up to `count` of the latest entries, oldest first, back to the last `EVENTS CLR`
+    pub fn latest(
+        &self,
+        storage: &mut impl Storage,
+        count: u8,
+    ) -> Result<Vec<Entry, { MAX_LISTED as usize }>, Error> {
+        let mut entries = Vec::<Entry, { MAX_LISTED as usize }>::new();
+        let mut index = self.next_entry;
+        let mut seq = self.next_seq;
+        while entries.len() < usize::from(count) && !entries.is_full() {
+            index = (index + self.entries - 1) % self.entries;
+            seq = seq.wrapping_sub(1);
+            let bytes = storage.read_slot::<ENTRY_LEN>(index)?;
+            // An older lap, a torn entry or the start of the log ends it
+            match Entry::decode(&bytes) {
+                Some(entry) if entry.seq == seq && entry.event != Event::Cleared => {
+                    let _ = entries.push(entry);
+                }
+                _ => break,
+            }
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+}
+
+/// Events recorded by any task, so a `cortex_m` critical-section mutex like the timer table
+static QUEUE: Mutex<RefCell<Deque<Event, QUEUE_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+
+/// Queue `event` for idle to log
+pub fn record(event: Event) {
+    interrupt::free(|cs| {
+        let _ = QUEUE.borrow(cs).borrow_mut().push_back(event);
+    });
+}
+
+/// Oldest event waiting to be logged
+pub fn take() -> Option<Event> {
+    interrupt::free(|cs| QUEUE.borrow(cs).borrow_mut().pop_front())
+}
+
+/// Events wait to be logged
+pub fn is_pending() -> bool {
+    interrupt::free(|cs| !QUEUE.borrow(cs).borrow().is_empty())
+}
+
+/// Watches GPS power, the fix and lost bytes on every SysTick tick, recording their changes
+pub struct Monitor {
+    /// GPS power seen at the last tick
+    powered: bool,
+    /// Fix validity last logged
+    fix: bool,
+    /// [`crate::timer::now_ms`] since the fix differs from the one logged
+    fix_changed_ms: Option<u32>,
+    /// [`crate::timer::now_ms`] of the last check for lost bytes
+    checked_ms: u32,
+    /// Lost bytes counted at the last check
+    lost: u32,
+    /// Bytes lost in the burst going on
+    burst: u32,
+}
+
+impl Monitor {
+    pub const fn new() -> Self {
+        Self {
+            powered: false,
+            fix: false,
+            fix_changed_ms: None,
+            checked_ms: 0,
+            lost: 0,
+            burst: 0,
+        }
+    }
+
+    /// Check the GPS at `now_ms` milliseconds, see [`crate::timer::now_ms`]
+    pub fn on_tick(&mut self, now_ms: u32, gps_power: bool, fix_valid: bool) {
+        if gps_power != self.powered {
+            self.powered = gps_power;
+            record(Event::GpsPower(gps_power));
+        }
+        // Turning the GPS off loses the fix, which isn't logged on its own
+        let fix_valid = fix_valid && gps_power;
+        if fix_valid == self.fix || !gps_power {
+            self.fix = fix_valid;
+            self.fix_changed_ms = None;
+        } else {
+            let since = *self.fix_changed_ms.get_or_insert(now_ms);
+            if now_ms.wrapping_sub(since) >= FIX_HOLD_MS {
+                self.fix = fix_valid;
+                self.fix_changed_ms = None;
+                record(Event::Fix(fix_valid));
+            }
+        }
+
+        if now_ms.wrapping_sub(self.checked_ms) < OVERFLOW_CHECK_MS {
+            return;
+        }
+        self.checked_ms = now_ms;
+        let lost = STATS.overruns.get().wrapping_add(STATS.dropped_bytes.get());
+        // `STATS CLR` starts the counters over
+        let new = lost.checked_sub(self.lost).unwrap_or(lost);
+        self.lost = lost;
+        if new > 0 {
+            self.burst = self.burst.saturating_add(new);
+        } else if self.burst > 0 {
+            record(Event::Overflow(self.burst.try_into().unwrap_or(u16::MAX)));
+            self.burst = 0;
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
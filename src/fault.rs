@@ -0,0 +1,251 @@
+//! Faults and panics recorded across the reset they cause, so a crash in the field can be
+//! debugged.
+//!
+//! A hard fault, or an exception or interrupt without a handler, stores a [`Record`] in a
+//! [`persist::Slot`], which startup neither zeroes nor initializes, and resets the MCU. A hard
+//! fault records the registers stacked on its entry, the stack pointer they were stacked at and
+//! the fault status and address registers. MemManage, BusFault and UsageFault aren't enabled, so
+//! they escalate to a hard fault and CFSR tells which it was. An exception without a handler, e.g.
+//! an interrupt enabled by mistake, records its number and the status registers only, as its
+//! stacked registers can't be found reliably.
+//!
+//! [`take`] returns the record once, at the next start, and it is sent to the host as
+//! `$PBRIDGE,FAULT,<exception>,<pc>,<lr>,<sp>,<xpsr>,<cfsr>,<hfsr>,<mmfar>,<bfar>,<r0>,<r1>,<r2>,<r3>,<r12>*hh`
+//! after the reset sentence, the registers as 8 hex digits.
+//!
+//! A panic in a release build stores the file name and line of the panic and its message,
+//! [`MESSAGE_LEN`] bytes at most, and resets the MCU. [`take_panic`] returns it once and it is sent
+//! as `$PBRIDGE,PANIC,<file>:<line>,<message>*hh`, the characters NMEA reserves replaced, `,` by
+//! `;` and `$`, `*` and anything unprintable by `_`. Debug builds keep halting on a panic for the
+//! debugger, see `main.rs`.
+
+use crate::nmea;
+use crate::persist::{Persistent, Slot};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::ExceptionFrame;
+use heapless::String;
+
+/// Longest `$PBRIDGE,FAULT` sentence including its line ending
+pub const SENTENCE_LEN: usize = 160;
+
+/// Exception number of a hard fault
+pub const HARD_FAULT: u32 = 3;
+
+/// Longest panic location and message kept
+pub const MESSAGE_LEN: usize = 96;
+
+/// Longest `$PBRIDGE,PANIC` sentence including its line ending
+pub const PANIC_SENTENCE_LEN: usize = MESSAGE_LEN + 20;
+
+/// Marks a stored record
+const MAGIC: u32 = 0xFA17_C0DE;
+
+/// Marks a stored panic
+const PANIC_MAGIC: u32 = 0xDEAD_B0A7;
+
+/// Set by the first panic, a panic while storing it resets right away
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Fault state when the MCU reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Record {
+    /// Exception number, [`HARD_FAULT`] or 16 and up for interrupts
+    pub exception: u32,
+    pub pc: u32,
+    pub lr: u32,
+    /// Address of the stacked registers
+    pub sp: u32,
+    pub xpsr: u32,
+    /// Configurable fault status register, MemManage, BusFault and UsageFault flags
+    pub cfsr: u32,
+    /// Hard fault status register
+    pub hfsr: u32,
+    /// Faulting address of a MemManage fault, valid if CFSR MMARVALID is set
+    pub mmfar: u32,
+    /// Faulting address of a BusFault, valid if CFSR BFARVALID is set
+    pub bfar: u32,
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+}
+
+impl Record {
+    /// Registers in sentence order
+    fn registers(&self) -> [u32; 13] {
+        [
+            self.pc, self.lr, self.sp, self.xpsr, self.cfsr, self.hfsr, self.mmfar, self.bfar,
+            self.r0, self.r1, self.r2, self.r3, self.r12,
+        ]
+    }
+
+    /// Write the name of the exception, e.g. `HARDFAULT` or `IRQ12`
+    fn write_exception(&self, out: &mut impl Write) -> fmt::Result {
+        match self.exception {
+            2 => out.write_str("NMI"),
+            HARD_FAULT => out.write_str("HARDFAULT"),
+            4 => out.write_str("MEMMANAGE"),
+            5 => out.write_str("BUSFAULT"),
+            6 => out.write_str("USAGEFAULT"),
+            exception @ 16.. => write!(out, "IRQ{}", exception - 16),
+            exception => write!(out, "EXC{}", exception),
+        }
+    }
+}
+
+// SAFETY: u32 fields only, which leave no padding
+unsafe impl Persistent for Record {}
+
+#[link_section = ".persist.FAULT"]
+static STORED: Slot<Record, MAGIC> = Slot::new();
+
+/// Location and message of a panic, `<file>:<line>,<message>`, truncated to [`MESSAGE_LEN`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Panic {
+    len: u32,
+    text: [u8; MESSAGE_LEN],
+}
+
+impl Panic {
+    const fn new() -> Self {
+        Self {
+            len: 0,
+            text: [0; MESSAGE_LEN],
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only printable ASCII is stored
+        core::str::from_utf8(&self.text[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Append `byte` as is if it fits
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.text.get_mut(self.len as usize) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+}
+
+/// Writing replaces the characters NMEA reserves and drops what doesn't fit
+impl Write for Panic {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(match byte {
+                b',' => b';',
+                b'$' | b'*' => b'_',
+                b' '..=b'~' => byte,
+                _ => b'_',
+            });
+        }
+        Ok(())
+    }
+}
+
+// SAFETY: a u32 and bytes, which leave no padding, a length beyond the text is checked when
+// taken
+unsafe impl Persistent for Panic {}
+
+#[link_section = ".persist.PANIC"]
+static STORED_PANIC: Slot<Panic, PANIC_MAGIC> = Slot::new();
+
+/// The record of the fault that reset the MCU, if any, cleared so it is reported once
+pub fn take() -> Option<Record> {
+    // SAFETY: called once at startup, before the handlers that write it can run
+    unsafe { STORED.take() }
+}
+
+/// The panic that reset the MCU, if any, cleared so it is reported once
+pub fn take_panic() -> Option<Panic> {
+    // SAFETY: called once at startup, before the panic handler that writes it can run
+    unsafe { STORED_PANIC.take() }.filter(|panic| panic.len as usize <= MESSAGE_LEN)
+}
+
+/// Called by the HardFault handler: record the fault and reset
+pub fn on_hard_fault(frame: &ExceptionFrame) -> ! {
+    store(Record {
+        exception: HARD_FAULT,
+        pc: frame.pc(),
+        lr: frame.lr(),
+        sp: frame as *const ExceptionFrame as u32,
+        xpsr: frame.xpsr(),
+        r0: frame.r0(),
+        r1: frame.r1(),
+        r2: frame.r2(),
+        r3: frame.r3(),
+        r12: frame.r12(),
+        ..status()
+    })
+}
+
+/// Called by the default handler for exception `irqn`, numbered from the first interrupt:
+/// record it and reset
+pub fn on_unexpected(irqn: i16) -> ! {
+    store(Record {
+        exception: (i32::from(irqn) + 16) as u32,
+        ..status()
+    })
+}
+
+/// Called by the panic handler of release builds: record the panic and reset
+pub fn on_panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        SCB::sys_reset();
+    }
+    let mut panic = Panic::new();
+    if let Some(location) = info.location() {
+        // The file name alone, the path of a dependency would take all the room
+        let file = location.file().rsplit('/').next().unwrap_or_default();
+        let _ = write!(panic, "{}:{}", file, location.line());
+    }
+    panic.push(b',');
+    let _ = write!(panic, "{}", info.message());
+    // SAFETY: only the panic handler writes it, once as guarded above, and it doesn't return
+    unsafe { STORED_PANIC.store(panic) };
+    SCB::sys_reset()
+}
+
+/// Write the `$PBRIDGE,PANIC` sentence including line ending
+pub fn write_panic_sentence(out: &mut impl Write, panic: &Panic) -> fmt::Result {
+    let mut body = String::<{ PANIC_SENTENCE_LEN - 6 }>::new();
+    write!(body, "PBRIDGE,PANIC,{}", panic.as_str())?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Write the `$PBRIDGE,FAULT` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, record: &Record) -> fmt::Result {
+    let mut body = String::<{ SENTENCE_LEN - 6 }>::new();
+    body.write_str("PBRIDGE,FAULT,")?;
+    record.write_exception(&mut body)?;
+    for register in record.registers() {
+        write!(body, ",{:08X}", register)?;
+    }
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Record with the fault status and address registers
+fn status() -> Record {
+    // SAFETY: read-only access to the fault status registers
+    let scb = unsafe { &*SCB::PTR };
+    Record {
+        cfsr: scb.cfsr.read(),
+        hfsr: scb.hfsr.read(),
+        mmfar: scb.mmfar.read(),
+        bfar: scb.bfar.read(),
+        ..Record::default()
+    }
+}
+
+fn store(record: Record) -> ! {
+    // SAFETY: only the fault handlers write it, and they don't return
+    unsafe { STORED.store(record) };
+    SCB::sys_reset()
+}
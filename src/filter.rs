@@ -0,0 +1,181 @@
+//! Sentence filter between GPS reception and host transmission.
+//! One bit per [`SentenceType`] selects whether sentences of that type are forwarded. `POS`
+//! selects the compact `$PBRIDGE,POS` sentence the bridge generates for every fix instead, see
+//! [`crate::pos`], it isn't part of `ALL`.
+//!
+//! Sentences failing their checksum are dropped before the filter, so line noise on the GPS
+//! UART doesn't reach parsers on the host. `PASSTHRU RAW` forwards them as received.
+//!
+//! `MODE BIN` stops forwarding sentences altogether and sends each completed fix as a binary
+//! record instead, see [`crate::protocol`].
+
+use crate::defaults;
+use crate::nmea::SentenceType;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Handling of sentences failing their checksum, selected by `PASSTHRU`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Passthru {
+    /// Only forward sentences with a valid checksum, the default
+    Valid,
+    /// Forward sentences as received
+    Raw,
+}
+
+impl Passthru {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Passthru::Valid => "VALID",
+            Passthru::Raw => "RAW",
+        }
+    }
+}
+
+/// Output to the host, selected by `MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Sentences passing the filter, the default
+    Nmea,
+    /// Binary fix records
+    Binary,
+}
+
+impl Output {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Output::Nmea => "NMEA",
+            Output::Binary => "BIN",
+        }
+    }
+}
+
+static PASSTHRU_RAW: AtomicBool = AtomicBool::new(false);
+static BINARY: AtomicBool = AtomicBool::new(false);
+
+pub fn passthru() -> Passthru {
+    match PASSTHRU_RAW.load(Ordering::Relaxed) {
+        true => Passthru::Raw,
+        false => Passthru::Valid,
+    }
+}
+
+pub fn set_passthru(passthru: Passthru) {
+    PASSTHRU_RAW.store(passthru == Passthru::Raw, Ordering::Relaxed);
+}
+
+pub fn output() -> Output {
+    match BINARY.load(Ordering::Relaxed) {
+        true => Output::Binary,
+        false => Output::Nmea,
+    }
+}
+
+pub fn set_output(output: Output) {
+    BINARY.store(output == Output::Binary, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    mask: u8,
+}
+
+impl Filter {
+    /// Forwarded without a saved configuration, `BRIDGE_DEFAULT_FILTERS` at build time.
+    /// Unknown sentence types fail the build.
+    pub const DEFAULT: Filter = Self::from_mask(Self::parse_types(defaults::DEFAULT_FILTERS));
+
+    /// Bits of every type
+    const MASK: u8 = ((1u16 << SentenceType::ALL.len()) - 1) as u8;
+
+    /// Forward every sentence of the GPS, without `$PBRIDGE,POS`
+    pub const fn all() -> Self {
+        Self {
+            mask: Self::MASK & !(1 << SentenceType::Pos as u8),
+        }
+    }
+
+    pub const fn from_mask(mask: u8) -> Self {
+        Self {
+            mask: mask & Self::MASK,
+        }
+    }
+
+    /// Mask of a comma separated list of sentence types, `ALL` or `NONE` like `FILTER`.
+    /// Evaluated at compile time, where panicking reports an unknown type.
+    const fn parse_types(types: &str) -> u8 {
+        let types = types.as_bytes();
+        if types.eq_ignore_ascii_case(b"ALL") {
+            return Self::all().mask;
+        }
+        if types.eq_ignore_ascii_case(b"NONE") {
+            return 0;
+        }
+        let mut mask = 0;
+        let mut start = 0;
+        while start <= types.len() {
+            let mut end = start;
+            while end < types.len() && types[end] != b',' {
+                end += 1;
+            }
+            let (_, rest) = types.split_at(start);
+            let (name, _) = rest.split_at(end - start);
+            let mut i = 0;
+            loop {
+                if i == SentenceType::ALL.len() {
+                    panic!("unknown sentence type in BRIDGE_DEFAULT_FILTERS");
+                }
+                if name.eq_ignore_ascii_case(SentenceType::ALL[i].name().as_bytes()) {
+                    mask |= 1 << SentenceType::ALL[i] as u8;
+                    break;
+                }
+                i += 1;
+            }
+            start = end + 1;
+        }
+        mask
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    pub fn bit(sentence_type: SentenceType) -> u8 {
+        1 << sentence_type as u8
+    }
+
+    /// Forward sentences of the types in `mask` in addition to the current ones
+    pub fn enable(&mut self, mask: u8) {
+        *self = Self::from_mask(self.mask | mask);
+    }
+
+    /// Stop forwarding sentences of the types in `mask`
+    pub fn disable(&mut self, mask: u8) {
+        self.mask &= !mask;
+    }
+
+    pub fn allows(&self, sentence_type: SentenceType) -> bool {
+        self.mask & Self::bit(sentence_type) != 0
+    }
+
+    /// Write the forwarded types as a comma separated list, `NONE` if empty
+    pub fn write_types(&self, out: &mut impl Write) -> fmt::Result {
+        let mut types = SentenceType::ALL
+            .into_iter()
+            .filter(|&sentence_type| self.allows(sentence_type));
+        match types.next() {
+            Some(first) => out.write_str(first.name())?,
+            None => return out.write_str("NONE"),
+        }
+        types.try_for_each(|sentence_type| write!(out, ",{}", sentence_type.name()))
+    }
+}
+
+// Associated consts are only evaluated once used, report unknown types in every build
+const _: Filter = Filter::DEFAULT;
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,234 @@
+//! Latest GPS fix, updated from parsed sentences so other subsystems can query the position
+//! without re-parsing text. Units follow [`crate::nmea`].
+
+use crate::adc;
+use crate::nmea::{Date, FixType, Gga, Position, Rmc, Sentence, Time, Vtg};
+use crate::protocol::{FixRecord, TEMPERATURE_UNKNOWN};
+use crate::ubx::NavPvt;
+use crate::units;
+
+/// HDOP reported before any sentence carried one, 99.99
+const UNKNOWN_HDOP: u16 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpsFix {
+    /// Latitude in 1e-7 degrees, north positive
+    pub lat: i32,
+    /// Longitude in 1e-7 degrees, east positive
+    pub lon: i32,
+    /// Altitude above mean sea level in millimetres
+    pub altitude: i32,
+    /// Speed over ground in millimetres per second
+    pub speed: u32,
+    /// Course over ground in hundredths of a degree
+    pub course: u16,
+    /// Horizontal dilution of precision in hundredths
+    pub hdop: u16,
+    /// Satellites used in the fix
+    pub sats: u8,
+    /// UTC time of the last sentence carrying one
+    pub timestamp: Option<Time>,
+    /// UTC date from RMC
+    pub date: Option<Date>,
+    pub fix_type: FixType,
+}
+
+impl GpsFix {
+    pub const fn new() -> Self {
+        Self {
+            lat: 0,
+            lon: 0,
+            altitude: 0,
+            speed: 0,
+            course: 0,
+            hdop: UNKNOWN_HDOP,
+            sats: 0,
+            timestamp: None,
+            date: None,
+            fix_type: FixType::None,
+        }
+    }
+
+    /// Position, speed and course are only meaningful with a fix
+    pub fn is_valid(&self) -> bool {
+        self.fix_type != FixType::None
+    }
+
+    /// Merge the fields of a parsed sentence. GSV carries no fix data and is ignored.
+    pub fn update(&mut self, sentence: &Sentence) {
+        match sentence {
+            Sentence::Rmc(rmc) => {
+                if rmc.time.is_some() {
+                    self.timestamp = rmc.time;
+                }
+                if rmc.date.is_some() {
+                    self.date = rmc.date;
+                }
+                if !rmc.valid {
+                    self.fix_type = FixType::None;
+                    return;
+                }
+                if let Some(position) = rmc.position {
+                    self.lat = position.latitude;
+                    self.lon = position.longitude;
+                }
+                if let Some(speed) = rmc.speed {
+                    self.speed = speed;
+                }
+                if let Some(course) = rmc.course {
+                    self.course = course;
+                }
+            }
+            Sentence::Gga(gga) => {
+                if gga.time.is_some() {
+                    self.timestamp = gga.time;
+                }
+                self.sats = gga.satellites;
+                if let Some(hdop) = gga.hdop {
+                    self.hdop = hdop;
+                }
+                if gga.quality == 0 {
+                    self.fix_type = FixType::None;
+                    return;
+                }
+                // GGA doesn't tell 2D from 3D, GSA refines it
+                if self.fix_type == FixType::None {
+                    self.fix_type = FixType::Fix2D;
+                }
+                if let Some(position) = gga.position {
+                    self.lat = position.latitude;
+                    self.lon = position.longitude;
+                }
+                if let Some(altitude) = gga.altitude {
+                    self.altitude = altitude;
+                }
+            }
+            Sentence::Gsa(gsa) => {
+                self.fix_type = gsa.fix;
+                if let Some(hdop) = gsa.hdop {
+                    self.hdop = hdop;
+                }
+            }
+            Sentence::Vtg(vtg) => {
+                if let Some(speed) = vtg.speed {
+                    self.speed = speed;
+                }
+                if let Some(course) = vtg.course {
+                    self.course = course;
+                }
+            }
+            Sentence::Gll(gll) => {
+                if gll.time.is_some() {
+                    self.timestamp = gll.time;
+                }
+                if let (true, Some(position)) = (gll.valid, gll.position) {
+                    self.lat = position.latitude;
+                    self.lon = position.longitude;
+                }
+            }
+            Sentence::Gsv(_) => {}
+        }
+    }
+
+    /// Take the fix of a UBX-NAV-PVT message, which carries a whole epoch. Its PDOP stands in
+    /// for the HDOP.
+    pub fn update_pvt(&mut self, pvt: &NavPvt) {
+        if pvt.time.is_some() {
+            self.timestamp = pvt.time;
+        }
+        if pvt.date.is_some() {
+            self.date = pvt.date;
+        }
+        self.fix_type = pvt.fix;
+        self.sats = pvt.satellites;
+        self.hdop = pvt.pdop;
+        if !self.is_valid() {
+            return;
+        }
+        self.lat = pvt.latitude;
+        self.lon = pvt.longitude;
+        self.altitude = pvt.altitude;
+        self.speed = pvt.speed;
+        self.course = pvt.course;
+    }
+
+    /// GGA of the fix, see [`crate::nmea::encode`]
+    pub fn gga(&self) -> Gga {
+        let valid = self.is_valid();
+        Gga {
+            time: self.timestamp,
+            position: self.position(),
+            quality: u8::from(valid),
+            satellites: self.sats,
+            hdop: Some(self.hdop),
+            altitude: valid.then_some(self.altitude),
+        }
+    }
+
+    /// RMC of the fix, see [`crate::nmea::encode`]
+    pub fn rmc(&self) -> Rmc {
+        let valid = self.is_valid();
+        Rmc {
+            time: self.timestamp,
+            valid,
+            position: self.position(),
+            speed: valid.then_some(self.speed),
+            course: valid.then_some(self.course),
+            date: self.date,
+        }
+    }
+
+    /// VTG of the fix, see [`crate::nmea::encode`]
+    pub fn vtg(&self) -> Vtg {
+        let valid = self.is_valid();
+        Vtg {
+            course: valid.then_some(self.course),
+            speed: valid.then_some(self.speed),
+        }
+    }
+
+    fn position(&self) -> Option<Position> {
+        self.is_valid().then_some(Position {
+            latitude: self.lat,
+            longitude: self.lon,
+        })
+    }
+
+    /// Binary record of the fix sent in `MODE BIN`, in the units selected by `UNITS`
+    pub fn record(&self) -> FixRecord {
+        let speed_unit = units::speed_unit();
+        let altitude_unit = units::altitude_unit();
+        let ms_of_day = self.timestamp.map_or(u32::MAX, |time| {
+            ((u32::from(time.hour) * 60 + u32::from(time.minute)) * 60 + u32::from(time.second))
+                * 1000
+                + u32::from(time.millisecond)
+        });
+        FixRecord {
+            fix_type: match self.fix_type {
+                FixType::None => 0,
+                FixType::Fix2D => 2,
+                FixType::Fix3D => 3,
+            },
+            sats: self.sats,
+            year: self.date.map_or(0, |date| date.year),
+            month: self.date.map_or(0, |date| date.month),
+            day: self.date.map_or(0, |date| date.day),
+            ms_of_day,
+            lat: self.lat,
+            lon: self.lon,
+            altitude: altitude_unit.convert(self.altitude),
+            speed: speed_unit.convert(self.speed),
+            course: self.course,
+            hdop: self.hdop,
+            speed_unit,
+            altitude_unit,
+            temperature: adc::temperature().unwrap_or(TEMPERATURE_UNKNOWN),
+        }
+    }
+}
+
+impl Default for GpsFix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
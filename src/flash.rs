@@ -0,0 +1,172 @@
+//! Internal flash erase and programming, for data kept in pages reserved in the `memory/*.x`
+//! layouts.
+//!
+//! Pages are 2KB, numbered across both banks of the L476, see [`chip`](crate::chip). Flash is
+//! programmed a double word of 64 bits at a time, and only from the erased state of all ones.
+//! The CPU stalls while the bank code is fetched from is busy, DMA keeps running.
+
+use crate::chip::{self, pac::FLASH};
+use crate::storage::{self, Storage};
+
+pub const BASE: u32 = 0x0800_0000;
+pub const PAGE_SIZE: usize = 2048;
+pub const PAGES: usize = chip::FLASH_PAGES;
+
+/// Programming granularity in bytes
+pub const DOUBLE_WORD: usize = 8;
+
+/// Keys unlocking FLASH_CR and then its option bytes, reference manual 3.3.5 and 3.4.2
+const KEYS: [u32; 2] = [0x4567_0123, 0xCDEF_89AB];
+const OPTION_KEYS: [u32; 2] = [0x0819_2A3B, 0x4C5D_6E7F];
+
+/// All error flags in FLASH_SR, write 1 to clear
+const SR_ERRORS: u32 = 0xC3FA;
+
+/// Erasing or programming failed, or the address is out of range or misaligned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error;
+
+pub const fn page_address(page: usize) -> u32 {
+    BASE + (page * PAGE_SIZE) as u32
+}
+
+/// Run `f` with FLASH_CR unlocked, locking it again afterwards
+pub fn unlocked<T>(flash: &FLASH, f: impl FnOnce(&FLASH) -> T) -> T {
+    if flash.cr.read().lock().bit_is_set() {
+        for key in KEYS {
+            // SAFETY: unlock sequence, any other value locks FLASH_CR until reset
+            flash.keyr.write(|w| unsafe { w.keyr().bits(key) });
+        }
+    }
+    let result = f(flash);
+    flash.cr.modify(|_, w| w.lock().set_bit());
+    result
+}
+
+/// Unlock the option bytes. FLASH_CR must be unlocked. They are locked again along with it.
+pub fn unlock_options(flash: &FLASH) {
+    if flash.cr.read().optlock().bit_is_set() {
+        for key in OPTION_KEYS {
+            // SAFETY: unlock sequence, any other value locks the option bytes until reset
+            flash.optkeyr.write(|w| unsafe { w.optkeyr().bits(key) });
+        }
+    }
+}
+
+/// Erase a page to all ones. FLASH_CR must be unlocked.
+pub fn erase_page(flash: &FLASH, page: usize) -> Result<(), Error> {
+    if page >= PAGES {
+        return Err(Error);
+    }
+    begin(flash);
+    let (bank, pnb) = (page / chip::FLASH_BANK_PAGES, page % chip::FLASH_BANK_PAGES);
+    // BKER selects the physical bank, mapped the other way round while booted from bank 2
+    #[cfg(feature = "ab-boot")]
+    let bank = bank ^ usize::from(crate::boot::bank() == 2);
+    // SAFETY: page index checked above, a bank has at most 256 pages
+    flash.cr.modify(|_, w| unsafe {
+        w.per()
+            .set_bit()
+            .pnb()
+            .bits(pnb as u8)
+            .bker()
+            .bit(bank == 1)
+    });
+    flash.cr.modify(|_, w| w.start().set_bit());
+    let result = end(flash);
+    flash.cr.modify(|_, w| w.per().clear_bit());
+    // The data cache may still hold the old contents
+    flash.acr.modify(|_, w| w.dcen().clear_bit());
+    flash.acr.modify(|_, w| w.dcrst().set_bit());
+    flash
+        .acr
+        .modify(|_, w| w.dcrst().clear_bit().dcen().set_bit());
+    result
+}
+
+/// Program `data` at `address`, both double word aligned, into erased flash.
+/// FLASH_CR must be unlocked.
+pub fn program(flash: &FLASH, address: u32, data: &[u8]) -> Result<(), Error> {
+    let end_address = address as usize + data.len();
+    if address < BASE
+        || end_address > page_address(PAGES) as usize
+        || !(address as usize).is_multiple_of(DOUBLE_WORD)
+        || !data.len().is_multiple_of(DOUBLE_WORD)
+    {
+        return Err(Error);
+    }
+    begin(flash);
+    flash.cr.modify(|_, w| w.pg().set_bit());
+    let mut result = Ok(());
+    for (i, chunk) in data.chunks_exact(DOUBLE_WORD).enumerate() {
+        let word = address as usize + i * DOUBLE_WORD;
+        let low = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        // SAFETY: aligned address within flash checked above, the two words are written in
+        // order as the programming sequence requires
+        unsafe {
+            core::ptr::write_volatile(word as *mut u32, low);
+            core::ptr::write_volatile((word + 4) as *mut u32, high);
+        }
+        result = end(flash);
+        if result.is_err() {
+            break;
+        }
+    }
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+    result
+}
+
+/// Read `len` bytes at `address`
+pub fn read(address: u32, len: usize) -> &'static [u8] {
+    // SAFETY: flash is always mapped and readable, callers read reserved pages
+    unsafe { core::slice::from_raw_parts(address as *const u8, len) }
+}
+
+/// All of internal flash as a [`Storage`] in pages, borrowing FLASH for writes and erases.
+/// Offsets count from [`BASE`]; callers keep to pages reserved for them, see [`Partition`].
+///
+/// [`Partition`]: crate::storage::Partition
+pub struct Flash<'a>(pub &'a FLASH);
+
+impl Storage for Flash<'_> {
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    fn capacity(&self) -> usize {
+        PAGES * PAGE_SIZE
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), storage::Error> {
+        if offset + buffer.len() > self.capacity() {
+            return Err(storage::Error);
+        }
+        buffer.copy_from_slice(read(BASE + offset as u32, buffer.len()));
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), storage::Error> {
+        unlocked(self.0, |flash| program(flash, BASE + offset as u32, data))?;
+        Ok(())
+    }
+
+    fn erase(&mut self, block: usize) -> Result<(), storage::Error> {
+        unlocked(self.0, |flash| erase_page(flash, block))?;
+        Ok(())
+    }
+}
+
+/// Wait for a previous operation and clear its error flags
+fn begin(flash: &FLASH) {
+    while flash.sr.read().bsy().bit_is_set() {}
+    // SAFETY: flags are cleared by writing 1
+    flash.sr.write(|w| unsafe { w.bits(SR_ERRORS | 1) });
+}
+
+/// Wait for the operation and check its error flags
+fn end(flash: &FLASH) -> Result<(), Error> {
+    while flash.sr.read().bsy().bit_is_set() {}
+    if flash.sr.read().bits() & SR_ERRORS != 0 {
+        return Err(Error);
+    }
+    Ok(())
+}
@@ -0,0 +1,366 @@
+//! Track log in internal flash for boards without an SD card, enabled by the `flash-log` feature.
+//!
+//! Valid fixes are stored as [`RECORD_LEN`] byte records, at most one per second, in the
+//! [`PAGES`] pages between the firmware and the configuration pages, see `memory/*.x` and
+//! [`pages`]. The log is circular over the blocks of any [`Storage`]: the block following the one
+//! being written is kept erased, which is how the end of the log is found again at boot, so the
+//! oldest block is dropped once the log wraps. A record cut short by a reset fails its CRC and is
+//! skipped.
+//!
+//! A page is erased every [`SLOTS_PER_PAGE`] records. On the single-bank L432 and L452 the erase
+//! stalls every instruction fetch for about 22ms, whichever task runs it, holding off all of
+//! them, long enough for USART2 to overrun at high baud rates. Only the dual-bank L476 keeps
+//! running code from the first bank while a page of the log, in the second, is erased.
+//! [`Log::append`] only programs, so USART1 never holds its locks across an erase: once a block
+//! is full, idle erases the one after the next with [`Log::prepare`], a block per call, and fixes
+//! arriving before it has are dropped, which at one record per second leaves idle a second.
+//! [`Log::erase`] erases the whole log through [`Log::prepare`] as well, a block per call.
+
+use crate::chip::pac::FLASH;
+use crate::cmd::Decimal;
+use crate::config;
+use crate::fix::GpsFix;
+use crate::flash::{self, Flash};
+use crate::nmea::{encode, Date, Position, Rmc, Time};
+use crate::storage::{self, Error, Partition, Storage};
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+/// First flash page of the log, the pages after it up to the configuration pages are reserved
+pub const FIRST_PAGE: usize = config::FIRST_PAGE - config::LOG_PAGES;
+pub const PAGES: usize = config::LOG_PAGES;
+
+/// Bytes per record, a multiple of the flash programming granularity
+pub const RECORD_LEN: usize = 16;
+
+pub const SLOTS_PER_PAGE: usize = flash::PAGE_SIZE / RECORD_LEN;
+
+/// Records kept before the oldest are dropped, one page is always erased
+pub const CAPACITY: usize = (PAGES - 1) * SLOTS_PER_PAGE;
+
+/// The CRC, the low half of a CRC-32 over the bytes before it
+const CRC: Range<usize> = 14..16;
+
+/// Output of the `DUMP` command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One RMC sentence per record
+    Nmea,
+    Csv,
+}
+
+/// A logged fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub date: Date,
+    /// Whole seconds
+    pub time: Time,
+    /// Latitude in 1e-7 degrees, north positive
+    pub lat: i32,
+    /// Longitude in 1e-7 degrees, east positive
+    pub lon: i32,
+    /// Speed over ground in centimetres per second
+    pub speed: u16,
+}
+
+impl Record {
+    /// Record of a valid fix with a date and time within the years 2000 to 2063
+    pub fn from_fix(fix: &GpsFix) -> Option<Self> {
+        let (Some(date), Some(time)) = (fix.date, fix.timestamp) else {
+            return None;
+        };
+        if !fix.is_valid() || !(2000..2064).contains(&date.year) {
+            return None;
+        }
+        Some(Self {
+            date,
+            time: Time {
+                millisecond: 0,
+                ..time
+            },
+            lat: fix.lat,
+            lon: fix.lon,
+            speed: (fix.speed / 10).try_into().unwrap_or(u16::MAX),
+        })
+    }
+
+    /// Date and time in 32 bits: years since 2000, month, day, hour, minute and second in 6, 4,
+    /// 5, 5, 6 and 6 bits. The erased value of all ones has no valid month.
+    fn packed_time(&self) -> u32 {
+        u32::from(self.date.year - 2000) << 26
+            | u32::from(self.date.month) << 22
+            | u32::from(self.date.day) << 17
+            | u32::from(self.time.hour) << 12
+            | u32::from(self.time.minute) << 6
+            | u32::from(self.time.second)
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[0..4].copy_from_slice(&self.packed_time().to_le_bytes());
+        record[4..8].copy_from_slice(&self.lat.to_le_bytes());
+        record[8..12].copy_from_slice(&self.lon.to_le_bytes());
+        record[12..14].copy_from_slice(&self.speed.to_le_bytes());
+        storage::seal(&mut record, CRC);
+        record
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        if !storage::is_sealed(record, CRC) {
+            return None;
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                record[offset],
+                record[offset + 1],
+                record[offset + 2],
+                record[offset + 3],
+            ])
+        };
+        let packed = word(0);
+        let date = Date {
+            year: 2000 + (packed >> 26) as u16,
+            month: (packed >> 22 & 0xF) as u8,
+            day: (packed >> 17 & 0x1F) as u8,
+        };
+        let time = Time {
+            hour: (packed >> 12 & 0x1F) as u8,
+            minute: (packed >> 6 & 0x3F) as u8,
+            second: (packed & 0x3F) as u8,
+            millisecond: 0,
+        };
+        if !(1..=12).contains(&date.month)
+            || !(1..=31).contains(&date.day)
+            || time.hour > 23
+            || time.minute > 59
+            || time.second > 60
+        {
+            return None;
+        }
+        Some(Self {
+            date,
+            time,
+            lat: word(4) as i32,
+            lon: word(8) as i32,
+            speed: u16::from_le_bytes([record[12], record[13]]),
+        })
+    }
+}
+
+/// The log pages of internal flash
+pub fn pages(flash: &FLASH) -> Partition<Flash<'_>> {
+    Partition::new(Flash(flash), FIRST_PAGE, PAGES)
+}
+
+/// Write position in the log, the same storage is passed to every call
+pub struct Log {
+    /// Slot the next record is written to, counted from the start of the storage
+    next: usize,
+    /// Slots of the storage
+    slots: usize,
+    /// Packed time of the last record written
+    last_time: Option<u32>,
+    /// The block of the next record and the one after it are erased
+    ready: bool,
+    /// Next block to erase of an erase of the whole log
+    erasing: Option<usize>,
+}
+
+impl Log {
+    pub fn new<S: Storage>(storage: &mut S) -> Self {
+        let per_block = S::ERASE_SIZE / RECORD_LEN;
+        let blocks = storage.blocks();
+        let mut is_blank =
+            |slot: usize| storage.is_erased(slot * RECORD_LEN, RECORD_LEN) == Ok(true);
+        // The log ends in the written block followed by an erased one. Without one, e.g. after
+        // flashing over other data, start over at the first block.
+        let next = (0..blocks)
+            .find(|&block| {
+                !is_blank(block * per_block) && is_blank((block + 1) % blocks * per_block)
+            })
+            .map(|block| {
+                let first = block * per_block;
+                (first..first + per_block)
+                    .find(|&slot| is_blank(slot))
+                    .unwrap_or(first + per_block)
+                    % (blocks * per_block)
+            })
+            .unwrap_or(0);
+        Self {
+            next,
+            slots: blocks * per_block,
+            last_time: None,
+            // Within a block the one after it was found erased
+            ready: !next.is_multiple_of(per_block),
+            erasing: None,
+        }
+    }
+
+    /// Append a record of `fix` unless it is invalid, undated, within the second of the last
+    /// record or the next block is still to be erased by [`Log::prepare`]. Only programs the
+    /// storage, never erases it.
+    pub fn append<S: Storage>(&mut self, storage: &mut S, fix: &GpsFix) -> Result<(), Error> {
+        if !self.ready {
+            return Ok(());
+        }
+        let Some(record) = Record::from_fix(fix) else {
+            return Ok(());
+        };
+        let packed_time = record.packed_time();
+        if self.last_time == Some(packed_time) {
+            return Ok(());
+        }
+        self.last_time = Some(packed_time);
+        let slot = self.next;
+        // The slot is used up even if writing fails half way
+        self.next = (self.next + 1) % self.slots;
+        if self.next.is_multiple_of(S::ERASE_SIZE / RECORD_LEN) {
+            self.ready = false;
+        }
+        storage.write_slot(slot, &record.encode())
+    }
+
+    /// A block is full and the next one can't be entered, or the log is being erased, before
+    /// [`Log::prepare`] has run
+    pub fn needs_erase(&self) -> bool {
+        !self.ready
+    }
+
+    /// Erase a block: the next one of an erase of the whole log, else the block the next record
+    /// goes to or the one after it if they hold older records, so the block following the one
+    /// being written stays erased
+    pub fn prepare<S: Storage>(&mut self, storage: &mut S) -> Result<(), Error> {
+        if let Some(block) = self.erasing {
+            storage.erase_if_used(block)?;
+            self.erasing = Some(block + 1).filter(|&next| next < storage.blocks());
+            self.ready = self.erasing.is_none();
+            return Ok(());
+        }
+        if self.ready {
+            return Ok(());
+        }
+        let block = self.next / (S::ERASE_SIZE / RECORD_LEN);
+        for block in [block, (block + 1) % storage.blocks()] {
+            if !storage.is_erased(block * S::ERASE_SIZE, S::ERASE_SIZE)? {
+                return storage.erase(block);
+            }
+        }
+        self.ready = true;
+        Ok(())
+    }
+
+    /// Start over with an empty log, whose blocks [`Log::prepare`] then erases. Nothing is
+    /// appended until it has erased them all.
+    pub fn erase(&mut self) {
+        self.next = 0;
+        self.last_time = None;
+        self.ready = false;
+        self.erasing = Some(0);
+    }
+
+    /// Read the records back from the oldest, see [`Dump`]
+    pub fn dump(&self, format: Format) -> Dump {
+        Dump {
+            // A full lap from the write position, erased slots before the oldest are skipped
+            slot: self.next,
+            slots: self.slots,
+            remaining: self.slots,
+            format,
+            sent: 0,
+        }
+    }
+}
+
+/// Records being sent to the host, a few at a time as the TX buffer has room, read from the
+/// storage of the [`Log`].
+/// Appending must pause until the dump is done, else new records appear at its start.
+pub struct Dump {
+    slot: usize,
+    slots: usize,
+    /// Slots left to read
+    remaining: usize,
+    format: Format,
+    sent: u32,
+}
+
+impl Dump {
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Records consumed so far
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    /// Oldest record not yet consumed, skipping erased, unreadable and corrupt slots.
+    /// `None` once the whole log has been read.
+    pub fn peek(&mut self, storage: &mut impl Storage) -> Option<Record> {
+        while self.remaining > 0 {
+            let bytes = storage.read_slot::<RECORD_LEN>(self.slot);
+            if let Some(record) = bytes.ok().and_then(|bytes| Record::decode(&bytes)) {
+                return Some(record);
+            }
+            self.skip();
+        }
+        None
+    }
+
+    /// Move past the record returned by [`Dump::peek`] once it has been sent
+    pub fn consume(&mut self) {
+        if self.remaining > 0 {
+            self.skip();
+            self.sent += 1;
+        }
+    }
+
+    fn skip(&mut self) {
+        self.slot = (self.slot + 1) % self.slots;
+        self.remaining -= 1;
+    }
+}
+
+/// Write `record` as a line in `format`, including line ending
+pub fn write_record(out: &mut impl Write, record: &Record, format: Format) -> fmt::Result {
+    match format {
+        Format::Nmea => write_rmc(out, record),
+        Format::Csv => write_csv(out, record),
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ,<lat>,<lon>,<speed m/s>`
+fn write_csv(out: &mut impl Write, record: &Record) -> fmt::Result {
+    let Record {
+        date, time, speed, ..
+    } = record;
+    write!(
+        out,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z,{},{},{}.{:02}\r\n",
+        date.year,
+        date.month,
+        date.day,
+        time.hour,
+        time.minute,
+        time.second,
+        Decimal(record.lat.into(), 7),
+        Decimal(record.lon.into(), 7),
+        speed / 100,
+        speed % 100
+    )
+}
+
+/// Valid RMC sentence with an empty course
+fn write_rmc(out: &mut impl Write, record: &Record) -> fmt::Result {
+    let rmc = Rmc {
+        time: Some(record.time),
+        valid: true,
+        position: Some(Position {
+            latitude: record.lat,
+            longitude: record.lon,
+        }),
+        speed: Some(u32::from(record.speed) * 10),
+        course: None,
+        date: Some(record.date),
+    };
+    encode::write_rmc(out, &rmc)
+}
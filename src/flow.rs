@@ -0,0 +1,46 @@
+//! RTS/CTS flow control on USART2, enabled by the `flow-control` feature.
+//!
+//! CTS is handled by the USART, which holds transmission while the host deasserts it. It must
+//! be PA0, the only USART2_CTS pin on the STM32L432KC (alternate function 7).
+//! RTS is driven as a GPIO output on [`RTS_PIN`] rather than by the USART: it is deasserted
+//! while the TX queue is above [`HIGH_WATER`], so the host pauses sending commands whose
+//! responses could not be queued, and asserted again below [`LOW_WATER`].
+
+use crate::chip::pac::{usart1, GPIOA};
+use crate::dma::TX_BUFFER_SIZE;
+
+/// GPIOA pin of the host RTS input, active low
+pub const RTS_PIN: u8 = 1;
+
+const CTS_PIN: u8 = 0;
+
+/// Queued TX bytes above which RTS is deasserted
+pub const HIGH_WATER: usize = TX_BUFFER_SIZE * 3 / 4;
+
+/// Queued TX bytes below which RTS is asserted again
+pub const LOW_WATER: usize = TX_BUFFER_SIZE / 4;
+
+/// Configure CTS as alternate function and RTS as asserted output, and enable CTS in USART2.
+/// GPIOA clock must be enabled and USART2 disabled.
+pub fn init(gpioa: &GPIOA, usart2: &usart1::RegisterBlock) {
+    // SAFETY: resets the RTS pin only
+    gpioa.bsrr.write(|w| unsafe { w.bits(1 << (RTS_PIN + 16)) });
+    // SAFETY: only the two bits of each pin are changed, 0b10 is alternate and 0b01 output mode
+    gpioa.moder.modify(|r, w| unsafe {
+        let bits = r.bits() & !(0b11 << (2 * CTS_PIN)) & !(0b11 << (2 * RTS_PIN));
+        w.bits(bits | 0b10 << (2 * CTS_PIN) | 0b01 << (2 * RTS_PIN))
+    });
+    gpioa.afrl.modify(|_, w| w.afrl0().af7());
+    usart2.cr3.modify(|_, w| w.ctse().enabled());
+}
+
+/// Deassert or assert RTS depending on the number of queued TX bytes
+pub fn update(queued: usize) {
+    // SAFETY: BSRR writes are atomic and only affect the RTS pin
+    let gpioa = unsafe { &*GPIOA::ptr() };
+    if queued > HIGH_WATER {
+        gpioa.bsrr.write(|w| unsafe { w.bits(1 << RTS_PIN) });
+    } else if queued < LOW_WATER {
+        gpioa.bsrr.write(|w| unsafe { w.bits(1 << (RTS_PIN + 16)) });
+    }
+}
@@ -0,0 +1,188 @@
+//! Circular geofence zones, reporting when the fix enters or leaves one as
+//! `$PBRIDGE,GEOFENCE,<id>,ENTER|EXIT*hh`. Zones are saved with the configuration, see
+//! [`crate::config::Zone`].
+//!
+//! Distances use an equirectangular approximation on a spherical earth, within a fraction of a
+//! percent for zones up to tens of kilometres. Leaving a zone takes [`HYSTERESIS_M`] beyond its
+//! radius so position noise at the boundary doesn't report a stream of transitions.
+//! With the `geofence-alarm` feature PB0 (D3) is driven high while the fix is inside any zone.
+
+use crate::config::{Zone, MAX_ZONES};
+use crate::fix::GpsFix;
+use crate::nmea;
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+/// Margin beyond the radius before a fix counts as outside again
+pub const HYSTERESIS_M: u32 = 10;
+
+/// Mean earth radius in metres
+const EARTH_RADIUS_M: f32 = 6_371_000.0;
+
+/// Metres per 1e-7 degrees of latitude
+const M_PER_UNIT: f32 = EARTH_RADIUS_M * core::f32::consts::PI / 180.0 / 1e7;
+
+/// Longitude span of a full turn in 1e-7 degrees
+const FULL_TURN: i64 = 3_600_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Enter,
+    Exit,
+}
+
+impl Transition {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transition::Enter => "ENTER",
+            Transition::Exit => "EXIT",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Index of the zone
+    pub zone: usize,
+    pub transition: Transition,
+}
+
+/// Zone index beyond the zones defined, or all [`MAX_ZONES`] are in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidZone;
+
+/// Zones and which of them the fix is inside of
+pub struct Geofence {
+    zones: Vec<Zone, MAX_ZONES>,
+    /// Bit per zone the fix is inside of
+    inside: u8,
+    /// Bit per zone whose side is known, set by the first valid fix after the zone changed
+    known: u8,
+}
+
+impl Geofence {
+    pub fn new(zones: Vec<Zone, MAX_ZONES>) -> Self {
+        Self {
+            zones,
+            inside: 0,
+            known: 0,
+        }
+    }
+
+    pub fn zones(&self) -> &Vec<Zone, MAX_ZONES> {
+        &self.zones
+    }
+
+    /// Replace zone `id`, or add it if `id` is the number of zones
+    pub fn set(&mut self, id: usize, zone: Zone) -> Result<(), InvalidZone> {
+        if id < self.zones.len() {
+            self.zones[id] = zone;
+        } else if id == self.zones.len() {
+            self.zones.push(zone).map_err(|_| InvalidZone)?;
+        } else {
+            return Err(InvalidZone);
+        }
+        self.inside &= !(1 << id);
+        self.known &= !(1 << id);
+        Ok(())
+    }
+
+    /// Remove all zones. The fix leaves them without reporting it.
+    pub fn clear(&mut self) {
+        self.zones.clear();
+        self.inside = 0;
+        self.known = 0;
+    }
+
+    /// The fix is inside at least one zone
+    pub fn is_inside_any(&self) -> bool {
+        self.inside != 0
+    }
+
+    /// Check the fix against each zone and return the transitions. The first valid fix after
+    /// a zone changed only reports entering it.
+    pub fn update(&mut self, fix: &GpsFix) -> Vec<Event, MAX_ZONES> {
+        let mut events = Vec::new();
+        if !fix.is_valid() {
+            return events;
+        }
+        for (id, zone) in self.zones.iter().enumerate() {
+            let bit = 1 << id;
+            let was_inside = self.inside & bit != 0;
+            let limit = if was_inside {
+                zone.radius_m.saturating_add(HYSTERESIS_M)
+            } else {
+                zone.radius_m
+            } as f32;
+            let inside = distance_squared_m(zone, fix) <= limit * limit;
+            let transition = match (self.known & bit != 0, was_inside, inside) {
+                (_, false, true) => Some(Transition::Enter),
+                (true, true, false) => Some(Transition::Exit),
+                _ => None,
+            };
+            if let Some(transition) = transition {
+                // One event per zone fits
+                let _ = events.push(Event {
+                    zone: id,
+                    transition,
+                });
+            }
+            self.known |= bit;
+            if inside {
+                self.inside |= bit;
+            } else {
+                self.inside &= !bit;
+            }
+        }
+        events
+    }
+}
+
+/// Squared distance between the zone center and the fix in square metres
+fn distance_squared_m(zone: &Zone, fix: &GpsFix) -> f32 {
+    let dlat = (i64::from(fix.lat) - i64::from(zone.lat)) as f32 * M_PER_UNIT;
+    // Shortest way around, across the antimeridian if need be
+    let dlon = (i64::from(fix.lon) - i64::from(zone.lon) + FULL_TURN / 2).rem_euclid(FULL_TURN)
+        - FULL_TURN / 2;
+    let mean_lat =
+        (i64::from(fix.lat) + i64::from(zone.lat)) as f32 / 2.0 * M_PER_UNIT / EARTH_RADIUS_M;
+    let dlon = dlon as f32 * M_PER_UNIT * cos(mean_lat);
+    dlat * dlat + dlon * dlon
+}
+
+/// Cosine of a latitude in radians by its Taylor series, within 3e-5 up to the poles
+fn cos(x: f32) -> f32 {
+    let x2 = x * x;
+    1.0 - x2 / 2.0 * (1.0 - x2 / 12.0 * (1.0 - x2 / 30.0 * (1.0 - x2 / 56.0)))
+}
+
+/// Write the `$PBRIDGE,GEOFENCE,<id>,ENTER|EXIT*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, event: &Event) -> fmt::Result {
+    let mut body = String::<32>::new();
+    write!(
+        body,
+        "PBRIDGE,GEOFENCE,{},{}",
+        event.zone,
+        event.transition.name()
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Alarm output on PB0, configured as push-pull output low. GPIOB clock is enabled here.
+#[cfg(feature = "geofence-alarm")]
+pub fn init_alarm(rcc: &crate::chip::pac::RCC, gpiob: &crate::chip::pac::GPIOB) {
+    rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+    set_alarm(false);
+    gpiob.moder.modify(|_, w| w.moder0().output());
+}
+
+/// Drive the alarm output
+#[cfg(feature = "geofence-alarm")]
+pub fn set_alarm(on: bool) {
+    // SAFETY: BSRR writes are atomic and only touch PB0, other GPIOB pins are left to their users
+    let gpiob = unsafe { &*crate::chip::pac::GPIOB::ptr() };
+    // Lower half of BSRR sets pins, upper half resets them
+    let bit = if on { 1 } else { 1 << 16 };
+    // SAFETY: zero bits have no effect
+    gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+}
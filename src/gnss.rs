@@ -0,0 +1,183 @@
+//! Satellite systems used by the receiver, selected by the `GNSS` host command.
+//!
+//! The selection is sent with UBX-CFG-GNSS, followed by UBX-CFG-CFG saving the navigation
+//! configuration to the receiver's battery backed RAM, so it survives a GPS power cycle while the
+//! backup supply lasts. It is reported as selected once the receiver acknowledges UBX-CFG-GNSS.
+//! Changing it restarts the receiver's tracking.
+//!
+//! The u-blox 7 of the GP-735T tracks GPS or GLONASS, not both at once. SBAS and QZSS augment
+//! GPS.
+
+use crate::ubx;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use heapless::Vec;
+
+pub const UBX_ID_CFG_CFG: u8 = 0x09;
+pub const UBX_ID_CFG_GNSS: u8 = 0x3E;
+
+/// UBX-CFG-CFG section of the navigation configuration, which includes UBX-CFG-GNSS
+const CFG_NAV_CONF: u32 = 1 << 3;
+
+/// UBX-CFG-CFG device battery backed RAM
+const DEVICE_BBR: u8 = 1 << 0;
+
+/// UBX-CFG-GNSS numTrkChUse, all channels the hardware has
+const TRACKING_CHANNELS_ALL: u8 = 0xFF;
+
+/// UBX-CFG-GNSS and UBX-CFG-CFG
+pub type Frames = Vec<u8, { 2 * ubx::MAX_FRAME_LEN }>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum System {
+    Gps,
+    Sbas,
+    Qzss,
+    Glonass,
+}
+
+impl System {
+    pub const ALL: [System; 4] = [System::Gps, System::Sbas, System::Qzss, System::Glonass];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            System::Gps => "GPS",
+            System::Sbas => "SBAS",
+            System::Qzss => "QZSS",
+            System::Glonass => "GLONASS",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|system| name.eq_ignore_ascii_case(system.name().as_bytes()))
+    }
+
+    /// UBX gnssId
+    fn id(&self) -> u8 {
+        match self {
+            System::Gps => 0,
+            System::Sbas => 1,
+            System::Qzss => 5,
+            System::Glonass => 6,
+        }
+    }
+
+    /// Tracking channels reserved and at most used, the receiver defaults
+    fn channels(&self) -> (u8, u8) {
+        match self {
+            System::Gps => (4, 16),
+            System::Sbas => (1, 3),
+            System::Qzss => (0, 3),
+            System::Glonass => (8, 14),
+        }
+    }
+}
+
+/// Enabled systems, one bit per [`System`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    mask: u8,
+}
+
+impl Selection {
+    /// Receiver default: GPS with SBAS and QZSS
+    pub const DEFAULT: Selection = Selection {
+        mask: 1 << System::Gps as u8 | 1 << System::Sbas as u8 | 1 << System::Qzss as u8,
+    };
+
+    /// Selection of `systems`, `None` unless exactly one of GPS and GLONASS is among them
+    pub fn new(systems: impl IntoIterator<Item = System>) -> Option<Self> {
+        let selection = Self {
+            mask: systems
+                .into_iter()
+                .fold(0, |mask, system| mask | 1 << system as u8),
+        };
+        (selection.contains(System::Gps) != selection.contains(System::Glonass))
+            .then_some(selection)
+    }
+
+    pub fn contains(&self, system: System) -> bool {
+        self.mask & 1 << system as u8 != 0
+    }
+
+    /// Write the enabled systems as a comma separated list
+    pub fn write_systems(&self, out: &mut impl Write) -> fmt::Result {
+        let mut separator = "";
+        for system in System::ALL
+            .into_iter()
+            .filter(|&system| self.contains(system))
+        {
+            write!(out, "{}{}", separator, system.name())?;
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+static SELECTED: AtomicU8 = AtomicU8::new(Selection::DEFAULT.mask);
+
+/// Sent and not yet acknowledged, 0 if none as a selection is never empty
+static PENDING: AtomicU8 = AtomicU8::new(0);
+
+/// Selection last acknowledged by the receiver
+pub fn selected() -> Selection {
+    Selection {
+        mask: SELECTED.load(Ordering::Relaxed),
+    }
+}
+
+/// Wait for the acknowledgement of a selection once its frames are queued
+pub fn request(selection: Selection) {
+    PENDING.store(selection.mask, Ordering::Relaxed);
+}
+
+/// Apply the acknowledgement of a requested UBX-CFG-GNSS, returning the pending selection with
+/// whether it was accepted. Other acknowledgements return `None`.
+pub fn on_ack(ack: &ubx::Ack) -> Option<(Selection, bool)> {
+    let (accepted, class, id) = match *ack {
+        ubx::Ack::Ack { class, id } => (true, class, id),
+        ubx::Ack::Nak { class, id } => (false, class, id),
+    };
+    if class != ubx::CLASS_CFG || id != UBX_ID_CFG_GNSS {
+        return None;
+    }
+    let mask = PENDING.swap(0, Ordering::Relaxed);
+    if mask == 0 {
+        return None;
+    }
+    if accepted {
+        SELECTED.store(mask, Ordering::Relaxed);
+    }
+    Some((Selection { mask }, accepted))
+}
+
+/// UBX-CFG-GNSS with a configuration block per system followed by UBX-CFG-CFG saving it
+pub fn frames(selection: Selection) -> Frames {
+    let mut payload: Vec<u8, { 4 + 8 * System::ALL.len() }> = Vec::new();
+    // Message version 0, hardware channels are read-only
+    let _ = payload.extend_from_slice(&[0, 0, TRACKING_CHANNELS_ALL, System::ALL.len() as u8]);
+    for system in System::ALL {
+        let (reserved, max) = system.channels();
+        let enable = u32::from(selection.contains(system));
+        let _ = payload.extend_from_slice(&[system.id(), reserved, max, 0]);
+        let _ = payload.extend_from_slice(&enable.to_le_bytes());
+    }
+    let mut frames = Frames::new();
+    // Both payloads are well below the maximum
+    let _ = frames.extend_from_slice(
+        &ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_GNSS, &payload).unwrap_or_default(),
+    );
+    let _ = frames.extend_from_slice(&cfg_cfg_save());
+    frames
+}
+
+/// UBX-CFG-CFG saving the navigation configuration to battery backed RAM
+fn cfg_cfg_save() -> ubx::Frame {
+    let mut payload = [0; 13];
+    // Clear and load masks are left at 0
+    payload[4..8].copy_from_slice(&CFG_NAV_CONF.to_le_bytes());
+    payload[12] = DEVICE_BBR;
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_CFG, &payload).unwrap_or_default()
+}
@@ -0,0 +1,169 @@
+//! GPS supervision, power cycling the GP-735T through its power pin when it stops delivering.
+//!
+//! While the GPS is on, [`Supervisor::on_tick`] runs on every SysTick [`timer`](crate::timer)
+//! tick. Without a valid fix for the configured timeout, or without a valid sentence for
+//! [`DATA_TIMEOUT_MS`], the GPS is turned off for [`OFF_MS`] and reported to the host as
+//! `$PBRIDGE,WARN,GPS_TIMEOUT*hh`. The IWDG resets the MCU long before that if USART1 receives
+//! nothing at all, see [`crate::watchdog`]; the data timeout catches bytes that never form a
+//! sentence, once probing for the GPS baud by [`crate::autobaud`] found no rate that works.
+//!
+//! Each cycle that doesn't bring a fix doubles the fix timeout, up to [`MAX_BACKOFF`] doublings,
+//! so a receiver without sky view isn't restarted every few minutes. Only a fix held for
+//! [`STABLE_FIX_MS`] restores the configured timeout, a fix that comes and goes keeps backing
+//! off. Turning the GPS off by command pauses supervision, turning it on starts the timeouts over.
+
+use crate::nmea;
+use crate::psm;
+use crate::stats::STATS;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Default fix timeout, beyond the cold start time to first fix of the GP-735T
+pub const DEFAULT_FIX_TIMEOUT_S: u16 = 300;
+
+/// Shortest fix timeout accepted, a cold start takes about 30s with a clear sky
+pub const MIN_FIX_TIMEOUT_S: u16 = 60;
+
+/// Time without a valid sentence before the GPS is power cycled, plus the update period in power
+/// save mode
+pub const DATA_TIMEOUT_MS: u32 = 10_000;
+
+/// Time the GPS is kept off by a power cycle
+pub const OFF_MS: u32 = 2_000;
+
+/// Time a fix has to be held before the fix timeout is restored
+pub const STABLE_FIX_MS: u32 = 30_000;
+
+/// Most doublings of the fix timeout
+pub const MAX_BACKOFF: u8 = 3;
+
+static CYCLING: AtomicBool = AtomicBool::new(false);
+
+/// A power cycle is running, idle must not enter Stop mode where SysTick halts
+pub fn is_cycling() -> bool {
+    CYCLING.load(Ordering::Relaxed)
+}
+
+/// End a running power cycle without turning the GPS on, as the host or the button switched
+/// GPS power meanwhile
+pub fn cancel_cycle() {
+    CYCLING.store(false, Ordering::Relaxed);
+}
+
+/// Change of GPS power requested by the supervisor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Turn the GPS off and report the timeout
+    PowerOff,
+    PowerOn,
+}
+
+pub struct Supervisor {
+    /// Fix timeout in milliseconds, `None` disables supervision
+    fix_timeout_ms: Option<u32>,
+    /// Doublings of the fix timeout since the last stable fix
+    backoff: u8,
+    /// GPS power seen at the last tick
+    powered: bool,
+    /// Time the GPS is turned on again during a power cycle
+    off_until: Option<u32>,
+    last_fix: u32,
+    /// Start of the fix held since then
+    fix_since: Option<u32>,
+    last_sentence: u32,
+    /// Sentences counted at the last tick
+    sentences: u32,
+}
+
+impl Supervisor {
+    /// Supervise with a fix timeout of `fix_timeout_s`, 0 disables it
+    pub fn new(fix_timeout_s: u16) -> Self {
+        let mut supervisor = Self {
+            fix_timeout_ms: None,
+            backoff: 0,
+            powered: false,
+            off_until: None,
+            last_fix: 0,
+            fix_since: None,
+            last_sentence: 0,
+            sentences: STATS.sentences.get(),
+        };
+        supervisor.set_fix_timeout(fix_timeout_s);
+        supervisor
+    }
+
+    /// Fix timeout in seconds, 0 if disabled
+    pub fn fix_timeout_s(&self) -> u16 {
+        self.fix_timeout_ms.map_or(0, |ms| (ms / 1000) as u16)
+    }
+
+    /// Change the fix timeout, 0 disables supervision. The timeouts start over.
+    pub fn set_fix_timeout(&mut self, fix_timeout_s: u16) {
+        self.fix_timeout_ms = (fix_timeout_s > 0).then(|| u32::from(fix_timeout_s) * 1000);
+        self.backoff = 0;
+        // Restarts the timeouts at the next tick, a running power cycle still completes
+        self.powered = false;
+    }
+
+    /// Check the GPS at `now` milliseconds, see [`crate::timer::now_ms`]
+    pub fn on_tick(&mut self, now: u32, gps_power: bool, fix_valid: bool) -> Option<Action> {
+        if let Some(off_until) = self.off_until {
+            if !is_cycling() {
+                self.off_until = None;
+            } else if (now.wrapping_sub(off_until) as i32) < 0 {
+                return None;
+            } else {
+                self.off_until = None;
+                CYCLING.store(false, Ordering::Relaxed);
+                self.restart(now);
+                self.powered = true;
+                return Some(Action::PowerOn);
+            }
+        }
+        let timeout = self.fix_timeout_ms?;
+        if !gps_power {
+            self.powered = false;
+            return None;
+        }
+        if !self.powered {
+            self.restart(now);
+            self.powered = true;
+        }
+        let sentences = STATS.sentences.get();
+        if sentences != self.sentences {
+            self.sentences = sentences;
+            self.last_sentence = now;
+        }
+        if fix_valid {
+            self.last_fix = now;
+            let since = *self.fix_since.get_or_insert(now);
+            if now.wrapping_sub(since) >= STABLE_FIX_MS {
+                self.backoff = 0;
+            }
+        } else {
+            self.fix_since = None;
+        }
+        if now.wrapping_sub(self.last_fix) < timeout << self.backoff
+            && now.wrapping_sub(self.last_sentence) < DATA_TIMEOUT_MS + psm::update_period_ms()
+        {
+            return None;
+        }
+        self.backoff = (self.backoff + 1).min(MAX_BACKOFF);
+        self.off_until = Some(now.wrapping_add(OFF_MS));
+        CYCLING.store(true, Ordering::Relaxed);
+        Some(Action::PowerOff)
+    }
+
+    fn restart(&mut self, now: u32) {
+        self.last_fix = now;
+        self.fix_since = None;
+        self.last_sentence = now;
+        self.sentences = STATS.sentences.get();
+    }
+}
+
+/// Write the `$PBRIDGE,WARN,GPS_TIMEOUT*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write) -> fmt::Result {
+    const BODY: &str = "PBRIDGE,WARN,GPS_TIMEOUT";
+    write!(out, "${}*{:02X}\r\n", BODY, nmea::checksum(BODY.as_bytes()))
+}
@@ -0,0 +1,166 @@
+//! Small driver traits between the application and the peripherals it drives, so the GPS power
+//! switch and the UARTs can be provided by another board or by a mock.
+//!
+//! The implementations here sit on the PAC, with the same register access the drivers used
+//! inline before. Methods take `&self` as PAC registers are written through shared references,
+//! a mock keeps its state in a `Cell`.
+
+use crate::baud::{self, Unsupported};
+use crate::chip::pac::{usart1, GPIOA};
+
+/// Output switching the supply of the GPS
+pub trait PowerPin {
+    /// Switch the GPS on or off
+    fn set_power(&self, on: bool);
+    /// Level the pin is driven to
+    fn is_powered(&self) -> bool;
+    /// Level read back from the pin, differing from [`PowerPin::is_powered`] if it is shorted
+    fn level(&self) -> bool;
+}
+
+/// Receive errors flagged by a UART
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RxErrors {
+    pub overrun: bool,
+    /// Set along with the received byte it applies to
+    pub framing: bool,
+    /// Set along with the received byte it applies to
+    pub noise: bool,
+}
+
+impl RxErrors {
+    pub fn any(&self) -> bool {
+        self.overrun || self.framing || self.noise
+    }
+}
+
+/// UART with its interrupt sources, one byte at a time
+pub trait SerialPort {
+    /// Program the baud from a `clock_hz` kernel clock. The port must be disabled.
+    fn set_baud(&self, clock_hz: u32, baud: u32) -> Result<(), Unsupported>;
+    fn set_enabled(&self, enabled: bool);
+    /// Received byte, if any
+    fn read(&self) -> Option<u8>;
+    /// Send a byte if the transmit register is empty, false if it isn't
+    fn write(&self, byte: u8) -> bool;
+    /// Interrupt on an empty transmit register, while bytes are queued
+    fn listen_tx_empty(&self, enabled: bool);
+    /// The last byte written has been shifted out
+    fn is_transmit_complete(&self) -> bool;
+    /// Interrupt once the last byte has been shifted out, e.g. before a baud change
+    fn listen_transmit_complete(&self, enabled: bool);
+    fn clear_transmit_complete(&self);
+    /// The line went idle after a byte, cleared by the call
+    fn take_idle(&self) -> bool;
+    /// Errors flagged without clearing them, framing and noise refer to the byte to be read
+    fn errors(&self) -> RxErrors;
+    fn clear_errors(&self);
+}
+
+/// Pin of GPIOA driven as push-pull output
+pub struct GpioaOutput {
+    pin: u32,
+}
+
+impl GpioaOutput {
+    /// Configure `pin` as output, driven low until set. GPIOA clock must be enabled.
+    pub fn new(gpioa: &GPIOA, pin: u32) -> Self {
+        let output = Self { pin };
+        output.set_power(false);
+        // SAFETY: 0b01 is output mode, push-pull by default, other pins are left unchanged
+        gpioa
+            .moder
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << (2 * pin)) | 0b01 << (2 * pin)) });
+        output
+    }
+}
+
+impl PowerPin for GpioaOutput {
+    fn set_power(&self, on: bool) {
+        // SAFETY: BSRR writes are atomic and only touch this pin, other GPIOA pins are left to
+        // their users
+        let gpioa = unsafe { &*GPIOA::ptr() };
+        // Lower half of BSRR sets pins, upper half resets them
+        let bit = if on { 1 } else { 1 << 16 } << self.pin;
+        // SAFETY: zero bits have no effect
+        gpioa.bsrr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    fn is_powered(&self) -> bool {
+        // SAFETY: read-only access
+        let gpioa = unsafe { &*GPIOA::ptr() };
+        gpioa.odr.read().bits() & 1 << self.pin != 0
+    }
+
+    fn level(&self) -> bool {
+        // SAFETY: read-only access
+        let gpioa = unsafe { &*GPIOA::ptr() };
+        gpioa.idr.read().bits() & 1 << self.pin != 0
+    }
+}
+
+/// USART1 and USART2 share the register layout, both dereference to it
+impl SerialPort for usart1::RegisterBlock {
+    fn set_baud(&self, clock_hz: u32, baud: u32) -> Result<(), Unsupported> {
+        baud::set(self, clock_hz, baud)
+    }
+
+    fn set_enabled(&self, enabled: bool) {
+        self.cr1.modify(|_, w| w.ue().bit(enabled));
+    }
+
+    fn read(&self) -> Option<u8> {
+        // Reading RDR clears RXNE
+        self.isr
+            .read()
+            .rxne()
+            .bit_is_set()
+            .then(|| self.rdr.read().rdr().bits() as u8)
+    }
+
+    fn write(&self, byte: u8) -> bool {
+        let empty = self.isr.read().txe().bit_is_set();
+        if empty {
+            self.tdr.write(|w| w.tdr().bits(byte.into()));
+        }
+        empty
+    }
+
+    fn listen_tx_empty(&self, enabled: bool) {
+        self.cr1.modify(|_, w| w.txeie().bit(enabled));
+    }
+
+    fn is_transmit_complete(&self) -> bool {
+        self.isr.read().tc().bit_is_set()
+    }
+
+    fn listen_transmit_complete(&self, enabled: bool) {
+        self.cr1.modify(|_, w| w.tcie().bit(enabled));
+    }
+
+    fn clear_transmit_complete(&self) {
+        self.icr.write(|w| w.tccf().set_bit());
+    }
+
+    fn take_idle(&self) -> bool {
+        let idle = self.isr.read().idle().bit_is_set();
+        if idle {
+            self.icr.write(|w| w.idlecf().set_bit());
+        }
+        idle
+    }
+
+    fn errors(&self) -> RxErrors {
+        let isr = self.isr.read();
+        RxErrors {
+            overrun: isr.ore().bit_is_set(),
+            framing: isr.fe().bit_is_set(),
+            noise: isr.nf().bit_is_set(),
+        }
+    }
+
+    fn clear_errors(&self) {
+        self.icr
+            .write(|w| w.orecf().set_bit().fecf().set_bit().ncf().set_bit());
+    }
+}
@@ -0,0 +1,298 @@
+//! Assisted hot start, enabled by the `hot-start` feature, to cut the time to first fix of a GPS
+//! that is turned off between fixes, e.g. by the schedule.
+//!
+//! The latest valid fix is kept while the GPS is on. Once it is turned off, idle saves its
+//! position and time as a [`crate::kv`] record of the configuration storage, flash or the
+//! EEPROM, so it survives a reset or power loss. A record takes 24 bytes, a flash page lasts
+//! about 80 power cycles before it is compacted into the other one. The configuration records
+//! and the EEPROM belong to idle and FLASH is shared with USART1 and DMA1_CH7 only, so no lock
+//! USART2 needs is held meanwhile, see [`crate::sync`]. Idle erases the page a compaction writes
+//! ahead of it, see [`crate::kv::Store::prepare`], so saving only programs flash. The erase
+//! still stalls every instruction fetch for about 22ms on the single-bank L432 and L452, holding
+//! off every task, SysTick and USART2 included, only the dual-bank L476 keeps running code from
+//! the other bank. Each new fix is also kept in a [`Slot`] in RAM, so a reset while the GPS is
+//! on, which saves nothing, seeds it with the fix before the reset rather than the one saved
+//! when it was last turned off, see [`recovered`].
+//!
+//! When the GPS is turned on again it is sent UBX-AID-INI with the time of the RTC and the saved
+//! position, once it is heard from, as it ignores frames while it starts up. The GP-735T is a
+//! u-blox 7, which has no UBX-MGA messages. The time is left out until the RTC has been set, the
+//! position if it is more than [`MAX_AGE_DAYS`] old, and nothing is sent without either. The
+//! receiver may have been moved while off, so the position is given with an accuracy of
+//! [`POSITION_ACC_CM`] and only narrows the search for satellites.
+
+use crate::aid::CLASS_AID;
+use crate::fix::GpsFix;
+use crate::n2k::days_since_1970;
+use crate::nmea::{Date, Time};
+use crate::persist::Slot;
+use crate::ubx::{self, Frame};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+
+/// UBX-AID-INI
+pub const ID_AID_INI: u8 = 0x01;
+
+/// Bytes of the UBX-AID-INI payload
+const AID_INI_LEN: usize = 48;
+
+/// UBX-AID-INI flags: position valid, time valid, position as latitude, longitude and altitude,
+/// time as UTC date and time
+const FLAG_POS: u32 = 1 << 0;
+const FLAG_TIME: u32 = 1 << 1;
+const FLAG_LLA: u32 = 1 << 5;
+const FLAG_UTC: u32 = 1 << 10;
+
+/// Bytes of a saved position and time
+pub const SEED_LEN: usize = 19;
+
+/// A saved position older than this isn't sent
+pub const MAX_AGE_DAYS: u32 = 30;
+
+/// Accuracy sent with the position, the receiver may have been moved while off
+pub const POSITION_ACC_CM: u32 = 10_000_000;
+
+/// Accuracy sent with the RTC time, which drifts by its crystal while the GPS is off
+pub const TIME_ACC_S: u16 = 2;
+
+/// Marks the latest fix kept in RAM
+const LATEST_MAGIC: u32 = 0x5EED_F1C5;
+
+/// Encoded seed of the latest fix, written from idle only
+#[link_section = ".persist.HOTSTART"]
+static LATEST: Slot<[u8; SEED_LEN], LATEST_MAGIC> = Slot::new();
+
+/// Position and time of a valid fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seed {
+    /// Coordinates in 1e-7 degrees
+    pub lat: i32,
+    pub lon: i32,
+    /// Altitude above mean sea level in millimetres
+    pub altitude: i32,
+    pub date: Date,
+    pub time: Time,
+}
+
+impl Seed {
+    /// Position and time of `fix` if it is valid and dated
+    pub fn of(fix: &GpsFix) -> Option<Self> {
+        if !fix.is_valid() {
+            return None;
+        }
+        Some(Self {
+            lat: fix.lat,
+            lon: fix.lon,
+            altitude: fix.altitude,
+            date: fix.date?,
+            time: fix.timestamp?,
+        })
+    }
+
+    /// Little endian coordinates and altitude, then the date and time
+    pub fn encode(&self) -> [u8; SEED_LEN] {
+        let mut bytes = [0; SEED_LEN];
+        bytes[0..4].copy_from_slice(&self.lat.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.lon.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.altitude.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.date.year.to_le_bytes());
+        bytes[14] = self.date.month;
+        bytes[15] = self.date.day;
+        bytes[16] = self.time.hour;
+        bytes[17] = self.time.minute;
+        bytes[18] = self.time.second;
+        bytes
+    }
+
+    /// Decode a saved seed, `None` if it has the wrong length
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; SEED_LEN] = bytes.try_into().ok()?;
+        let i32_at = |offset: usize| {
+            i32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        Some(Self {
+            lat: i32_at(0),
+            lon: i32_at(4),
+            altitude: i32_at(8),
+            date: Date {
+                year: u16::from_le_bytes([bytes[12], bytes[13]]),
+                month: bytes[14],
+                day: bytes[15],
+            },
+            time: Time {
+                hour: bytes[16],
+                minute: bytes[17],
+                second: bytes[18],
+                millisecond: 0,
+            },
+        })
+    }
+}
+
+/// Latest saved seed, read by USART1 when the GPS is turned on
+static SAVED: Mutex<Cell<Option<Seed>>> = Mutex::new(Cell::new(None));
+
+/// The GPS was turned on and hasn't been sent the aiding yet
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Seed loaded from storage at boot, or saved since
+pub fn saved() -> Option<Seed> {
+    interrupt::free(|cs| SAVED.borrow(cs).get())
+}
+
+/// Set the seed sent at the next power on
+pub fn restore(seed: Option<Seed>) {
+    interrupt::free(|cs| SAVED.borrow(cs).set(seed));
+}
+
+/// The latest fix before the reset, kept in RAM since the GPS last had one, newer than the seed
+/// saved to the configuration storage if any. Called at startup, before idle runs.
+pub fn recovered() -> Option<Seed> {
+    // SAFETY: only idle writes it, which doesn't run yet
+    unsafe { LATEST.load() }.and_then(|bytes| Seed::decode(&bytes))
+}
+
+/// Send the aiding once the GPS is heard from, called when it is turned on
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// The aiding was requested since the last call
+pub fn take_request() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Follows the GPS from idle for the seed to save when it is turned off
+pub struct Tracker {
+    powered: bool,
+    /// Latest valid fix since the GPS was turned on
+    latest: Option<Seed>,
+}
+
+impl Tracker {
+    pub const fn new() -> Self {
+        Self {
+            powered: false,
+            latest: None,
+        }
+    }
+
+    /// Check the GPS and its fix, returning the seed to save once it is turned off
+    pub fn on_idle(&mut self, gps_power: bool, fix: &GpsFix) -> Option<Seed> {
+        let was_powered = core::mem::replace(&mut self.powered, gps_power);
+        if gps_power {
+            if let Some(seed) = Seed::of(fix).filter(|&seed| self.latest != Some(seed)) {
+                // SAFETY: only idle accesses it once running
+                unsafe { LATEST.store(seed.encode()) };
+                self.latest = Some(seed);
+            }
+            return None;
+        }
+        if !was_powered {
+            return None;
+        }
+        let seed = self.latest.take()?;
+        restore(Some(seed));
+        Some(seed)
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// UBX-AID-INI frame with the current UTC time of the RTC, `None` before it is set, and the
+/// saved seed, `None` if there is neither
+pub fn frame(now: Option<(Date, Time)>) -> Option<Frame> {
+    let seed = saved().filter(|seed| match now {
+        Some((date, _)) => {
+            days_since_1970(date).saturating_sub(days_since_1970(seed.date)) <= MAX_AGE_DAYS
+        }
+        None => true,
+    });
+    if now.is_none() && seed.is_none() {
+        return None;
+    }
+    ubx::encode(CLASS_AID, ID_AID_INI, &aid_ini(now, seed.as_ref())).ok()
+}
+
+/// UBX-AID-INI payload, the time applying on receipt, altitude in centimetres
+fn aid_ini(now: Option<(Date, Time)>, seed: Option<&Seed>) -> [u8; AID_INI_LEN] {
+    let mut payload = [0; AID_INI_LEN];
+    let mut flags = 0;
+    if let Some(seed) = seed {
+        payload[0..4].copy_from_slice(&seed.lat.to_le_bytes());
+        payload[4..8].copy_from_slice(&seed.lon.to_le_bytes());
+        payload[8..12].copy_from_slice(&(seed.altitude / 10).to_le_bytes());
+        payload[12..16].copy_from_slice(&POSITION_ACC_CM.to_le_bytes());
+        flags |= FLAG_POS | FLAG_LLA;
+    }
+    if let Some((date, time)) = now {
+        // Year since 2000 and month as YYMM, day and time as DDHHMMSS
+        let date_field = date.year.saturating_sub(2000) * 100 + u16::from(date.month);
+        let time_field = u32::from(date.day) * 1_000_000
+            + u32::from(time.hour) * 10_000
+            + u32::from(time.minute) * 100
+            + u32::from(time.second);
+        payload[18..20].copy_from_slice(&date_field.to_le_bytes());
+        payload[20..24].copy_from_slice(&time_field.to_le_bytes());
+        payload[24..28].copy_from_slice(&(u32::from(time.millisecond) * 1_000_000).to_le_bytes());
+        payload[28..32].copy_from_slice(&(u32::from(TIME_ACC_S) * 1000).to_le_bytes());
+        flags |= FLAG_TIME | FLAG_UTC;
+    }
+    payload[44..48].copy_from_slice(&flags.to_le_bytes());
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aid_ini_carries_the_utc_time_and_position() {
+        let date = Date {
+            year: 2024,
+            month: 3,
+            day: 9,
+        };
+        let time = Time {
+            hour: 14,
+            minute: 5,
+            second: 7,
+            millisecond: 250,
+        };
+        let seed = Seed {
+            lat: 515_000_000,
+            lon: -1_250_000,
+            altitude: 12_345,
+            date,
+            time,
+        };
+        let payload = aid_ini(Some((date, time)), Some(&seed));
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u32_at(0) as i32, 515_000_000);
+        assert_eq!(u32_at(4) as i32, -1_250_000);
+        assert_eq!(u32_at(8), 1234);
+        assert_eq!(u32_at(12), POSITION_ACC_CM);
+        assert_eq!(u16::from_le_bytes([payload[18], payload[19]]), 2403);
+        assert_eq!(u32_at(20), 9_140_507);
+        assert_eq!(u32_at(24), 250_000_000);
+        assert_eq!(u32_at(28), 2000);
+        assert_eq!(u32_at(44), 0x0423);
+    }
+
+    #[test]
+    fn aid_ini_leaves_out_an_unset_time() {
+        let payload = aid_ini(None, None);
+        assert!(payload.iter().all(|&byte| byte == 0));
+    }
+}
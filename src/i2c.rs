@@ -0,0 +1,180 @@
+//! I2C1 as a blocking master on PB6 SCL (D5) and PB7 SDA (D4), alternate function 4, open
+//! drain, for the `display` and `eeprom` features.
+//!
+//! Transfers run at 400kHz and poll the status flags, so they block for their duration and are
+//! preempted by interrupts. The Nucleo-L432KC ties PB7 to PA5, the PPS input, through SB18,
+//! which must be removed. SB16 ties PB6 to the unused PA6.
+
+use crate::chip::pac::{i2c1, GPIOB, I2C1, RCC};
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+/// 400kHz from HSI16: PRESC 1, SCLDEL 3, SDADEL 2, SCLH 3, SCLL 9, reference manual table 235
+const TIMINGR: u32 = 0x1032_0309;
+
+/// Polls of a status flag before a transfer is given up, far longer than a byte at 400kHz
+const TIMEOUT_POLLS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The device didn't acknowledge, e.g. it isn't connected or is busy
+    Nack,
+    /// Bus error or lost arbitration
+    Bus,
+    /// A flag didn't come up in time, e.g. SCL held low
+    Timeout,
+}
+
+impl i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Error::Bus => ErrorKind::Bus,
+            Error::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+/// I2C1 as a blocking master
+pub struct I2c {
+    i2c1: I2C1,
+}
+
+impl I2c {
+    /// Configure I2C1 on PB6 and PB7, clocked from HSI16. GPIOB and I2C1 clocks are enabled
+    /// here.
+    pub fn new(rcc: &RCC, gpiob: &GPIOB, i2c1: I2C1) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb1enr1.modify(|_, w| w.i2c1en().set_bit());
+        // HSI16 is started by power::init for USART2
+        rcc.ccipr.modify(|_, w| w.i2c1sel().hsi16());
+
+        gpiob
+            .otyper
+            .modify(|_, w| w.ot6().open_drain().ot7().open_drain());
+        gpiob.afrl.modify(|_, w| w.afrl6().af4().afrl7().af4());
+        gpiob
+            .moder
+            .modify(|_, w| w.moder6().alternate().moder7().alternate());
+
+        // SAFETY: timing computed for the 16MHz kernel clock
+        i2c1.timingr.write(|w| unsafe { w.bits(TIMINGR) });
+        i2c1.cr1.write(|w| w.pe().set_bit());
+        Self { i2c1 }
+    }
+
+    /// Wait for `flag`, failing on NACK, bus errors or timeout
+    fn wait(&self, flag: impl Fn(&i2c1::isr::R) -> bool) -> Result<(), Error> {
+        for _ in 0..TIMEOUT_POLLS {
+            let isr = self.i2c1.isr.read();
+            if isr.nackf().bit_is_set() {
+                return Err(Error::Nack);
+            }
+            if isr.berr().bit_is_set() || isr.arlo().bit_is_set() {
+                return Err(Error::Bus);
+            }
+            if flag(&isr) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Transfer one operation in chunks of up to 255 bytes, starting with a (repeated) start
+    /// condition and ending with a stop condition if `last`
+    fn operation(
+        &mut self,
+        address: u8,
+        operation: &mut Operation<'_>,
+        last: bool,
+    ) -> Result<(), Error> {
+        let (read, len) = match operation {
+            Operation::Read(buffer) => (true, buffer.len()),
+            Operation::Write(bytes) => (false, bytes.len()),
+        };
+        let mut done = 0;
+        let mut start = true;
+        loop {
+            let chunk = (len - done).min(255);
+            let reload = done + chunk < len;
+            self.i2c1.cr2.write(|w| {
+                w.sadd()
+                    .bits(u16::from(address) << 1)
+                    .rd_wrn()
+                    .bit(read)
+                    .nbytes()
+                    .bits(chunk as u8)
+                    .reload()
+                    .bit(reload)
+                    .autoend()
+                    .bit(last && !reload)
+                    .start()
+                    .bit(start)
+            });
+            start = false;
+            for i in done..done + chunk {
+                match operation {
+                    Operation::Read(buffer) => {
+                        self.wait(|isr| isr.rxne().bit_is_set())?;
+                        buffer[i] = self.i2c1.rxdr.read().rxdata().bits();
+                    }
+                    Operation::Write(bytes) => {
+                        self.wait(|isr| isr.txis().bit_is_set())?;
+                        self.i2c1.txdr.write(|w| w.txdata().bits(bytes[i]));
+                    }
+                }
+            }
+            done += chunk;
+            if !reload {
+                break;
+            }
+            self.wait(|isr| isr.tcr().bit_is_set())?;
+        }
+        if last {
+            self.wait(|isr| isr.stopf().bit_is_set())?;
+            self.i2c1.icr.write(|w| w.stopcf().set_bit());
+        } else {
+            self.wait(|isr| isr.tc().bit_is_set())?;
+        }
+        Ok(())
+    }
+
+    /// Release the bus after a failed transfer
+    fn recover(&mut self) {
+        if self.i2c1.isr.read().busy().bit_is_set() {
+            self.i2c1.cr2.modify(|_, w| w.stop().set_bit());
+        }
+        // Toggling PE resets the state machine and flags, it must stay low for 3 APB cycles
+        self.i2c1.cr1.modify(|_, w| w.pe().clear_bit());
+        cortex_m::asm::delay(3);
+        self.i2c1.cr1.modify(|_, w| w.pe().set_bit());
+        self.i2c1.icr.write(|w| {
+            w.nackcf()
+                .set_bit()
+                .stopcf()
+                .set_bit()
+                .berrcf()
+                .set_bit()
+                .arlocf()
+                .set_bit()
+        });
+    }
+}
+
+impl ErrorType for I2c {
+    type Error = Error;
+}
+
+impl i2c::I2c for I2c {
+    /// Consecutive operations of the same kind aren't merged, each starts with a repeated
+    /// start. The display only sends single writes, the EEPROM a write and a read.
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        let count = operations.len();
+        for (i, operation) in operations.iter_mut().enumerate() {
+            if let Err(error) = self.operation(address, operation, i + 1 == count) {
+                self.recover();
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! The latest fix as registers of an I2C slave on I2C1, enabled by the `i2c-slave` feature, so a
+//! host such as an Arduino reads it over two wires instead of parsing the UART stream.
+//!
+//! The bridge answers at [`ADDRESS`], `BRIDGE_I2C_ADDRESS` at build time. A write sets the
+//! register pointer, further bytes written are ignored, and a read returns the registers from
+//! the pointer on, 0xFF past the last. The registers are copied when the bridge is addressed for
+//! reading, so all bytes of a read belong to one fix. Registers 0x00 to 0x20 hold the binary fix
+//! record of `MODE BIN`, latitude at 0x0A, longitude at 0x0E, altitude at 0x12, speed at 0x16
+//! and the UTC date and time from 0x02, all little endian, followed by [`STATUS`].
+//!
+//! Pins: PB6 SCL (D5), PB7 SDA (D4) as alternate function 4, open drain, those of the display,
+//! which can't be combined with it. The Nucleo-L432KC ties PB7 to PA5 through SB18, which must
+//! be removed. The host pulls both lines up. Clock stretching holds the bus while the interrupt
+//! handler is preempted, and an address match wakes the MCU from Stop mode.
+
+#[cfg(feature = "display")]
+compile_error!("feature `i2c-slave` takes I2C1 and its pins from `display`");
+
+use crate::chip::pac::{GPIOB, I2C1, RCC};
+use crate::defaults;
+use crate::fix::GpsFix;
+use crate::protocol::FixRecord;
+use crate::quality;
+
+/// 7-bit slave address, `BRIDGE_I2C_ADDRESS`
+pub const ADDRESS: u8 = defaults::I2C_ADDRESS;
+
+/// Register of the status bits: [`STATUS_POWER`], [`STATUS_VALID`] and [`STATUS_QUALITY`]
+pub const STATUS: usize = FixRecord::LEN;
+
+/// The GPS is powered
+pub const STATUS_POWER: u8 = 1 << 0;
+/// The fix is valid, 2D or 3D
+pub const STATUS_VALID: u8 = 1 << 1;
+/// The fix passes the [`crate::quality`] gate
+pub const STATUS_QUALITY: u8 = 1 << 2;
+
+/// Number of registers
+pub const LEN: usize = STATUS + 1;
+
+/// Read past the last register
+const PAST_END: u8 = 0xFF;
+
+/// Data setup and hold times of the 400kHz timing from HSI16 of the I2C master, the only fields
+/// of TIMINGR a slave uses
+const TIMINGR: u32 = 0x1032_0309;
+
+/// Register contents
+pub type Registers = [u8; LEN];
+
+/// Registers for `fix`
+pub fn registers(fix: &GpsFix, gps_power: bool) -> Registers {
+    let mut registers = [0; LEN];
+    registers[..STATUS].copy_from_slice(&fix.record().to_bytes());
+    let mut status = 0;
+    if gps_power {
+        status |= STATUS_POWER;
+    }
+    if fix.is_valid() {
+        status |= STATUS_VALID;
+    }
+    if quality::gate().passes(fix) {
+        status |= STATUS_QUALITY;
+    }
+    registers[STATUS] = status;
+    registers
+}
+
+/// What the master asked for, see [`Slave::on_event`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The master reads, the registers have to be copied with [`Slave::load`]
+    Read,
+    /// Nothing to do
+    None,
+}
+
+pub struct Slave {
+    i2c1: I2C1,
+    /// Register read or set next
+    pointer: usize,
+    /// The first byte of a write is the pointer
+    pointer_written: bool,
+    /// Copy of the registers being read
+    registers: Registers,
+}
+
+impl Slave {
+    /// Route PB6 and PB7 to I2C1 and answer at [`ADDRESS`]. The address, receive, transmit and
+    /// stop interrupts are enabled.
+    pub fn new(rcc: &RCC, gpiob: &GPIOB, i2c1: I2C1) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb1enr1.modify(|_, w| w.i2c1en().set_bit());
+        // HSI16 is started by power::init for USART2 and by I2C1 itself in Stop mode
+        rcc.ccipr.modify(|_, w| w.i2c1sel().hsi16());
+
+        gpiob
+            .otyper
+            .modify(|_, w| w.ot6().open_drain().ot7().open_drain());
+        gpiob.afrl.modify(|_, w| w.afrl6().af4().afrl7().af4());
+        gpiob
+            .moder
+            .modify(|_, w| w.moder6().alternate().moder7().alternate());
+
+        // SAFETY: timing computed for the 16MHz kernel clock
+        i2c1.timingr.write(|w| unsafe { w.bits(TIMINGR) });
+        i2c1.oar1
+            .write(|w| w.oa1().bits(u16::from(ADDRESS) << 1).oa1en().set_bit());
+        i2c1.cr1.write(|w| {
+            w.addrie()
+                .set_bit()
+                .rxie()
+                .set_bit()
+                .txie()
+                .set_bit()
+                .stopie()
+                .set_bit()
+                .nackie()
+                .set_bit()
+                .errie()
+                .set_bit()
+                .wupen()
+                .set_bit()
+                .pe()
+                .set_bit()
+        });
+        Self {
+            i2c1,
+            pointer: 0,
+            pointer_written: false,
+            registers: [PAST_END; LEN],
+        }
+    }
+
+    /// Handle the event interrupt. The registers are loaded before the first byte is sent,
+    /// clock stretching holds the master meanwhile.
+    pub fn on_event(&mut self) -> Event {
+        let isr = self.i2c1.isr.read();
+        if isr.addr().bit_is_set() {
+            self.pointer_written = false;
+            if isr.dir().bit_is_set() {
+                // Drop a byte left in TXDR by a read the master ended early
+                self.i2c1.isr.write(|w| w.txe().set_bit());
+                return Event::Read;
+            }
+            self.i2c1.icr.write(|w| w.addrcf().set_bit());
+        }
+        if isr.rxne().bit_is_set() {
+            let byte = self.i2c1.rxdr.read().rxdata().bits();
+            if !self.pointer_written {
+                self.pointer = usize::from(byte);
+                self.pointer_written = true;
+            }
+        }
+        if isr.txis().bit_is_set() {
+            let byte = self
+                .registers
+                .get(self.pointer)
+                .copied()
+                .unwrap_or(PAST_END);
+            self.i2c1.txdr.write(|w| w.txdata().bits(byte));
+            self.pointer = self.pointer.saturating_add(1);
+        }
+        if isr.nackf().bit_is_set() {
+            // The master ends a read by not acknowledging its last byte
+            self.i2c1.icr.write(|w| w.nackcf().set_bit());
+        }
+        if isr.stopf().bit_is_set() {
+            self.i2c1.icr.write(|w| w.stopcf().set_bit());
+        }
+        Event::None
+    }
+
+    /// Copy the registers for a read and release the address phase
+    pub fn load(&mut self, registers: Registers) {
+        self.registers = registers;
+        self.i2c1.icr.write(|w| w.addrcf().set_bit());
+    }
+}
+
+/// Handle the error interrupt: clear bus errors, arbitration loss and overruns, which the master
+/// recovers from by retrying
+pub fn on_error() {
+    // SAFETY: ICR writes only clear the flags written as 1, the error flags are not used by
+    // Slave
+    let i2c1 = unsafe { &*I2C1::ptr() };
+    i2c1.icr
+        .write(|w| w.berrcf().set_bit().arlocf().set_bit().ovrcf().set_bit());
+}
@@ -0,0 +1,79 @@
+//! Command lines received on ports other than USART2, the USB virtual COM port with the `usb`
+//! feature and the Bluetooth module with the `ble` feature, the input side of
+//! [`crate::router`].
+//!
+//! Each port assembles lines in its own [`crate::cmd::LineBuffer`] in its interrupt handler and
+//! queues them here, then pends USART2, which runs them in order like its own lines. The
+//! response, and the lines `SATS` and `LOG EVENTS` send ahead of it, go back to the port the
+//! command came from whatever `ROUTE` selects. Up to [`QUEUE_LEN`] lines wait, a port answers a
+//! line arriving while they are all taken with `ERR BUSY` itself.
+//!
+//! A lone `0` or `1` toggles GPS power only on USART2. `AID`, `BRIDGE` and `DUMP`, which stream
+//! bytes on USART2, are answered `ERR HOSTONLY` on the other ports.
+
+use crate::cmd::{Error, MAX_LINE_LEN};
+use crate::router::Sink;
+use heapless::{Deque, Vec};
+
+/// Lines waiting for USART2
+pub const QUEUE_LEN: usize = 4;
+
+/// Line received on a port other than USART2
+pub struct Received {
+    /// The port, named like its sink
+    pub port: Sink,
+    line: Vec<u8, MAX_LINE_LEN>,
+    error: Option<Error>,
+}
+
+impl Received {
+    /// The line without its ending, or the error its line buffer reported instead
+    pub fn line(&self) -> Result<&[u8], Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(&self.line),
+        }
+    }
+}
+
+/// Queue of lines waiting for USART2
+pub struct Inbox {
+    lines: Deque<Received, QUEUE_LEN>,
+}
+
+impl Inbox {
+    pub const fn new() -> Self {
+        Self {
+            lines: Deque::new(),
+        }
+    }
+
+    /// Queue `line` received on `port`, false if the queue is full
+    pub fn push(&mut self, port: Sink, line: Result<&[u8], Error>) -> bool {
+        let received = match line {
+            Ok(line) => Received {
+                port,
+                // A line buffer holds no more than MAX_LINE_LEN bytes
+                line: Vec::from_slice(line).unwrap_or_default(),
+                error: None,
+            },
+            Err(error) => Received {
+                port,
+                line: Vec::new(),
+                error: Some(error),
+            },
+        };
+        self.lines.push_back(received).is_ok()
+    }
+
+    /// Take the oldest line
+    pub fn pop(&mut self) -> Option<Received> {
+        self.lines.pop_front()
+    }
+}
+
+impl Default for Inbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,118 @@
+//! Status LED on the board's LED pin, PA4 (A3) on the Nucleo or PA6 (A5) with `spi-slave`,
+//! enabled by the `indicator` feature. Active high, wire it with a series resistor to ground.
+//!
+//! The pattern shows the GPS state, see [`pattern`]: off while the GPS is powered down, fast
+//! blink for a while after bytes were lost to an overrun or a full queue, solid on with a 3D fix
+//! and a slow blink while searching. Each tick of the SysTick [`timer`](crate::timer) advances it,
+//! waking the core from Sleep mode but not from Stop mode.
+
+use crate::board;
+use crate::chip::pac::GPIOA;
+use crate::nmea::FixType;
+use crate::stats::STATS;
+use crate::timer::TICK_HZ;
+
+/// GPIOA pin of the LED
+pub const LED_PIN: u32 = board::PINS.led;
+
+/// Period of the blink while searching
+const SLOW_PERIOD_TICKS: u32 = TICK_HZ;
+
+/// Period of the blink after an overflow
+const FAST_PERIOD_TICKS: u32 = TICK_HZ / 5;
+
+/// Time the fast blink lasts after the last lost byte
+const OVERFLOW_TICKS: u32 = 2 * TICK_HZ;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Off,
+    Solid,
+    SlowBlink,
+    FastBlink,
+}
+
+impl Pattern {
+    /// LED state `ticks` into the pattern, blinks are lit for the first half of their period
+    pub fn is_lit(&self, ticks: u32) -> bool {
+        match self {
+            Pattern::Off => false,
+            Pattern::Solid => true,
+            Pattern::SlowBlink => ticks % SLOW_PERIOD_TICKS < SLOW_PERIOD_TICKS / 2,
+            Pattern::FastBlink => ticks % FAST_PERIOD_TICKS < FAST_PERIOD_TICKS / 2,
+        }
+    }
+}
+
+/// Pattern for the GPS state, an overflow shows over the fix
+pub fn pattern(gps_power: bool, fix_type: FixType, overflow: bool) -> Pattern {
+    if !gps_power {
+        Pattern::Off
+    } else if overflow {
+        Pattern::FastBlink
+    } else if fix_type == FixType::Fix3D {
+        Pattern::Solid
+    } else {
+        Pattern::SlowBlink
+    }
+}
+
+/// Advances the pattern on each tick of the SysTick timer
+pub struct Indicator {
+    ticks: u32,
+    /// Overruns and dropped bytes counted at the last tick
+    losses: u32,
+    /// Ticks left of the fast blink
+    overflow_ticks: u32,
+}
+
+impl Indicator {
+    /// Configure the LED pin as push-pull output low. GPIOA clock must be enabled.
+    pub fn new(gpioa: &GPIOA) -> Self {
+        off();
+        // SAFETY: 0b01 is output mode, other pins are left unchanged
+        gpioa.moder.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << (2 * LED_PIN)) | 0b01 << (2 * LED_PIN))
+        });
+        Self {
+            ticks: 0,
+            losses: losses(),
+            overflow_ticks: 0,
+        }
+    }
+
+    /// Advance the pattern by a tick, called from the SysTick interrupt
+    pub fn on_tick(&mut self, gps_power: bool, fix_type: FixType) {
+        self.ticks = self.ticks.wrapping_add(1);
+        let losses = losses();
+        // Fewer after `STATS CLR`
+        if losses > self.losses {
+            self.overflow_ticks = OVERFLOW_TICKS;
+        } else {
+            self.overflow_ticks = self.overflow_ticks.saturating_sub(1);
+        }
+        self.losses = losses;
+        let pattern = pattern(gps_power, fix_type, self.overflow_ticks > 0);
+        set_led(pattern.is_lit(self.ticks));
+    }
+}
+
+/// Turn the LED off, e.g. before entering Stop mode where SysTick doesn't run
+pub fn off() {
+    set_led(false);
+}
+
+fn set_led(on: bool) {
+    // SAFETY: BSRR writes are atomic and only touch the LED pin, other GPIOA pins are left to
+    // their users
+    let gpioa = unsafe { &*GPIOA::ptr() };
+    // Lower half of BSRR sets pins, upper half resets them
+    let bit = if on { 1 } else { 1 << 16 } << LED_PIN;
+    // SAFETY: zero bits have no effect
+    gpioa.bsrr.write(|w| unsafe { w.bits(bit) });
+}
+
+/// Bytes lost so far, overruns count as one each
+fn losses() -> u32 {
+    STATS.overruns.get().wrapping_add(STATS.dropped_bytes.get())
+}
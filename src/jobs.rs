@@ -0,0 +1,69 @@
+//! Commands USART2 hands to idle because they wait on storage for milliseconds, so no lock
+//! shared with USART2 is held across the wait, see [`crate::sync`]: `SAVE`, `SELFTEST`,
+//! `LOG EVENTS <n>` reading the EEPROM with the `eeprom` feature, and `DUMP` and `ERASE` of the
+//! flash log with the `flash-log` feature.
+//!
+//! USART2 parses the command, collects what it needs from its own state into a [`Job`] and
+//! queues it with the port the command came from, answering nothing itself. Idle runs the jobs
+//! in order and sends the response, and the lines `LOG EVENTS` sends ahead of it, to that port.
+//! The records of a `DUMP` follow its response.
+//! Up to [`QUEUE_LEN`] jobs wait, a command arriving while they are all taken is answered
+//! `ERR BUSY`.
+
+use crate::config::Config;
+#[cfg(feature = "flash-log")]
+use crate::flashlog::Format;
+use crate::router::Sink;
+use heapless::Deque;
+
+/// Jobs waiting for idle
+pub const QUEUE_LEN: usize = 2;
+
+/// Command run by idle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Job {
+    /// `SAVE` of the settings as USART2 found them
+    Save(Config),
+    /// `SELFTEST`
+    SelfTest,
+    /// `LOG EVENTS <n>`
+    #[cfg(feature = "eeprom")]
+    Events(u8),
+    /// `DUMP` of the flash log
+    #[cfg(feature = "flash-log")]
+    Dump(Format),
+    /// `ERASE` of the flash log
+    #[cfg(feature = "flash-log")]
+    Erase,
+}
+
+/// Queue of jobs waiting for idle
+pub struct Jobs {
+    jobs: Deque<(Sink, Job), QUEUE_LEN>,
+}
+
+impl Jobs {
+    pub const fn new() -> Self {
+        Self { jobs: Deque::new() }
+    }
+
+    /// Queue `job` of a command received on `port`, false if the queue is full
+    pub fn push(&mut self, port: Sink, job: Job) -> bool {
+        self.jobs.push_back((port, job)).is_ok()
+    }
+
+    /// Take the oldest job, with the port to answer
+    pub fn pop(&mut self) -> Option<(Sink, Job)> {
+        self.jobs.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,598 @@
+//! Append-only key-value records on a [`Storage`], so a changed setting writes a few bytes
+//! instead of a whole configuration and the storage is erased far less often, see
+//! [`crate::config`].
+//!
+//! The storage is split into two banks of half its blocks each. The current bank starts with a
+//! header, [`MAGIC`], a sequence number and a CRC, followed by records: the key, the length of
+//! the value, the value, zero padding and the low half of a CRC-32 of the bytes before it, a
+//! multiple of [`ALIGN`] bytes in all. The last record of a key holds its value, a torn record
+//! fails its CRC and is skipped.
+//!
+//! A value that doesn't fit in the current bank compacts it into the other one: that bank is
+//! erased, the latest record of every key is copied and the header is written last, with the
+//! next sequence number, so a reset halfway leaves the full bank current. Of two banks with a
+//! valid header the one with the later sequence number is current. The other bank is erased
+//! ahead of time by [`Store::prepare`], a block per call, so a compaction only programs.
+
+use crate::storage::{self, Error, Storage};
+
+/// Keys are below this
+pub const KEYS: usize = 16;
+
+/// Longest value
+pub const MAX_VALUE: usize = 64;
+
+/// Identifies the header of a bank, "KV"
+pub const MAGIC: u16 = 0x4B56;
+
+/// Records and the header are multiples of the flash programming granularity
+pub const ALIGN: usize = 8;
+
+/// Magic, sequence number and CRC
+const HEADER_LEN: usize = ALIGN;
+
+/// Key and length before the value, CRC after it
+const OVERHEAD: usize = 4;
+
+/// Key of an erased record, past the last record of a bank
+const ERASED: u8 = 0xFF;
+
+/// Bytes of a record holding `len` bytes of value
+const fn record_len(len: usize) -> usize {
+    (OVERHEAD + len).next_multiple_of(ALIGN)
+}
+
+/// Latest record of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    /// Offset of the record in the bank
+    offset: usize,
+    /// Length of its value
+    len: usize,
+}
+
+/// Current bank and the latest record of every key, the same storage is passed to every call
+pub struct Store {
+    /// `None` on a blank storage until the first value is written
+    bank: Option<usize>,
+    seq: u32,
+    /// Offset in the bank of the next record
+    end: usize,
+    latest: [Option<Entry>; KEYS],
+    /// The bank that isn't current is known to be erased
+    standby_erased: bool,
+}
+
+impl Store {
+    /// Find the current bank and read its records
+    pub fn new(storage: &mut impl Storage) -> Self {
+        let seqs = [0, 1].map(|bank| read_header(storage, bank));
+        let bank = match seqs {
+            // Wrapping comparison, the sequence number counts compactions
+            [Some(first), Some(second)] => {
+                Some(usize::from((second.wrapping_sub(first) as i32) > 0))
+            }
+            [Some(_), None] => Some(0),
+            [None, Some(_)] => Some(1),
+            [None, None] => None,
+        };
+        let mut store = Self {
+            bank,
+            seq: bank.and_then(|bank| seqs[bank]).unwrap_or(0),
+            end: HEADER_LEN,
+            latest: [None; KEYS],
+            standby_erased: false,
+        };
+        if let Some(bank) = bank {
+            store.scan(storage, bank);
+        }
+        store
+    }
+
+    /// The storage holds no values, e.g. it was never written
+    pub fn is_blank(&self) -> bool {
+        self.bank.is_none()
+    }
+
+    /// The bank the next compaction writes may hold records, see [`Store::prepare`]
+    pub fn needs_erase(&self) -> bool {
+        self.bank.is_some() && !self.standby_erased
+    }
+
+    /// Erase the first used block of the bank the next compaction writes, if any. Nothing is
+    /// erased on a blank storage, which may hold the records of earlier firmware.
+    pub fn prepare<S: Storage>(&mut self, storage: &mut S) -> Result<(), Error> {
+        let Some(bank) = self.bank else {
+            return Ok(());
+        };
+        let blocks = storage.blocks() / 2;
+        let standby = bank ^ 1;
+        for block in standby * blocks..(standby + 1) * blocks {
+            if !storage.is_erased(block * S::ERASE_SIZE, S::ERASE_SIZE)? {
+                return storage.erase(block);
+            }
+        }
+        self.standby_erased = true;
+        Ok(())
+    }
+
+    /// Read the latest value of `key` into `buffer`, `None` if there is none
+    pub fn get<'a>(
+        &self,
+        storage: &mut impl Storage,
+        key: u8,
+        buffer: &'a mut [u8; MAX_VALUE],
+    ) -> Result<Option<&'a [u8]>, Error> {
+        let entry = self.latest.get(usize::from(key)).copied().flatten();
+        let (Some(bank), Some(entry)) = (self.bank, entry) else {
+            return Ok(None);
+        };
+        let value = &mut buffer[..entry.len];
+        storage.read(bank_offset(storage, bank) + entry.offset + 2, value)?;
+        Ok(Some(value))
+    }
+
+    /// Append `value` for `key` unless it is the latest value already, compacting the bank into
+    /// the other one if it doesn't fit. The record is read back rather than trusting the write.
+    pub fn set(&mut self, storage: &mut impl Storage, key: u8, value: &[u8]) -> Result<(), Error> {
+        if usize::from(key) >= KEYS || value.len() > MAX_VALUE {
+            return Err(Error);
+        }
+        let mut buffer = [0; MAX_VALUE];
+        if self.get(storage, key, &mut buffer)? == Some(value) {
+            return Ok(());
+        }
+        let len = record_len(value.len());
+        let mut record = [0; record_len(MAX_VALUE)];
+        let record = &mut record[..len];
+        record[0] = key;
+        record[1] = value.len() as u8;
+        record[2..2 + value.len()].copy_from_slice(value);
+        storage::seal(record, len - 2..len);
+
+        let bank = match self.bank {
+            Some(bank) if self.end + len <= bank_len(storage) => bank,
+            _ => self.compact(storage)?,
+        };
+        if self.end + len > bank_len(storage) {
+            return Err(Error);
+        }
+        let offset = self.end;
+        // The record is used up even if writing fails half way
+        self.end += len;
+        let address = bank_offset(storage, bank) + offset;
+        storage.write(address, record)?;
+        let mut written = [0; record_len(MAX_VALUE)];
+        storage.read(address, &mut written[..len])?;
+        if written[..len] != *record {
+            return Err(Error);
+        }
+        self.latest[usize::from(key)] = Some(Entry {
+            offset,
+            len: value.len(),
+        });
+        Ok(())
+    }
+
+    /// Copy the latest records into the other bank, which becomes current, and return it. The
+    /// bank is erased first unless [`Store::prepare`] has erased it.
+    fn compact(&mut self, storage: &mut impl Storage) -> Result<usize, Error> {
+        let target = self.bank.map_or(0, |bank| bank ^ 1);
+        let blocks = storage.blocks() / 2;
+        if !self.standby_erased {
+            (target * blocks..(target + 1) * blocks)
+                .try_for_each(|block| storage.erase_if_used(block))?;
+        }
+        let (from, to) = (
+            self.bank.map(|bank| bank_offset(storage, bank)),
+            bank_offset(storage, target),
+        );
+        let mut end = HEADER_LEN;
+        let mut latest = [None; KEYS];
+        for (key, entry) in self.latest.iter().enumerate() {
+            let (Some(from), Some(entry)) = (from, entry) else {
+                continue;
+            };
+            let len = record_len(entry.len);
+            let mut record = [0; record_len(MAX_VALUE)];
+            storage.read(from + entry.offset, &mut record[..len])?;
+            storage.write(to + end, &record[..len])?;
+            latest[key] = Some(Entry {
+                offset: end,
+                len: entry.len,
+            });
+            end += len;
+        }
+        let seq = self.seq.wrapping_add(1);
+        let mut header = [0; HEADER_LEN];
+        header[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        header[2..6].copy_from_slice(&seq.to_le_bytes());
+        storage::seal(&mut header, HEADER_LEN - 2..HEADER_LEN);
+        storage.write(to, &header)?;
+        *self = Self {
+            bank: Some(target),
+            seq,
+            end,
+            latest,
+            standby_erased: false,
+        };
+        Ok(target)
+    }
+
+    /// Index the records of `bank` and find its end. A record running past the bank or that
+    /// can't be read ends it, and it fills up.
+    fn scan(&mut self, storage: &mut impl Storage, bank: usize) {
+        let (base, size) = (bank_offset(storage, bank), bank_len(storage));
+        let mut offset = HEADER_LEN;
+        while offset + ALIGN <= size {
+            let mut record = [0; record_len(MAX_VALUE)];
+            if storage.read(base + offset, &mut record[..2]).is_err() {
+                offset = size;
+                break;
+            }
+            let (key, value_len) = (record[0], usize::from(record[1]));
+            if key == ERASED {
+                break;
+            }
+            let len = record_len(value_len);
+            if value_len > MAX_VALUE || offset + len > size {
+                offset = size;
+                break;
+            }
+            let record = &mut record[..len];
+            if storage.read(base + offset, record).is_ok()
+                && storage::is_sealed(record, len - 2..len)
+                && usize::from(key) < KEYS
+            {
+                self.latest[usize::from(key)] = Some(Entry {
+                    offset,
+                    len: value_len,
+                });
+            }
+            offset += len;
+        }
+        self.end = offset;
+    }
+}
+
+/// Sequence number of `bank` if its header is valid
+fn read_header(storage: &mut impl Storage, bank: usize) -> Option<u32> {
+    let mut header = [0; HEADER_LEN];
+    storage.read(bank_offset(storage, bank), &mut header).ok()?;
+    if header[0..2] != MAGIC.to_le_bytes()
+        || !storage::is_sealed(&header, HEADER_LEN - 2..HEADER_LEN)
+    {
+        return None;
+    }
+    Some(u32::from_le_bytes([
+        header[2], header[3], header[4], header[5],
+    ]))
+}
+
+/// Bytes of a bank, half the blocks
+fn bank_len<S: Storage>(storage: &S) -> usize {
+    storage.blocks() / 2 * S::ERASE_SIZE
+}
+
+fn bank_offset(storage: &impl Storage, bank: usize) -> usize {
+    bank * bank_len(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Erase block of [`Ram`], two per bank
+    const BLOCK: usize = 64;
+
+    /// State of the supply at a write or erase
+    enum Power {
+        On,
+        /// Fails half way
+        Cut,
+        Off,
+    }
+
+    /// Flash in RAM, whose writes only clear bits, with the power cut at a chosen write or erase
+    struct Ram {
+        bytes: [u8; 4 * BLOCK],
+        /// Writes and erases so far
+        operations: usize,
+        erases: usize,
+        /// Operation the power fails during
+        cut_at: Option<usize>,
+    }
+
+    impl Ram {
+        fn new() -> Self {
+            Self {
+                bytes: [0xFF; 4 * BLOCK],
+                operations: 0,
+                erases: 0,
+                cut_at: None,
+            }
+        }
+
+        /// Cut the power during the `n`th write or erase from now, counted from 0
+        fn cut_after(&mut self, n: usize) {
+            self.cut_at = Some(self.operations + n);
+        }
+
+        /// Power up again after a cut
+        fn restore(&mut self) {
+            self.cut_at = None;
+        }
+
+        fn power(&mut self) -> Power {
+            let power = match self.cut_at {
+                Some(at) if self.operations > at => return Power::Off,
+                Some(at) if self.operations == at => Power::Cut,
+                _ => Power::On,
+            };
+            self.operations += 1;
+            power
+        }
+
+        fn check(&self, offset: usize, len: usize) -> Result<(), Error> {
+            match offset + len <= self.bytes.len() {
+                true => Ok(()),
+                false => Err(Error),
+            }
+        }
+    }
+
+    impl Storage for Ram {
+        const ERASE_SIZE: usize = BLOCK;
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+
+        fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+            self.check(offset, buffer.len())?;
+            if matches!(self.cut_at, Some(at) if self.operations > at) {
+                return Err(Error);
+            }
+            buffer.copy_from_slice(&self.bytes[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+            self.check(offset, data.len())?;
+            let len = match self.power() {
+                Power::On => data.len(),
+                Power::Cut => data.len() / 2,
+                Power::Off => return Err(Error),
+            };
+            for (byte, &value) in self.bytes[offset..offset + len].iter_mut().zip(data) {
+                *byte &= value;
+            }
+            match len == data.len() {
+                true => Ok(()),
+                false => Err(Error),
+            }
+        }
+
+        fn erase(&mut self, block: usize) -> Result<(), Error> {
+            let start = block * BLOCK;
+            self.check(start, BLOCK)?;
+            let len = match self.power() {
+                Power::On => BLOCK,
+                Power::Cut => BLOCK / 2,
+                Power::Off => return Err(Error),
+            };
+            self.bytes[start..start + len].fill(0xFF);
+            self.erases += 1;
+            match len == BLOCK {
+                true => Ok(()),
+                false => Err(Error),
+            }
+        }
+    }
+
+    fn value(store: &Store, ram: &mut Ram, key: u8) -> Option<[u8; 4]> {
+        let mut buffer = [0; MAX_VALUE];
+        let value = store.get(ram, key, &mut buffer).unwrap();
+        value.map(|value| value.try_into().unwrap())
+    }
+
+    /// Write the header of `bank` with sequence number `seq`
+    fn write_header(ram: &mut Ram, bank: usize, seq: u32) {
+        let mut header = [0; HEADER_LEN];
+        header[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        header[2..6].copy_from_slice(&seq.to_le_bytes());
+        storage::seal(&mut header, HEADER_LEN - 2..HEADER_LEN);
+        ram.write(bank_offset(ram, bank), &header).unwrap();
+    }
+
+    /// Records of 4 byte values fitting in a bank after its header
+    const RECORDS_PER_BANK: usize = (2 * BLOCK - HEADER_LEN) / record_len(4);
+
+    #[test]
+    fn values_are_read_back_after_a_reset() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        assert!(store.is_blank());
+        assert_eq!(value(&store, &mut ram, 0), None);
+        store.set(&mut ram, 0, &[1; 4]).unwrap();
+        store.set(&mut ram, 3, &[3; 4]).unwrap();
+        store.set(&mut ram, 0, &[2; 4]).unwrap();
+
+        let store = Store::new(&mut ram);
+        assert_eq!(store.bank, Some(0));
+        assert_eq!(value(&store, &mut ram, 0), Some([2; 4]));
+        assert_eq!(value(&store, &mut ram, 3), Some([3; 4]));
+        assert_eq!(value(&store, &mut ram, 1), None);
+    }
+
+    #[test]
+    fn same_value_isnt_written_again() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        store.set(&mut ram, 0, &[1; 4]).unwrap();
+        let operations = ram.operations;
+        store.set(&mut ram, 0, &[1; 4]).unwrap();
+        assert_eq!(ram.operations, operations);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_long_values() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        assert_eq!(store.set(&mut ram, KEYS as u8, &[0; 4]), Err(Error));
+        assert_eq!(store.set(&mut ram, 0, &[0; MAX_VALUE + 1]), Err(Error));
+        assert!(store.is_blank());
+    }
+
+    #[test]
+    fn later_sequence_number_is_current_across_the_wrap() {
+        let mut ram = Ram::new();
+        write_header(&mut ram, 0, u32::MAX);
+        write_header(&mut ram, 1, 0);
+        let store = Store::new(&mut ram);
+        assert_eq!((store.bank, store.seq), (Some(1), 0));
+
+        let mut ram = Ram::new();
+        write_header(&mut ram, 0, 5);
+        write_header(&mut ram, 1, 4);
+        let store = Store::new(&mut ram);
+        assert_eq!((store.bank, store.seq), (Some(0), 5));
+
+        let mut ram = Ram::new();
+        write_header(&mut ram, 1, 7);
+        let store = Store::new(&mut ram);
+        assert_eq!((store.bank, store.seq), (Some(1), 7));
+    }
+
+    #[test]
+    fn full_bank_is_compacted_into_the_other() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        for i in 0..3 * RECORDS_PER_BANK as u8 {
+            store.set(&mut ram, i % 3, &[i; 4]).unwrap();
+        }
+        // Compacted more than once, past the compaction writing the first header
+        assert!(store.seq >= 3);
+
+        let last = 3 * RECORDS_PER_BANK as u8 - 3;
+        let reloaded = Store::new(&mut ram);
+        assert_eq!((reloaded.bank, reloaded.seq), (store.bank, store.seq));
+        for key in 0..3 {
+            assert_eq!(value(&reloaded, &mut ram, key), Some([last + key; 4]));
+        }
+    }
+
+    #[test]
+    fn other_bank_is_erased_ahead_of_the_compaction() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        assert!(!store.needs_erase());
+        for i in 0..RECORDS_PER_BANK as u8 + 1 {
+            store.set(&mut ram, i % 2, &[i; 4]).unwrap();
+        }
+        assert_eq!(store.bank, Some(1));
+
+        // Bank 0, just compacted from, is erased a block per call
+        let erases = ram.erases;
+        while store.needs_erase() {
+            store.prepare(&mut ram).unwrap();
+        }
+        assert_eq!(ram.erases, erases + 2);
+        assert!(ram.bytes[..2 * BLOCK].iter().all(|&byte| byte == 0xFF));
+
+        let erases = ram.erases;
+        for i in 0..RECORDS_PER_BANK as u8 {
+            store.set(&mut ram, i % 2, &[0x80 | i; 4]).unwrap();
+        }
+        assert_eq!(store.bank, Some(0));
+        assert_eq!(ram.erases, erases);
+        let last = 0x80 | (RECORDS_PER_BANK as u8 - 1);
+        let store = Store::new(&mut ram);
+        assert_eq!(value(&store, &mut ram, 0), Some([last; 4]));
+        assert_eq!(value(&store, &mut ram, 1), Some([last - 1; 4]));
+    }
+
+    #[test]
+    fn blank_storage_isnt_prepared() {
+        let mut ram = Ram::new();
+        ram.bytes[2 * BLOCK] = 0;
+        let mut store = Store::new(&mut ram);
+        assert!(!store.needs_erase());
+        store.prepare(&mut ram).unwrap();
+        assert_eq!(ram.erases, 0);
+    }
+
+    #[test]
+    fn torn_record_is_skipped() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        store.set(&mut ram, 0, &[1; 4]).unwrap();
+        ram.cut_after(0);
+        assert_eq!(store.set(&mut ram, 0, &[2; 4]), Err(Error));
+        ram.restore();
+
+        let mut store = Store::new(&mut ram);
+        assert_eq!(value(&store, &mut ram, 0), Some([1; 4]));
+        // The next record goes after the torn one
+        store.set(&mut ram, 0, &[3; 4]).unwrap();
+        let store = Store::new(&mut ram);
+        assert_eq!(value(&store, &mut ram, 0), Some([3; 4]));
+        assert_eq!(store.end, HEADER_LEN + 3 * record_len(4));
+    }
+
+    #[test]
+    fn reset_during_compaction_keeps_the_full_bank() {
+        let mut ram = Ram::new();
+        let mut store = Store::new(&mut ram);
+        for i in 0..RECORDS_PER_BANK as u8 {
+            store.set(&mut ram, i % 2, &[i; 4]).unwrap();
+        }
+        // Copying the first record into the other bank
+        ram.cut_after(0);
+        assert_eq!(store.set(&mut ram, 0, &[0xAA; 4]), Err(Error));
+        ram.restore();
+
+        let mut store = Store::new(&mut ram);
+        assert_eq!((store.bank, store.seq), (Some(0), 1));
+        let last = RECORDS_PER_BANK as u8 - 1;
+        assert_eq!(value(&store, &mut ram, 0), Some([last; 4]));
+        assert_eq!(value(&store, &mut ram, 1), Some([last - 1; 4]));
+        // The half written bank is erased again by the next compaction
+        store.set(&mut ram, 0, &[0xAA; 4]).unwrap();
+        let store = Store::new(&mut ram);
+        assert_eq!((store.bank, store.seq), (Some(1), 2));
+        assert_eq!(value(&store, &mut ram, 0), Some([0xAA; 4]));
+        assert_eq!(value(&store, &mut ram, 1), Some([last - 1; 4]));
+    }
+
+    #[test]
+    fn power_cut_at_any_write_keeps_the_old_or_the_new_value() {
+        // A setting written into a bank with room, then one compacting it
+        for records in [2, RECORDS_PER_BANK] {
+            for cut in 0.. {
+                let mut ram = Ram::new();
+                let mut store = Store::new(&mut ram);
+                store.set(&mut ram, 1, &[0x11; 4]).unwrap();
+                for i in 1..records as u8 {
+                    store.set(&mut ram, 0, &[i; 4]).unwrap();
+                }
+                let old = [records as u8 - 1; 4];
+                ram.cut_after(cut);
+                let written = store.set(&mut ram, 0, &[0x55; 4]).is_ok();
+                ram.restore();
+
+                let store = Store::new(&mut ram);
+                let value_0 = value(&store, &mut ram, 0);
+                assert_eq!(value(&store, &mut ram, 1), Some([0x11; 4]), "cut at {cut}");
+                if written {
+                    assert_eq!(value_0, Some([0x55; 4]));
+                    break;
+                }
+                assert!(
+                    value_0 == Some(old) || value_0 == Some([0x55; 4]),
+                    "cut at {cut}: {value_0:?}"
+                );
+            }
+        }
+    }
+}
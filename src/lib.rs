@@ -0,0 +1,110 @@
+//! Peripheral drivers and protocol handling for the GP-735T bridge.
+//! The RTIC application in `main.rs` wires them to interrupts. NMEA, UBX and the binary output
+//! framing live in the `gp735t-proto` workspace crate, shared with host tools.
+
+#![cfg_attr(not(test), no_std)]
+
+pub use gp735t_proto::{geo, n2k, nmea, protocol, pubx, ubx};
+
+pub mod adc;
+pub mod aid;
+pub mod anchor;
+pub mod autobaud;
+pub mod battery;
+pub mod baud;
+#[cfg(feature = "lora")]
+pub mod beacon;
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod board;
+#[cfg(feature = "ab-boot")]
+pub mod boot;
+pub mod bridge;
+#[cfg(feature = "button")]
+pub mod button;
+#[cfg(feature = "can")]
+pub mod can;
+pub mod chip;
+pub mod clocks;
+pub mod cmd;
+pub mod config;
+pub mod decimate;
+pub mod defaults;
+pub mod dfu;
+#[cfg(feature = "display")]
+pub mod display;
+pub mod dma;
+#[cfg(feature = "eeprom")]
+pub mod eeprom;
+#[cfg(feature = "eeprom")]
+pub mod eventlog;
+pub mod fault;
+pub mod filter;
+pub mod fix;
+pub mod flash;
+#[cfg(feature = "flash-log")]
+pub mod flashlog;
+#[cfg(feature = "flow-control")]
+pub mod flow;
+pub mod geofence;
+pub mod gnss;
+pub mod gps_ctrl;
+pub mod hal;
+#[cfg(feature = "hot-start")]
+pub mod hotstart;
+#[cfg(any(feature = "display", feature = "eeprom"))]
+pub mod i2c;
+#[cfg(feature = "i2c-slave")]
+pub mod i2c_slave;
+pub mod inbox;
+#[cfg(feature = "indicator")]
+pub mod indicator;
+pub mod jobs;
+pub mod kv;
+#[cfg(any(feature = "usb", feature = "ble"))]
+pub mod linequeue;
+pub mod load;
+pub mod log;
+#[cfg(feature = "lora")]
+pub mod lora;
+pub mod nav;
+pub mod persist;
+#[cfg(feature = "rs485")]
+pub mod poll;
+pub mod pos;
+pub mod power;
+pub mod pps;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod psm;
+pub mod quality;
+pub mod rate;
+pub mod report;
+pub mod reset;
+pub mod ringbuf;
+pub mod router;
+#[cfg(feature = "rs485")]
+pub mod rs485;
+pub mod rtc;
+pub mod schedule;
+#[cfg(feature = "sd-log")]
+pub mod sdlog;
+pub mod selftest;
+pub mod sky;
+pub mod smoothing;
+pub mod source;
+pub mod speed_alarm;
+#[cfg(feature = "spi-slave")]
+pub mod spi_slave;
+pub mod stack;
+pub mod stats;
+pub mod storage;
+pub mod sync;
+pub mod timer;
+pub mod ttff;
+pub mod uart;
+pub mod units;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod version;
+pub mod watchdog;
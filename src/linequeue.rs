@@ -0,0 +1,170 @@
+//! Queue of whole lines for a sink taking them a byte or a packet at a time, USB and the
+//! Bluetooth module, applying the sink's [`Overflow`] policy to a line that doesn't fit.
+//!
+//! Lines are kept in order of their [`Class`], a line going ahead of the queued lines of lower
+//! classes. A line partly taken by the sink stays first until its end is taken, so neither a
+//! line of a higher class nor dropping the oldest lines ever cuts one short, and a line is
+//! queued whole or not at all. A blocked line is [`Held`] and queued once the sink has taken
+//! enough.
+
+use crate::ringbuf::RingBuffer;
+use crate::router::{self, Class, Held, Overflow, Sink};
+
+pub struct LineQueue<const N: usize> {
+    sink: Sink,
+    queue: RingBuffer<N>,
+    /// The sink has taken part of the oldest line
+    started: bool,
+    /// Bytes from the front up to the end of the lines of each class but [`Class::Bulk`], which
+    /// end with the queue
+    ends: [usize; Class::ALL.len() - 1],
+    /// Line waiting for room with `OVERFLOW BLOCK`
+    held: Held,
+}
+
+impl<const N: usize> LineQueue<N> {
+    /// Queue of the lines for `sink`
+    pub const fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            queue: RingBuffer::new(),
+            started: false,
+            ends: [0; Class::ALL.len() - 1],
+            held: Held::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queue `line` of `class`, dropping lines of lower classes to make room for it, then by the
+    /// sink's overflow policy. A blocked line is held until [`LineQueue::consume`] makes room,
+    /// the lines of its class and lower ones pushed meanwhile are dropped, see [`Held`].
+    pub fn push(&mut self, class: Class, line: &[u8]) {
+        if self.held.blocks(class) {
+            router::record_drop(self.sink, 1, line.len());
+            return;
+        }
+        if line.len() <= N {
+            for lower in class.lower() {
+                self.drop_oldest(lower, line.len());
+            }
+            match router::overflow(self.sink) {
+                Overflow::DropNewest => {}
+                Overflow::DropOldest => self.drop_oldest(class, line.len()),
+                Overflow::Block(ms) => {
+                    if line.len() > self.queue.free() && self.held.hold(class, line, ms) {
+                        return;
+                    }
+                }
+            }
+        }
+        if !self.insert(class, line) {
+            router::record_drop(self.sink, 1, line.len());
+        }
+    }
+
+    /// Contiguous bytes the sink may take next
+    pub fn peek(&self) -> &[u8] {
+        self.queue.peek()
+    }
+
+    /// Release `count` bytes taken from [`LineQueue::peek`]
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.queue.peek().len());
+        if let Some(&last) = self.queue.peek()[..count].last() {
+            self.started = last != b'\n';
+        }
+        self.queue.consume(count);
+        for end in &mut self.ends {
+            *end = end.saturating_sub(count);
+        }
+        let free = self.queue.free();
+        if self.held.line().is_some_and(|(_, line)| line.len() <= free) {
+            if let Some((class, line)) = self.held.take() {
+                self.insert(class, &line);
+            }
+        }
+    }
+
+    /// Take the next byte
+    pub fn pop(&mut self) -> Option<u8> {
+        let byte = *self.peek().first()?;
+        self.consume(1);
+        Some(byte)
+    }
+
+    /// Drop everything queued or held, for a sink that went away
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.started = false;
+        self.ends = [0; Class::ALL.len() - 1];
+        self.held.release();
+    }
+
+    /// Called by SysTick at `now_ms`: drop the held line once its wait is over
+    pub fn on_tick(&mut self, now_ms: u32) {
+        self.held.on_tick(self.sink, now_ms);
+    }
+
+    /// Queue `line` after the lines of `class` and the line the sink has started, false if it
+    /// doesn't fit
+    fn insert(&mut self, class: Class, line: &[u8]) -> bool {
+        let at = self.end(class).max(self.started_end());
+        if self.queue.insert(at, line).is_err() {
+            return false;
+        }
+        for end in self.ends.iter_mut().skip(class as usize) {
+            *end = (*end).max(at) + line.len();
+        }
+        true
+    }
+
+    /// Offset of the first line of `class`
+    fn start(&self, class: Class) -> usize {
+        match class as usize {
+            0 => 0,
+            index => self.ends[index - 1],
+        }
+    }
+
+    /// Offset following the last line of `class`
+    fn end(&self, class: Class) -> usize {
+        self.ends
+            .get(class as usize)
+            .copied()
+            .unwrap_or(self.queue.len())
+    }
+
+    /// Offset following the line the sink has started, 0 if it hasn't
+    fn started_end(&self) -> usize {
+        if self.started {
+            self.queue
+                .find(b'\n', 0)
+                .map_or(self.queue.len(), |index| index + 1)
+        } else {
+            0
+        }
+    }
+
+    /// Drop the oldest lines of `class` the sink hasn't started, counting them, until `room`
+    /// bytes are free
+    fn drop_oldest(&mut self, class: Class, room: usize) {
+        while room > self.queue.free() {
+            let start = self.start(class).max(self.started_end());
+            if start >= self.end(class) {
+                return;
+            }
+            let Some(newline) = self.queue.find(b'\n', start) else {
+                return;
+            };
+            let len = newline + 1 - start;
+            self.queue.remove(start, len);
+            for end in self.ends.iter_mut().skip(class as usize) {
+                *end = end.saturating_sub(len);
+            }
+            router::record_drop(self.sink, 1, len);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! CPU load, the share of time the core is awake running interrupt handlers and idle's work
+//! rather than asleep in WFI, reported by `STATS` to check that the navigation rate, DMA, the
+//! display and logging fit the clock, see [`crate::clocks`].
+//!
+//! DWT CYCCNT counts core clock cycles. [`crate::power::sleep`] passes the cycles each WFI takes
+//! to [`record_sleep`], and SysTick closes a window every [`WINDOW_MS`], the cycles awake being
+//! the CYCCNT cycles of the window less those asleep. The core clock is gated in Sleep, so
+//! CYCCNT only counts across WFI while a debugger keeps it running, the result is the same
+//! either way, and the length of the window is taken from SysTick, which keeps counting. Stop
+//! mode halts both, so time the GPS is off in Stop isn't part of any window.
+
+use crate::clocks;
+use crate::stats::STATS;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::{DCB, DWT};
+
+/// Length of a measurement window
+pub const WINDOW_MS: u32 = 1000;
+
+/// Cycles spent in WFI during the current window
+static SLEEP_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+/// Load of the last window in permille
+static LATEST: AtomicU32 = AtomicU32::new(0);
+
+/// Start the cycle counter, called from init
+pub fn init(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+}
+
+/// Add the `cycles` of a WFI, as counted by CYCCNT around it
+pub fn record_sleep(cycles: u32) {
+    SLEEP_CYCLES.fetch_add(cycles, Ordering::Relaxed);
+}
+
+/// Load of the last window in permille
+pub fn latest() -> u32 {
+    LATEST.load(Ordering::Relaxed)
+}
+
+/// Measurement window, advanced by SysTick
+pub struct Meter {
+    start_ms: u32,
+    start_cycles: u32,
+}
+
+impl Meter {
+    pub const fn new() -> Self {
+        Self {
+            start_ms: 0,
+            start_cycles: 0,
+        }
+    }
+
+    /// Close the window once [`WINDOW_MS`] have passed, setting [`latest`] and the peak counted
+    /// by `STATS`
+    pub fn on_tick(&mut self, now_ms: u32) {
+        let elapsed_ms = now_ms.wrapping_sub(self.start_ms);
+        if elapsed_ms < WINDOW_MS {
+            return;
+        }
+        let cycles = DWT::cycle_count();
+        let awake = cycles
+            .wrapping_sub(self.start_cycles)
+            .saturating_sub(SLEEP_CYCLES.swap(0, Ordering::Relaxed));
+        let window = u64::from(elapsed_ms) * u64::from(clocks::SYSCLK_HZ / 1000);
+        let load = (u64::from(awake) * 1000 / window).min(1000) as u32;
+        LATEST.store(load, Ordering::Relaxed);
+        STATS.cpu_load_peak.record(load as usize);
+        self.start_ms = now_ms;
+        self.start_cycles = cycles;
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
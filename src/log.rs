@@ -0,0 +1,37 @@
+//! Debug messages over RTT with the `log` feature, compiled out without it.
+//!
+//! The macros take defmt format strings and arguments, e.g. `listen_gps::info!("baud {}", baud)`.
+//! Arguments aren't evaluated without the feature, so they shouldn't have side effects.
+//! The level shown is selected with `DEFMT_LOG` at build time, see `.cargo/config.toml`.
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        defmt::debug!($($arg)*);
+    }};
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        defmt::info!($($arg)*);
+    }};
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        defmt::warn!($($arg)*);
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        defmt::error!($($arg)*);
+    }};
+}
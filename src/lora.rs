@@ -0,0 +1,273 @@
+//! SX1276/77/78/79 LoRa radio, e.g. on an RFM95W module, on SPI1 for the `lora` feature, see
+//! [`crate::beacon`].
+//!
+//! Pins: PB3 SCK (D13), PB4 MISO (D12), PB5 MOSI (D11) as alternate function 5, PB1 NSS (D6),
+//! the pins of the SD card. The radio's reset is left to its power on reset and DIO0 isn't used,
+//! the end of a transmission is polled from RegIrqFlags. Packets are sent in explicit header
+//! mode, 125kHz bandwidth, coding rate 4/5, CRC on, an 8 symbol preamble and the private sync
+//! word 0x12, at 14dBm from PA_BOOST, the only output wired on an RFM95W.
+
+#[cfg(feature = "sd-log")]
+compile_error!("feature `lora` takes SPI1 and its pins from `sd-log`");
+
+#[cfg(feature = "spi-slave")]
+compile_error!("feature `lora` takes PB1 and PB3 to PB5 from `spi-slave`");
+
+use crate::chip::pac::{GPIOB, RCC, SPI1};
+use crate::clocks;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Lowest and highest carrier frequency of the SX127x family
+pub const MIN_FREQUENCY_KHZ: u32 = 137_000;
+pub const MAX_FREQUENCY_KHZ: u32 = 1_020_000;
+
+/// Spreading factors, 6 needs implicit header mode and isn't supported
+pub const MIN_SF: u8 = 7;
+pub const MAX_SF: u8 = 12;
+
+/// Longest payload of a packet
+pub const MAX_PAYLOAD: usize = 255;
+
+/// Chip select pin on GPIOB
+const NSS_PIN: u32 = 1;
+
+/// Fastest SPI clock up to the radio's 10MHz, BR divides PCLK2 by 2^(BR+1)
+const BR: u8 = {
+    let mut br = 0;
+    while br < 7 && clocks::PCLK2_HZ >> (br + 1) > 10_000_000 {
+        br += 1;
+    }
+    br
+};
+
+/// Registers, the address with its top bit set writes
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0E;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_2: u8 = 0x1E;
+const REG_PREAMBLE_MSB: u8 = 0x20;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+const WRITE: u8 = 0x80;
+
+/// RegOpMode with LongRangeMode set
+const MODE_SLEEP: u8 = 0x80;
+const MODE_STANDBY: u8 = 0x81;
+const MODE_TX: u8 = 0x83;
+
+/// RegVersion of the SX1276 to SX1279
+const VERSION: u8 = 0x12;
+
+/// TxDone in RegIrqFlags
+const IRQ_TX_DONE: u8 = 1 << 3;
+
+/// PA_BOOST at 2 + OutputPower dBm
+const PA_CONFIG: u8 = 0x80 | 12;
+
+/// 125kHz bandwidth, coding rate 4/5, explicit header
+const MODEM_CONFIG_1: u8 = 0x72;
+
+/// CRC on, added to the spreading factor in the top nibble
+const MODEM_CONFIG_2: u8 = 0x04;
+
+/// AGC on, added to LowDataRateOptimize
+const MODEM_CONFIG_3: u8 = 0x04;
+
+/// Symbols of the preamble, before the 4.25 the radio adds
+pub const PREAMBLE_LEN: u16 = 8;
+
+/// Private network sync word, LoRaWAN uses 0x34
+const SYNC_WORD: u8 = 0x12;
+
+/// A radio answered with the SX127x version at boot
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// A radio was found at boot
+pub fn is_present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Low data rate optimization, mandated by Semtech where a symbol at 125kHz exceeds 16ms
+pub fn low_data_rate(sf: u8) -> bool {
+    sf >= 11
+}
+
+/// Time on air in microseconds of a packet of `len` bytes at spreading factor `sf`, from the
+/// SX1276 datasheet section 4.1.1.7
+pub fn airtime_us(sf: u8, len: usize) -> u32 {
+    // 125kHz bandwidth
+    let symbol_us = (1u32 << sf) * 8;
+    let de = i32::from(low_data_rate(sf));
+    let sf = i32::from(sf);
+    // Explicit header and CRC
+    let numerator = 8 * len as i32 - 4 * sf + 28 + 16;
+    let denominator = 4 * (sf - 2 * de);
+    let blocks = if numerator > 0 {
+        (numerator + denominator - 1) / denominator
+    } else {
+        0
+    };
+    // Coding rate 4/5
+    let payload_symbols = 8 + blocks as u32 * 5;
+    // The preamble and 4.25 symbols of sync word, in quarter symbols
+    let preamble_quarters = u32::from(PREAMBLE_LEN) * 4 + 17;
+    preamble_quarters * symbol_us / 4 + payload_symbols * symbol_us
+}
+
+/// SPI1 in mode 0 with 8 bit frames and the radio on it
+pub struct Radio {
+    spi1: SPI1,
+}
+
+impl Radio {
+    /// Configure SPI1 and its pins, check the radio's version and put it in LoRa sleep mode.
+    /// GPIOB and SPI1 clocks are enabled here.
+    pub fn new(rcc: &RCC, gpiob: &GPIOB, spi1: SPI1) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb2enr.modify(|_, w| w.spi1en().set_bit());
+
+        // NSS idles high
+        // SAFETY: setting one pin
+        gpiob.bsrr.write(|w| unsafe { w.bits(1 << NSS_PIN) });
+        gpiob.moder.modify(|_, w| {
+            w.moder3()
+                .alternate()
+                .moder4()
+                .alternate()
+                .moder5()
+                .alternate()
+                .moder1()
+                .output()
+        });
+        gpiob.ospeedr.modify(|_, w| {
+            w.ospeedr3()
+                .very_high_speed()
+                .ospeedr5()
+                .very_high_speed()
+                .ospeedr1()
+                .very_high_speed()
+        });
+        gpiob
+            .afrl
+            .modify(|_, w| w.afrl3().af5().afrl4().af5().afrl5().af5());
+
+        // Master with software chip select, 8 bit frames raising RXNE per byte
+        // SAFETY: 0b0111 selects 8 bit frames
+        spi1.cr2
+            .write(|w| unsafe { w.ds().bits(0b0111).frxth().set_bit() });
+        spi1.cr1.write(|w| {
+            w.mstr()
+                .set_bit()
+                .ssm()
+                .set_bit()
+                .ssi()
+                .set_bit()
+                .br()
+                .bits(BR)
+                .spe()
+                .set_bit()
+        });
+
+        let mut radio = Self { spi1 };
+        let present = radio.read(REG_VERSION) == VERSION;
+        PRESENT.store(present, Ordering::Relaxed);
+        if present {
+            // LongRangeMode can only be changed in sleep mode
+            radio.write(REG_OP_MODE, 0x00);
+            radio.write(REG_OP_MODE, MODE_SLEEP);
+            radio.write(REG_OP_MODE, MODE_STANDBY);
+            radio.write(REG_PA_CONFIG, PA_CONFIG);
+            radio.write(REG_MODEM_CONFIG_1, MODEM_CONFIG_1);
+            radio.write(REG_PREAMBLE_MSB, (PREAMBLE_LEN >> 8) as u8);
+            radio.write(REG_PREAMBLE_MSB + 1, PREAMBLE_LEN as u8);
+            radio.write(REG_SYNC_WORD, SYNC_WORD);
+            radio.write(REG_FIFO_TX_BASE_ADDR, 0);
+            radio.sleep();
+        }
+        radio
+    }
+
+    /// Start sending `payload` at `frequency_khz` and spreading factor `sf`, see
+    /// [`Radio::is_done`] for its end
+    pub fn transmit(&mut self, frequency_khz: u32, sf: u8, payload: &[u8]) {
+        let payload = &payload[..payload.len().min(MAX_PAYLOAD)];
+        // The FIFO is only accessible in standby
+        self.write(REG_OP_MODE, MODE_STANDBY);
+        // Frf = f * 2^19 / 32MHz
+        let frf = u64::from(frequency_khz) * 2048 / 125;
+        self.write(REG_FRF_MSB, (frf >> 16) as u8);
+        self.write(REG_FRF_MSB + 1, (frf >> 8) as u8);
+        self.write(REG_FRF_MSB + 2, frf as u8);
+        self.write(REG_MODEM_CONFIG_2, sf << 4 | MODEM_CONFIG_2);
+        self.write(
+            REG_MODEM_CONFIG_3,
+            MODEM_CONFIG_3 | u8::from(low_data_rate(sf)) << 3,
+        );
+        self.write(REG_IRQ_FLAGS, 0xFF);
+        self.write(REG_FIFO_ADDR_PTR, 0);
+        self.select(true);
+        self.transfer_byte(REG_FIFO | WRITE);
+        for &byte in payload {
+            self.transfer_byte(byte);
+        }
+        self.deselect();
+        self.write(REG_PAYLOAD_LENGTH, payload.len() as u8);
+        self.write(REG_OP_MODE, MODE_TX);
+    }
+
+    /// The packet started by [`Radio::transmit`] has been sent, the radio is back in standby
+    pub fn is_done(&mut self) -> bool {
+        self.read(REG_IRQ_FLAGS) & IRQ_TX_DONE != 0
+    }
+
+    /// Sleep mode, drawing about 1µA, keeping the registers
+    pub fn sleep(&mut self) {
+        self.write(REG_IRQ_FLAGS, 0xFF);
+        self.write(REG_OP_MODE, MODE_SLEEP);
+    }
+
+    fn read(&mut self, address: u8) -> u8 {
+        self.select(true);
+        self.transfer_byte(address & !WRITE);
+        let value = self.transfer_byte(0);
+        self.deselect();
+        value
+    }
+
+    fn write(&mut self, address: u8, value: u8) {
+        self.select(true);
+        self.transfer_byte(address | WRITE);
+        self.transfer_byte(value);
+        self.deselect();
+    }
+
+    fn transfer_byte(&mut self, byte: u8) -> u8 {
+        while self.spi1.sr.read().txe().bit_is_clear() {}
+        // SAFETY: 8 bit access to DR sends a single frame, a 16 bit access would pack two
+        unsafe { core::ptr::write_volatile(self.spi1.dr.as_ptr() as *mut u8, byte) };
+        while self.spi1.sr.read().rxne().bit_is_clear() {}
+        // SAFETY: as above, FRXTH raises RXNE for each byte
+        unsafe { core::ptr::read_volatile(self.spi1.dr.as_ptr() as *const u8) }
+    }
+
+    /// Wait for the last frame to leave and raise NSS
+    fn deselect(&mut self) {
+        while self.spi1.sr.read().bsy().bit_is_set() {}
+        self.select(false);
+    }
+
+    fn select(&mut self, selected: bool) {
+        // NSS is active low, lower half of BSRR sets pins, upper half resets them
+        let bit = if selected { 1 << 16 } else { 1 } << NSS_PIN;
+        // SAFETY: BSRR writes are atomic and only touch the NSS pin
+        let gpiob = unsafe { &*GPIOB::ptr() };
+        gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+    }
+}
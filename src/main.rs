@@ -1,153 +1,2759 @@
 //! USART1 reads GPS data from GP-735T and sends it over USART2.
-//! USART2 reads input and toggles GPS ON/OFF if b'0'/b'1'.
-//! TODO: DMA
+//! USART2 reads command lines from the host, see `cmd`. Single b'0'/b'1' bytes still toggle GPS
+//! ON/OFF.
+//! Command lines received on USB and from the Bluetooth module are run by USART2 as well and
+//! answered on their port, see `inbox`.
+//! Shared state is owned by RTIC resources instead of `static mut` globals, task priorities and
+//! the rules for shared state are in `sync`.
+//! USART1 reception is done by DMA into a circular buffer, flushed on IDLE line and half/full transfer.
+//! Received bytes are assembled into sentences which USART2 transmits by DMA from a double buffer.
+//! Parsed sentences update the shared `GpsFix`. The host selects which sentence types are forwarded.
+//! USART1 transmits queued UBX frames to the GPS, UBX acknowledgements are reported to the host.
+//! In aiding mode the host uploads UBX aiding frames to the GPS between commands, see `aid`.
+//! In raw bridge mode bytes pass through unchanged both ways until the host escapes, see `bridge`.
+//! Changing the navigation rate also resizes the RX DMA window and raises the host baud if needed.
+//! Host baud changes take effect once the response has been sent, GPS baud changes once the
+//! UBX-CFG-PRT frame has been sent.
+//! Pins are those of the board selected by a `board-*` feature, see `board`.
+//! TIM2 timestamps the GPS 1PPS timepulse in microseconds since boot.
+//! The RTC is set from RMC time and aligned to the timepulse.
+//! With the `flow-control` feature USART2 uses RTS/CTS, see `flow`.
+//! With the `rs485` feature USART2 drives an RS-485 transceiver shared by several bridges, see
+//! `rs485`.
+//! With the `host-autobaud` feature USART2 detects the host baud from a `U` sync byte, see `baud`.
+//! With the `usb` feature forwarded sentences are also sent to a USB virtual COM port, see `usb`,
+//! and GPS power moves to A8.
+//! With the `sd-log` feature fixes are logged to an SD card from idle while `LOG ON`, see `sdlog`.
+//! With the `flash-log` feature fixes are logged to internal flash and read back with `DUMP`,
+//! see `flashlog`. Forwarding pauses while a dump is sent.
+//! With the `display` feature the fix is shown on an SSD1306 OLED, redrawn from idle every second,
+//! see `display`.
+//! With the `ble` feature forwarded sentences are also sent to a Bluetooth serial module on
+//! LPUART1, see `ble`.
+//! `ROUTE` selects the sinks of forwarded sentences, synthesized sentences and reports, the host,
+//! USB and the Bluetooth module, see `router`.
+//! With the `can` feature the fix is broadcast on CAN every period from SysTick, see `can`.
+//! With the `lora` feature SysTick sends the fix from a LoRa radio every interval, see `beacon`.
+//! With the `spi-slave` feature fixes and sentences are also streamed from an SPI slave port, see
+//! `spi_slave`.
+//! With the `i2c-slave` feature an I2C master reads the latest fix as registers, see `i2c_slave`.
+//! With the `indicator` feature an LED shows the GPS state, see `indicator`.
+//! With the `button` feature a push-button toggles GPS power and SD logging, see `button`.
+//! Baud rates, filter, navigation rate, GPS power, geofence zones and the fix timeout are loaded
+//! from flash at boot, see `config`, or from an external EEPROM with the `eeprom` feature, which
+//! also logs resets, GPS power cycles and alarms written from idle, see `eeprom` and `eventlog`.
+//! With the `hot-start` feature idle saves the last fix there when the GPS is turned off, and
+//! it is sent back to the GPS as aiding when it is turned on, see `hotstart`.
+//! SysTick counts milliseconds and runs software timers, see `timer`, and measures the CPU load
+//! from DWT CYCCNT, see `load`.
+//! The GPS is power cycled if it delivers no fix for `FIXTIMEOUT`, see `gps_ctrl`.
+//! USART1 probes for the GPS baud when no valid sentence arrives for a while, see `autobaud`.
+//! Each GGA completes a fix, which is checked against the geofence zones, see `geofence`.
+//! The reset cause and the resets since power-on are reported with a
+//! `$PBRIDGE,RESET,<cause>,<count>` sentence before any GPS data, followed by `$PBRIDGE,FAULT`
+//! with the registers of a fault that caused it, or `$PBRIDGE,PANIC` with the location and
+//! message of a panic, see `fault`. The count and the records are kept in RAM, see `persist`.
+//! The IWDG resets the MCU if USART1 stops receiving while the GPS is on, see `watchdog`.
+//! Init paints the stack and the RTC wakeup warns once it runs low, see `stack`.
+//! Between interrupts the core sleeps with WFI, running from voltage range 2. While the GPS is off
+//! it enters Stop mode until the host or the Bluetooth module sends a byte.
 
 #![no_std]
 #![no_main]
 
-use cortex_m_rt::entry;
-use heapless::spsc::Queue;
-use panic_semihosting as _; // logs messages to the host stderr; requires a debugger
-use stm32l4::stm32l4x2::{self, interrupt};
+// Debug builds log panics to the host stderr by semihosting, which requires a debugger, or over
+// RTT with the `log` feature. Release builds keep the message for the next boot and reset so a
+// field unit recovers, see `fault`.
+#[cfg(all(debug_assertions, feature = "log"))]
+use panic_probe as _;
+#[cfg(all(debug_assertions, not(feature = "log")))]
+use panic_semihosting as _;
 
-static mut USART1_PERIPHERAL: Option<stm32l4x2::USART1> = None;
-static mut USART2_PERIPHERAL: Option<stm32l4x2::USART2> = None;
-static mut GPIOA_PERIPHERAL: Option<stm32l4x2::GPIOA> = None;
-static mut BUFFER: Option<Queue<u16, 64>> = None;
+#[cfg(not(debug_assertions))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    listen_gps::fault::on_panic(info)
+}
+
+#[cfg(feature = "log")]
+use defmt_rtt as _;
+
+// Defined here rather than in the library, whose object file the linker may leave out
+#[cortex_m_rt::exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    listen_gps::fault::on_hard_fault(frame)
+}
+
+#[cortex_m_rt::exception]
+unsafe fn DefaultHandler(irqn: i16) -> ! {
+    listen_gps::fault::on_unexpected(irqn)
+}
+
+#[rtic::app(device = listen_gps::chip::pac, peripherals = true)]
+mod app {
+    use core::fmt::Write;
+    use heapless::{String, Vec};
+    use listen_gps::adc::Adc;
+    use listen_gps::aid;
+    use listen_gps::anchor::{self, Anchor, AnchorWatch};
+    use listen_gps::autobaud::{self, Autobaud};
+    use listen_gps::battery::{self, Monitor};
+    use listen_gps::baud::{self, Detection};
+    #[cfg(feature = "lora")]
+    use listen_gps::beacon::{self, Beacon};
+    #[cfg(feature = "ble")]
+    use listen_gps::ble::Ble;
+    use listen_gps::board;
+    #[cfg(feature = "ab-boot")]
+    use listen_gps::boot;
+    use listen_gps::bridge::{self, Escape};
+    #[cfg(feature = "button")]
+    use listen_gps::button::{self, Button, Press};
+    #[cfg(feature = "can")]
+    use listen_gps::can::{self, Can};
+    use listen_gps::chip::pac;
+    use listen_gps::clocks;
+    #[cfg(feature = "lora")]
+    use listen_gps::cmd::LoraChange;
+    use listen_gps::cmd::{
+        self, AnchorChange, Command, FilterChange, LineBuffer, Port, Pubx, RouteChange,
+        ScheduleChange, UnitsChange, ZoneChange,
+    };
+    #[cfg(not(feature = "eeprom"))]
+    use listen_gps::config;
+    use listen_gps::config::{Config, Store};
+    use listen_gps::decimate::{self, Decimator};
+    use listen_gps::dfu;
+    #[cfg(feature = "display")]
+    use listen_gps::display;
+    use listen_gps::dma::{CircularRx, DoubleBufferTx, RX_BUFFER_SIZE, TX_BUFFER_SIZE};
+    #[cfg(feature = "eeprom")]
+    use listen_gps::eeprom::Eeprom;
+    #[cfg(feature = "eeprom")]
+    use listen_gps::eventlog::{self, Event};
+    use listen_gps::fault;
+    use listen_gps::filter::{self, Filter, Output, Passthru};
+    use listen_gps::fix::GpsFix;
+    #[cfg(feature = "flash-log")]
+    use listen_gps::flashlog;
+    #[cfg(feature = "flow-control")]
+    use listen_gps::flow;
+    use listen_gps::geofence::{self, Geofence};
+    use listen_gps::gnss;
+    use listen_gps::gps_ctrl::{self, Action, Supervisor};
+    use listen_gps::hal::{GpioaOutput, PowerPin, SerialPort};
+    #[cfg(feature = "hot-start")]
+    use listen_gps::hotstart;
+    #[cfg(feature = "i2c-slave")]
+    use listen_gps::i2c_slave::{self, Slave};
+    use listen_gps::inbox::Inbox;
+    #[cfg(feature = "indicator")]
+    use listen_gps::indicator::{self, Indicator};
+    use listen_gps::jobs::{Job, Jobs};
+    use listen_gps::load;
+    #[cfg(feature = "lora")]
+    use listen_gps::lora;
+    use listen_gps::nav::Trip;
+    use listen_gps::nmea::{self, encode};
+    #[cfg(feature = "rs485")]
+    use listen_gps::poll::{self, Poll};
+    use listen_gps::pos;
+    use listen_gps::power;
+    use listen_gps::pps::{self, Pps};
+    #[cfg(feature = "profiling")]
+    use listen_gps::profile;
+    use listen_gps::psm;
+    use listen_gps::quality;
+    use listen_gps::rate::{self, Profile};
+    use listen_gps::report::{self, Reporter};
+    use listen_gps::reset;
+    use listen_gps::ringbuf::RingBuffer;
+    use listen_gps::router::{self, Class, Sink};
+    #[cfg(feature = "rs485")]
+    use listen_gps::rs485::{self, Received};
+    use listen_gps::rtc::Rtc;
+    use listen_gps::schedule::Schedule;
+    #[cfg(feature = "sd-log")]
+    use listen_gps::sdlog;
+    use listen_gps::selftest;
+    use listen_gps::sky::Sky;
+    use listen_gps::smoothing::Smoother;
+    use listen_gps::source::{self, Source};
+    use listen_gps::speed_alarm::{self, SpeedAlarm};
+    #[cfg(feature = "spi-slave")]
+    use listen_gps::spi_slave::{self, Stream};
+    use listen_gps::stack;
+    use listen_gps::stats::STATS;
+    #[cfg(feature = "flash-log")]
+    use listen_gps::storage;
+    use listen_gps::timer;
+    use listen_gps::ttff::{self, Ttff};
+    use listen_gps::uart::{self, Reconfigure};
+    use listen_gps::units;
+    #[cfg(feature = "usb")]
+    use listen_gps::usb;
+    use listen_gps::watchdog::{self, Watchdog};
+    use listen_gps::{pubx, ubx};
+    use rtic::Mutex;
+    #[cfg(feature = "usb")]
+    use usb_device::bus::UsbBusAllocator;
+
+    /// Bytes queued for transmission to the GPS, holds a few configuration frames.
+    /// Must be a power of two.
+    const GPS_TX_QUEUE_LEN: usize = 256;
+
+    /// USART2 clock, HSI16 so it keeps receiving in Stop mode
+    const HOST_CLOCK_HZ: u32 = power::WAKEUP_CLOCK_HZ;
+
+    type Response = String<{ cmd::MAX_RESPONSE_LEN }>;
+
+    /// Bytes from the GPS collected before they are queued for the host in raw bridge mode
+    const RAW_CHUNK_LEN: usize = 64;
+
+    /// USB virtual COM port, a placeholder without the `usb` feature
+    #[cfg(feature = "usb")]
+    type UsbSerial = usb::Serial;
+    #[cfg(not(feature = "usb"))]
+    type UsbSerial = ();
+
+    /// Bluetooth serial module, a placeholder without the `ble` feature
+    #[cfg(feature = "ble")]
+    type BleUart = Ble;
+    #[cfg(not(feature = "ble"))]
+    type BleUart = ();
+
+    /// Fixes queued for the SD card and the card itself, placeholders without the `sd-log` feature
+    #[cfg(feature = "sd-log")]
+    type Track = sdlog::Track;
+    #[cfg(not(feature = "sd-log"))]
+    type Track = ();
+    #[cfg(feature = "sd-log")]
+    type SdLogger = sdlog::Logger;
+    #[cfg(not(feature = "sd-log"))]
+    type SdLogger = ();
+
+    /// Flash track log and the dump being sent, placeholders without the `flash-log` feature
+    #[cfg(feature = "flash-log")]
+    type FlashLog = flashlog::Log;
+    #[cfg(not(feature = "flash-log"))]
+    type FlashLog = ();
+    #[cfg(feature = "flash-log")]
+    type LogDump = Option<flashlog::Dump>;
+    #[cfg(not(feature = "flash-log"))]
+    type LogDump = ();
+
+    /// Status display, a placeholder without the `display` feature
+    #[cfg(feature = "display")]
+    type OledDisplay = display::Display;
+    #[cfg(not(feature = "display"))]
+    type OledDisplay = ();
+
+    /// CAN broadcast, a placeholder without the `can` feature
+    #[cfg(feature = "can")]
+    type CanBus = Can;
+    #[cfg(not(feature = "can"))]
+    type CanBus = ();
+
+    /// LoRa beacon, a placeholder without the `lora` feature
+    #[cfg(feature = "lora")]
+    type LoraBeacon = Beacon;
+    #[cfg(not(feature = "lora"))]
+    type LoraBeacon = ();
+
+    /// SPI stream, a placeholder without the `spi-slave` feature
+    #[cfg(feature = "spi-slave")]
+    type SpiStream = Stream;
+    #[cfg(not(feature = "spi-slave"))]
+    type SpiStream = ();
+
+    /// I2C register map, a placeholder without the `i2c-slave` feature
+    #[cfg(feature = "i2c-slave")]
+    type I2cRegisters = Slave;
+    #[cfg(not(feature = "i2c-slave"))]
+    type I2cRegisters = ();
+
+    /// Status LED, a placeholder without the `indicator` feature
+    #[cfg(feature = "indicator")]
+    type StatusLed = Indicator;
+    #[cfg(not(feature = "indicator"))]
+    type StatusLed = ();
+
+    /// Push-button, a placeholder without the `button` feature
+    #[cfg(feature = "button")]
+    type PushButton = Button;
+    #[cfg(not(feature = "button"))]
+    type PushButton = ();
+
+    /// Configuration and event log storage, a placeholder without the `eeprom` feature
+    #[cfg(feature = "eeprom")]
+    type ExternalStorage = Eeprom;
+    #[cfg(not(feature = "eeprom"))]
+    type ExternalStorage = ();
 
-/// Queue received bytes and enable USART2 TXE interrupt. Ignore null bytes.
-#[interrupt]
-fn USART1() {
-    let usart1 = unsafe { USART1_PERIPHERAL.as_mut() }.unwrap();
-    let usart2 = unsafe { USART2_PERIPHERAL.as_mut() }.unwrap();
-    let buffer = unsafe { BUFFER.as_mut() }.unwrap();
+    /// Watch for events of the log, a placeholder without the `eeprom` feature
+    #[cfg(feature = "eeprom")]
+    type EventMonitor = eventlog::Monitor;
+    #[cfg(not(feature = "eeprom"))]
+    type EventMonitor = ();
 
-    if usart1.isr.read().rxne().bit_is_set() {
-        // Read off USART1, this clears RXNE flag
-        let received_byte = usart1.rdr.read().rdr().bits();
-        if received_byte != 0 {
-            // Queue byte, do nothing if queue is full
-            if buffer.enqueue(received_byte).is_ok() {
-                // Enable USART2 TXE interrupt as buffer is now non-empty
-                usart2.cr1.modify(|_, w| w.txeie().enabled());
+    /// Last fix saved for a hot start, a placeholder without the `hot-start` feature
+    #[cfg(feature = "hot-start")]
+    type HotStart = hotstart::Tracker;
+    #[cfg(not(feature = "hot-start"))]
+    type HotStart = ();
+
+    /// Fixes kept for polls, a placeholder without the `rs485` feature
+    #[cfg(feature = "rs485")]
+    type BusPoll = Poll;
+    #[cfg(not(feature = "rs485"))]
+    type BusPoll = ();
+
+    #[shared]
+    struct Shared {
+        host_tx: DoubleBufferTx,
+        gps_tx: RingBuffer<GPS_TX_QUEUE_LEN>,
+        fix: GpsFix,
+        sky: Sky,
+        smoother: Smoother,
+        rate: &'static Profile,
+        filter: Filter,
+        gps_pin: GpioaOutput,
+        pps: Pps,
+        rtc: Rtc,
+        /// GPS baud requested by the host, applied by USART1 once queued bytes are sent
+        gps_baud: u32,
+        usb: UsbSerial,
+        ble: BleUart,
+        /// Command lines from USB and the Bluetooth module, run by USART2
+        inbox: Inbox,
+        track: Track,
+        geofence: Geofence,
+        speed_alarm: SpeedAlarm,
+        anchor: AnchorWatch,
+        schedule: Schedule,
+        battery: Monitor,
+        trip: Trip,
+        flash: pac::FLASH,
+        flash_log: FlashLog,
+        dump: LogDump,
+        supervisor: Supervisor,
+        autobaud: Autobaud,
+        /// Aiding mode, entered by `AID`
+        aid: Option<aid::Upload>,
+        poll: BusPoll,
+        spi_stream: SpiStream,
+        /// Commands USART2 hands to idle
+        jobs: Jobs,
+    }
+
+    #[local]
+    struct Local {
+        usart1: pac::USART1,
+        gps_rx: CircularRx,
+        nmea_parser: nmea::Parser,
+        ubx_parser: ubx::Parser,
+        decimator: Decimator,
+        usart2: pac::USART2,
+        host_baud: u32,
+        /// Host baud change waiting for pending responses to be sent
+        reconfigure: Reconfigure,
+        current_gps_baud: u32,
+        /// GPS still runs its defaults, the saved rate and baud are sent once it is heard from
+        gps_setup: bool,
+        scb: cortex_m::peripheral::SCB,
+        watchdog: Watchdog,
+        logger: SdLogger,
+        display: OledDisplay,
+        i2c_slave: I2cRegisters,
+        can: CanBus,
+        beacon: LoraBeacon,
+        monitor: EventMonitor,
+        hot_start: HotStart,
+        indicator: StatusLed,
+        button: PushButton,
+        adc: Adc,
+        eeprom: ExternalStorage,
+        /// Configuration records, also holding the position of a hot start
+        store: Store,
+    }
+
+    #[init(local = [
+        gps_rx_buffer: [u8; RX_BUFFER_SIZE] = [0; RX_BUFFER_SIZE],
+        host_tx_buffers: [[u8; TX_BUFFER_SIZE]; 2] = [[0; TX_BUFFER_SIZE]; 2],
+        #[cfg(feature = "spi-slave")]
+        spi_buffers: [[u8; spi_slave::BUFFER_SIZE]; 2] = [[0; spi_slave::BUFFER_SIZE]; 2],
+        #[cfg(feature = "usb")]
+        usb_bus: Option<UsbBusAllocator<usb::Bus>> = None,
+    ])]
+    fn init(mut cx: init::Context) -> (Shared, Local) {
+        stack::paint();
+        let dp = cx.device;
+
+        // Enable peripheral clocks - DMA1, GPIOA, USART1, USART2, PWR, TIM2, RTC
+        // They stay enabled in Sleep mode by the RCC_xxxSMENR reset values
+        dp.RCC.ahb1enr.write(|w| w.dma1en().set_bit());
+        dp.RCC.ahb2enr.write(|w| w.gpioaen().set_bit());
+        dp.RCC.apb2enr.write(|w| w.usart1en().set_bit());
+        dp.RCC.apb1enr1.write(|w| {
+            w.usart2en()
+                .set_bit()
+                .pwren()
+                .set_bit()
+                .tim2en()
+                .set_bit()
+                .rtcapben()
+                .set_bit()
+        });
+
+        // Before anything else is configured or the reset flags are cleared
+        dfu::take_request(&dp.RCC, &dp.PWR, &dp.RTC, &dp.SYSCFG);
+        let reset_cause = reset::Cause::take(&dp.RCC);
+        let resets = reset::count(reset_cause);
+        listen_gps::info!(
+            "reset cause {=str}, {=u32} since power-on",
+            reset_cause.name(),
+            resets
+        );
+        let fault = fault::take();
+        if let Some(fault) = &fault {
+            listen_gps::warn!("reset by a fault at {=u32:#010x}", fault.pc);
+        }
+        let panic = fault::take_panic();
+        if let Some(panic) = &panic {
+            listen_gps::warn!("reset by a panic at {=str}", panic.as_str());
+        }
+        reset::set_bor_level(&dp.FLASH);
+
+        let mut rtc = Rtc::new(dp.RTC, &dp.RCC, &dp.PWR);
+        #[cfg(feature = "ab-boot")]
+        boot::on_reset(&dp.FLASH);
+        // Wake up from Stop in time to service the watchdog
+        rtc.enable_wakeup(&dp.EXTI, watchdog::SERVICE_PERIOD_S);
+        clocks::init(&dp.RCC, &dp.FLASH);
+        power::init(&dp.PWR);
+        timer::init(cx.core.SYST, clocks::SYSCLK_HZ);
+        load::init(&mut cx.core.DCB, &mut cx.core.DWT);
+        #[cfg(feature = "eeprom")]
+        let mut eeprom = Eeprom::new(&dp.RCC, &dp.GPIOB, dp.I2C1);
+        #[cfg(feature = "eeprom")]
+        let storage = &mut eeprom.config();
+        #[cfg(not(feature = "eeprom"))]
+        let storage = &mut config::flash_pages(&dp.FLASH);
+        let store = Store::new(storage);
+        let config = store.load(storage).unwrap_or_default();
+        #[cfg(feature = "hot-start")]
+        hotstart::restore(hotstart::recovered().or_else(|| store.hot_start(storage)));
+        #[cfg(not(feature = "eeprom"))]
+        let eeprom = ();
+        #[cfg(feature = "eeprom")]
+        eventlog::record(Event::Reset(reset_cause));
+
+        // Keep the debugger connected while the core sleeps or stops, at the cost of extra current,
+        // and freeze the watchdog while the core is halted at a breakpoint
+        #[cfg(debug_assertions)]
+        {
+            dp.DBGMCU
+                .cr
+                .modify(|_, w| w.dbg_sleep().set_bit().dbg_stop().set_bit());
+            dp.DBGMCU
+                .apb1fzr1
+                .modify(|_, w| w.dbg_iwdg_stop().set_bit());
+        }
+
+        // USART1, USART2 and the TIM2 PPS input on the pins of the board
+        board::init(&dp.GPIOA);
+        let gps_pin = GpioaOutput::new(&dp.GPIOA, board::PINS.gps_power);
+        gps_pin.set_power(config.gps_power);
+        #[cfg(feature = "indicator")]
+        let indicator = Indicator::new(&dp.GPIOA);
+        #[cfg(not(feature = "indicator"))]
+        let indicator = ();
+        #[cfg(feature = "button")]
+        let button = Button::new(&dp.GPIOA, &dp.EXTI);
+        #[cfg(not(feature = "button"))]
+        let button = ();
+        #[cfg(feature = "usb")]
+        let usb = usb::Serial::new(usb::init(
+            &dp.RCC,
+            &dp.CRS,
+            &dp.PWR,
+            &dp.GPIOA,
+            dp.USB,
+            cx.local.usb_bus,
+        ));
+        #[cfg(not(feature = "usb"))]
+        let usb = ();
+        #[cfg(feature = "ble")]
+        let ble = Ble::new(&dp.RCC, &dp.GPIOC, dp.LPUART1);
+        #[cfg(not(feature = "ble"))]
+        let ble = ();
+        // After the clock enables above, which overwrite the registers
+        #[cfg(feature = "geofence-alarm")]
+        geofence::init_alarm(&dp.RCC, &dp.GPIOB);
+        #[cfg(feature = "speed-alarm")]
+        speed_alarm::init_alarm(&dp.GPIOA);
+        #[cfg(feature = "anchor-alarm")]
+        anchor::init_alarm(&dp.GPIOA);
+        let adc = Adc::new(dp.ADC1, dp.ADC_COMMON, &dp.RCC);
+        #[cfg(feature = "display")]
+        let display = display::Display::new(&dp.RCC, &dp.GPIOB, dp.I2C1);
+        #[cfg(not(feature = "display"))]
+        let display = ();
+        #[cfg(feature = "can")]
+        let can = Can::new(&dp.RCC, &dp.GPIOA, dp.CAN1);
+        #[cfg(not(feature = "can"))]
+        let can = ();
+        #[cfg(feature = "lora")]
+        let beacon = Beacon::new(lora::Radio::new(&dp.RCC, &dp.GPIOB, dp.SPI1));
+        #[cfg(not(feature = "lora"))]
+        let beacon = ();
+        #[cfg(feature = "eeprom")]
+        let monitor = eventlog::Monitor::new();
+        #[cfg(not(feature = "eeprom"))]
+        let monitor = ();
+        #[cfg(feature = "hot-start")]
+        let hot_start = hotstart::Tracker::new();
+        #[cfg(not(feature = "hot-start"))]
+        let hot_start = ();
+        #[cfg(feature = "spi-slave")]
+        let spi_stream = Stream::new(
+            &dp.RCC,
+            &dp.GPIOA,
+            &dp.GPIOB,
+            &dp.DMA2,
+            &dp.EXTI,
+            dp.SPI3,
+            cx.local.spi_buffers,
+        );
+        #[cfg(not(feature = "spi-slave"))]
+        let spi_stream = ();
+        #[cfg(feature = "i2c-slave")]
+        let i2c_slave = Slave::new(&dp.RCC, &dp.GPIOB, dp.I2C1);
+        #[cfg(not(feature = "i2c-slave"))]
+        let i2c_slave = ();
+        #[cfg(feature = "sd-log")]
+        let (track, logger) = (
+            sdlog::Track::new(),
+            sdlog::Logger::new(&dp.RCC, dp.GPIOB, dp.SPI1),
+        );
+        #[cfg(not(feature = "sd-log"))]
+        let (track, logger) = ((), ());
+        #[cfg(feature = "flash-log")]
+        let (flash_log, dump) = (flashlog::Log::new(&mut flashlog::pages(&dp.FLASH)), None);
+        #[cfg(not(feature = "flash-log"))]
+        let (flash_log, dump) = ((), ());
+
+        // The GPS starts at its default baud, the host at the saved one unless its clock can't
+        // reach it or the navigation rate needs more. Defaults are supported by their clocks.
+        let _ = dp.USART1.set_baud(clocks::PCLK2_HZ, baud::GPS_DEFAULT);
+        power::enable_stop_wakeup(&dp.RCC, &dp.USART2);
+        let host_baud = Some(config.host_baud.max(config.rate.host_baud))
+            .filter(|&baud| baud::divider(HOST_CLOCK_HZ, baud).is_ok())
+            .unwrap_or(baud::HOST_DEFAULT);
+        let _ = dp.USART2.set_baud(HOST_CLOCK_HZ, host_baud);
+
+        // USART1 interfaces with GPS - received bytes are written to memory by DMA
+        let gps_rx = CircularRx::new(
+            &dp.DMA1,
+            &dp.USART1,
+            cx.local.gps_rx_buffer,
+            config.rate.rx_window,
+        );
+        // Enable DMA reception and error interrupt, RXNE interrupt is not used in DMA mode
+        dp.USART1.cr3.write(|w| w.dmar().enabled().eie().enabled());
+        // Enable receiver, transmitter and IDLE interrupt to flush partially filled DMA buffer,
+        // keeping oversampling set with baud
+        // TXE interrupt is enabled on demand when bytes are queued for the GPS
+        dp.USART1.cr1.modify(|_, w| {
+            w.re()
+                .enabled()
+                .te()
+                .enabled()
+                .ue()
+                .enabled()
+                .idleie()
+                .enabled()
+        });
+        // USART2 interfaces with UART adaptor - transmitted bytes are read from memory by DMA
+        let mut host_tx = DoubleBufferTx::new(&dp.DMA1, &dp.USART2, cx.local.host_tx_buffers);
+        let mut report = Response::new();
+        // The sentences fit in the empty buffer, a reset records a fault or a panic but not
+        // both, and DMA waits for the USART to be enabled
+        let _ = reset::write_sentence(&mut report, reset_cause, resets);
+        let _ = host_tx.write(report.as_bytes());
+        if let Some(fault) = fault {
+            let mut sentence = String::<{ fault::SENTENCE_LEN }>::new();
+            let _ = fault::write_sentence(&mut sentence, &fault);
+            let _ = host_tx.write(sentence.as_bytes());
+        }
+        if let Some(panic) = panic {
+            let mut sentence = String::<{ fault::PANIC_SENTENCE_LEN }>::new();
+            let _ = fault::write_panic_sentence(&mut sentence, &panic);
+            let _ = host_tx.write(sentence.as_bytes());
+        }
+        dp.USART2.cr3.write(|w| w.dmat().enabled());
+        #[cfg(feature = "flow-control")]
+        flow::init(&dp.GPIOA, &dp.USART2);
+        #[cfg(feature = "rs485")]
+        rs485::init(&dp.GPIOA, &dp.USART2, host_baud);
+        #[cfg(feature = "host-autobaud")]
+        baud::start_detection(&dp.USART2);
+        // Enable receiver, transmitter and RXNE interrupt, keeping oversampling set with baud
+        dp.USART2.cr1.modify(|_, w| {
+            w.re()
+                .enabled()
+                .te()
+                .enabled()
+                .ue()
+                .enabled()
+                .rxneie()
+                .enabled()
+        });
+
+        // NVIC USART1, USART2, DMA1_CH5, DMA1_CH7 global interrupts are unmasked by RTIC when init returns
+        (
+            Shared {
+                host_tx,
+                gps_tx: RingBuffer::new(),
+                fix: GpsFix::new(),
+                sky: Sky::new(),
+                smoother: Smoother::new(),
+                rate: config.rate,
+                filter: config.filter,
+                gps_pin,
+                pps: Pps::new(dp.TIM2, clocks::PCLK1_HZ),
+                rtc,
+                gps_baud: config.gps_baud,
+                usb,
+                ble,
+                inbox: Inbox::new(),
+                track,
+                geofence: Geofence::new(config.zones.clone()),
+                speed_alarm: SpeedAlarm::new(),
+                anchor: AnchorWatch::new(),
+                schedule: Schedule::new(),
+                battery: Monitor::new(),
+                trip: Trip::new(),
+                flash: dp.FLASH,
+                flash_log,
+                dump,
+                supervisor: Supervisor::new(config.fix_timeout_s),
+                autobaud: Autobaud::new(),
+                aid: None,
+                poll: BusPoll::default(),
+                spi_stream,
+                jobs: Jobs::new(),
+            },
+            Local {
+                usart1: dp.USART1,
+                gps_rx,
+                nmea_parser: nmea::Parser::new(),
+                ubx_parser: ubx::Parser::new(),
+                decimator: Decimator::new(),
+                usart2: dp.USART2,
+                host_baud,
+                reconfigure: Reconfigure::new(),
+                current_gps_baud: baud::GPS_DEFAULT,
+                gps_setup: config.rate != rate::DEFAULT || config.gps_baud != baud::GPS_DEFAULT,
+                scb: cx.core.SCB,
+                // Started last so that the slow LSE startup isn't counted
+                watchdog: Watchdog::start(dp.IWDG),
+                logger,
+                display,
+                i2c_slave,
+                can,
+                beacon,
+                monitor,
+                hot_start,
+                indicator,
+                button,
+                adc,
+                eeprom,
+                store,
+            },
+        )
+    }
+
+    /// Sleep until the next interrupt. USART and DMA keep running in Sleep mode and their
+    /// interrupts wake the core. With the GPS off and all responses sent, stop until USART2, or
+    /// LPUART1 for the Bluetooth module, receives a byte instead, unless USB has to stay
+    /// responsive to the host.
+    /// The watchdog is serviced on every wakeup. Queued fixes are written to the SD card before
+    /// sleeping, preempted by the interrupts, and the display is redrawn once a second.
+    /// Commands handed over by USART2 are run, see `jobs`, recorded events are written to the
+    /// EEPROM, the next block of the flash log is erased once USART1 has filled one, the last fix
+    /// is saved once the GPS is turned off and the configuration records are erased ahead of
+    /// their compaction. The EEPROM and the configuration records belong to idle alone, so no
+    /// task waits for their writes.
+    #[idle(
+        local = [scb, watchdog, logger, display, hot_start, eeprom, store],
+        shared = [
+            host_tx, gps_pin, track, fix, sky, smoother, rtc, flash, flash_log, dump, usb, ble,
+            jobs,
+        ]
+    )]
+    fn idle(mut cx: idle::Context) -> ! {
+        loop {
+            while let Some((port, job)) = cx.shared.jobs.lock(|jobs| jobs.pop()) {
+                run_job(port, job, &mut cx.local, &mut cx.shared);
             }
+            #[cfg(feature = "eeprom")]
+            while let Some(event) = eventlog::take() {
+                let time = cx.shared.rtc.lock(|rtc| rtc.now());
+                if cx.local.eeprom.append(event, time).is_err() {
+                    listen_gps::warn!("event log write failed");
+                }
+            }
+            #[cfg(feature = "flash-log")]
+            let _ = prepare_flash_log(&mut cx.shared.flash, &mut cx.shared.flash_log);
+            #[cfg(feature = "hot-start")]
+            save_hot_start(&mut cx.local, &mut cx.shared);
+            prepare_config(&mut cx.local, &mut cx.shared);
+            #[cfg(feature = "sd-log")]
+            write_track(
+                cx.local.logger,
+                &mut cx.shared.track,
+                &mut cx.shared.host_tx,
+            );
+            #[cfg(feature = "display")]
+            cx.local.display.refresh(|| display::Status {
+                gps_power: cx.shared.gps_pin.lock(|gps_pin| gps_pin.is_powered()),
+                fix: {
+                    let fix = cx.shared.fix.lock(|fix| *fix);
+                    cx.shared.smoother.lock(|smoother| smoother.smooth(&fix))
+                },
+                satellites: cx.shared.sky.lock(|sky| sky.satellites().clone()),
+            });
+            // Interrupts pending after the check still wake WFI and run once it returns
+            cortex_m::interrupt::free(|_| {
+                let gps_off = cx.shared.gps_pin.lock(|gps_pin| !gps_pin.is_powered());
+                let queued = !cx.shared.host_tx.lock(|host_tx| host_tx.is_idle());
+                cx.local.watchdog.service(gps_off || psm::is_sleeping());
+                if dfu::is_pending() && !queued {
+                    // The `OK` of `DFU` is out of DMA, wait for the USART to shift it out
+                    while !host_transmission_complete() {}
+                    dfu::reset();
+                }
+                #[cfg(feature = "ab-boot")]
+                if boot::is_swap_pending() && !queued {
+                    while !host_transmission_complete() {}
+                    boot::swap();
+                }
+                #[cfg(feature = "sd-log")]
+                if !cx.shared.track.lock(|track| track.is_empty()) {
+                    // Queued while writing the last one
+                    return;
+                }
+                if !cx.shared.jobs.lock(|jobs| jobs.is_empty()) {
+                    // Handed over while running the last one
+                    return;
+                }
+                #[cfg(feature = "eeprom")]
+                if eventlog::is_pending() {
+                    return;
+                }
+                if gps_ctrl::is_cycling() {
+                    // Stop would halt SysTick, which turns the GPS on again
+                    power::sleep();
+                    return;
+                }
+                #[cfg(feature = "ble")]
+                if !cx.shared.ble.lock(|ble| ble.is_empty()) {
+                    // Stop would halt LPUART1 with sentences queued for the module
+                    power::sleep();
+                    return;
+                }
+                #[cfg(feature = "button")]
+                if button::is_held() {
+                    // Stop would halt the TIM2 timing of the press
+                    power::sleep();
+                    return;
+                }
+                if gps_off && !queued && !cfg!(feature = "usb") {
+                    // DMA is done, wait for the USART to shift out the last bytes
+                    while !host_transmission_complete() {}
+                    #[cfg(feature = "indicator")]
+                    indicator::off();
+                    power::stop(cx.local.scb);
+                } else {
+                    power::sleep();
+                }
+            });
+        }
+    }
+
+    /// Save the last fix of the GPS once it is turned off, see `hotstart`. The configuration
+    /// records are idle's own, and the FLASH lock taken across a compaction is shared with
+    /// USART1 and DMA1_CH7 but not with USART2.
+    #[cfg(feature = "hot-start")]
+    fn save_hot_start(local: &mut idle::LocalResources, shared: &mut idle::SharedResources) {
+        let gps_power = shared.gps_pin.lock(|gps_pin| gps_pin.is_powered());
+        let fix = shared.fix.lock(|fix| *fix);
+        let Some(seed) = local.hot_start.on_idle(gps_power, &fix) else {
+            return;
+        };
+        let store = &mut local.store;
+        #[cfg(feature = "eeprom")]
+        let saved = store.save_hot_start(&mut local.eeprom.config(), &seed);
+        #[cfg(not(feature = "eeprom"))]
+        let saved = shared
+            .flash
+            .lock(|flash| store.save_hot_start(&mut config::flash_pages(flash), &seed));
+        if saved.is_err() {
+            listen_gps::warn!("hot start save failed");
         }
     }
-    // See reference manual p.1206 or ch. 38.7.
-    // RXNE interrupt can also be triggered by overrun error. Flag must be cleared.
-    if usart1.isr.read().ore().bit_is_set() {
-        usart1.icr.write(|w| w.orecf().set_bit());
+
+    /// Erase a block of the configuration records the next compaction writes, so saving the
+    /// hot start seed or `SAVE` only programs, see `kv`
+    fn prepare_config(local: &mut idle::LocalResources, shared: &mut idle::SharedResources) {
+        let store = &mut local.store;
+        if !store.needs_erase() {
+            return;
+        }
+        #[cfg(feature = "eeprom")]
+        let result = store.prepare(&mut local.eeprom.config());
+        #[cfg(not(feature = "eeprom"))]
+        let result = shared
+            .flash
+            .lock(|flash| store.prepare(&mut config::flash_pages(flash)));
+        #[cfg(feature = "eeprom")]
+        let _ = shared;
+        if result.is_err() {
+            listen_gps::warn!("configuration erase failed");
+        }
     }
-}
 
-/// Turn on/off A12 based on received byte
-#[interrupt]
-fn USART2() {
-    let usart2 = unsafe { USART2_PERIPHERAL.as_mut() }.unwrap();
-    let gpioa = unsafe { GPIOA_PERIPHERAL.as_mut() }.unwrap();
-    let buffer = unsafe { BUFFER.as_mut() }.unwrap();
+    /// Erase a block of the flash log once USART1 has filled one, or while `ERASE` runs, so
+    /// appending a fix only programs flash, see `flashlog`. The locks are held for one block.
+    #[cfg(feature = "flash-log")]
+    fn prepare_flash_log(
+        flash: &mut impl Mutex<T = pac::FLASH>,
+        flash_log: &mut impl Mutex<T = FlashLog>,
+    ) -> Result<(), storage::Error> {
+        if !flash_log.lock(|flash_log| flash_log.needs_erase()) {
+            return Ok(());
+        }
+        let result = flash.lock(|flash| {
+            flash_log.lock(|flash_log| flash_log.prepare(&mut flashlog::pages(flash)))
+        });
+        if result.is_err() {
+            listen_gps::warn!("flash log erase failed");
+        }
+        result
+    }
+
+    /// Run a command USART2 handed over and send the response to the port it came from
+    fn run_job(
+        port: Sink,
+        job: Job,
+        local: &mut idle::LocalResources,
+        shared: &mut idle::SharedResources,
+    ) {
+        let mut response = Response::new();
+        // A response cut short by the buffer size is still sent
+        let _ = perform(job, port, local, shared, &mut response);
+        answer(port, response, shared);
+        // The records of a dump follow its response
+        #[cfg(feature = "flash-log")]
+        send_dump(&mut shared.dump, &mut shared.flash, &mut shared.host_tx);
+        // In polled mode the response takes a turn of its own, as in USART2
+        #[cfg(feature = "rs485")]
+        if port == Sink::Host && rs485::is_polled() {
+            rs485::begin_turn();
+            rs485::end_turn();
+            shared.host_tx.lock(|host_tx| host_tx.flush());
+        }
+    }
 
-    if usart2.isr.read().txe().bit_is_set() {
-        match buffer.dequeue() {
-            // Write dequeued byte
-            Some(byte) => {
-                usart2.tdr.write(|w| w.tdr().bits(byte));
-                if buffer.is_empty() {
-                    usart2.cr1.modify(|_, w| w.txeie().disabled());
+    /// Run a job of a command received on `port` and write its response line, without line
+    /// ending
+    fn perform(
+        job: Job,
+        port: Sink,
+        local: &mut idle::LocalResources,
+        shared: &mut idle::SharedResources,
+        response: &mut impl Write,
+    ) -> core::fmt::Result {
+        let result = match job {
+            Job::SelfTest => {
+                // USART1 owns the UART and preempts idle as soon as it is pended
+                selftest::request_uart();
+                rtic::pend(pac::Interrupt::USART1);
+                let uart = loop {
+                    if let Some(passed) = selftest::uart_result() {
+                        break passed;
+                    }
+                };
+                let results = selftest::Results {
+                    uart,
+                    queue: selftest::queue(),
+                    flash: shared.flash.lock(|flash| selftest::flash(flash)),
+                    rtc: selftest::rtc(|| shared.rtc.lock(|rtc| rtc.subseconds())),
+                    power: shared.gps_pin.lock(|gps_pin| selftest::power(gps_pin)),
+                };
+                return cmd::write_selftest(response, &results);
+            }
+            Job::Save(config) => {
+                let store = &mut local.store;
+                #[cfg(feature = "eeprom")]
+                let saved = store
+                    .save(&mut local.eeprom.config(), &config)
+                    .map_err(|_| cmd::Error::Eeprom);
+                #[cfg(not(feature = "eeprom"))]
+                let saved = shared
+                    .flash
+                    .lock(|flash| store.save(&mut config::flash_pages(flash), &config))
+                    .map_err(|_| cmd::Error::Flash);
+                saved
+            }
+            #[cfg(feature = "eeprom")]
+            Job::Events(count) => match local.eeprom.latest(count) {
+                Ok(entries) => {
+                    for entry in &entries {
+                        let mut line = Response::new();
+                        // Fits in a response
+                        let _ = cmd::write_event(&mut line, entry);
+                        answer(port, line, shared);
+                    }
+                    return cmd::write_events(response, entries.len());
                 }
+                Err(_) => Err(cmd::Error::Eeprom),
+            },
+            #[cfg(feature = "flash-log")]
+            Job::Dump(format) => {
+                let dump = shared.flash_log.lock(|flash_log| flash_log.dump(format));
+                shared.dump.lock(|running| *running = Some(dump));
+                Ok(())
             }
-            // Buffer is empty, disable USART2 TXE interrupt
-            None => usart2.cr1.modify(|_, w| w.txeie().disabled()),
+            #[cfg(feature = "flash-log")]
+            Job::Erase => {
+                shared.dump.lock(|running| *running = None);
+                shared.flash_log.lock(|flash_log| flash_log.erase());
+                // A page at a time, USART1 flushes its RX ring between them
+                let mut erased = Ok(());
+                while erased.is_ok() && shared.flash_log.lock(|flash_log| flash_log.needs_erase()) {
+                    erased = prepare_flash_log(&mut shared.flash, &mut shared.flash_log);
+                }
+                erased.map_err(|_| cmd::Error::Flash)
+            }
+        };
+        #[cfg(not(feature = "eeprom"))]
+        let _ = port;
+        match result {
+            Ok(()) => write!(response, "OK"),
+            Err(error) => write!(response, "ERR {}", error.as_str()),
         }
     }
 
-    // Received command from UART adaptor - toggle GPS ON/OFF
-    if usart2.isr.read().rxne().bit_is_set() {
-        // Read off USART2, this clears RXNE flag
-        let received_byte = usart2.rdr.read().rdr().bits();
+    /// Queue a response line of idle for `port`, appending the line ending
+    fn answer(port: Sink, mut response: Response, shared: &mut idle::SharedResources) {
+        cmd::terminate(&mut response);
+        send_reply(
+            port,
+            response.as_bytes(),
+            &mut shared.host_tx,
+            &mut shared.usb,
+            &mut shared.ble,
+        );
+    }
 
-        // Turn off if '0', turn on if '1'
-        if received_byte == b'0'.into() {
-            gpioa.bsrr.write(|w| w.br12().set_bit());
-        } else if received_byte == b'1'.into() {
-            gpioa.bsrr.write(|w| w.bs12().set_bit());
+    /// Write queued fixes to the SD card, closing the file once logging stops.
+    /// A card error stops logging and is reported as `LOG ERR SD`.
+    #[cfg(feature = "sd-log")]
+    fn write_track(
+        logger: &mut sdlog::Logger,
+        track: &mut impl Mutex<T = sdlog::Track>,
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+    ) {
+        while let Some((fix, format)) = track.lock(|track| track.pop()) {
+            if logger.write(&fix, format).is_err() {
+                listen_gps::warn!("SD card error, logging stopped");
+                track.lock(|track| track.set_format(None));
+                let mut report = Response::new();
+                let _ = write!(report, "LOG ERR SD");
+                respond(host_tx, report);
+            }
+        }
+        if track.lock(|track| track.format().is_none()) {
+            logger.close();
         }
     }
-    if usart2.isr.read().ore().bit_is_set() {
-        usart2.icr.write(|w| w.orecf().set_bit());
+
+    /// USART2 has shifted out its last byte, which Stop mode would otherwise cut short
+    fn host_transmission_complete() -> bool {
+        // SAFETY: read-only access to a status register, USART2 is otherwise owned by its task
+        let usart2 = unsafe { &*pac::USART2::ptr() };
+        usart2.is_transmit_complete()
+    }
+
+    /// Queue a response line for the host, appending the line ending.
+    /// A line that doesn't fit in the TX buffer is dropped and counted.
+    fn respond(host_tx: &mut impl Mutex<T = DoubleBufferTx>, mut response: Response) {
+        cmd::terminate(&mut response);
+        send_host(host_tx, Class::Response, response.as_bytes());
+    }
+
+    /// Queue a complete line of `class` for the host and start sending it, a line that doesn't
+    /// fit is dropped and counted
+    fn send_host(host_tx: &mut impl Mutex<T = DoubleBufferTx>, class: Class, line: &[u8]) {
+        host_tx.lock(|host_tx| {
+            queue_host(host_tx, class, line);
+            host_tx.flush();
+        });
+    }
+
+    /// Queue a forwarded sentence for the host, sent once the `BATCH` threshold is reached or
+    /// the GPS pauses, see `usart1`
+    fn forward_host(host_tx: &mut impl Mutex<T = DoubleBufferTx>, sentence: &[u8]) {
+        host_tx.lock(|host_tx| queue_host(host_tx, Class::Bulk, sentence));
+    }
+
+    /// Queue `line` of `source` for the sinks `ROUTE` selects for it, by the priority class of
+    /// the source, see `router`. Reports are sent to the host right away, sentences once the
+    /// `BATCH` threshold is reached.
+    fn route(
+        source: router::Source,
+        line: &[u8],
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+        usb: &mut impl Mutex<T = UsbSerial>,
+        ble: &mut impl Mutex<T = BleUart>,
+    ) {
+        let sinks = router::route(source);
+        if sinks.contains(Sink::Host) {
+            match source.class() {
+                Class::Bulk => forward_host(host_tx, line),
+                class => send_host(host_tx, class, line),
+            }
+        }
+        #[cfg(feature = "usb")]
+        if sinks.contains(Sink::Usb) {
+            usb.lock(|usb| usb.write(source.class(), line));
+        }
+        #[cfg(not(feature = "usb"))]
+        let _ = usb;
+        #[cfg(feature = "ble")]
+        if sinks.contains(Sink::Ble) {
+            ble.lock(|ble| ble.write(source.class(), line));
+        }
+        #[cfg(not(feature = "ble"))]
+        let _ = ble;
     }
-}
 
-#[entry]
-fn main() -> ! {
-    // Device defaults to 4MHz clock
-
-    let dp = stm32l4x2::Peripherals::take().unwrap();
-
-    // Enable peripheral clocks - GPIOA, USART1, USART2
-    dp.RCC.ahb2enr.write(|w| w.gpioaen().set_bit());
-    dp.RCC.apb2enr.write(|w| w.usart1en().set_bit());
-    dp.RCC.apb1enr1.write(|w| w.usart2en().set_bit());
-
-    // USART1: Configure A9 (TX), A10 (RX) as alternate function 7
-    // USART2: Configure A2 (TX), A3 (RX) as alternate function 7
-    // GPIOA: A12 as push-pull output
-    dp.GPIOA.moder.write(|w| {
-        w.moder2()
-            .alternate()
-            .moder3()
-            .alternate()
-            .moder9()
-            .alternate()
-            .moder10()
-            .alternate()
-            .moder12()
-            .output() // push-pull by default
-    });
-    dp.GPIOA.ospeedr.write(|w| {
-        w.ospeedr2()
-            .very_high_speed()
-            .ospeedr3()
-            .very_high_speed()
-            .ospeedr9()
-            .very_high_speed()
-            .ospeedr10()
-            .very_high_speed()
-    });
-    dp.GPIOA.afrl.write(|w| w.afrl2().af7().afrl3().af7());
-    dp.GPIOA.afrh.write(|w| w.afrh9().af7().afrh10().af7());
-
-    // Configure baud rate 9600
-    dp.USART1.brr.write(|w| w.brr().bits(417)); // 4Mhz / 9600 approx. 417
-    dp.USART2.brr.write(|w| w.brr().bits(417)); // 4Mhz / 9600 approx. 417
-
-    // USART1 interfaces with GPS - enable receiver and RXNE interrupt
-    dp.USART1
-        .cr1
-        .write(|w| w.re().enabled().ue().enabled().rxneie().enabled());
-    // USART2 interfaces with UART adaptor - enable receiver, transmitter and RXNE interrupt
-    // TXE interrupt is enabled by USART1 on demand
-    dp.USART2.cr1.write(|w| {
-        w.re()
-            .enabled()
-            .te()
-            .enabled()
-            .ue()
-            .enabled()
-            .rxneie()
-            .enabled()
-    });
-
-    unsafe {
-        BUFFER = Some(Queue::default());
-        // Unmask NVIC USART1, USART2 global interrupts
-        cortex_m::peripheral::NVIC::unmask(stm32l4x2::Interrupt::USART1);
-        cortex_m::peripheral::NVIC::unmask(stm32l4x2::Interrupt::USART2);
-        USART1_PERIPHERAL = Some(dp.USART1);
-        USART2_PERIPHERAL = Some(dp.USART2);
-        GPIOA_PERIPHERAL = Some(dp.GPIOA);
-    }
-
-    #[allow(clippy::empty_loop)]
-    loop {}
+    /// Route `line` of `source` from USART1
+    fn forward(shared: &mut usart1::SharedResources, source: router::Source, line: &[u8]) {
+        route(
+            source,
+            line,
+            &mut shared.host_tx,
+            &mut shared.usb,
+            &mut shared.ble,
+        );
+    }
+
+    fn queue_host(host_tx: &mut DoubleBufferTx, class: Class, line: &[u8]) {
+        host_tx.write_line(class, line);
+        STATS.host_tx_peak.record(host_tx.queued());
+        #[cfg(feature = "flow-control")]
+        flow::update(host_tx.queued());
+    }
+
+    /// Assemble bytes received by DMA into sentences, queue each complete sentence passing its
+    /// checksum and the filter for transmission and update the fix from it. Sentences are queued
+    /// as a whole like the `$PBRIDGE` sentences and responses sent between them, so lines never
+    /// interleave on USART2. Null bytes are ignored by the NMEA parser.
+    /// UBX frames are parsed from the same stream and acknowledgements reported, see
+    /// `report_packet`.
+    /// With `SRC UBX` the fix comes from NAV-PVT messages instead of sentences, see `on_nav_pvt`.
+    /// Forwarding pauses while a dump is sent and in aiding mode. In raw bridge mode all bytes
+    /// are forwarded unchanged instead, and sentences are only parsed.
+    /// Forwarded sentences are decimated by `DECIMATE`, see `decimate`.
+    /// A sentence that doesn't fit in the TX buffer is dropped and counted.
+    /// The first valid sentence completes a pending `gps_setup`.
+    fn receive(
+        gps_rx: &mut CircularRx,
+        nmea_parser: &mut nmea::Parser,
+        ubx_parser: &mut ubx::Parser,
+        decimator: &mut Decimator,
+        gps_setup: &mut bool,
+        shared: &mut usart1::SharedResources,
+    ) {
+        let raw = bridge::is_raw();
+        let mut chunk = Vec::<u8, RAW_CHUNK_LEN>::new();
+        let mut received = 0;
+        gps_rx.flush(|received_byte| {
+            received += 1;
+            watchdog::rx_activity();
+            if raw && chunk.push(received_byte).is_err() {
+                send_host(&mut shared.host_tx, Class::Bulk, &chunk);
+                chunk.clear();
+                let _ = chunk.push(received_byte);
+            }
+            if let Some(packet) = ubx_parser.push(received_byte) {
+                match ubx::NavPvt::parse(&packet) {
+                    Some(pvt) if source::source() == Source::Ubx => {
+                        on_nav_pvt(&pvt, decimator, gps_setup, shared)
+                    }
+                    // Acknowledgements pass through unchanged in raw mode
+                    _ if !raw => report_packet(&packet, shared),
+                    _ => {}
+                }
+            }
+
+            if received_byte == 0 {
+                return;
+            }
+            if let Some(sentence) = nmea_parser.push(received_byte) {
+                #[cfg(feature = "flash-log")]
+                let dumping = shared.dump.lock(|dump| dump.is_some());
+                #[cfg(not(feature = "flash-log"))]
+                let dumping = false;
+                let aiding = shared.aid.lock(|aid| aid.is_some());
+                let sentence_type = nmea::SentenceType::of(sentence);
+                let verified = nmea::verify(sentence).is_ok();
+                if verified {
+                    lock_gps_baud(shared);
+                } else {
+                    STATS.checksum_errors.increment();
+                }
+                if !raw
+                    && !dumping
+                    && !aiding
+                    && !is_polled()
+                    && filter::output() == Output::Nmea
+                    && (verified || filter::passthru() == Passthru::Raw)
+                    && shared.filter.lock(|filter| filter.allows(sentence_type))
+                    && decimator.passes(sentence_type, sentence)
+                {
+                    forward(shared, router::Source::Gps, sentence);
+                }
+                #[cfg(feature = "spi-slave")]
+                if verified
+                    && spi_slave::content().sentences()
+                    && shared.filter.lock(|filter| filter.allows(sentence_type))
+                {
+                    shared
+                        .spi_stream
+                        .lock(|stream| stream.push_sentence(sentence));
+                }
+                match nmea::Sentence::parse(sentence) {
+                    Ok(sentence) => {
+                        STATS.sentences.increment();
+                        on_gps_heard(gps_setup, shared);
+                        if let nmea::Sentence::Rmc(nmea::Rmc {
+                            valid: true,
+                            time: Some(time),
+                            date: Some(date),
+                            ..
+                        }) = sentence
+                        {
+                            discipline_rtc(shared, date, time);
+                        }
+                        shared.sky.lock(|sky| sky.update(&sentence));
+                        // Sentences left over from switching to UBX don't make epochs
+                        if source::source() == Source::Nmea {
+                            shared.fix.lock(|fix| fix.update(&sentence));
+                            // GGA completes the fix of an epoch, it follows RMC
+                            if matches!(sentence, nmea::Sentence::Gga(_)) {
+                                complete_epoch(decimator, shared, dumping);
+                            }
+                        }
+                    }
+                    Err(nmea::Error::Unsupported) => {}
+                    Err(_) => STATS.invalid_sentences.increment(),
+                }
+            }
+        });
+        if !chunk.is_empty() {
+            send_host(&mut shared.host_tx, Class::Bulk, &chunk);
+        }
+        STATS.gps_rx_peak.record(received);
+    }
+
+    /// Account for data passing its checksum at the GPS baud, or lock onto the rate being probed
+    /// and report it
+    fn lock_gps_baud(shared: &mut usart1::SharedResources) {
+        if let Some(baud) = shared
+            .autobaud
+            .lock(|autobaud| autobaud.on_sentence(timer::now_ms()))
+        {
+            shared.gps_baud.lock(|gps_baud| *gps_baud = baud);
+            listen_gps::info!("GPS found at {=u32} baud", baud);
+            let mut report = Response::new();
+            // Fits in a response
+            let _ = autobaud::write_sentence(&mut report, baud);
+            forward(shared, router::Source::Report, report.as_bytes());
+        }
+    }
+
+    /// Complete an epoch from a NAV-PVT message with `SRC UBX`, counted as a sentence. In
+    /// `MODE NMEA` GGA and RMC are synthesized from the fix and forwarded like received ones.
+    fn on_nav_pvt(
+        pvt: &ubx::NavPvt,
+        decimator: &mut Decimator,
+        gps_setup: &mut bool,
+        shared: &mut usart1::SharedResources,
+    ) {
+        #[cfg(feature = "flash-log")]
+        let dumping = shared.dump.lock(|dump| dump.is_some());
+        #[cfg(not(feature = "flash-log"))]
+        let dumping = false;
+        STATS.sentences.increment();
+        lock_gps_baud(shared);
+        on_gps_heard(gps_setup, shared);
+        if let (nmea::FixType::Fix2D | nmea::FixType::Fix3D, Some(date), Some(time)) =
+            (pvt.fix, pvt.date, pvt.time)
+        {
+            discipline_rtc(shared, date, time);
+        }
+        let fix = shared.fix.lock(|fix| {
+            fix.update_pvt(pvt);
+            *fix
+        });
+        if !bridge::is_raw()
+            && !dumping
+            && !is_polled()
+            && shared.aid.lock(|aid| aid.is_none())
+            && filter::output() == Output::Nmea
+        {
+            // RMC precedes GGA like in the NMEA output of the GPS
+            for sentence_type in [nmea::SentenceType::Rmc, nmea::SentenceType::Gga] {
+                if !shared.filter.lock(|filter| filter.allows(sentence_type)) {
+                    continue;
+                }
+                let mut sentence = Response::new();
+                // Both fit in a response
+                let _ = match sentence_type {
+                    nmea::SentenceType::Rmc => encode::write_rmc(&mut sentence, &fix.rmc()),
+                    _ => encode::write_gga(&mut sentence, &fix.gga()),
+                };
+                if !decimator.passes(sentence_type, sentence.as_bytes()) {
+                    continue;
+                }
+                forward(shared, router::Source::Synth, sentence.as_bytes());
+            }
+        }
+        complete_epoch(decimator, shared, dumping);
+    }
+
+    /// Report a UBX acknowledgement. In aiding mode acknowledgements are counted and reported
+    /// as `AID ACK|NAK`, and other frames are forwarded to the host.
+    fn report_packet(packet: &ubx::Packet, shared: &mut usart1::SharedResources) {
+        let mut report = Response::new();
+        let aiding = shared
+            .aid
+            .lock(|aid| aid.as_mut().map(|upload| upload.on_packet(packet)));
+        match aiding {
+            Some(Some(ack)) => {
+                let _ = aid::write_ack(&mut report, &ack);
+                respond(&mut shared.host_tx, report);
+            }
+            Some(None) => {
+                // Parsed payloads fit in a frame
+                if let Ok(frame) = ubx::encode(packet.class, packet.id, packet.payload) {
+                    send_host(&mut shared.host_tx, Class::Response, &frame);
+                }
+            }
+            None => {
+                if let Some(ack) = ubx::Ack::parse(packet) {
+                    let _ = match gnss::on_ack(&ack) {
+                        Some((selection, accepted)) => {
+                            cmd::write_gnss_ack(&mut report, &selection, accepted)
+                        }
+                        None => cmd::write_ack(&mut report, &ack),
+                    };
+                    respond(&mut shared.host_tx, report);
+                }
+            }
+        }
+    }
+
+    /// Send the completed fix as `$PBRIDGE,POS` if the filter selects it, update the smoothing
+    /// with it and report the geofence transitions of the smoothed one and speed and anchor alarm
+    /// changes. If the fix passes the quality gate, send it as a binary record in `MODE BIN`, add
+    /// it to the trip, queue it for the SD card and append it to the flash log, unless that is
+    /// being dumped.
+    fn complete_epoch(
+        decimator: &mut Decimator,
+        shared: &mut usart1::SharedResources,
+        dumping: bool,
+    ) {
+        let fix = shared.fix.lock(|fix| *fix);
+        if filter::output() == Output::Nmea
+            && !bridge::is_raw()
+            && !dumping
+            && !is_polled()
+            && shared.aid.lock(|aid| aid.is_none())
+            && shared
+                .filter
+                .lock(|filter| filter.allows(nmea::SentenceType::Pos))
+        {
+            let mut sentence = Response::new();
+            // Fits in a response
+            let _ = pos::write_sentence(&mut sentence, &fix);
+            if decimator.passes(nmea::SentenceType::Pos, sentence.as_bytes()) {
+                forward(shared, router::Source::Synth, sentence.as_bytes());
+            }
+        }
+        let smoothed = shared.smoother.lock(|smoother| {
+            smoother.update(&fix);
+            smoother.smooth(&fix)
+        });
+        let events = shared.geofence.lock(|geofence| geofence.update(&smoothed));
+        for event in &events {
+            let mut report = Response::new();
+            // Fits in a response
+            let _ = geofence::write_sentence(&mut report, event);
+            forward(shared, router::Source::Report, report.as_bytes());
+            #[cfg(feature = "eeprom")]
+            eventlog::record(Event::Geofence {
+                zone: event.zone as u8,
+                transition: event.transition,
+            });
+        }
+        #[cfg(feature = "geofence-alarm")]
+        if !events.is_empty() {
+            geofence::set_alarm(shared.geofence.lock(|geofence| geofence.is_inside_any()));
+        }
+        if let Some(event) = shared.speed_alarm.lock(|alarm| alarm.update(&fix)) {
+            let mut report = Response::new();
+            // Fits in a response
+            let _ = speed_alarm::write_sentence(&mut report, &event);
+            forward(shared, router::Source::Report, report.as_bytes());
+            #[cfg(feature = "speed-alarm")]
+            speed_alarm::set_alarm(event.raised);
+            #[cfg(feature = "eeprom")]
+            eventlog::record(Event::SpeedAlarm(event.raised));
+        }
+        if let Some(event) = shared.anchor.lock(|anchor| anchor.update(&fix)) {
+            let mut report = Response::new();
+            // Fits in a response
+            let _ = anchor::write_sentence(&mut report, &event);
+            forward(shared, router::Source::Report, report.as_bytes());
+            #[cfg(feature = "anchor-alarm")]
+            anchor::set_alarm(event.raised);
+            #[cfg(feature = "eeprom")]
+            eventlog::record(Event::AnchorAlarm(event.raised));
+        }
+        if !quality::gate().passes(&fix) {
+            return;
+        }
+        if filter::output() == Output::Binary
+            && !dumping
+            && !is_polled()
+            && !bridge::is_raw()
+            && shared.aid.lock(|aid| aid.is_none())
+        {
+            send_host(&mut shared.host_tx, Class::Bulk, &fix.record().encode());
+        }
+        #[cfg(feature = "rs485")]
+        if rs485::is_polled() {
+            shared.poll.lock(|poll| poll.push(&fix));
+        }
+        #[cfg(feature = "spi-slave")]
+        if spi_slave::content().fixes() {
+            shared
+                .spi_stream
+                .lock(|stream| stream.push_fix(&fix.record()));
+        }
+        shared.trip.lock(|trip| trip.update(&fix));
+        #[cfg(feature = "sd-log")]
+        shared.track.lock(|track| track.push(&fix));
+        #[cfg(feature = "flash-log")]
+        if !dumping {
+            let flash_log = &mut shared.flash_log;
+            let result = shared.flash.lock(|flash| {
+                flash_log.lock(|flash_log| flash_log.append(&mut flashlog::pages(flash), &fix))
+            });
+            if result.is_err() {
+                listen_gps::warn!("flash log write failed");
+            }
+        }
+    }
+
+    /// Send the GPS a requested hot start and complete a pending `gps_setup` once it is heard
+    /// from. The aiding goes first, the baud is switched after the setup.
+    fn on_gps_heard(gps_setup: &mut bool, shared: &mut usart1::SharedResources) {
+        #[cfg(feature = "hot-start")]
+        if hotstart::take_request() {
+            let now = shared.rtc.lock(|rtc| rtc.now());
+            if let Some(frame) = hotstart::frame(now) {
+                if send_gps(&mut shared.gps_tx, &frame).is_err() {
+                    listen_gps::warn!("hot start dropped, GPS queue full");
+                }
+            }
+        }
+        if *gps_setup {
+            configure_gps(shared);
+            *gps_setup = false;
+        }
+    }
+
+    /// Send the saved navigation rate and baud to the GPS, the baud is switched once sent
+    fn configure_gps(shared: &mut usart1::SharedResources) {
+        let profile = shared.rate.lock(|rate| *rate);
+        let gps_baud = shared.gps_baud.lock(|gps_baud| *gps_baud);
+        // The queue is empty this early and holds all frames
+        if profile != rate::DEFAULT {
+            let _ = send_gps(&mut shared.gps_tx, &rate::cfg_rate(profile));
+        }
+        if source::source() == Source::Ubx {
+            let _ = send_gps(&mut shared.gps_tx, &source::frames(Source::Ubx));
+        }
+        if gps_baud != baud::GPS_DEFAULT {
+            let _ = send_gps(&mut shared.gps_tx, &baud::cfg_prt(gps_baud));
+        }
+    }
+
+    /// Check the RTC against RMC time, which refers to the last timepulse if there was one
+    /// within the last second
+    fn discipline_rtc(shared: &mut usart1::SharedResources, date: nmea::Date, time: nmea::Time) {
+        let since_pulse = shared.pps.lock(|pps| {
+            pps.last()
+                .map(|pulse| pps.now() - pulse.timestamp)
+                .filter(|&us| us < u64::from(pps::TICK_HZ))
+        });
+        shared.rtc.lock(|rtc| rtc.on_rmc(date, time, since_pulse));
+    }
+
+    /// Handle the GPS UART: drain bytes received by DMA when the line goes idle after each burst
+    /// of sentences or when pended by DMA, transmit queued bytes and clear errors.
+    #[task(
+        binds = USART1,
+        priority = 2,
+        local = [
+            usart1,
+            gps_rx,
+            nmea_parser,
+            ubx_parser,
+            decimator,
+            current_gps_baud,
+            gps_setup,
+        ],
+        shared = [
+            host_tx, gps_tx, fix, sky, smoother, rate, filter, pps, rtc, gps_baud, autobaud, usb,
+            ble, track, geofence, speed_alarm, anchor, trip, flash, flash_log, dump, aid, poll,
+            spi_stream,
+        ]
+    )]
+    fn usart1(mut cx: usart1::Context) {
+        #[cfg(feature = "profiling")]
+        let _span = profile::enter(profile::Handler::Usart1);
+        let usart1 = cx.local.usart1;
+        let gps_rx = cx.local.gps_rx;
+        // Pended by idle running `SELFTEST`
+        selftest::run_requested_uart(usart1);
+
+        // The flush below handles the bytes received before the line went idle
+        let idle = usart1.take_idle();
+        receive(
+            gps_rx,
+            cx.local.nmea_parser,
+            cx.local.ubx_parser,
+            cx.local.decimator,
+            cx.local.gps_setup,
+            &mut cx.shared,
+        );
+        if idle {
+            // The GPS paused after a burst, send what `BATCH` held back
+            cx.shared.host_tx.lock(|host_tx| host_tx.flush());
+        }
+
+        // Navigation rate changed, everything received so far has just been flushed
+        let rx_window = cx.shared.rate.lock(|rate| rate.rx_window);
+        if gps_rx.window() != rx_window {
+            gps_rx.set_window(rx_window);
+        }
+
+        let sent = cx.shared.gps_tx.lock(|gps_tx| {
+            // Write dequeued byte if TDR is empty, it stays queued otherwise
+            if let Some(&byte) = gps_tx.peek().first() {
+                if usart1.write(byte) {
+                    gps_tx.consume(1);
+                }
+            }
+            // Keep TXE interrupt enabled only while bytes are queued
+            usart1.listen_tx_empty(!gps_tx.is_empty());
+            gps_tx.is_empty()
+        });
+        // The aiding frame has been handed to USART1, the host may send the next one
+        let drained = sent
+            && cx
+                .shared
+                .aid
+                .lock(|aid| aid.as_mut().is_some_and(|upload| upload.take_drained()));
+        if drained {
+            send_host(&mut cx.shared.host_tx, Class::Response, b"AID NEXT\r\n");
+        }
+
+        // Probing switches right away, a requested baud after the UBX-CFG-PRT frame, TC is set
+        // once its last byte has been shifted out
+        let probe_baud = cx.shared.autobaud.lock(|autobaud| autobaud.probe_baud());
+        let gps_baud = probe_baud.unwrap_or_else(|| cx.shared.gps_baud.lock(|gps_baud| *gps_baud));
+        if gps_baud != *cx.local.current_gps_baud
+            && (probe_baud.is_some() || sent && !*cx.local.gps_setup)
+        {
+            if probe_baud.is_some() || usart1.is_transmit_complete() {
+                let _ = uart::reconfigure(usart1, clocks::PCLK2_HZ, gps_baud);
+                // Bytes received at the old baud don't belong to the next sentence
+                cx.local.nmea_parser.reset();
+                *cx.local.current_gps_baud = gps_baud;
+                listen_gps::info!("GPS baud {=u32}", gps_baud);
+            } else {
+                usart1.listen_transmit_complete(true);
+            }
+        }
+
+        // See reference manual p.1206 or ch. 38.7.
+        // With EIE set, overrun, framing and noise errors trigger the interrupt. Flags must be cleared.
+        let errors = usart1.errors();
+        if errors.overrun {
+            STATS.overruns.increment();
+        }
+        count_receive_errors(errors.framing, errors.noise);
+        if errors.framing {
+            // The corrupted byte was flushed above, drop the sentence in progress, a corrupted
+            // `$` or line ending would otherwise merge it with the next one
+            cx.local.nmea_parser.reset();
+        }
+        if errors.any() {
+            usart1.clear_errors();
+        }
+    }
+
+    /// Count framing and noise errors of a received byte
+    fn count_receive_errors(framing: bool, noise: bool) {
+        if framing || noise {
+            listen_gps::warn!(
+                "receive error, framing {=bool} noise {=bool}",
+                framing,
+                noise
+            );
+        }
+        if framing {
+            STATS.framing_errors.increment();
+        }
+        if noise {
+            STATS.noise_errors.increment();
+        }
+    }
+
+    /// Let USART1 drain the DMA buffer when it is half or completely full, before DMA wraps around.
+    #[task(binds = DMA1_CH5, priority = 2)]
+    fn dma1_ch5(_: dma1_ch5::Context) {
+        #[cfg(feature = "profiling")]
+        let _span = profile::enter(profile::Handler::Dma1Ch5);
+        CircularRx::clear_interrupt_flags();
+        rtic::pend(pac::Interrupt::USART1);
+    }
+
+    /// Timestamp the GPS timepulse, keeping track of TIM2 overflows, and align the RTC to it.
+    #[task(binds = TIM2, priority = 5, shared = [pps, rtc])]
+    fn tim2(mut cx: tim2::Context) {
+        let latency = cx
+            .shared
+            .pps
+            .lock(|pps| pps.on_interrupt().map(|pulse| pps.now() - pulse.timestamp));
+        if let Some(latency) = latency {
+            cx.shared.rtc.lock(|rtc| rtc.on_pulse(latency));
+        }
+    }
+
+    /// Clear the periodic RTC wakeup, sample the battery and advance the GPS schedule, which both
+    /// count their time in wakeups as SysTick halts in Stop mode. Low battery turns the GPS off
+    /// and keeps the schedule from turning it on, `$PBRIDGE,WARN,LOW_BATTERY` is sent once, as
+    /// is `$PBRIDGE,WARN,LOW_STACK` once the stack runs low, see `stack`.
+    /// Idle services the watchdog and redraws the display once this returns.
+    #[task(
+        binds = RTC_WKUP,
+        priority = 1,
+        local = [adc, battery_s: u32 = 0, restore_gps: bool = false],
+        shared = [rtc, schedule, battery, host_tx, usb, ble, gps_pin, fix]
+    )]
+    fn rtc_wkup(mut cx: rtc_wkup::Context) {
+        cx.shared.rtc.lock(|rtc| rtc.on_wakeup());
+        #[cfg(feature = "ab-boot")]
+        boot::on_wakeup(watchdog::SERVICE_PERIOD_S.into());
+        *cx.local.battery_s += u32::from(watchdog::SERVICE_PERIOD_S);
+        if *cx.local.battery_s >= battery::SAMPLE_PERIOD_S {
+            *cx.local.battery_s = 0;
+            let readings = cx.local.adc.sample();
+            match cx
+                .shared
+                .battery
+                .lock(|monitor| monitor.on_sample(readings))
+            {
+                Some(battery::Event::Low) => {
+                    listen_gps::warn!("low battery, {=u16}mV", readings.vbat_mv);
+                    let mut warning = Response::new();
+                    // Fits in a response
+                    let _ = battery::write_sentence(&mut warning);
+                    route(
+                        router::Source::Report,
+                        warning.as_bytes(),
+                        &mut cx.shared.host_tx,
+                        &mut cx.shared.usb,
+                        &mut cx.shared.ble,
+                    );
+                    #[cfg(feature = "eeprom")]
+                    eventlog::record(Event::LowBattery);
+                    *cx.local.restore_gps = cx.shared.gps_pin.lock(|gps_pin| {
+                        let powered = gps_pin.is_powered();
+                        switch_gps_power(gps_pin, false);
+                        powered
+                    });
+                }
+                Some(battery::Event::Recovered) => {
+                    let scheduled = cx.shared.schedule.lock(|schedule| schedule.windows());
+                    if *cx.local.restore_gps && scheduled.is_none() {
+                        cx.shared
+                            .gps_pin
+                            .lock(|gps_pin| switch_gps_power(gps_pin, true));
+                    }
+                    *cx.local.restore_gps = false;
+                }
+                None => {}
+            }
+            // Along with the battery, scanning the stack takes milliseconds at 4MHz
+            if let Some(free) = stack::check() {
+                listen_gps::warn!("low stack, {=usize} bytes never used", free);
+                let mut warning = Response::new();
+                // Fits in a response
+                let _ = stack::write_sentence(&mut warning, free);
+                route(
+                    router::Source::Report,
+                    warning.as_bytes(),
+                    &mut cx.shared.host_tx,
+                    &mut cx.shared.usb,
+                    &mut cx.shared.ble,
+                );
+            }
+        }
+        let low = cx.shared.battery.lock(|monitor| monitor.is_low());
+        let fix = cx.shared.fix.lock(|fix| *fix);
+        let power = cx.shared.schedule.lock(|schedule| {
+            schedule.on_wakeup(
+                watchdog::SERVICE_PERIOD_S.into(),
+                quality::gate().passes(&fix),
+            )
+        });
+        if let Some(on) = power.filter(|&on| !(on && low)) {
+            listen_gps::info!("schedule turns GPS {=str}", if on { "on" } else { "off" });
+            cx.shared
+                .gps_pin
+                .lock(|gps_pin| switch_gps_power(gps_pin, on));
+        }
+        #[cfg(feature = "display")]
+        display::request_refresh();
+    }
+
+    /// Load the next SPI batch once the master ends a transaction.
+    #[cfg(feature = "spi-slave")]
+    #[task(binds = EXTI4, priority = 2, shared = [spi_stream])]
+    fn exti4(mut cx: exti4::Context) {
+        cx.shared.spi_stream.lock(|stream| stream.on_deselect());
+    }
+
+    /// Serve the I2C register map, copying the latest fix when the master starts a read.
+    #[cfg(feature = "i2c-slave")]
+    #[task(binds = I2C1_EV, priority = 2, local = [i2c_slave], shared = [fix, gps_pin])]
+    fn i2c1_ev(mut cx: i2c1_ev::Context) {
+        if cx.local.i2c_slave.on_event() == i2c_slave::Event::Read {
+            let gps_power = cx.shared.gps_pin.lock(|gps_pin| gps_pin.is_powered());
+            let registers = cx
+                .shared
+                .fix
+                .lock(|fix| i2c_slave::registers(fix, gps_power));
+            cx.local.i2c_slave.load(registers);
+        }
+    }
+
+    /// Recover from I2C bus errors, the master retries.
+    #[cfg(feature = "i2c-slave")]
+    #[task(binds = I2C1_ER, priority = 2)]
+    fn i2c1_er(_cx: i2c1_er::Context) {
+        i2c_slave::on_error();
+    }
+
+    /// Debounce the push-button. A short press toggles GPS power, a long press SD logging.
+    #[cfg(feature = "button")]
+    #[task(binds = EXTI9_5, priority = 1, local = [button], shared = [pps, gps_pin, track])]
+    fn exti9_5(mut cx: exti9_5::Context) {
+        let now = cx.shared.pps.lock(|pps| pps.now());
+        match cx.local.button.on_interrupt(now) {
+            Some(Press::Short) => cx
+                .shared
+                .gps_pin
+                .lock(|gps_pin| switch_gps_power(gps_pin, !gps_pin.is_powered())),
+            #[cfg(feature = "sd-log")]
+            Some(Press::Long) => cx.shared.track.lock(|track| {
+                let format = match track.format() {
+                    Some(_) => None,
+                    None => Some(sdlog::Format::Csv),
+                };
+                track.set_format(format);
+            }),
+            _ => {}
+        }
+    }
+
+    /// Run the due software timers, measure the CPU load, end the wait of lines held by
+    /// `OVERFLOW BLOCK`, supervise the GPS, time its first fix, send the periodic
+    /// `$PBRIDGE,STATUS`, broadcast the fix on CAN and LoRa and advance the status LED pattern.
+    /// A power cycle of the supervisor is reported as `$PBRIDGE,WARN,GPS_TIMEOUT`, USART1 is
+    /// pended when probing for the GPS baud switches rates.
+    #[task(
+        binds = SysTick,
+        priority = 4,
+        local = [
+            indicator,
+            can,
+            beacon,
+            monitor,
+            reporter: Reporter = Reporter::new(),
+            ttff: Ttff = Ttff::new(),
+            load: load::Meter = load::Meter::new(),
+        ],
+        shared = [host_tx, usb, ble, gps_pin, fix, supervisor, gps_baud, autobaud]
+    )]
+    fn sys_tick(mut cx: sys_tick::Context) {
+        timer::on_tick();
+        cx.local.load.on_tick(timer::now_ms());
+        cx.shared
+            .host_tx
+            .lock(|host_tx| host_tx.on_tick(timer::now_ms()));
+        #[cfg(feature = "usb")]
+        cx.shared.usb.lock(|usb| usb.on_tick(timer::now_ms()));
+        #[cfg(feature = "ble")]
+        cx.shared.ble.lock(|ble| ble.on_tick(timer::now_ms()));
+        let gps_power = cx.shared.gps_pin.lock(|gps_pin| gps_pin.is_powered());
+        if cx.local.reporter.on_tick(timer::now_ms()) {
+            let mut report = Response::new();
+            // Fits in a response
+            let _ = report::write_sentence(&mut report, cx.local.reporter.uptime_s(), gps_power);
+            route(
+                router::Source::Report,
+                report.as_bytes(),
+                &mut cx.shared.host_tx,
+                &mut cx.shared.usb,
+                &mut cx.shared.ble,
+            );
+        }
+        let mut fix = cx.shared.fix.lock(|fix| *fix);
+        match cx
+            .local
+            .ttff
+            .on_tick(timer::now_ms(), gps_power, fix.is_valid())
+        {
+            Some(ttff::Event::PoweredOn) => {
+                fix = cx.shared.fix.lock(|fix| {
+                    fix.fix_type = nmea::FixType::None;
+                    *fix
+                });
+                #[cfg(feature = "hot-start")]
+                hotstart::request();
+            }
+            Some(ttff::Event::Fix { ms }) => {
+                listen_gps::info!("TTFF {=u32}ms", ms);
+                let mut report = Response::new();
+                // Fits in a response
+                let _ = ttff::write_sentence(&mut report, ms);
+                route(
+                    router::Source::Report,
+                    report.as_bytes(),
+                    &mut cx.shared.host_tx,
+                    &mut cx.shared.usb,
+                    &mut cx.shared.ble,
+                );
+            }
+            None => {}
+        }
+        let action = cx
+            .shared
+            .supervisor
+            .lock(|supervisor| supervisor.on_tick(timer::now_ms(), gps_power, fix.is_valid()));
+        match action {
+            Some(Action::PowerOff) => {
+                cx.shared.gps_pin.lock(|gps_pin| gps_pin.set_power(false));
+                #[cfg(feature = "eeprom")]
+                eventlog::record(Event::GpsTimeout);
+                let mut report = Response::new();
+                // Fits in a response
+                let _ = gps_ctrl::write_sentence(&mut report);
+                route(
+                    router::Source::Report,
+                    report.as_bytes(),
+                    &mut cx.shared.host_tx,
+                    &mut cx.shared.usb,
+                    &mut cx.shared.ble,
+                );
+            }
+            Some(Action::PowerOn) => cx.shared.gps_pin.lock(|gps_pin| gps_pin.set_power(true)),
+            None => {}
+        }
+        let gps_power = cx.shared.gps_pin.lock(|gps_pin| gps_pin.is_powered());
+        let gps_baud = cx.shared.gps_baud.lock(|gps_baud| *gps_baud);
+        let switch = cx
+            .shared
+            .autobaud
+            .lock(|autobaud| autobaud.on_tick(timer::now_ms(), gps_power, gps_baud));
+        if switch {
+            rtic::pend(pac::Interrupt::USART1);
+        }
+        #[cfg(feature = "can")]
+        cx.local.can.on_tick(timer::now_ms(), &fix);
+        #[cfg(feature = "lora")]
+        cx.local.beacon.on_tick(timer::now_ms(), &fix);
+        #[cfg(feature = "eeprom")]
+        cx.local
+            .monitor
+            .on_tick(timer::now_ms(), gps_power, fix.is_valid());
+        #[cfg(feature = "indicator")]
+        cx.local.indicator.on_tick(gps_power, fix.fix_type);
+    }
+
+    /// Swap TX buffers once DMA has finished sending one, and refill them with a running dump
+    /// or poll.
+    #[task(binds = DMA1_CH7, priority = 2, shared = [host_tx, dump, flash, poll])]
+    fn dma1_ch7(mut cx: dma1_ch7::Context) {
+        #[cfg(feature = "profiling")]
+        let _span = profile::enter(profile::Handler::Dma1Ch7);
+        cx.shared.host_tx.lock(|host_tx| {
+            host_tx.on_transfer_complete();
+            #[cfg(feature = "flow-control")]
+            flow::update(host_tx.queued());
+        });
+        #[cfg(feature = "flash-log")]
+        send_dump(
+            &mut cx.shared.dump,
+            &mut cx.shared.flash,
+            &mut cx.shared.host_tx,
+        );
+        #[cfg(feature = "rs485")]
+        send_poll(&mut cx.shared.poll, &mut cx.shared.host_tx);
+    }
+
+    /// Queue the kept fixes of a running poll while they fit in the TX buffer, the next transfer
+    /// complete interrupt continues. The `POLL` sentence follows the last one and ends the turn.
+    #[cfg(feature = "rs485")]
+    fn send_poll(poll: &mut impl Mutex<T = Poll>, host_tx: &mut impl Mutex<T = DoubleBufferTx>) {
+        poll.lock(|poll| {
+            if !poll.is_replying() {
+                return;
+            }
+            host_tx.lock(|host_tx| {
+                let output = filter::output();
+                while let Some(fix) = poll.peek() {
+                    if host_tx.write(&poll::encode_fix(fix, output)).is_err() {
+                        return;
+                    }
+                    poll.consume();
+                }
+                let mut line = Response::new();
+                // Fits in a response
+                let _ = poll.write_end(&mut line, rs485::address().unwrap_or(0));
+                if host_tx.write(line.as_bytes()).is_ok() {
+                    poll.end();
+                    rs485::end_turn();
+                    host_tx.flush();
+                }
+            });
+        });
+    }
+
+    /// Forwarding stops in the polled mode of the `rs485` feature, see `poll`
+    #[cfg(feature = "rs485")]
+    fn is_polled() -> bool {
+        rs485::is_polled()
+    }
+    #[cfg(not(feature = "rs485"))]
+    fn is_polled() -> bool {
+        false
+    }
+
+    /// Queue dumped records while they fit in the TX buffer, the next transfer complete
+    /// interrupt continues. `DUMP END <count>` follows the last one.
+    #[cfg(feature = "flash-log")]
+    fn send_dump(
+        dump: &mut impl Mutex<T = Option<flashlog::Dump>>,
+        flash: &mut impl Mutex<T = pac::FLASH>,
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+    ) {
+        dump.lock(|running| {
+            let Some(dump) = running else {
+                return;
+            };
+            let done = flash.lock(|flash| {
+                let pages = &mut flashlog::pages(flash);
+                host_tx.lock(|host_tx| {
+                    let mut line = Response::new();
+                    while let Some(record) = dump.peek(pages) {
+                        line.clear();
+                        let _ = flashlog::write_record(&mut line, &record, dump.format());
+                        if host_tx.write(line.as_bytes()).is_err() {
+                            return false;
+                        }
+                        dump.consume();
+                    }
+                    line.clear();
+                    let _ = write!(line, "DUMP END {}\r\n", dump.sent());
+                    let done = host_tx.write(line.as_bytes()).is_ok();
+                    #[cfg(feature = "flow-control")]
+                    flow::update(host_tx.queued());
+                    done
+                })
+            });
+            if done {
+                *running = None;
+            }
+        });
+    }
+
+    /// Send the sentences queued for the Bluetooth module and queue the command lines received
+    /// from it for USART2
+    #[cfg(feature = "ble")]
+    #[task(
+        binds = LPUART1,
+        priority = 2,
+        local = [ble_line: LineBuffer = LineBuffer::new()],
+        shared = [ble, inbox]
+    )]
+    fn lpuart1(mut cx: lpuart1::Context) {
+        #[cfg(feature = "profiling")]
+        let _span = profile::enter(profile::Handler::Lpuart1);
+        let line = cx.local.ble_line;
+        let inbox = &mut cx.shared.inbox;
+        cx.shared.ble.lock(|ble| match ble.on_interrupt() {
+            Some(Ok(byte)) => {
+                if let Some(received) = line.push(byte) {
+                    if !queue_command(inbox, Sink::Ble, received) {
+                        ble.write(Class::Response, b"ERR BUSY\r\n");
+                    }
+                }
+            }
+            Some(Err(error)) => line.reject(error),
+            None => {}
+        });
+    }
+
+    /// Enumerate and serve the USB virtual COM port, and queue the command lines received on it
+    /// for USART2
+    #[cfg(feature = "usb")]
+    #[task(
+        binds = USB_FS,
+        priority = 2,
+        local = [usb_line: LineBuffer = LineBuffer::new()],
+        shared = [usb, inbox]
+    )]
+    fn usb_fs(mut cx: usb_fs::Context) {
+        let line = cx.local.usb_line;
+        let inbox = &mut cx.shared.inbox;
+        cx.shared.usb.lock(|usb| {
+            usb.poll();
+            let mut bytes = [0; 64];
+            loop {
+                let len = usb.read(&mut bytes);
+                if len == 0 {
+                    break;
+                }
+                for &byte in &bytes[..len] {
+                    if let Some(received) = line.push(byte) {
+                        if !queue_command(inbox, Sink::Usb, received) {
+                            usb.write(Class::Response, b"ERR BUSY\r\n");
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Queue a command line received on `port` and pend USART2 to run it, false if the inbox is
+    /// full
+    #[cfg(any(feature = "usb", feature = "ble"))]
+    fn queue_command(
+        inbox: &mut impl Mutex<T = Inbox>,
+        port: Sink,
+        line: Result<&[u8], cmd::Error>,
+    ) -> bool {
+        let queued = inbox.lock(|inbox| inbox.push(port, line));
+        if queued {
+            rtic::pend(pac::Interrupt::USART2);
+        }
+        queued
+    }
+
+    /// Turn GPS on/off on request of the host or the button, ending a power cycle of the
+    /// supervisor
+    fn switch_gps_power(gps_pin: &impl PowerPin, on: bool) {
+        gps_ctrl::cancel_cycle();
+        gps_pin.set_power(on);
+    }
+
+    /// Queue UBX frames or `$PUBX` sentences for transmission to the GPS by USART1, as a whole
+    /// or not at all
+    fn send_gps(
+        gps_tx: &mut impl Mutex<T = RingBuffer<GPS_TX_QUEUE_LEN>>,
+        frame: &[u8],
+    ) -> Result<(), cmd::Error> {
+        gps_tx
+            .lock(|gps_tx| {
+                let written = gps_tx.write(frame);
+                STATS.gps_tx_peak.record(gps_tx.len());
+                written
+            })
+            .map_err(|_| cmd::Error::Busy)?;
+        // USART1 handler enables its TXE interrupt
+        rtic::pend(pac::Interrupt::USART1);
+        Ok(())
+    }
+
+    /// Run a command parsed from a line received on `port` and send the response back there
+    fn run_command(
+        port: Sink,
+        command: Result<Command, cmd::Error>,
+        local: &mut usart2::LocalResources,
+        shared: &mut usart2::SharedResources,
+    ) {
+        let command = command.and_then(|command| {
+            if port != Sink::Host && command.is_host_only() {
+                Err(cmd::Error::HostOnly)
+            } else {
+                Ok(command)
+            }
+        });
+        let mut response = Response::new();
+        // A response cut short by the buffer size is still sent
+        let _ = match command {
+            Ok(command) => execute(command, port, local, shared, &mut response),
+            Err(error) => write!(response, "ERR {}", error.as_str()),
+        };
+        // Commands handed to idle are answered from there
+        if !response.is_empty() {
+            reply(port, response, shared);
+        }
+    }
+
+    /// Queue a response line for `port`, appending the line ending
+    fn reply(port: Sink, mut response: Response, shared: &mut usart2::SharedResources) {
+        cmd::terminate(&mut response);
+        send_reply(
+            port,
+            response.as_bytes(),
+            &mut shared.host_tx,
+            &mut shared.usb,
+            &mut shared.ble,
+        );
+    }
+
+    /// Queue a response `line` with its ending for `port`
+    fn send_reply(
+        port: Sink,
+        line: &[u8],
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+        usb: &mut impl Mutex<T = UsbSerial>,
+        ble: &mut impl Mutex<T = BleUart>,
+    ) {
+        match port {
+            Sink::Host => send_host(host_tx, Class::Response, line),
+            #[cfg(feature = "usb")]
+            Sink::Usb => usb.lock(|usb| usb.write(Class::Response, line)),
+            #[cfg(feature = "ble")]
+            Sink::Ble => ble.lock(|ble| ble.write(Class::Response, line)),
+            // Lines only come from the ports built in
+            #[cfg(not(feature = "usb"))]
+            Sink::Usb => {}
+            #[cfg(not(feature = "ble"))]
+            Sink::Ble => {}
+        }
+        #[cfg(not(feature = "usb"))]
+        let _ = usb;
+        #[cfg(not(feature = "ble"))]
+        let _ = ble;
+    }
+
+    /// Hand `job` of a command received on `port` to idle, which answers it. Nothing is written
+    /// to `response` unless the queue is full.
+    fn defer(
+        job: Job,
+        port: Sink,
+        shared: &mut usart2::SharedResources,
+        response: &mut impl Write,
+    ) -> core::fmt::Result {
+        if !shared.jobs.lock(|jobs| jobs.push(port, job)) {
+            return write!(response, "ERR {}", cmd::Error::Busy.as_str());
+        }
+        Ok(())
+    }
+
+    /// Run a command received on `port` and write its response line, without line ending
+    fn execute(
+        command: Command,
+        port: Sink,
+        local: &mut usart2::LocalResources,
+        shared: &mut usart2::SharedResources,
+        response: &mut impl Write,
+    ) -> core::fmt::Result {
+        let result = match command {
+            Command::Power(on) => {
+                shared.gps_pin.lock(|gps_pin| switch_gps_power(gps_pin, on));
+                Ok(())
+            }
+            Command::Status => {
+                let fix = shared.fix.lock(|fix| *fix);
+                let rate = shared.rate.lock(|rate| *rate);
+                let gps_power = shared.gps_pin.lock(|gps_pin| gps_pin.is_powered());
+                return cmd::write_status(response, gps_power, rate, &fix);
+            }
+            Command::Rate(profile) => {
+                if profile != rate::DEFAULT && psm::update_period_ms() > 0 {
+                    // Power save needs 1Hz
+                    Err(cmd::Error::Argument)
+                } else {
+                    send_gps(&mut shared.gps_tx, &rate::cfg_rate(profile)).map(|_| {
+                        shared.rate.lock(|rate| *rate = profile);
+                        // USART1 handler resizes the RX DMA window
+                        rtic::pend(pac::Interrupt::USART1);
+                        if profile.host_baud > *local.host_baud {
+                            request_host_baud(local, profile.host_baud);
+                        }
+                    })
+                }
+            }
+            Command::Baud {
+                port: Port::Host,
+                baud,
+            } => baud::divider(HOST_CLOCK_HZ, baud)
+                .map(|_| request_host_baud(local, baud))
+                .map_err(|_| cmd::Error::Argument),
+            Command::Baud {
+                port: Port::Gps,
+                baud,
+            } => baud::divider(clocks::PCLK2_HZ, baud)
+                .map_err(|_| cmd::Error::Argument)
+                .and_then(|_| send_gps(&mut shared.gps_tx, &baud::cfg_prt(baud)))
+                .map(|_| shared.gps_baud.lock(|gps_baud| *gps_baud = baud)),
+            Command::Psm(mode) => {
+                let rate = shared.rate.lock(|rate| *rate);
+                if mode != psm::Mode::Off && rate != rate::DEFAULT {
+                    Err(cmd::Error::Argument)
+                } else {
+                    send_gps(&mut shared.gps_tx, &psm::frames(mode)).map(|_| psm::select(mode))
+                }
+            }
+            Command::Src(None) => return cmd::write_src(response, source::source()),
+            Command::Src(Some(selected)) => send_gps(&mut shared.gps_tx, &source::frames(selected))
+                .map(|_| source::select(selected)),
+            Command::Gnss(None) => return cmd::write_gnss(response, &gnss::selected()),
+            Command::Gnss(Some(selection)) => {
+                send_gps(&mut shared.gps_tx, &gnss::frames(selection))
+                    .map(|_| gnss::request(selection))
+            }
+            Command::Aid(true) => {
+                let upload = aid::Upload::new(GPS_TX_QUEUE_LEN);
+                shared.aid.lock(|aid| *aid = Some(upload));
+                Ok(())
+            }
+            Command::Aid(false) => match shared.aid.lock(|aid| aid.take()) {
+                Some(upload) => return aid::write_end(response, &upload),
+                None => Err(cmd::Error::Argument),
+            },
+            Command::Bridge => {
+                // Raw bytes follow the response
+                local.escape.reset();
+                bridge::set_raw(true);
+                Ok(())
+            }
+            Command::Ubx { class, id, payload } => ubx::encode(class, id, &payload)
+                .map_err(|_| cmd::Error::Argument)
+                .and_then(|frame| send_gps(&mut shared.gps_tx, &frame)),
+            Command::Pubx(Pubx::Rate { sentence, rate }) => {
+                let mut line = Response::new();
+                // Fits in a response
+                let _ = pubx::write_rate(&mut line, sentence.name(), &pubx::Rates::uart1(rate));
+                send_gps(&mut shared.gps_tx, line.as_bytes())
+            }
+            Command::Pubx(Pubx::Baud(baud)) => {
+                let config = pubx::PortConfig {
+                    port: pubx::PORT_UART1,
+                    input: pubx::PROTO_UBX | pubx::PROTO_NMEA,
+                    output: pubx::PROTO_UBX | pubx::PROTO_NMEA,
+                    baud,
+                };
+                let mut line = Response::new();
+                // Fits in a response
+                let _ = pubx::write_port(&mut line, &config);
+                baud::divider(clocks::PCLK2_HZ, baud)
+                    .map_err(|_| cmd::Error::Argument)
+                    .and_then(|_| send_gps(&mut shared.gps_tx, line.as_bytes()))
+                    .map(|_| shared.gps_baud.lock(|gps_baud| *gps_baud = baud))
+            }
+            Command::Time => {
+                let now = shared.rtc.lock(|rtc| rtc.now());
+                return cmd::write_time(response, now);
+            }
+            Command::Version => return cmd::write_version(response),
+            Command::Filter(change) => {
+                let filter = shared.filter.lock(|filter| {
+                    match change {
+                        FilterChange::Query => {}
+                        FilterChange::Set(mask) => *filter = Filter::from_mask(mask),
+                        FilterChange::Enable(mask) => filter.enable(mask),
+                        FilterChange::Disable(mask) => filter.disable(mask),
+                    }
+                    *filter
+                });
+                return cmd::write_filter(response, &filter);
+            }
+            Command::Passthru(passthru) => {
+                if let Some(passthru) = passthru {
+                    filter::set_passthru(passthru);
+                }
+                return cmd::write_passthru(response, filter::passthru());
+            }
+            Command::Dfu => {
+                // A reset would leave the SD card file without its last fixes
+                #[cfg(feature = "sd-log")]
+                let logging = shared.track.lock(|track| track.format().is_some());
+                #[cfg(not(feature = "sd-log"))]
+                let logging = false;
+                if logging {
+                    Err(cmd::Error::Busy)
+                } else {
+                    listen_gps::info!("entering the bootloader");
+                    dfu::request();
+                    Ok(())
+                }
+            }
+            Command::Save => {
+                let config = Config {
+                    host_baud: local.reconfigure.pending().unwrap_or(*local.host_baud),
+                    gps_baud: shared.gps_baud.lock(|gps_baud| *gps_baud),
+                    filter: shared.filter.lock(|filter| *filter),
+                    rate: shared.rate.lock(|rate| *rate),
+                    gps_power: shared.gps_pin.lock(|gps_pin| gps_pin.is_powered()),
+                    zones: shared.geofence.lock(|geofence| geofence.zones().clone()),
+                    fix_timeout_s: shared
+                        .supervisor
+                        .lock(|supervisor| supervisor.fix_timeout_s()),
+                };
+                return defer(Job::Save(config), port, shared, response);
+            }
+            Command::Decimate(factor) => {
+                if let Some(factor) = factor {
+                    decimate::set_factor(factor);
+                }
+                return cmd::write_decimation(response, decimate::factor());
+            }
+            Command::Mode(output) => {
+                if let Some(output) = output {
+                    filter::set_output(output);
+                }
+                return cmd::write_mode(response, filter::output());
+            }
+            Command::Units(change) => {
+                match change {
+                    Some(UnitsChange::Speed(unit)) => units::set_speed_unit(unit),
+                    Some(UnitsChange::Altitude(unit)) => units::set_altitude_unit(unit),
+                    None => {}
+                }
+                return cmd::write_units(response, units::speed_unit(), units::altitude_unit());
+            }
+            Command::Report(period_s) => {
+                if let Some(period_s) = period_s {
+                    report::set_period(period_s);
+                }
+                return cmd::write_report_period(response, report::period_s());
+            }
+            Command::Batch(threshold) => {
+                return shared.host_tx.lock(|host_tx| {
+                    if let Some(threshold) = threshold {
+                        host_tx.set_threshold(threshold.into());
+                    }
+                    cmd::write_batch(response, host_tx.threshold())
+                });
+            }
+            Command::Quality(change) => {
+                if let Some(change) = change {
+                    let mut gate = quality::gate();
+                    change.apply(&mut gate);
+                    quality::set_gate(gate);
+                }
+                return cmd::write_quality(response, &quality::gate());
+            }
+            Command::Smooth(alpha) => {
+                let alpha = shared.smoother.lock(|smoother| {
+                    if let Some(alpha) = alpha {
+                        smoother.set_alpha(alpha);
+                    }
+                    smoother.alpha()
+                });
+                return cmd::write_smooth(response, alpha);
+            }
+            Command::SpeedAlarm(limit_kmh) => {
+                let limit_kmh = shared.speed_alarm.lock(|alarm| {
+                    if let Some(limit_kmh) = limit_kmh {
+                        alarm.set_limit(limit_kmh);
+                        #[cfg(feature = "speed-alarm")]
+                        speed_alarm::set_alarm(false);
+                    }
+                    alarm.limit_kmh()
+                });
+                return cmd::write_speed_alarm(response, limit_kmh);
+            }
+            Command::Anchor(AnchorChange::Query) => {
+                return shared
+                    .anchor
+                    .lock(|anchor| cmd::write_anchor(response, anchor));
+            }
+            Command::Anchor(change) => {
+                let fix = shared.fix.lock(|fix| *fix);
+                let watched = match change {
+                    AnchorChange::Set(_) if !fix.is_valid() => None,
+                    AnchorChange::Set(radius_m) => Some(Some(Anchor {
+                        lat: fix.lat,
+                        lon: fix.lon,
+                        radius_m,
+                    })),
+                    _ => Some(None),
+                };
+                match watched {
+                    Some(watched) => {
+                        shared.anchor.lock(|watch| watch.set(watched));
+                        #[cfg(feature = "anchor-alarm")]
+                        anchor::set_alarm(false);
+                        Ok(())
+                    }
+                    None => Err(cmd::Error::NoFix),
+                }
+            }
+            Command::Schedule(ScheduleChange::Query) => {
+                return shared
+                    .schedule
+                    .lock(|schedule| cmd::write_schedule(response, schedule));
+            }
+            Command::Schedule(change) => {
+                let windows = match change {
+                    ScheduleChange::Set(windows) => Some(windows),
+                    _ => None,
+                };
+                if let Some(on) = shared.schedule.lock(|schedule| schedule.set(windows)) {
+                    shared.gps_pin.lock(|gps_pin| switch_gps_power(gps_pin, on));
+                }
+                Ok(())
+            }
+            Command::Battery(None) => {
+                return shared
+                    .battery
+                    .lock(|monitor| cmd::write_battery(response, monitor));
+            }
+            Command::Battery(Some(threshold_mv)) => {
+                battery::set_threshold(Some(threshold_mv).filter(|&mv| mv != 0));
+                Ok(())
+            }
+            Command::FixTimeout(None) => {
+                let fix_timeout_s = shared
+                    .supervisor
+                    .lock(|supervisor| supervisor.fix_timeout_s());
+                return cmd::write_fix_timeout(response, fix_timeout_s);
+            }
+            Command::FixTimeout(Some(fix_timeout_s)) => {
+                shared
+                    .supervisor
+                    .lock(|supervisor| supervisor.set_fix_timeout(fix_timeout_s));
+                Ok(())
+            }
+            Command::Zone(ZoneChange::Query(id)) => {
+                let zone = shared
+                    .geofence
+                    .lock(|geofence| geofence.zones().get(id).copied());
+                return cmd::write_zone(response, id, zone.as_ref());
+            }
+            Command::Zone(ZoneChange::Set(id, zone)) => {
+                let result = shared.geofence.lock(|geofence| geofence.set(id, zone));
+                // The zone's side is known again with the next fix
+                #[cfg(feature = "geofence-alarm")]
+                geofence::set_alarm(shared.geofence.lock(|geofence| geofence.is_inside_any()));
+                result.map_err(|_| cmd::Error::Argument)
+            }
+            Command::Zone(ZoneChange::Clear) => {
+                shared.geofence.lock(|geofence| geofence.clear());
+                #[cfg(feature = "geofence-alarm")]
+                geofence::set_alarm(false);
+                Ok(())
+            }
+            Command::Sats => {
+                let sky = shared.sky.lock(|sky| sky.clone());
+                for satellite in sky.satellites() {
+                    let mut line = Response::new();
+                    // Fits in a response
+                    let _ = cmd::write_satellite(&mut line, satellite);
+                    reply(port, line, shared);
+                }
+                return cmd::write_sats(response, &sky);
+            }
+            Command::Trip(reset) => {
+                if reset {
+                    shared.trip.lock(|trip| trip.reset());
+                    Ok(())
+                } else {
+                    let trip = shared.trip.lock(|trip| *trip);
+                    return cmd::write_trip(response, &trip);
+                }
+            }
+            Command::Stats(clear) => {
+                if clear {
+                    STATS.clear();
+                    Ok(())
+                } else {
+                    return cmd::write_stats(response, &STATS, stack::free(), load::latest());
+                }
+            }
+            Command::Ttff => return cmd::write_ttff(response, &STATS),
+            #[cfg(feature = "sd-log")]
+            Command::Log(format) => {
+                shared.track.lock(|track| track.set_format(format));
+                Ok(())
+            }
+            #[cfg(feature = "flash-log")]
+            Command::Dump(format) => return defer(Job::Dump(format), port, shared, response),
+            #[cfg(feature = "ab-boot")]
+            Command::Boot(false) => return cmd::write_boot(response),
+            #[cfg(feature = "ab-boot")]
+            Command::Boot(true) => {
+                if boot::other_bank_valid() {
+                    listen_gps::info!("booting the other bank");
+                    boot::request_swap();
+                    Ok(())
+                } else {
+                    Err(cmd::Error::NoImage)
+                }
+            }
+            #[cfg(feature = "rs485")]
+            Command::Rs485(address) => {
+                if let Some(address) = address {
+                    let address = Some(address).filter(|&address| address != 0);
+                    if address.is_none() {
+                        // Leaves polled mode
+                        shared.poll.lock(|poll| poll.clear());
+                    }
+                    rs485::set_address(address);
+                }
+                return cmd::write_rs485(response);
+            }
+            #[cfg(feature = "rs485")]
+            Command::Poll(None) => {
+                let kept = shared.poll.lock(|poll| poll.kept());
+                return cmd::write_poll(response, kept);
+            }
+            #[cfg(feature = "rs485")]
+            Command::Poll(Some(polled)) => {
+                if polled && rs485::address().is_none() {
+                    Err(cmd::Error::NoAddress)
+                } else {
+                    if !polled {
+                        shared.poll.lock(|poll| poll.clear());
+                    }
+                    rs485::set_polled(polled);
+                    Ok(())
+                }
+            }
+            #[cfg(feature = "can")]
+            Command::Can(None) => return cmd::write_can(response),
+            #[cfg(feature = "can")]
+            Command::Can(Some(period_ms)) => {
+                can::set_period(period_ms);
+                Ok(())
+            }
+            Command::Route(RouteChange::Query) => return cmd::write_route(response),
+            Command::Route(RouteChange::Set(source, sinks)) => {
+                router::set_route(source, sinks);
+                Ok(())
+            }
+            Command::Route(RouteChange::Sentences(sinks)) => {
+                router::set_route(router::Source::Gps, sinks);
+                router::set_route(router::Source::Synth, sinks);
+                Ok(())
+            }
+            Command::Overflow(None) => return cmd::write_overflow(response),
+            Command::Overflow(Some((sink, overflow))) => {
+                router::set_overflow(sink, overflow);
+                Ok(())
+            }
+            Command::SelfTest => return defer(Job::SelfTest, port, shared, response),
+            #[cfg(feature = "lora")]
+            Command::Lora(_) if !lora::is_present() => Err(cmd::Error::NoRadio),
+            #[cfg(feature = "lora")]
+            Command::Lora(LoraChange::Query) => return cmd::write_lora(response),
+            #[cfg(feature = "lora")]
+            Command::Lora(change) => {
+                match change {
+                    LoraChange::Interval(interval_s) => beacon::set_interval(interval_s),
+                    LoraChange::FrequencyKhz(frequency_khz) => beacon::set_frequency(frequency_khz),
+                    LoraChange::Sf(sf) => beacon::set_sf(sf),
+                    LoraChange::Query => {}
+                }
+                Ok(())
+            }
+            #[cfg(feature = "profiling")]
+            Command::Profile => return cmd::write_profile(response),
+            #[cfg(feature = "eeprom")]
+            Command::Events(Some(count)) => {
+                return defer(Job::Events(count), port, shared, response)
+            }
+            #[cfg(feature = "eeprom")]
+            Command::Events(None) => {
+                eventlog::record(Event::Cleared);
+                Ok(())
+            }
+            #[cfg(feature = "spi-slave")]
+            Command::Spi(None) => {
+                let (queued, dropped) = shared
+                    .spi_stream
+                    .lock(|stream| (stream.queued(), stream.dropped()));
+                return cmd::write_spi(response, queued, dropped);
+            }
+            #[cfg(feature = "spi-slave")]
+            Command::Spi(Some(content)) => {
+                spi_slave::set_content(content);
+                Ok(())
+            }
+            #[cfg(feature = "flash-log")]
+            Command::Erase => return defer(Job::Erase, port, shared, response),
+        };
+        match result {
+            Ok(()) => write!(response, "OK"),
+            Err(error) => write!(response, "ERR {}", error.as_str()),
+        }
+    }
+
+    /// Queue a byte of an aiding frame for the GPS. `None` outside of aiding mode or if the byte
+    /// isn't part of a frame.
+    fn upload_byte(
+        aid: &mut impl Mutex<T = Option<aid::Upload>>,
+        gps_tx: &mut impl Mutex<T = RingBuffer<GPS_TX_QUEUE_LEN>>,
+        byte: u8,
+    ) -> Option<aid::Step> {
+        aid.lock(|aid| {
+            let upload = aid.as_mut().filter(|upload| upload.accepts(byte))?;
+            let queued = queue_gps_byte(gps_tx, byte);
+            upload.push(byte, queued)
+        })
+    }
+
+    /// Queue a byte from the host for the GPS, a byte that doesn't fit is dropped and counted
+    fn queue_gps_byte(gps_tx: &mut impl Mutex<T = RingBuffer<GPS_TX_QUEUE_LEN>>, byte: u8) -> bool {
+        let (idle, queued) = gps_tx.lock(|gps_tx| {
+            let idle = gps_tx.is_empty();
+            let queued = gps_tx.push(byte).is_ok();
+            STATS.gps_tx_peak.record(gps_tx.len());
+            (idle, queued)
+        });
+        if !queued {
+            STATS.dropped_bytes.increment();
+        }
+        if idle {
+            // USART1 handler enables its TXE interrupt
+            rtic::pend(pac::Interrupt::USART1);
+        }
+        queued
+    }
+
+    /// Switch the host baud once the response to the current command has been sent,
+    /// see USART2 TC interrupt
+    fn request_host_baud(local: &mut usart2::LocalResources, baud: u32) {
+        local.reconfigure.request(&*local.usart2, baud);
+    }
+
+    /// Apply a pending host baud change once all queued responses have been sent, or dropped
+    /// after `uart::DRAIN_TIMEOUT_MS`, then confirm it with a `BAUD <rate>` line at the new baud.
+    /// Called on USART2 TC, which is set when the last byte of a DMA transfer has been shifted
+    /// out, and when pended by the drain timeout.
+    fn switch_host_baud(
+        local: &mut usart2::LocalResources,
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+    ) {
+        let usart2 = &*local.usart2;
+        let reconfigure = &mut *local.reconfigure;
+        let Some(switched) =
+            host_tx.lock(|host_tx| reconfigure.poll(usart2, HOST_CLOCK_HZ, host_tx))
+        else {
+            return;
+        };
+        let baud = switched.baud;
+        *local.host_baud = baud;
+        #[cfg(feature = "rs485")]
+        rs485::set_baud(usart2, baud);
+        if switched.timed_out {
+            listen_gps::info!("host baud {=u32}, queued bytes dropped", baud);
+        } else {
+            listen_gps::info!("host baud {=u32}", baud);
+        }
+
+        let mut confirmation = Response::new();
+        let _ = write!(confirmation, "BAUD {}", baud);
+        respond(host_tx, confirmation);
+    }
+
+    /// Lock onto the baud of the host's sync byte and confirm it with a `BAUD <rate>` line, see
+    /// `baud::start_detection`. Received bytes are dropped until then, a failed detection is
+    /// armed again.
+    fn sync_host_baud(
+        local: &mut usart2::LocalResources,
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+    ) {
+        let usart2 = &*local.usart2;
+        let _ = usart2.read();
+        match baud::detection(usart2, HOST_CLOCK_HZ) {
+            Detection::Pending => {}
+            Detection::Failed => baud::restart_detection(usart2),
+            Detection::Locked(baud) => {
+                // The measured BRR is off by the measurement error. Detected rates are
+                // supported.
+                let _ = uart::reconfigure(usart2, HOST_CLOCK_HZ, baud);
+                *local.host_baud = baud;
+                #[cfg(feature = "rs485")]
+                rs485::set_baud(usart2, baud);
+                *local.syncing = false;
+                listen_gps::info!("host baud {=u32} detected", baud);
+
+                let mut confirmation = Response::new();
+                let _ = write!(confirmation, "BAUD {}", baud);
+                respond(host_tx, confirmation);
+            }
+        }
+        if usart2.errors().any() {
+            usart2.clear_errors();
+        }
+    }
+
+    /// Select or deselect the bridge on an address byte of the RS-485 bus, sending what was held
+    /// once selected, or replying to the poll in polled mode. Returns true for a byte addressed
+    /// to the bridge.
+    #[cfg(feature = "rs485")]
+    fn take_bus_byte(
+        host_tx: &mut impl Mutex<T = DoubleBufferTx>,
+        poll: &mut impl Mutex<T = Poll>,
+        byte: u8,
+    ) -> bool {
+        match rs485::on_receive(byte) {
+            Received::Taken => true,
+            Received::Dropped => false,
+            Received::Selected if rs485::is_polled() => {
+                poll.lock(|poll| poll.start());
+                rs485::begin_turn();
+                send_poll(poll, host_tx);
+                false
+            }
+            Received::Selected => {
+                host_tx.lock(|host_tx| host_tx.flush());
+                false
+            }
+        }
+    }
+
+    /// Assemble command lines from the UART adaptor, execute them and queue the response.
+    #[task(
+        binds = USART2,
+        priority = 3,
+        local = [
+            usart2,
+            host_baud,
+            reconfigure,
+            line: LineBuffer = LineBuffer::new(),
+            escape: Escape = Escape::new(),
+            // Waiting for the sync byte of the host
+            syncing: bool = cfg!(feature = "host-autobaud"),
+        ],
+        shared = [
+            host_tx, gps_tx, fix, sky, smoother, rate, filter, gps_pin, rtc, gps_baud, usb, ble,
+            inbox, track, geofence, speed_alarm, anchor, schedule, battery, trip, supervisor, aid,
+            poll, spi_stream, jobs,
+        ]
+    )]
+    fn usart2(mut cx: usart2::Context) {
+        #[cfg(feature = "profiling")]
+        let _span = profile::enter(profile::Handler::Usart2);
+        // Pended by the timer ending the guard time after `+++`
+        if cx.local.escape.take_escaped() {
+            bridge::set_raw(false);
+            send_host(&mut cx.shared.host_tx, Class::Response, b"OK\r\n");
+        }
+        // Pended by the USB and Bluetooth handlers, see `inbox`
+        while let Some(received) = cx.shared.inbox.lock(|inbox| inbox.pop()) {
+            let command = received.line().and_then(Command::parse);
+            run_command(received.port, command, &mut cx.local, &mut cx.shared);
+        }
+        if *cx.local.syncing {
+            sync_host_baud(&mut cx.local, &mut cx.shared.host_tx);
+            return;
+        }
+        // Framing and noise flags are set along with RXNE for the byte in RDR
+        let errors = cx.local.usart2.errors();
+        let received = cx.local.usart2.read();
+        #[cfg(feature = "rs485")]
+        let received = received
+            .filter(|&byte| take_bus_byte(&mut cx.shared.host_tx, &mut cx.shared.poll, byte));
+        if let Some(received_byte) = received {
+            if bridge::is_raw() {
+                // Bytes with a framing error are passed on as well, the GPS checks its input
+                queue_gps_byte(&mut cx.shared.gps_tx, received_byte);
+                cx.local.escape.push(received_byte, timer::now_ms());
+            } else if let Some(step) =
+                upload_byte(&mut cx.shared.aid, &mut cx.shared.gps_tx, received_byte)
+            {
+                // A byte with a framing error fails the frame's checksum
+                if let aid::Step::Failed(error) = step {
+                    let mut response = Response::new();
+                    let _ = write!(response, "AID ERR {}", error.as_str());
+                    respond(&mut cx.shared.host_tx, response);
+                }
+            } else if errors.framing {
+                // The byte can't be trusted, fail its line instead of guessing what was sent
+                cx.local.line.reject(cmd::Error::Framing);
+                #[cfg(feature = "host-autobaud")]
+                if received_byte == 0 {
+                    // A break, the host syncs again and starts a new line
+                    *cx.local.line = LineBuffer::new();
+                    baud::restart_detection(cx.local.usart2);
+                    *cx.local.syncing = true;
+                }
+            } else if cx.local.line.is_empty() && matches!(received_byte, b'0' | b'1') {
+                // No command starts with a digit, so a lone '0'/'1' keeps toggling GPS OFF/ON
+                cx.shared
+                    .gps_pin
+                    .lock(|gps_pin| switch_gps_power(gps_pin, received_byte == b'1'));
+            } else if let Some(line) = cx.local.line.push(received_byte) {
+                let command = line.and_then(Command::parse);
+                run_command(Sink::Host, command, &mut cx.local, &mut cx.shared);
+                // In polled mode the response takes a turn of its own
+                #[cfg(feature = "rs485")]
+                if rs485::is_polled() {
+                    rs485::begin_turn();
+                    rs485::end_turn();
+                    cx.shared.host_tx.lock(|host_tx| host_tx.flush());
+                }
+            }
+        }
+        #[cfg(feature = "rs485")]
+        cx.shared.host_tx.lock(|host_tx| {
+            let usart2 = &*cx.local.usart2;
+            if rs485::on_transmit_complete(usart2, host_tx.is_sending())
+                && cx.local.reconfigure.pending().is_none()
+            {
+                usart2.listen_transmit_complete(false);
+            }
+        });
+        if cx.local.reconfigure.pending().is_some() {
+            switch_host_baud(&mut cx.local, &mut cx.shared.host_tx);
+        }
+        // An overrun may be flagged after the byte above was read
+        let errors = cx.local.usart2.errors();
+        if errors.overrun {
+            STATS.overruns.increment();
+        }
+        count_receive_errors(errors.framing, errors.noise);
+        if errors.any() {
+            cx.local.usart2.clear_errors();
+        }
+    }
 }
@@ -0,0 +1,145 @@
+//! Trip statistics for bike computer style use: distance travelled, trip and moving time,
+//! average and maximum speed, reported by `TRIP?` and reset by `TRIP RESET`.
+//!
+//! Distance is the haversine distance between fixes, which are only passed in once they pass
+//! the [`quality`](crate::quality) gate. Distance is only added once the position has moved
+//! [`MIN_STEP_M`] from the last counted point, so position noise while standing still doesn't
+//! add up.
+
+use crate::fix::GpsFix;
+use crate::nmea::Time;
+
+/// Movement from the last counted point before it counts as travelled
+pub const MIN_STEP_M: f32 = 5.0;
+
+/// Speed from which time counts as moving, in millimetres per second
+pub const MOVING_SPEED: u32 = 500;
+
+/// Longest time between fixes counted as trip time, longer gaps are pauses such as GPS off
+const MAX_GAP_MS: u32 = 10_000;
+
+/// Mean earth radius in metres
+const EARTH_RADIUS_M: f32 = 6_371_000.0;
+
+const MS_PER_DAY: u32 = 86_400_000;
+
+/// Accumulated trip, see the module documentation
+#[derive(Debug, Clone, Copy)]
+pub struct Trip {
+    /// Metres travelled
+    distance_m: f32,
+    /// Last counted position in 1e-7 degrees
+    anchor: Option<(i32, i32)>,
+    /// UTC time of day of the last fix in milliseconds
+    last_ms: Option<u32>,
+    /// Milliseconds between counted fixes
+    elapsed_ms: u32,
+    /// Milliseconds between counted fixes at moving speed
+    moving_ms: u32,
+    /// Fastest speed over ground in millimetres per second
+    max_speed: u32,
+}
+
+impl Trip {
+    pub const fn new() -> Self {
+        Self {
+            distance_m: 0.0,
+            anchor: None,
+            last_ms: None,
+            elapsed_ms: 0,
+            moving_ms: 0,
+            max_speed: 0,
+        }
+    }
+
+    /// Start a new trip
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Account for a completed fix
+    pub fn update(&mut self, fix: &GpsFix) {
+        let Some(time) = fix.timestamp else {
+            return;
+        };
+        if !fix.is_valid() {
+            return;
+        }
+        let now_ms = ms_of_day(&time);
+        if let Some(last_ms) = self.last_ms {
+            // Wraps at midnight
+            let delta = (now_ms + MS_PER_DAY - last_ms) % MS_PER_DAY;
+            if delta <= MAX_GAP_MS {
+                self.elapsed_ms += delta;
+                if fix.speed >= MOVING_SPEED {
+                    self.moving_ms += delta;
+                }
+            }
+        }
+        self.last_ms = Some(now_ms);
+        self.max_speed = self.max_speed.max(fix.speed);
+
+        let position = (fix.lat, fix.lon);
+        match self.anchor {
+            Some(anchor) => {
+                let step = haversine_m(anchor, position);
+                if step >= MIN_STEP_M {
+                    self.distance_m += step;
+                    self.anchor = Some(position);
+                }
+            }
+            None => self.anchor = Some(position),
+        }
+    }
+
+    pub fn distance_m(&self) -> f32 {
+        self.distance_m
+    }
+
+    /// Trip time in seconds, excluding pauses without fixes
+    pub fn elapsed_s(&self) -> u32 {
+        self.elapsed_ms / 1000
+    }
+
+    /// Time at moving speed in seconds
+    pub fn moving_s(&self) -> u32 {
+        self.moving_ms / 1000
+    }
+
+    /// Average speed while moving in millimetres per second
+    pub fn average_speed(&self) -> u32 {
+        if self.moving_ms == 0 {
+            return 0;
+        }
+        (self.distance_m * 1_000_000.0 / self.moving_ms as f32) as u32
+    }
+
+    /// Fastest speed in millimetres per second
+    pub fn max_speed(&self) -> u32 {
+        self.max_speed
+    }
+}
+
+impl Default for Trip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Great circle distance in metres between two positions in 1e-7 degrees
+pub fn haversine_m(from: (i32, i32), to: (i32, i32)) -> f32 {
+    let radians = |value: i64| value as f32 * 1e-7 * core::f32::consts::PI / 180.0;
+    let lat1 = radians(from.0.into());
+    let lat2 = radians(to.0.into());
+    // Differences are taken in integers to keep the precision of close positions
+    let dlat = radians(i64::from(to.0) - i64::from(from.0));
+    let dlon = radians(i64::from(to.1) - i64::from(from.1));
+    let a = libm::sinf(dlat / 2.0) * libm::sinf(dlat / 2.0)
+        + libm::cosf(lat1) * libm::cosf(lat2) * libm::sinf(dlon / 2.0) * libm::sinf(dlon / 2.0);
+    2.0 * EARTH_RADIUS_M * libm::asinf(libm::sqrtf(a.min(1.0)))
+}
+
+fn ms_of_day(time: &Time) -> u32 {
+    ((u32::from(time.hour) * 60 + u32::from(time.minute)) * 60 + u32::from(time.second)) * 1000
+        + u32::from(time.millisecond)
+}
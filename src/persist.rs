@@ -0,0 +1,115 @@
+//! Data kept in RAM through a reset: fault and panic records, see [`crate::fault`], the count of
+//! resets since power-on, see [`crate::reset`], and the latest fix for the hot start, see
+//! `hotstart`.
+//!
+//! A [`Slot`] is placed in the `.persist` section, the first 1K of SRAM1 in `memory/*.x`, which
+//! startup neither zeroes nor initializes. Unlike the `.uninit` section of cortex-m-rt, which
+//! follows `.bss`, it is at the same address in every build, so a slot survives a firmware update
+//! or an `ab-boot` bank swap too. A slot holds its value between a magic word, telling the slots
+//! apart, and a CRC-32 of the value, so neither RAM left random at power-on nor a slot another
+//! build placed differently is taken for a value.
+//!
+//! SRAM keeps its contents through a reset but not through a power cycle.
+
+use crate::storage;
+use core::cell::UnsafeCell;
+use core::mem::{size_of, MaybeUninit};
+
+/// Type kept in a [`Slot`]
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value and the type must have no padding, as its bytes are
+/// checksummed.
+pub unsafe trait Persistent: Copy {}
+
+// SAFETY: integers and arrays of bytes have neither invalid values nor padding
+unsafe impl Persistent for u32 {}
+// SAFETY: as above
+unsafe impl<const N: usize> Persistent for [u8; N] {}
+
+/// Value as kept in RAM
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Stored<T> {
+    magic: u32,
+    value: T,
+    crc: u32,
+}
+
+/// Value of type `T` kept through a reset, marked by `MAGIC`. Declare it as a static in the
+/// `.persist` section, e.g. `#[link_section = ".persist.NAME"]`.
+pub struct Slot<T, const MAGIC: u32> {
+    stored: UnsafeCell<MaybeUninit<Stored<T>>>,
+}
+
+// SAFETY: callers of the unsafe methods ensure a slot isn't accessed concurrently
+unsafe impl<T: Send, const MAGIC: u32> Sync for Slot<T, MAGIC> {}
+
+impl<T: Persistent, const MAGIC: u32> Slot<T, MAGIC> {
+    /// A slot whose contents are whatever RAM holds at startup, in the `.persist` section
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self {
+            stored: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The value stored, if the magic word and the CRC match
+    ///
+    /// # Safety
+    ///
+    /// The slot must not be written meanwhile.
+    pub unsafe fn load(&self) -> Option<T> {
+        // SAFETY: every bit pattern of the fields is valid, and the caller excludes writers
+        let stored = unsafe { self.stored.get().cast::<Stored<T>>().read_volatile() };
+        (stored.magic == MAGIC && stored.crc == crc(&stored.value)).then_some(stored.value)
+    }
+
+    /// Store `value`, replacing what the slot held
+    ///
+    /// # Safety
+    ///
+    /// The slot must not be accessed meanwhile.
+    pub unsafe fn store(&self, value: T) {
+        let stored = Stored {
+            magic: MAGIC,
+            value,
+            crc: crc(&value),
+        };
+        // SAFETY: the caller excludes other accesses
+        unsafe { self.stored.get().cast::<Stored<T>>().write_volatile(stored) };
+    }
+
+    /// The value stored, cleared so it is taken once
+    ///
+    /// # Safety
+    ///
+    /// The slot must not be accessed meanwhile.
+    pub unsafe fn take(&self) -> Option<T> {
+        // SAFETY: the caller excludes other accesses
+        let value = unsafe { self.load() }?;
+        // SAFETY: as above
+        unsafe { self.clear() };
+        Some(value)
+    }
+
+    /// Forget the value stored
+    ///
+    /// # Safety
+    ///
+    /// The slot must not be accessed meanwhile.
+    pub unsafe fn clear(&self) {
+        // SAFETY: the caller excludes other accesses, the magic word is the first field of
+        // the repr(C) Stored
+        unsafe { self.stored.get().cast::<u32>().write_volatile(!MAGIC) };
+    }
+}
+
+/// CRC-32 of the bytes of `value`
+fn crc<T: Persistent>(value: &T) -> u32 {
+    // SAFETY: a Persistent type has no padding, so all its bytes are initialized
+    let bytes =
+        unsafe { core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) };
+    storage::crc32(bytes)
+}
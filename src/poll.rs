@@ -0,0 +1,131 @@
+//! Polled output on a shared RS-485 bus, `POLL ON` with the `rs485` feature.
+//!
+//! In polled mode the bridge stops forwarding and transmits only in turns given by the host, see
+//! [`crate::rs485`]. Fixes passing the [`crate::quality`] gate are kept meanwhile, up to
+//! [`CAPACITY`], dropping the oldest once full. Selecting the bridge with its address byte polls
+//! it: it sends the kept fixes, RMC and GGA sentences or binary records following `MODE`, then
+//! `$PBRIDGE,POLL,<address>,<sent>,<dropped>*hh` with the number of fixes sent and dropped since
+//! the last poll, and releases the bus. Status reports and warnings are held until then. A
+//! command received while selected takes a turn of its own, ending with its response, so the
+//! host waits for the `POLL` sentence before sending one.
+//!
+//! The reply is refilled from the transfer complete interrupt like a flash log dump, so it isn't
+//! limited by the TX buffer size.
+
+use crate::filter::Output;
+use crate::fix::GpsFix;
+use crate::nmea::{self, encode};
+use core::fmt::{self, Write};
+use heapless::{Deque, String, Vec};
+
+/// Fixes kept between polls, half a minute at 1Hz
+pub const CAPACITY: usize = 32;
+
+/// One fix of a reply, RMC and GGA or a binary record
+pub type Encoded = Vec<u8, { 2 * nmea::MAX_SENTENCE_LEN }>;
+
+pub struct Poll {
+    fixes: Deque<GpsFix, CAPACITY>,
+    /// Fixes dropped since the last poll
+    dropped: u32,
+    /// Fixes sent by the reply in progress
+    reply: Option<u32>,
+}
+
+impl Poll {
+    pub const fn new() -> Self {
+        Self {
+            fixes: Deque::new(),
+            dropped: 0,
+            reply: None,
+        }
+    }
+
+    /// Fixes waiting for a poll
+    pub fn kept(&self) -> usize {
+        self.fixes.len()
+    }
+
+    /// Keep a fix until the next poll, dropping the oldest once full
+    pub fn push(&mut self, fix: &GpsFix) {
+        if self.fixes.is_full() {
+            self.fixes.pop_front();
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        let _ = self.fixes.push_back(*fix);
+    }
+
+    /// Drop the kept fixes, when leaving polled mode
+    pub fn clear(&mut self) {
+        self.fixes.clear();
+        self.dropped = 0;
+        self.reply = None;
+    }
+
+    /// Start a reply with the fixes kept so far, restarting one in progress
+    pub fn start(&mut self) {
+        self.reply = Some(0);
+    }
+
+    pub fn is_replying(&self) -> bool {
+        self.reply.is_some()
+    }
+
+    /// Next fix of the reply in progress
+    pub fn peek(&self) -> Option<&GpsFix> {
+        self.reply?;
+        self.fixes.front()
+    }
+
+    /// The fix of [`Poll::peek`] has been queued
+    pub fn consume(&mut self) {
+        if let Some(sent) = self.reply.as_mut() {
+            self.fixes.pop_front();
+            *sent += 1;
+        }
+    }
+
+    /// The reply in progress has queued every kept fix
+    pub fn is_done(&self) -> bool {
+        self.reply.is_some() && self.fixes.is_empty()
+    }
+
+    /// Write the `POLL` sentence ending the reply in progress
+    pub fn write_end(&self, out: &mut impl Write, address: u8) -> fmt::Result {
+        write_sentence(out, address, self.reply.unwrap_or(0), self.dropped)
+    }
+
+    /// The `POLL` sentence has been queued
+    pub fn end(&mut self) {
+        self.dropped = 0;
+        self.reply = None;
+    }
+}
+
+impl Default for Poll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encode a kept fix for the `output` of `MODE`
+pub fn encode_fix(fix: &GpsFix, output: Output) -> Encoded {
+    match output {
+        Output::Nmea => {
+            let mut sentences = String::<{ 2 * nmea::MAX_SENTENCE_LEN }>::new();
+            // Both fit
+            let _ = encode::write_rmc(&mut sentences, &fix.rmc());
+            let _ = encode::write_gga(&mut sentences, &fix.gga());
+            sentences.into_bytes()
+        }
+        // A record frame is shorter
+        Output::Binary => Vec::from_slice(&fix.record().encode()).unwrap_or_default(),
+    }
+}
+
+/// Write the `$PBRIDGE,POLL,<address>,<sent>,<dropped>*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, address: u8, sent: u32, dropped: u32) -> fmt::Result {
+    let mut body = String::<48>::new();
+    write!(body, "PBRIDGE,POLL,{},{},{}", address, sent, dropped)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
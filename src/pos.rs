@@ -0,0 +1,53 @@
+//! Compact position sentence for telemetry links such as LoRa or satellite modems, selected by
+//! `FILTER +POS` or `FILTER POS` alone.
+//!
+//! One `$PBRIDGE,POS,<time>,<lat>,<lon>,<alt>,<speed>,<course>,<sats>,<hdop>*hh` sentence per
+//! fix carries what otherwise takes RMC and GGA, in about half the bytes: UTC time as
+//! `hhmmss.ss`, latitude and longitude in signed decimal degrees with 6 decimals, altitude in
+//! whole metres, speed in metres per second with one decimal, course in whole degrees, the
+//! satellites used and the HDOP with one decimal. Without a fix the position, altitude, speed
+//! and course are empty. It is sent on completing an epoch, like the GGA and RMC synthesized
+//! from UBX-NAV-PVT, and is decimated by `DECIMATE` like them.
+
+use crate::cmd::Decimal;
+use crate::fix::GpsFix;
+use crate::nmea;
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// Write the `$PBRIDGE,POS` sentence of `fix` including line ending
+pub fn write_sentence(out: &mut impl Write, fix: &GpsFix) -> fmt::Result {
+    let mut body = String::<96>::new();
+    body.push_str("PBRIDGE,POS,").map_err(|_| fmt::Error)?;
+    if let Some(time) = fix.timestamp {
+        write!(
+            body,
+            "{:02}{:02}{:02}.{:02}",
+            time.hour,
+            time.minute,
+            time.second,
+            time.millisecond / 10
+        )?;
+    }
+    if fix.is_valid() {
+        write!(
+            body,
+            ",{},{},{},{},{}",
+            Decimal(i64::from(fix.lat) / 10, 6),
+            Decimal(i64::from(fix.lon) / 10, 6),
+            fix.altitude / 1000,
+            // Tenths of a metre per second
+            Decimal(i64::from(fix.speed / 100), 1),
+            fix.course / 100
+        )?;
+    } else {
+        body.push_str(",,,,,").map_err(|_| fmt::Error)?;
+    }
+    write!(
+        body,
+        ",{},{}",
+        fix.sats,
+        Decimal(i64::from(fix.hdop / 10), 1)
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
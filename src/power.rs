@@ -0,0 +1,57 @@
+//! Power management. The core sleeps between interrupts and enters Stop mode while the GPS is
+//! off, woken by a command byte received on USART2, or on LPUART1 with the `ble` feature.
+//!
+//! Stop 1 is used rather than Stop 2: on the STM32L432 only LPUART1 can wake the device from
+//! Stop 2, USART1 and USART2 support wakeup from Stop 0 and Stop 1.
+//! SRAM, registers and the MSI range are retained, the PLL is restored by
+//! [`clocks::restore`] after wakeup.
+
+use crate::chip::pac::{usart1, PWR, RCC};
+use crate::{clocks, load};
+use cortex_m::peripheral::{DWT, SCB};
+
+/// PWR_CR1 VOS value of voltage range 2, up to 26MHz
+const VOS_RANGE2: u8 = 0b10;
+
+/// PWR_CR1 LPMS value selecting Stop 1 on deep sleep
+const LPMS_STOP1: u8 = 0b001;
+
+/// Clock of a USART set up by [`enable_stop_wakeup`]
+pub const WAKEUP_CLOCK_HZ: u32 = 16_000_000;
+
+/// Switch to voltage range 2 if SYSCLK allows, which consumes less than range 1.
+/// PWR clock must be enabled and SYSCLK configured.
+pub fn init(pwr: &PWR) {
+    if clocks::SYSCLK_HZ <= clocks::RANGE2_MAX_HZ {
+        pwr.cr1.modify(|_, w| unsafe { w.vos().bits(VOS_RANGE2) });
+        while pwr.sr2.read().vosf().bit_is_set() {}
+    }
+    pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(LPMS_STOP1) });
+}
+
+/// Clock USART2 from HSI16, which the USART switches on by itself when a start bit is detected
+/// in Stop mode, and let its RXNE interrupt wake the device. Baud must be set for
+/// [`WAKEUP_CLOCK_HZ`] afterwards. USART2 must be disabled.
+pub fn enable_stop_wakeup(rcc: &RCC, usart2: &usart1::RegisterBlock) {
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+    rcc.ccipr.modify(|_, w| w.usart2sel().hsi16());
+    usart2.cr1.modify(|_, w| w.uesm().set_bit());
+}
+
+/// Sleep until the next interrupt, peripherals and DMA keep running. The time asleep counts
+/// towards the CPU load, see [`load`].
+pub fn sleep() {
+    let start = DWT::cycle_count();
+    cortex_m::asm::wfi();
+    load::record_sleep(DWT::cycle_count().wrapping_sub(start));
+}
+
+/// Enter Stop 1 until a wakeup interrupt. Anything being transmitted must have completed,
+/// USART1 and DMA are stopped.
+pub fn stop(scb: &mut SCB) {
+    scb.set_sleepdeep();
+    cortex_m::asm::wfi();
+    scb.clear_sleepdeep();
+    clocks::restore();
+}
@@ -0,0 +1,100 @@
+//! 1PPS timepulse capture. TIM2 counts microseconds since boot and channel 1 captures the
+//! rising edge of the GPS timepulse on PA5, so the capture doesn't depend on interrupt latency.
+//! Overflows of the 32-bit counter are counted to keep timestamps monotonic.
+//! TIM2 is halted in Stop mode, time spent there is not counted.
+
+use crate::chip::pac::TIM2;
+
+/// Counter ticks per second
+pub const TICK_HZ: u32 = 1_000_000;
+
+/// Capture/compare 1 and update interrupt flags in TIM2_SR
+const SR_CC1IF: u32 = 1 << 1;
+const SR_UIF: u32 = 1 << 0;
+
+/// Captured timepulse edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    /// Microseconds since boot
+    pub timestamp: u64,
+    /// Pulses captured since boot, including this one
+    pub count: u32,
+}
+
+pub struct Pps {
+    tim2: TIM2,
+    /// Counter overflows handled so far
+    overflows: u32,
+    last: Option<Pulse>,
+}
+
+impl Pps {
+    /// Start TIM2 at [`TICK_HZ`] from its `clock_hz` kernel clock, capturing rising edges on
+    /// TI1 with capture and update interrupts enabled. TIM2 clock must be enabled and PA5
+    /// configured as alternate function 1.
+    pub fn new(tim2: TIM2, clock_hz: u32) -> Self {
+        tim2.psc
+            .write(|w| w.psc().bits((clock_hz / TICK_HZ - 1) as u16));
+        tim2.arr.write(|w| w.arr().bits(u32::MAX));
+        // Filter out glitches shorter than 8 clock cycles
+        tim2.ccmr1_input()
+            .write(|w| w.cc1s().ti1().ic1f().fck_int_n8());
+        // Rising edge, CC1P and CC1NP clear
+        tim2.ccer.write(|w| w.cc1e().set_bit());
+        tim2.dier.write(|w| w.cc1ie().set_bit().uie().set_bit());
+        // Load the prescaler without raising an update interrupt
+        tim2.cr1.write(|w| w.urs().set_bit());
+        tim2.egr.write(|w| w.ug().set_bit());
+        tim2.cr1.modify(|_, w| w.cen().set_bit());
+
+        Self {
+            tim2,
+            overflows: 0,
+            last: None,
+        }
+    }
+
+    /// Handle the TIM2 interrupt. Returns the pulse if one was captured.
+    pub fn on_interrupt(&mut self) -> Option<Pulse> {
+        let sr = self.tim2.sr.read().bits();
+        let pulse = (sr & SR_CC1IF != 0).then(|| {
+            // Reading CCR1 clears CC1IF
+            let captured = self.tim2.ccr1().read().bits();
+            let pulse = Pulse {
+                timestamp: self.extend(captured, sr & SR_UIF != 0),
+                count: self.last.map_or(1, |last| last.count.wrapping_add(1)),
+            };
+            self.last = Some(pulse);
+            pulse
+        });
+        if sr & SR_UIF != 0 {
+            // SAFETY: flags are cleared by writing 0, writing 1 leaves them unchanged
+            self.tim2.sr.write(|w| unsafe { w.bits(!SR_UIF) });
+            self.overflows = self.overflows.wrapping_add(1);
+        }
+        pulse
+    }
+
+    /// Latest captured pulse
+    pub fn last(&self) -> Option<Pulse> {
+        self.last
+    }
+
+    /// Microseconds since boot, on the same timescale as [`Pulse::timestamp`]
+    pub fn now(&self) -> u64 {
+        let counter = self.tim2.cnt.read().bits();
+        let overflow_pending = self.tim2.sr.read().bits() & SR_UIF != 0;
+        self.extend(counter, overflow_pending)
+    }
+
+    /// Extend a counter value to 64 bits. An overflow not handled yet happened before the value
+    /// was latched if the value is in the lower half of the range.
+    fn extend(&self, counter: u32, overflow_pending: bool) -> u64 {
+        let overflows = if overflow_pending && counter < 1 << 31 {
+            self.overflows.wrapping_add(1)
+        } else {
+            self.overflows
+        };
+        (u64::from(overflows) << 32) | u64::from(counter)
+    }
+}
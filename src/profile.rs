@@ -0,0 +1,135 @@
+//! Duration of the USART and DMA interrupt handlers, enabled by the `profiling` feature and
+//! reported by `PROFILE?`, to catch a handler grown too slow for the bytes it must keep up with.
+//!
+//! Each handler opens a [`Span`] on entry, which reads DWT CYCCNT, started by
+//! [`crate::load::init`], and records the cycles since when dropped on exit. A duration includes
+//! the time the handler was preempted by one of higher priority, e.g. SysTick above all of them
+//! and USART2 above USART1 and DMA, see [`crate::sync`], so a maximum well above the average may
+//! be preemption rather than the handler itself. Durations are kept since boot in core clock
+//! cycles, see [`tenths_of_us`].
+
+use crate::clocks;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::peripheral::DWT;
+
+/// Profiled interrupt handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    /// GPS reception
+    Usart1,
+    /// Host reception and commands
+    Usart2,
+    /// GPS RX DMA half and full transfer
+    Dma1Ch5,
+    /// Host TX DMA transfer complete
+    Dma1Ch7,
+    /// Bluetooth module, with the `ble` feature
+    Lpuart1,
+}
+
+impl Handler {
+    pub const ALL: [Handler; 5] = [
+        Handler::Usart1,
+        Handler::Usart2,
+        Handler::Dma1Ch5,
+        Handler::Dma1Ch7,
+        Handler::Lpuart1,
+    ];
+
+    /// Name of the interrupt the handler is bound to
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Handler::Usart1 => "USART1",
+            Handler::Usart2 => "USART2",
+            Handler::Dma1Ch5 => "DMA1_CH5",
+            Handler::Dma1Ch7 => "DMA1_CH7",
+            Handler::Lpuart1 => "LPUART1",
+        }
+    }
+
+    /// The handler's feature is enabled
+    pub fn is_built(&self) -> bool {
+        *self != Handler::Lpuart1 || cfg!(feature = "ble")
+    }
+}
+
+/// Runs and cycles of a handler. Each is updated on its own, a reader preempting
+/// [`Span::drop`] may see a run without its cycles.
+struct Durations {
+    count: AtomicU32,
+    /// Sum of the cycles, the low word wraps after under a minute in the handler at 80MHz
+    total_low: AtomicU32,
+    total_high: AtomicU32,
+    max_cycles: AtomicU32,
+}
+
+impl Durations {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU32::new(0),
+            total_low: AtomicU32::new(0),
+            total_high: AtomicU32::new(0),
+            max_cycles: AtomicU32::new(0),
+        }
+    }
+
+    fn record(&self, cycles: u32) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let low = self.total_low.fetch_add(cycles, Ordering::Relaxed);
+        if low.checked_add(cycles).is_none() {
+            self.total_high.fetch_add(1, Ordering::Relaxed);
+        }
+        self.max_cycles.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    fn total(&self) -> u64 {
+        u64::from(self.total_high.load(Ordering::Relaxed)) << 32
+            | u64::from(self.total_low.load(Ordering::Relaxed))
+    }
+}
+
+static DURATIONS: [Durations; Handler::ALL.len()] = [
+    Durations::new(),
+    Durations::new(),
+    Durations::new(),
+    Durations::new(),
+    Durations::new(),
+];
+
+/// Run of a handler, recorded when dropped
+pub struct Span {
+    handler: Handler,
+    start: u32,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        DURATIONS[self.handler as usize].record(DWT::cycle_count().wrapping_sub(self.start));
+    }
+}
+
+/// Start timing a run of `handler`, called first thing in it
+pub fn enter(handler: Handler) -> Span {
+    Span {
+        handler,
+        start: DWT::cycle_count(),
+    }
+}
+
+/// Runs, average and maximum cycles of `handler`, `None` before it first ran
+pub fn summary(handler: Handler) -> Option<(u32, u32, u32)> {
+    let durations = &DURATIONS[handler as usize];
+    let count = durations.count.load(Ordering::Relaxed);
+    (count > 0).then(|| {
+        (
+            count,
+            (durations.total() / u64::from(count)) as u32,
+            durations.max_cycles.load(Ordering::Relaxed),
+        )
+    })
+}
+
+/// Core clock `cycles` in tenths of a microsecond
+pub fn tenths_of_us(cycles: u32) -> u64 {
+    u64::from(cycles) * 10 / u64::from(clocks::SYSCLK_HZ / 1_000_000)
+}
@@ -0,0 +1,108 @@
+//! Receiver power save modes, selected by the `PSM` host command.
+//!
+//! Power save mode is configured with UBX-CFG-PM2 and switched on with UBX-CFG-RXM. In cyclic
+//! tracking the receiver keeps tracking at reduced power and outputs a solution every update
+//! period. Update periods above [`MAX_CYCLIC_TRACKING_S`] use ON/OFF operation instead, where the
+//! receiver sleeps between fixes and sends nothing meanwhile. Both need the 1Hz navigation rate.
+//!
+//! The update period is kept in [`update_period_ms`] so the watchdog and the GPS supervisor
+//! allow for the quiet time between fixes.
+
+use crate::ubx;
+use core::sync::atomic::{AtomicU32, Ordering};
+use heapless::Vec;
+
+pub const UBX_ID_CFG_RXM: u8 = 0x11;
+pub const UBX_ID_CFG_PM2: u8 = 0x3B;
+
+/// Update period of `PSM ON`
+pub const DEFAULT_UPDATE_PERIOD_S: u32 = 1;
+
+/// Longest update period using cyclic tracking
+pub const MAX_CYCLIC_TRACKING_S: u32 = 10;
+
+/// Longest update period accepted
+pub const MAX_UPDATE_PERIOD_S: u32 = 3600;
+
+/// Retry period after a failed acquisition, the receiver default
+const SEARCH_PERIOD_MS: u32 = 10_000;
+
+/// UBX-CFG-PM2 flags: update ephemeris (bit 12), mode (bits 17-18) 0 ON/OFF, 1 cyclic tracking
+const FLAGS_UPDATE_EPH: u32 = 1 << 12;
+const FLAGS_CYCLIC: u32 = 1 << 17;
+
+/// UBX-CFG-RXM lpMode values
+const LP_MODE_CONTINUOUS: u8 = 0;
+const LP_MODE_POWER_SAVE: u8 = 1;
+
+/// UBX-CFG-PM2 payload length of protocol version 1
+const PM2_LEN: usize = 44;
+
+/// Both configuration frames
+pub type Frames = Vec<u8, { 2 * ubx::MAX_FRAME_LEN }>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Continuous tracking at full power
+    Off,
+    /// Power save with a fix every `update_period_s` seconds
+    On { update_period_s: u32 },
+}
+
+/// Update period of the selected mode, 0 in continuous mode
+static UPDATE_PERIOD_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Time between fixes in power save mode, 0 in continuous mode
+pub fn update_period_ms() -> u32 {
+    UPDATE_PERIOD_MS.load(Ordering::Relaxed)
+}
+
+/// The receiver may send nothing for longer than a second between fixes
+pub fn is_sleeping() -> bool {
+    update_period_ms() > 1000
+}
+
+/// Record the mode once its frames are queued
+pub fn select(mode: Mode) {
+    let period_ms = match mode {
+        Mode::Off => 0,
+        Mode::On { update_period_s } => update_period_s * 1000,
+    };
+    UPDATE_PERIOD_MS.store(period_ms, Ordering::Relaxed);
+}
+
+/// UBX-CFG-PM2 followed by UBX-CFG-RXM for power save, UBX-CFG-RXM alone for continuous mode
+pub fn frames(mode: Mode) -> Frames {
+    let mut frames = Frames::new();
+    let lp_mode = match mode {
+        Mode::Off => LP_MODE_CONTINUOUS,
+        Mode::On { update_period_s } => {
+            let _ = frames.extend_from_slice(&cfg_pm2(update_period_s));
+            LP_MODE_POWER_SAVE
+        }
+    };
+    let _ = frames.extend_from_slice(&cfg_rxm(lp_mode));
+    frames
+}
+
+/// UBX-CFG-PM2 frame with an update period, cyclic tracking up to [`MAX_CYCLIC_TRACKING_S`]
+fn cfg_pm2(update_period_s: u32) -> ubx::Frame {
+    let flags = if update_period_s <= MAX_CYCLIC_TRACKING_S {
+        FLAGS_UPDATE_EPH | FLAGS_CYCLIC
+    } else {
+        FLAGS_UPDATE_EPH
+    };
+    let mut payload = [0; PM2_LEN];
+    payload[0] = 1;
+    payload[4..8].copy_from_slice(&flags.to_le_bytes());
+    payload[8..12].copy_from_slice(&(update_period_s * 1000).to_le_bytes());
+    payload[12..16].copy_from_slice(&SEARCH_PERIOD_MS.to_le_bytes());
+    // Grid offset, on time and minimum acquisition time are left at 0
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_PM2, &payload).unwrap_or_default()
+}
+
+/// UBX-CFG-RXM frame selecting a low power mode
+fn cfg_rxm(lp_mode: u8) -> ubx::Frame {
+    // First byte is reserved, always 8
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_RXM, &[8, lp_mode]).unwrap_or_default()
+}
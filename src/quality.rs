@@ -0,0 +1,57 @@
+//! Quality gate of completed fixes, set by `QUALITY`. Fixes failing it aren't sent in `MODE BIN`,
+//! counted by the trip or logged to the SD card and flash, so the positions of a cold start,
+//! often from a few satellites and far off, don't end up in tracks and the odometer.
+//! `STATUS`, the display and the geofence still see every fix.
+
+use crate::fix::GpsFix;
+use crate::nmea::FixType;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+/// Satellites a fix can be reported with, the GSA list
+pub const MAX_SATS: u8 = 12;
+
+/// Worst HDOP that can be set, in hundredths
+pub const MAX_HDOP: u16 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gate {
+    /// `Fix2D` or `Fix3D`
+    pub min_fix: FixType,
+    pub min_sats: u8,
+    /// In hundredths
+    pub max_hdop: u16,
+}
+
+impl Gate {
+    /// 2D fix from 4 satellites with an HDOP of at most 5
+    pub const DEFAULT: Gate = Gate {
+        min_fix: FixType::Fix2D,
+        min_sats: 4,
+        max_hdop: 500,
+    };
+
+    pub fn passes(&self, fix: &GpsFix) -> bool {
+        fix.fix_type >= self.min_fix && fix.sats >= self.min_sats && fix.hdop <= self.max_hdop
+    }
+}
+
+static MIN_3D: AtomicBool = AtomicBool::new(false);
+static MIN_SATS: AtomicU8 = AtomicU8::new(Gate::DEFAULT.min_sats);
+static HDOP_LIMIT: AtomicU16 = AtomicU16::new(Gate::DEFAULT.max_hdop);
+
+pub fn gate() -> Gate {
+    Gate {
+        min_fix: match MIN_3D.load(Ordering::Relaxed) {
+            true => FixType::Fix3D,
+            false => FixType::Fix2D,
+        },
+        min_sats: MIN_SATS.load(Ordering::Relaxed),
+        max_hdop: HDOP_LIMIT.load(Ordering::Relaxed),
+    }
+}
+
+pub fn set_gate(gate: Gate) {
+    MIN_3D.store(gate.min_fix == FixType::Fix3D, Ordering::Relaxed);
+    MIN_SATS.store(gate.min_sats, Ordering::Relaxed);
+    HDOP_LIMIT.store(gate.max_hdop, Ordering::Relaxed);
+}
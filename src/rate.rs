@@ -0,0 +1,57 @@
+//! GPS navigation rate profiles, selected by the `RATE` host command.
+//! Higher rates produce more NMEA output per second, so each profile also sets the USART1 RX
+//! DMA window and the host baud needed to forward it.
+
+use crate::ubx;
+
+pub const UBX_ID_CFG_RATE: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// Navigation solutions per second
+    pub hz: u8,
+    /// Measurement period in milliseconds sent in UBX-CFG-RATE
+    pub period_ms: u16,
+    /// Bytes of the RX DMA buffer in use
+    pub rx_window: usize,
+    /// Minimum host baud to keep up with the output
+    pub host_baud: u32,
+}
+
+pub const PROFILES: [Profile; 3] = [
+    Profile {
+        hz: 1,
+        period_ms: 1000,
+        rx_window: 256,
+        host_baud: 115_200,
+    },
+    Profile {
+        hz: 5,
+        period_ms: 200,
+        rx_window: 512,
+        host_baud: 115_200,
+    },
+    Profile {
+        hz: 10,
+        period_ms: 100,
+        rx_window: 1024,
+        host_baud: 230_400,
+    },
+];
+
+/// Power-on rate of the GP-735T
+pub const DEFAULT: &Profile = &PROFILES[0];
+
+/// Profile for a supported rate
+pub fn profile(hz: u8) -> Option<&'static Profile> {
+    PROFILES.iter().find(|profile| profile.hz == hz)
+}
+
+/// UBX-CFG-RATE frame selecting the profile's measurement period, one navigation solution per
+/// measurement, aligned to GPS time
+pub fn cfg_rate(profile: &Profile) -> ubx::Frame {
+    let period = profile.period_ms.to_le_bytes();
+    let payload = [period[0], period[1], 1, 0, 1, 0];
+    // Payload is well below the maximum
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_RATE, &payload).unwrap_or_default()
+}
@@ -0,0 +1,120 @@
+//! Bridge status sent to the host in-band as a proprietary sentence, so host software can
+//! monitor the bridge without sending commands.
+//!
+//! Every `REPORT` period [`Reporter::on_tick`] asks for a
+//! `$PBRIDGE,STATUS,<uptime>,<gps>,<host_tx>,<gps_tx>,<gps_rx>,<ore>,<fe>,<ne>,<drop>,<bad>,<cksum>,<vbat>,<temp>,<id>*hh`
+//! sentence: uptime in seconds, GPS power `0` or `1`, the queue high-water marks in bytes, the
+//! [`STATS`] error counters, cleared together with them by `STATS CLR`, the battery voltage in
+//! millivolts and the MCU temperature in degrees Celsius with one decimal, both empty until first
+//! sampled, see [`crate::battery`] and [`crate::adc`], and the bridge's abbreviated device ID in
+//! 8 hexadecimal digits, see [`crate::version`].
+//!
+//! Uptime is counted from SysTick [`timer`](crate::timer) ticks, which halt in Stop mode, so it
+//! doesn't advance and no reports are sent while the GPS is off and the MCU stops.
+
+use crate::adc;
+use crate::battery;
+use crate::cmd::Decimal;
+use crate::nmea;
+use crate::stats::{Stats, STATS};
+use crate::version;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU16, Ordering};
+use heapless::String;
+
+/// Report period at boot
+pub const DEFAULT_PERIOD_S: u16 = 10;
+
+/// Longest report period accepted
+pub const MAX_PERIOD_S: u16 = 3600;
+
+/// Seconds between reports, 0 disables them
+static PERIOD_S: AtomicU16 = AtomicU16::new(DEFAULT_PERIOD_S);
+
+pub fn period_s() -> u16 {
+    PERIOD_S.load(Ordering::Relaxed)
+}
+
+/// Change the report period, the next report follows `period_s` after the last one
+pub fn set_period(period_s: u16) {
+    PERIOD_S.store(period_s, Ordering::Relaxed);
+}
+
+/// Counts uptime and decides when a report is due
+pub struct Reporter {
+    /// [`crate::timer::now_ms`] of the last whole second counted
+    second_ms: u32,
+    uptime_s: u32,
+    /// Seconds since the last report
+    elapsed_s: u32,
+}
+
+impl Reporter {
+    pub const fn new() -> Self {
+        Self {
+            second_ms: 0,
+            uptime_s: 0,
+            elapsed_s: 0,
+        }
+    }
+
+    pub fn uptime_s(&self) -> u32 {
+        self.uptime_s
+    }
+
+    /// Called on every SysTick tick at `now` milliseconds, true once a report is due
+    pub fn on_tick(&mut self, now: u32) -> bool {
+        let mut due = false;
+        while now.wrapping_sub(self.second_ms) >= 1000 {
+            self.second_ms = self.second_ms.wrapping_add(1000);
+            self.uptime_s = self.uptime_s.wrapping_add(1);
+            self.elapsed_s += 1;
+            let period_s = u32::from(period_s());
+            if period_s != 0 && self.elapsed_s >= period_s {
+                self.elapsed_s = 0;
+                due = true;
+            }
+        }
+        due
+    }
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the `$PBRIDGE,STATUS` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, uptime_s: u32, gps_power: bool) -> fmt::Result {
+    let mut body = String::<{ nmea::MAX_SENTENCE_LEN }>::new();
+    write_body(&mut body, uptime_s, gps_power, &STATS)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Characters between `$` and `*`
+fn write_body(out: &mut impl Write, uptime_s: u32, gps_power: bool, stats: &Stats) -> fmt::Result {
+    write!(
+        out,
+        "PBRIDGE,STATUS,{},{},{},{},{},{},{},{},{},{},{},",
+        uptime_s,
+        u8::from(gps_power),
+        stats.host_tx_peak.get(),
+        stats.gps_tx_peak.get(),
+        stats.gps_rx_peak.get(),
+        stats.overruns.get(),
+        stats.framing_errors.get(),
+        stats.noise_errors.get(),
+        stats.dropped_bytes.get(),
+        stats.invalid_sentences.get(),
+        stats.checksum_errors.get()
+    )?;
+    if let Some(mv) = battery::vbat_mv() {
+        write!(out, "{}", mv)?;
+    }
+    out.write_char(',')?;
+    if let Some(temperature) = adc::temperature() {
+        write!(out, "{}", Decimal(temperature.into(), 1))?;
+    }
+    write!(out, ",{:08X}", version::short_id())
+}
@@ -0,0 +1,146 @@
+//! Reset cause and brown-out reset threshold.
+//!
+//! The cause is read from the RCC_CSR reset flags, which accumulate until cleared, and reported
+//! to the host as a `$PBRIDGE,RESET,<cause>,<count>*hh` sentence at startup, with the resets since
+//! power-on counted in a [`Slot`]. The BOR threshold is an option byte, programmed once
+//! if it differs from [`BOR_LEVEL`].
+
+use crate::chip::pac::{FLASH, RCC};
+use crate::persist::Slot;
+use crate::{flash, nmea};
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// BOR_LEV option value, 4 resets below about 2.8V. The GP-735T needs at least 3.0V, so a
+/// battery sagging further is held in reset rather than logging garbage.
+pub const BOR_LEVEL: u8 = 4;
+
+/// Marks the reset count
+const COUNT_MAGIC: u32 = 0xB007_C047;
+
+#[link_section = ".persist.RESETS"]
+static COUNT: Slot<u32, COUNT_MAGIC> = Slot::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// Entering Standby or Shutdown while forbidden
+    LowPower,
+    WindowWatchdog,
+    /// See [`crate::watchdog`]
+    Watchdog,
+    /// `SYSRESETREQ`, e.g. from a debugger
+    Software,
+    Firewall,
+    /// Option bytes were loaded, e.g. after programming [`BOR_LEVEL`]
+    OptionBytes,
+    /// Power-on or brown-out, which share the BOR flag
+    BrownOut,
+    /// NRST pin, e.g. the reset button
+    Pin,
+    Unknown,
+}
+
+impl Cause {
+    /// Read and clear the reset flags. Other causes also pull NRST low, so the pin flag is
+    /// only reported if no other flag is set.
+    pub fn take(rcc: &RCC) -> Self {
+        let csr = rcc.csr.read();
+        let cause = if csr.lpwrstf().bit_is_set() {
+            Cause::LowPower
+        } else if csr.wwdgrstf().bit_is_set() {
+            Cause::WindowWatchdog
+        } else if csr.iwdgrstf().bit_is_set() {
+            Cause::Watchdog
+        } else if csr.sftrstf().bit_is_set() {
+            Cause::Software
+        } else if csr.firewallrstf().bit_is_set() {
+            Cause::Firewall
+        } else if csr.oblrstf().bit_is_set() {
+            Cause::OptionBytes
+        } else if csr.borrstf().bit_is_set() {
+            Cause::BrownOut
+        } else if csr.pinrstf().bit_is_set() {
+            Cause::Pin
+        } else {
+            Cause::Unknown
+        };
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+        cause
+    }
+
+    /// Cause of its code in the event log, `cause as u8` in declaration order
+    pub fn from_code(code: u8) -> Self {
+        [
+            Cause::LowPower,
+            Cause::WindowWatchdog,
+            Cause::Watchdog,
+            Cause::Software,
+            Cause::Firewall,
+            Cause::OptionBytes,
+            Cause::BrownOut,
+            Cause::Pin,
+        ]
+        .get(usize::from(code))
+        .copied()
+        .unwrap_or(Cause::Unknown)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cause::LowPower => "LOWPOWER",
+            Cause::WindowWatchdog => "WWDG",
+            Cause::Watchdog => "IWDG",
+            Cause::Software => "SOFTWARE",
+            Cause::Firewall => "FIREWALL",
+            Cause::OptionBytes => "OPTION",
+            Cause::BrownOut => "BOR",
+            Cause::Pin => "PIN",
+            Cause::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Count the reset by `cause`, returning the resets since power-on, 0 for the power-on itself.
+/// SRAM may keep its contents through a short power cut, so the count restarts on the BOR flag
+/// rather than when the slot is found invalid only.
+pub fn count(cause: Cause) -> u32 {
+    // SAFETY: called once at startup, nothing else accesses the slot
+    let count = match unsafe { COUNT.load() } {
+        Some(count) if cause != Cause::BrownOut => count.wrapping_add(1),
+        _ => 0,
+    };
+    // SAFETY: as above
+    unsafe { COUNT.store(count) };
+    count
+}
+
+/// Write the `$PBRIDGE,RESET,<cause>,<count>*hh` sentence including line ending, `count` as
+/// returned by [`count`]
+pub fn write_sentence(out: &mut impl Write, cause: Cause, count: u32) -> fmt::Result {
+    let mut body = String::<40>::new();
+    write!(body, "PBRIDGE,RESET,{},{}", cause.name(), count)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Program [`BOR_LEVEL`] unless already set. Loading the new option bytes resets the MCU, so
+/// this doesn't return if the level changes.
+pub fn set_bor_level(flash: &FLASH) {
+    if flash.optr.read().bor_lev().bits() == BOR_LEVEL {
+        return;
+    }
+    flash::unlocked(flash, |flash| {
+        flash::unlock_options(flash);
+        while flash.sr.read().bsy().bit_is_set() {}
+        // SAFETY: BOR_LEV values 0 to 4 are valid
+        flash
+            .optr
+            .modify(|_, w| unsafe { w.bor_lev().bits(BOR_LEVEL) });
+        flash.cr.modify(|_, w| w.optstrt().set_bit());
+        while flash.sr.read().bsy().bit_is_set() {}
+        flash.cr.modify(|_, w| w.obl_launch().set_bit());
+        // The option byte loader resets the MCU
+        loop {
+            cortex_m::asm::nop();
+        }
+    })
+}
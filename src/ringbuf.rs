@@ -0,0 +1,158 @@
+//! Byte ring buffer with a power-of-two capacity `N`.
+//!
+//! Besides single bytes, both ends expose contiguous slices: a producer such as a DMA channel
+//! fills [`RingBuffer::writable`] and then commits what it wrote, a consumer reads
+//! [`RingBuffer::peek`] and then consumes what it used. Read and write positions count up
+//! freely and are masked into the buffer, so all `N` bytes are usable.
+
+use crate::dma::BufferFull;
+
+pub struct RingBuffer<const N: usize> {
+    buffer: [u8; N],
+    /// Total bytes consumed, wrapping
+    read: usize,
+    /// Total bytes committed, wrapping
+    write: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "capacity must be a power of two");
+        N - 1
+    };
+
+    pub const fn new() -> Self {
+        // Evaluate the capacity check for every N in use
+        let _ = Self::MASK;
+        Self {
+            buffer: [0; N],
+            read: 0,
+            write: 0,
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Bytes committed and not consumed yet
+    pub fn len(&self) -> usize {
+        self.write.wrapping_sub(self.read)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Room left for the producer
+    pub fn free(&self) -> usize {
+        N - self.len()
+    }
+
+    /// Append one byte
+    pub fn push(&mut self, byte: u8) -> Result<(), BufferFull> {
+        if self.free() == 0 {
+            return Err(BufferFull);
+        }
+        self.buffer[self.write & Self::MASK] = byte;
+        self.write = self.write.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Append `data` as a whole. Nothing is written and `Err` is returned if it doesn't fit.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), BufferFull> {
+        if data.len() > self.free() {
+            return Err(BufferFull);
+        }
+        let mut data = data;
+        while !data.is_empty() {
+            let writable = self.writable();
+            let count = writable.len().min(data.len());
+            writable[..count].copy_from_slice(&data[..count]);
+            self.commit(count);
+            data = &data[count..];
+        }
+        Ok(())
+    }
+
+    /// Contiguous free space after the write position, up to the end of the buffer. Bytes
+    /// written to it become readable once committed.
+    pub fn writable(&mut self) -> &mut [u8] {
+        let start = self.write & Self::MASK;
+        let end = (start + self.free()).min(N);
+        &mut self.buffer[start..end]
+    }
+
+    /// Make `count` bytes written to [`RingBuffer::writable`] readable, at most the free space
+    pub fn commit(&mut self, count: usize) {
+        self.write = self.write.wrapping_add(count.min(self.free()));
+    }
+
+    /// Remove the oldest byte
+    pub fn pop(&mut self) -> Option<u8> {
+        let byte = *self.peek().first()?;
+        self.consume(1);
+        Some(byte)
+    }
+
+    /// Contiguous readable bytes from the read position, up to the end of the buffer. More may
+    /// follow at the start of the buffer once these are consumed.
+    pub fn peek(&self) -> &[u8] {
+        let start = self.read & Self::MASK;
+        let end = (start + self.len()).min(N);
+        &self.buffer[start..end]
+    }
+
+    /// Release `count` bytes read from [`RingBuffer::peek`], at most the readable length
+    pub fn consume(&mut self, count: usize) {
+        self.read = self.read.wrapping_add(count.min(self.len()));
+    }
+
+    /// Position of the first `byte` at or after the readable byte `from`
+    pub fn find(&self, byte: u8, from: usize) -> Option<usize> {
+        (from..self.len()).find(|&i| self.buffer[self.read.wrapping_add(i) & Self::MASK] == byte)
+    }
+
+    /// Remove `count` readable bytes following the first `offset` ones, which move up to close
+    /// the gap
+    pub fn remove(&mut self, offset: usize, count: usize) {
+        let offset = offset.min(self.len());
+        let count = count.min(self.len() - offset);
+        for i in (0..offset).rev() {
+            let from = self.read.wrapping_add(i) & Self::MASK;
+            let to = self.read.wrapping_add(i + count) & Self::MASK;
+            self.buffer[to] = self.buffer[from];
+        }
+        self.read = self.read.wrapping_add(count);
+    }
+
+    /// Insert `data` after the first `offset` readable bytes, which move down to make room.
+    /// Nothing is inserted and `Err` is returned if it doesn't fit.
+    pub fn insert(&mut self, offset: usize, data: &[u8]) -> Result<(), BufferFull> {
+        if data.len() > self.free() {
+            return Err(BufferFull);
+        }
+        let offset = offset.min(self.len());
+        self.read = self.read.wrapping_sub(data.len());
+        for i in 0..offset {
+            let from = self.read.wrapping_add(i + data.len()) & Self::MASK;
+            let to = self.read.wrapping_add(i) & Self::MASK;
+            self.buffer[to] = self.buffer[from];
+        }
+        for (i, &byte) in data.iter().enumerate() {
+            self.buffer[self.read.wrapping_add(offset + i) & Self::MASK] = byte;
+        }
+        Ok(())
+    }
+
+    /// Drop everything readable
+    pub fn clear(&mut self) {
+        self.read = self.write;
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,343 @@
+//! Routing of the bridge's output to its sinks, set by `ROUTE`.
+//!
+//! Each [`Source`] goes to any combination of the [`Sink`]s built in: sentences forwarded from
+//! the GPS, sentences the bridge synthesizes, RMC and GGA from UBX-NAV-PVT and `$PBRIDGE,POS`,
+//! and the `$PBRIDGE` reports, status, time to first fix, alarms and warnings. By default the
+//! sentences go to every sink and the reports to the host only. Responses to commands go back to
+//! the port of the command, see [`crate::inbox`], binary fix records, dumps and raw bridge mode
+//! always go to the host.
+//!
+//! Every sink has its own queue of whole lines, so a slow sink never holds up another:
+//!
+//! - the host, USART2, queues lines in the DMA TX buffer filled while the other one is sent
+//! - USB, with the `usb` feature, queues [`crate::usb::QUEUE_LEN`] bytes while a terminal has
+//!   the port open, handed to the CDC-ACM port as its packets go out
+//! - the Bluetooth module, with the `ble` feature, queues [`crate::ble::QUEUE_LEN`] bytes
+//!   drained by LPUART1
+//!
+//! Lines are queued by [`Class`], reports ahead of responses to commands ahead of sentences, so
+//! an alarm or an `OK` isn't stuck behind a burst of GSV at 9600 baud. Only what the sink has
+//! started sending goes out before them: the line in progress on USB and the Bluetooth module,
+//! the transfer in progress on the host. A line that doesn't fit first drops lines of lower
+//! classes, then is handled by the sink's [`Overflow`] policy, set by `OVERFLOW`: dropped, the
+//! default, which keeps what is queued complete, or room is made for it by dropping the oldest
+//! lines of its class, which keeps the sink current, or the line is [`Held`] up to a timeout
+//! while the sink sends, which keeps the sink complete without holding up the task routing it.
+//! Dropped lines are counted by `STATS` for each sink. The SD card and the LoRa beacon take
+//! fixes rather than sentences and have their own `LOG` and `LORA` switches.
+
+use crate::stats::STATS;
+use crate::timer;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use heapless::Vec;
+
+/// Producer of output lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Sentences forwarded from the GPS
+    Gps,
+    /// Sentences synthesized by the bridge
+    Synth,
+    /// `$PBRIDGE` reports
+    Report,
+}
+
+impl Source {
+    pub const ALL: [Source; 3] = [Source::Gps, Source::Synth, Source::Report];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Gps => "GPS",
+            Source::Synth => "SYNTH",
+            Source::Report => "REPORT",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|source| name.eq_ignore_ascii_case(source.as_str().as_bytes()))
+    }
+
+    /// Priority of the source's lines
+    pub fn class(&self) -> Class {
+        match self {
+            Source::Gps | Source::Synth => Class::Bulk,
+            Source::Report => Class::Alarm,
+        }
+    }
+}
+
+/// Priority class of a line, highest first. A sink sends a line ahead of the lines of lower
+/// classes it has queued, and drops them to make room for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// `$PBRIDGE` reports, alarms and warnings
+    Alarm,
+    /// Responses to commands
+    Response,
+    /// Sentences, binary fix records and raw GPS data
+    Bulk,
+}
+
+impl Class {
+    pub const ALL: [Class; 3] = [Class::Alarm, Class::Response, Class::Bulk];
+
+    /// Classes of lower priority, lowest first
+    pub fn lower(self) -> impl Iterator<Item = Class> {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .take_while(move |&class| class != self)
+    }
+}
+
+/// Consumer of output lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    /// USART2
+    Host,
+    /// USB virtual COM port
+    Usb,
+    /// Bluetooth serial module on LPUART1
+    Ble,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 3] = [Sink::Host, Sink::Usb, Sink::Ble];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Sink::Host => "HOST",
+            Sink::Usb => "USB",
+            Sink::Ble => "BLE",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|sink| name.eq_ignore_ascii_case(sink.as_str().as_bytes()))
+            .filter(Sink::is_built)
+    }
+
+    /// The sink's feature is enabled
+    pub fn is_built(&self) -> bool {
+        match self {
+            Sink::Host => true,
+            Sink::Usb => cfg!(feature = "usb"),
+            Sink::Ble => cfg!(feature = "ble"),
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// Set of sinks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sinks(u8);
+
+impl Sinks {
+    pub const NONE: Sinks = Sinks(0);
+
+    /// Every sink built in
+    pub fn all() -> Self {
+        Sink::ALL
+            .into_iter()
+            .filter(Sink::is_built)
+            .fold(Self::NONE, |sinks, sink| sinks.with(sink))
+    }
+
+    pub fn with(self, sink: Sink) -> Self {
+        Self(self.0 | sink.bit())
+    }
+
+    pub fn contains(&self, sink: Sink) -> bool {
+        self.0 & sink.bit() != 0
+    }
+
+    /// Parse a comma separated list of sink names, or `NONE`
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        if word.eq_ignore_ascii_case(b"NONE") {
+            return Some(Self::NONE);
+        }
+        word.split(|&byte| byte == b',')
+            .try_fold(Self::NONE, |sinks, name| {
+                Sink::from_name(name).map(|sink| sinks.with(sink))
+            })
+    }
+
+    /// Write the sinks as a comma separated list, or `NONE`
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        if *self == Self::NONE {
+            return out.write_str("NONE");
+        }
+        let mut separator = "";
+        for sink in Sink::ALL.into_iter().filter(|sink| self.contains(*sink)) {
+            write!(out, "{}{}", separator, sink.as_str())?;
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+/// Mask of every sink, those not built in are left out when read
+const ALL_SINKS: u8 = (1 << Sink::ALL.len()) - 1;
+
+const HOST_ONLY: u8 = 1 << Sink::Host as u8;
+
+/// Sinks of each source until set
+static ROUTES: [AtomicU8; Source::ALL.len()] = [
+    AtomicU8::new(ALL_SINKS),
+    AtomicU8::new(ALL_SINKS),
+    AtomicU8::new(HOST_ONLY),
+];
+
+/// What a sink does with a line that doesn't fit its queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the line, what is queued goes out complete
+    DropNewest,
+    /// Drop the oldest lines of the line's class not being sent until it fits
+    DropOldest,
+    /// Wait up to this many milliseconds for the sink to make room, then drop the line
+    Block(u16),
+}
+
+impl Overflow {
+    /// Longest wait of [`Overflow::Block`]
+    pub const MAX_BLOCK_MS: u16 = 100;
+
+    /// Write the policy as `NEWEST`, `OLDEST` or `BLOCK:<ms>`
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        match self {
+            Overflow::DropNewest => out.write_str("NEWEST"),
+            Overflow::DropOldest => out.write_str("OLDEST"),
+            Overflow::Block(ms) => write!(out, "BLOCK:{}", ms),
+        }
+    }
+
+    /// Kind in the low byte, the wait of [`Overflow::Block`] above it, so a policy is changed
+    /// by a single store
+    const fn to_bits(self) -> u32 {
+        match self {
+            Overflow::DropNewest => 0,
+            Overflow::DropOldest => 1,
+            Overflow::Block(ms) => 2 | (ms as u32) << 8,
+        }
+    }
+
+    const fn from_bits(bits: u32) -> Self {
+        match bits & 0xFF {
+            1 => Overflow::DropOldest,
+            2 => Overflow::Block((bits >> 8) as u16),
+            _ => Overflow::DropNewest,
+        }
+    }
+}
+
+/// Longest line a sink set to [`Overflow::Block`] holds, a response, a longer one is dropped
+pub const MAX_HELD_LEN: usize = 160;
+
+/// Line a sink set to [`Overflow::Block`] holds while it waits for room. The sink queues it as
+/// soon as it has sent enough, and SysTick drops it once the wait is over, so no task waits for
+/// the sink under its lock, see [`crate::sync`]. Lines of its class and lower ones routed to the
+/// sink meanwhile are dropped, so they don't overtake it.
+pub struct Held {
+    /// Empty while no line is held
+    line: Vec<u8, MAX_HELD_LEN>,
+    class: Class,
+    /// [`crate::timer::now_ms`] the wait ends at
+    deadline_ms: u32,
+}
+
+impl Held {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            class: Class::Bulk,
+            deadline_ms: 0,
+        }
+    }
+
+    /// Hold `line` of `class` for up to `ms`, rounded up to a tick, false if another line is held
+    /// or it is too long
+    pub fn hold(&mut self, class: Class, line: &[u8], ms: u16) -> bool {
+        if !self.line.is_empty() || self.line.extend_from_slice(line).is_err() {
+            return false;
+        }
+        self.class = class;
+        self.deadline_ms = timer::now_ms().wrapping_add(u32::from(ms));
+        true
+    }
+
+    /// A line of `class` routed now would overtake the line held
+    pub fn blocks(&self, class: Class) -> bool {
+        !self.line.is_empty() && class as usize >= self.class as usize
+    }
+
+    /// The line held and its class
+    pub fn line(&self) -> Option<(Class, &[u8])> {
+        (!self.line.is_empty()).then_some((self.class, &self.line[..]))
+    }
+
+    /// Take the line held to queue it
+    pub fn take(&mut self) -> Option<(Class, Vec<u8, MAX_HELD_LEN>)> {
+        (!self.line.is_empty()).then(|| (self.class, core::mem::take(&mut self.line)))
+    }
+
+    /// Forget the line held, returns its length
+    pub fn release(&mut self) -> usize {
+        let len = self.line.len();
+        self.line.clear();
+        len
+    }
+
+    /// Drop the line held, counting it for `sink`, once its wait is over at `now_ms`
+    pub fn on_tick(&mut self, sink: Sink, now_ms: u32) {
+        if !self.line.is_empty() && now_ms.wrapping_sub(self.deadline_ms) < u32::MAX / 2 {
+            record_drop(sink, 1, self.release());
+        }
+    }
+}
+
+impl Default for Held {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Overflow policy of each sink until set
+static OVERFLOWS: [AtomicU32; Sink::ALL.len()] = [
+    AtomicU32::new(Overflow::DropNewest.to_bits()),
+    AtomicU32::new(Overflow::DropNewest.to_bits()),
+    AtomicU32::new(Overflow::DropNewest.to_bits()),
+];
+
+/// Sinks `source` goes to
+pub fn route(source: Source) -> Sinks {
+    Sinks(ROUTES[source as usize].load(Ordering::Relaxed) & Sinks::all().0)
+}
+
+/// Send the next lines of `source` to `sinks`
+pub fn set_route(source: Source, sinks: Sinks) {
+    ROUTES[source as usize].store(sinks.0, Ordering::Relaxed);
+}
+
+pub fn overflow(sink: Sink) -> Overflow {
+    Overflow::from_bits(OVERFLOWS[sink as usize].load(Ordering::Relaxed))
+}
+
+/// Handle the next lines that don't fit the queue of `sink` by `overflow`
+pub fn set_overflow(sink: Sink, overflow: Overflow) {
+    OVERFLOWS[sink as usize].store(overflow.to_bits(), Ordering::Relaxed);
+}
+
+/// Count `lines` of `bytes` in all dropped by `sink`
+pub fn record_drop(sink: Sink, lines: u32, bytes: usize) {
+    STATS.dropped_lines[sink as usize].add(lines);
+    STATS.dropped_bytes.add(bytes as u32);
+}
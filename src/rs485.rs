@@ -0,0 +1,233 @@
+//! RS-485 half-duplex host link on USART2, enabled by the `rs485` feature.
+//!
+//! The transceiver's driver enable (DE) is driven from the board's [`DriverEnable`] pin, either by
+//! the USART's DEM hardware on a USART2_DE pin (PA1, alternate function 7) or as a GPIO output
+//! around each DMA transfer. DE is asserted [`ASSERT_US`] before the first start bit and
+//! deasserted [`DEASSERT_US`] after the last stop bit, `BRIDGE_RS485_ASSERT_US` and
+//! `BRIDGE_RS485_DEASSERT_US` at build time. The DEM hardware counts them in sample times, an
+//! eighth of a bit with the oversampling of [`crate::baud`], up to 31, so they are rounded up
+//! and cut to about 4 bits. The receiver enable is expected to be tied to the inverted DE, so the
+//! bridge doesn't hear itself.
+//!
+//! With an address set by `RS485 <address>`, or `BRIDGE_RS485_ADDRESS` at build time, several
+//! bridges share one bus. The host selects a bridge with an address byte, `0x80` plus the
+//! address, which NMEA and commands, being ASCII, never contain. Only the selected bridge takes
+//! the bytes that follow and transmits, the others drop their input and hold their output, which
+//! is dropped like that of a slow host once the TX buffers are full. The host has to wait for the
+//! selected bridge to fall silent before selecting another. Without an address the bridge takes
+//! every byte and transmits whenever it has something to send.
+//!
+//! In the polled mode of [`crate::poll`] being selected isn't enough: the bridge transmits in
+//! turns, a poll or the response to a command, started by [`begin_turn`] and ended by
+//! [`end_turn`] once the DMA transfer holding their last bytes has started.
+
+#[cfg(feature = "flow-control")]
+compile_error!("feature `rs485` replaces RTS/CTS, it can't be combined with `flow-control`");
+
+use crate::board::{self, DriverEnable, PINS};
+use crate::chip::pac::{usart1, GPIOA, USART2};
+use crate::clocks;
+use crate::defaults;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// DE lead time before the first start bit, `BRIDGE_RS485_ASSERT_US`
+pub const ASSERT_US: u32 = defaults::RS485_ASSERT_US;
+
+/// DE hold time after the last stop bit, `BRIDGE_RS485_DEASSERT_US`
+pub const DEASSERT_US: u32 = defaults::RS485_DEASSERT_US;
+
+/// Highest address, the lower 7 bits of an address byte
+pub const MAX_ADDRESS: u8 = 0x7F;
+
+/// Mark of an address byte
+const ADDRESS_MARK: u8 = 0x80;
+
+/// Longest DEAT and DEDT in sample times
+const MAX_SAMPLES: u32 = 31;
+
+/// Samples per bit with OVER8 set
+const SAMPLES_PER_BIT: u32 = 8;
+
+/// Bus address, 0 takes every byte
+static ADDRESS: AtomicU8 = AtomicU8::new(defaults::RS485_ADDRESS);
+
+/// The host's last address byte selected this bridge
+static SELECTED: AtomicBool = AtomicBool::new(false);
+
+/// Transmission in turns, see [`crate::poll`]
+static POLLED: AtomicBool = AtomicBool::new(false);
+
+/// A turn is in progress in polled mode
+static TURN: AtomicBool = AtomicBool::new(false);
+
+/// The turn ends with the next transfer started
+static TURN_ENDING: AtomicBool = AtomicBool::new(false);
+
+/// Bus address, `None` without addressing
+pub fn address() -> Option<u8> {
+    Some(ADDRESS.load(Ordering::Relaxed)).filter(|&address| address != 0)
+}
+
+/// Set or clear the bus address, clearing it leaves polled mode. The bridge stays selected
+/// until the host selects another.
+pub fn set_address(address: Option<u8>) {
+    ADDRESS.store(address.unwrap_or(0), Ordering::Relaxed);
+    if address.is_none() {
+        set_polled(false);
+    }
+}
+
+pub fn is_polled() -> bool {
+    POLLED.load(Ordering::Relaxed)
+}
+
+/// Enter or leave polled mode, which needs an address
+pub fn set_polled(polled: bool) {
+    POLLED.store(polled && address().is_some(), Ordering::Relaxed);
+    TURN.store(false, Ordering::Relaxed);
+    TURN_ENDING.store(false, Ordering::Relaxed);
+}
+
+/// The bridge may transmit: it has no address, or is selected and in its turn when polled
+pub fn may_transmit() -> bool {
+    if address().is_none() {
+        return true;
+    }
+    SELECTED.load(Ordering::Relaxed) && (!is_polled() || TURN.load(Ordering::Relaxed))
+}
+
+/// Start a turn of polled mode, what is queued may be sent
+pub fn begin_turn() {
+    TURN_ENDING.store(false, Ordering::Relaxed);
+    TURN.store(true, Ordering::Relaxed);
+}
+
+/// End the turn once what is queued has been handed to DMA, the TX queue has to be flushed
+pub fn end_turn() {
+    TURN_ENDING.store(true, Ordering::Relaxed);
+}
+
+/// Called by the TX DMA channel when it could start a transfer, ending a turn that was ending
+pub fn on_transfer_start() {
+    if TURN_ENDING.swap(false, Ordering::Relaxed) {
+        TURN.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Route DE to its pin, deasserted, and set the DE timing for `baud`. GPIOA clock must be
+/// enabled and USART2 disabled.
+pub fn init(gpioa: &GPIOA, usart2: &usart1::RegisterBlock, baud: u32) {
+    match PINS.host_de {
+        DriverEnable::Usart(pin) => {
+            board::set_alternate(gpioa, pin);
+            // DE active high
+            usart2
+                .cr3
+                .modify(|_, w| w.dem().set_bit().dep().clear_bit());
+            write_timing(usart2, baud);
+        }
+        DriverEnable::Gpio(pin) => {
+            // SAFETY: resets the DE pin only
+            gpioa.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+            // SAFETY: only the two bits of the pin are changed, 0b01 is output mode
+            gpioa.moder.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b11 << (2 * pin)) | 0b01 << (2 * pin))
+            });
+        }
+    }
+}
+
+/// Scale the DEM timing to a new baud. USART2 must be done sending, it is briefly disabled.
+pub fn set_baud(usart2: &usart1::RegisterBlock, baud: u32) {
+    if let DriverEnable::Usart(_) = PINS.host_de {
+        // DEAT and DEDT can only be written while the USART is disabled
+        usart2.cr1.modify(|_, w| w.ue().clear_bit());
+        write_timing(usart2, baud);
+        usart2.cr1.modify(|_, w| w.ue().set_bit());
+    }
+}
+
+/// What became of a received byte, see [`on_receive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    /// The byte is for this bridge
+    Taken,
+    /// The byte is for another bridge, or an address byte
+    Dropped,
+    /// An address byte selected this bridge, its held output can be sent
+    Selected,
+}
+
+/// Filter a received byte: address bytes select or deselect the bridge, other bytes are taken
+/// while selected
+pub fn on_receive(byte: u8) -> Received {
+    let Some(address) = address() else {
+        return Received::Taken;
+    };
+    if byte & ADDRESS_MARK == 0 {
+        return match SELECTED.load(Ordering::Relaxed) {
+            true => Received::Taken,
+            false => Received::Dropped,
+        };
+    }
+    let selected = byte & !ADDRESS_MARK == address;
+    match SELECTED.swap(selected, Ordering::Relaxed) {
+        false if selected => Received::Selected,
+        _ => Received::Dropped,
+    }
+}
+
+/// Assert a GPIO driver enable before DMA starts a transfer, waiting [`ASSERT_US`] if it was
+/// deasserted. The DEM hardware needs nothing.
+pub fn before_transmit() {
+    let DriverEnable::Gpio(pin) = PINS.host_de else {
+        return;
+    };
+    // SAFETY: ODR is only read, BSRR writes are atomic and only affect the DE pin
+    let gpioa = unsafe { &*GPIOA::ptr() };
+    if gpioa.odr.read().bits() & 1 << pin != 0 {
+        return;
+    }
+    gpioa.bsrr.write(|w| unsafe { w.bits(1 << pin) });
+    cortex_m::asm::delay(clocks::SYSCLK_HZ / 1_000_000 * ASSERT_US);
+    // The transfer starting also enables the TC interrupt deasserting it
+    cortex_m::interrupt::free(|_| {
+        // SAFETY: CR1 is modified in a critical section, USART2 also changes it from its task
+        let usart2 = unsafe { &*USART2::ptr() };
+        usart2.cr1.modify(|_, w| w.tcie().set_bit());
+    });
+}
+
+/// Checked on every interrupt of the host port: deassert a GPIO driver enable [`DEASSERT_US`]
+/// after the last stop bit once DMA is no longer `sending`. Returns true when it was deasserted,
+/// for the TC interrupt to be disabled unless a baud change waits for it.
+pub fn on_transmit_complete(usart2: &usart1::RegisterBlock, sending: bool) -> bool {
+    let DriverEnable::Gpio(pin) = PINS.host_de else {
+        return false;
+    };
+    if sending || usart2.isr.read().tc().bit_is_clear() {
+        return false;
+    }
+    // SAFETY: ODR is only read, BSRR writes are atomic and only affect the DE pin
+    let gpioa = unsafe { &*GPIOA::ptr() };
+    if gpioa.odr.read().bits() & 1 << pin == 0 {
+        return false;
+    }
+    cortex_m::asm::delay(clocks::SYSCLK_HZ / 1_000_000 * DEASSERT_US);
+    gpioa.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+    true
+}
+
+/// Program DEAT and DEDT, rounded up to sample times at `baud`
+fn write_timing(usart2: &usart1::RegisterBlock, baud: u32) {
+    let samples = |us: u32| {
+        let samples = (u64::from(us) * u64::from(SAMPLES_PER_BIT * baud)).div_ceil(1_000_000);
+        samples.min(MAX_SAMPLES.into()) as u8
+    };
+    usart2.cr1.modify(|_, w| {
+        w.deat()
+            .bits(samples(ASSERT_US))
+            .dedt()
+            .bits(samples(DEASSERT_US))
+    });
+}
@@ -0,0 +1,250 @@
+//! Real-time clock disciplined from GPS time.
+//!
+//! The RTC runs from the 32.768kHz LSE crystal in the backup domain. It is set from the first
+//! valid RMC and then shifted onto the second boundary at each PPS edge, so between GPS updates
+//! it only drifts by the crystal tolerance. `SSR` counts [`SUBSECOND_TICKS`] per second.
+
+use crate::chip::pac::{EXTI, PWR, RCC, RTC};
+use crate::nmea::{Date, Time};
+
+/// Asynchronous and synchronous prescalers dividing LSE into 1Hz, with a 4096Hz subsecond clock
+const PREDIV_A: u32 = 7;
+const PREDIV_S: u32 = 4095;
+
+pub const SUBSECOND_TICKS: u32 = PREDIV_S + 1;
+
+const MS_PER_DAY: u32 = 24 * 60 * 60 * 1000;
+
+/// RTC time more than this far from GPS time is set again rather than shifted
+const MAX_OFFSET_MS: u32 = 500;
+
+pub struct Rtc {
+    rtc: RTC,
+    /// Calendar set from GPS time since boot
+    synced: bool,
+}
+
+impl Rtc {
+    /// Start the LSE and clock the RTC from it, unless it kept running in the backup domain
+    /// through reset. This waits for the crystal to start, which can take a second or two.
+    /// PWR clock must be enabled.
+    pub fn new(rtc: RTC, rcc: &RCC, pwr: &PWR) -> Self {
+        // Backup domain is write protected after reset
+        pwr.cr1.modify(|_, w| w.dbp().set_bit());
+        let bdcr = rcc.bdcr.read();
+        if bdcr.rtcen().bit_is_clear() || !bdcr.rtcsel().is_lse() {
+            rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+            while rcc.bdcr.read().lserdy().bit_is_clear() {}
+            rcc.bdcr.modify(|_, w| w.rtcsel().lse().rtcen().set_bit());
+        }
+
+        let rtc = Self { rtc, synced: false };
+        if rtc.rtc.isr.read().inits().bit_is_clear() {
+            // Calendar was never set, prescalers can only be written in initialization mode
+            rtc.initialize(|rtc| {
+                // SAFETY: values fit the 7 and 15 bit fields, written separately as required
+                rtc.prer.write(|w| unsafe { w.bits(PREDIV_S) });
+                rtc.prer
+                    .write(|w| unsafe { w.bits(PREDIV_A << 16 | PREDIV_S) });
+            });
+        }
+        rtc
+    }
+
+    /// Calendar was set from GPS time since boot
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// The subsecond counter, counting down within each second
+    pub fn subseconds(&self) -> u32 {
+        self.read().0
+    }
+
+    /// Current UTC date and time, once the calendar has been set. It may have been set before
+    /// a reset and not been synchronized since, see [`Rtc::is_synced`].
+    pub fn now(&self) -> Option<(Date, Time)> {
+        if self.rtc.isr.read().inits().bit_is_clear() {
+            return None;
+        }
+        let (ss, tr, dr) = self.read();
+        let date = Date {
+            year: 2000 + u16::from(from_bcd(dr >> 16)),
+            month: from_bcd(dr >> 8 & 0x1F),
+            day: from_bcd(dr & 0x3F),
+        };
+        let time = Time {
+            hour: from_bcd(tr >> 16 & 0x3F),
+            minute: from_bcd(tr >> 8 & 0x7F),
+            second: from_bcd(tr & 0x7F),
+            millisecond: (PREDIV_S.saturating_sub(ss) * 1000 / SUBSECOND_TICKS) as u16,
+        };
+        Some((date, time))
+    }
+
+    /// Check the RTC against the date and time of a valid RMC. `since_pulse_us` is the time
+    /// elapsed since the PPS edge the RMC refers to, if there was one in the last second.
+    /// The calendar is set if it was not synchronized yet or is off by more than
+    /// [`MAX_OFFSET_MS`], otherwise PPS edges keep it aligned.
+    pub fn on_rmc(&mut self, date: Date, time: Time, since_pulse_us: Option<u64>) {
+        let since_pulse_ms = since_pulse_us.map_or(0, |us| (us / 1000) as u32);
+        let gps_ms = (ms_of_day(&time) + since_pulse_ms) % MS_PER_DAY;
+        let in_sync = self.synced
+            && self.now().is_some_and(|(rtc_date, rtc_time)| {
+                let offset = ms_of_day(&rtc_time).abs_diff(gps_ms);
+                // The GPS date may already be behind the RTC date just after midnight
+                let date_ok = rtc_date == date || gps_ms < ms_of_day(&time);
+                offset.min(MS_PER_DAY - offset) <= MAX_OFFSET_MS && date_ok
+            });
+        if in_sync {
+            return;
+        }
+        self.set(&date, &time);
+        if let Some(us) = since_pulse_us {
+            // The calendar starts at the RMC time, advance it by the time since the pulse
+            self.shift(ticks(us) as i32);
+        }
+        self.synced = true;
+    }
+
+    /// Align the subseconds with a PPS edge which occurred `latency_us` ago
+    pub fn on_pulse(&mut self, latency_us: u64) {
+        if !self.synced {
+            return;
+        }
+        let (ss, _, _) = self.read();
+        // Ticks elapsed in the current second, which should be the latency
+        let elapsed = PREDIV_S.saturating_sub(ss);
+        let ahead =
+            (elapsed + SUBSECOND_TICKS - ticks(latency_us) % SUBSECOND_TICKS) % SUBSECOND_TICKS;
+        // Take the shorter way to the second boundary
+        if ahead < SUBSECOND_TICKS / 2 {
+            self.shift(-(ahead as i32));
+        } else {
+            self.shift((SUBSECOND_TICKS - ahead) as i32);
+        }
+    }
+
+    /// Raise the RTC_WKUP interrupt every `period_s` seconds, through EXTI line 20 so it also
+    /// wakes the core from Stop mode
+    pub fn enable_wakeup(&mut self, exti: &EXTI, period_s: u16) {
+        self.unlocked(|rtc| {
+            rtc.cr.modify(|_, w| w.wute().clear_bit());
+            while rtc.isr.read().wutwf().bit_is_clear() {}
+            // SAFETY: any 16 bit value is a valid period
+            rtc.wutr
+                .write(|w| unsafe { w.wut().bits(period_s.saturating_sub(1)) });
+            // SAFETY: 0b100 clocks the wakeup timer from the 1Hz ck_spre
+            rtc.cr.modify(|_, w| unsafe {
+                w.wucksel().bits(0b100).wutie().set_bit().wute().set_bit()
+            });
+        });
+        exti.imr1.modify(|_, w| w.mr20().set_bit());
+        exti.rtsr1.modify(|_, w| w.tr20().set_bit());
+    }
+
+    /// Handle the RTC_WKUP interrupt
+    pub fn on_wakeup(&mut self) {
+        self.rtc.isr.modify(|_, w| w.wutf().clear_bit());
+        // SAFETY: PR1 is write-1-to-clear, only line 20 is affected
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.pr1.write(|w| w.pr20().set_bit());
+    }
+
+    /// Enter initialization mode with write protection disabled, run `f` and resume counting
+    fn initialize(&self, f: impl FnOnce(&RTC)) {
+        self.unlocked(|rtc| {
+            rtc.isr.modify(|_, w| w.init().set_bit());
+            while rtc.isr.read().initf().bit_is_clear() {}
+            f(rtc);
+            rtc.isr.modify(|_, w| w.init().clear_bit());
+        });
+    }
+
+    fn unlocked(&self, f: impl FnOnce(&RTC)) {
+        // SAFETY: key sequence from reference manual 38.3.8, any other value locks again
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xCA) });
+        self.rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+        f(&self.rtc);
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xFF) });
+    }
+
+    /// Set the calendar, the subseconds restart from zero
+    fn set(&self, date: &Date, time: &Time) {
+        let tr = to_bcd(time.hour) << 16 | to_bcd(time.minute) << 8 | to_bcd(time.second);
+        let dr = to_bcd((date.year % 100) as u8) << 16
+            | u32::from(weekday(date)) << 13
+            | to_bcd(date.month) << 8
+            | to_bcd(date.day);
+        self.initialize(|rtc| {
+            // SAFETY: BCD values of a valid time and date, 24 hour format
+            rtc.tr.write(|w| unsafe { w.bits(tr) });
+            rtc.dr.write(|w| unsafe { w.bits(dr) });
+        });
+    }
+
+    /// Advance the clock by `ticks` subseconds if positive, delay it if negative, less than a
+    /// second either way. Skipped while a previous shift is still in progress.
+    fn shift(&self, ticks: i32) {
+        let magnitude = ticks.unsigned_abs();
+        if magnitude == 0 || magnitude >= SUBSECOND_TICKS {
+            return;
+        }
+        let shiftr = if ticks > 0 {
+            // Adding a second and subtracting the remainder advances by `ticks`
+            1 << 31 | (SUBSECOND_TICKS - magnitude)
+        } else {
+            magnitude
+        };
+        if self.rtc.isr.read().shpf().bit_is_set() {
+            return;
+        }
+        self.unlocked(|rtc| {
+            // SAFETY: ADD1S and a SUBFS value below SUBSECOND_TICKS
+            rtc.shiftr.write(|w| unsafe { w.bits(shiftr) });
+        });
+    }
+
+    /// Read SSR, TR and DR. Reading SSR freezes the shadow registers until DR is read.
+    fn read(&self) -> (u32, u32, u32) {
+        // Shadow registers are resynchronized after initialization and shifts
+        while self.rtc.isr.read().rsf().bit_is_clear() {}
+        let ss = self.rtc.ssr.read().bits();
+        let tr = self.rtc.tr.read().bits();
+        let dr = self.rtc.dr.read().bits();
+        (ss, tr, dr)
+    }
+}
+
+/// Subsecond ticks in `us` microseconds
+fn ticks(us: u64) -> u32 {
+    (us * u64::from(SUBSECOND_TICKS) / 1_000_000) as u32
+}
+
+fn ms_of_day(time: &Time) -> u32 {
+    ((u32::from(time.hour) * 60 + u32::from(time.minute)) * 60 + u32::from(time.second)) * 1000
+        + u32::from(time.millisecond)
+}
+
+fn to_bcd(value: u8) -> u32 {
+    u32::from(value / 10) << 4 | u32::from(value % 10)
+}
+
+fn from_bcd(bcd: u32) -> u8 {
+    ((bcd >> 4 & 0xF) * 10 + (bcd & 0xF)) as u8
+}
+
+/// Day of the week, Monday is 1 as in RTC_DR
+fn weekday(date: &Date) -> u8 {
+    // Sakamoto's method, 0 is Sunday
+    const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = date.year - u16::from(date.month < 3);
+    let month = usize::from(date.month.clamp(1, 12) - 1);
+    let day =
+        (year + year / 4 - year / 100 + year / 400 + OFFSETS[month] + u16::from(date.day)) % 7;
+    if day == 0 {
+        7
+    } else {
+        day as u8
+    }
+}
@@ -0,0 +1,88 @@
+//! Duty cycling of the GPS for battery powered tracking, set by `SCHEDULE <on_s> <off_s>`.
+//!
+//! The GPS is turned on for a window of up to `on_s` seconds. The first fix passing the
+//! [`crate::quality`] gate ends the window early, once it has been logged like any other fix, and
+//! the GPS is turned off for `off_s` seconds before the next window. With the GPS off the MCU
+//! spends the off time in Stop mode, see [`crate::power`].
+//!
+//! The schedule is driven by the RTC wakeup interrupt rather than SysTick, which halts in Stop
+//! mode: [`Schedule::on_wakeup`] runs every [`crate::watchdog::SERVICE_PERIOD_S`]. Stop 1 is kept
+//! rather than Stop 2 so the host can still wake the bridge during the off time. The fix left
+//! over from the previous window is dropped when the GPS is turned on, see [`crate::ttff`].
+//! `PWR` and the button still switch the GPS, until the next window starts or ends.
+
+/// Longest on window accepted, well beyond a cold start
+pub const MAX_ON_S: u32 = 3600;
+
+/// Longest off time accepted
+pub const MAX_OFF_S: u32 = 86_400;
+
+/// Shortest on window accepted, a hot start takes about a second
+pub const MIN_ON_S: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Windows {
+    /// Longest time the GPS is on waiting for a fix
+    pub on_s: u32,
+    /// Time the GPS is off between windows
+    pub off_s: u32,
+}
+
+pub struct Schedule {
+    windows: Option<Windows>,
+    /// The GPS is in its on window
+    on: bool,
+    /// Seconds since the window or the off time started
+    elapsed_s: u32,
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Self {
+            windows: None,
+            on: false,
+            elapsed_s: 0,
+        }
+    }
+
+    pub fn windows(&self) -> Option<Windows> {
+        self.windows
+    }
+
+    /// The GPS is in its on window, false without a schedule
+    pub fn is_on(&self) -> bool {
+        self.windows.is_some() && self.on
+    }
+
+    /// Start duty cycling with an on window, or stop it leaving the GPS as it is. Returns the
+    /// GPS power to switch to.
+    pub fn set(&mut self, windows: Option<Windows>) -> Option<bool> {
+        self.windows = windows;
+        self.elapsed_s = 0;
+        self.on = windows.is_some();
+        windows.map(|_| true)
+    }
+
+    /// Advance by `elapsed_s` seconds, `fix_passes` when the current fix passes the quality
+    /// gate. Returns the GPS power to switch to when a window starts or ends.
+    pub fn on_wakeup(&mut self, elapsed_s: u32, fix_passes: bool) -> Option<bool> {
+        let windows = self.windows?;
+        self.elapsed_s = self.elapsed_s.saturating_add(elapsed_s);
+        let done = match self.on {
+            true => fix_passes || self.elapsed_s >= windows.on_s,
+            false => self.elapsed_s >= windows.off_s,
+        };
+        if !done {
+            return None;
+        }
+        self.on = !self.on;
+        self.elapsed_s = 0;
+        Some(self.on)
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,472 @@
+//! Track logging to a FAT formatted SD card on SPI1, enabled by the `sd-log` feature.
+//!
+//! Each fix with a date is appended to a file named after its UTC date, `YYMMDD.CSV` or
+//! `YYMMDD.GPX`, so files rotate at midnight. GPX files are kept well formed by rewriting the
+//! closing tags after every point. The file is flushed after each fix, so at most the fix being
+//! written is lost on power loss.
+//!
+//! Pins: PB3 SCK (D13), PB4 MISO (D12), PB5 MOSI (D11) as alternate function 5, PB1 CS (D6).
+//! Card access blocks, so it runs in idle where interrupts preempt it, fed by [`Track`].
+
+use crate::chip::pac::{GPIOB, RCC, SPI1};
+use crate::clocks;
+use crate::cmd::Decimal;
+use crate::fix::GpsFix;
+use crate::nmea::{Date, Time};
+use core::convert::Infallible;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use embedded_sdmmc::{
+    Mode, RawDirectory, RawFile, RawVolume, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager,
+};
+use heapless::{Deque, String};
+
+/// Fixes waiting for idle to write them
+pub const QUEUE_LEN: usize = 8;
+
+/// Chip select pin on GPIOB
+const CS_PIN: u32 = 1;
+
+/// SPI clock during card initialization must not exceed 400kHz
+const INIT_BR: u8 = 0b111;
+
+/// Fastest SPI clock up to 20MHz once the card is initialized, BR divides PCLK2 by 2^(BR+1)
+const FAST_BR: u8 = {
+    let mut br = 0;
+    while br < 7 && clocks::PCLK2_HZ >> (br + 1) > 20_000_000 {
+        br += 1;
+    }
+    br
+};
+
+const CSV_HEADER: &str = "time,lat,lon,alt_m,speed_mps,course_deg,sats,hdop\r\n";
+const GPX_HEADER: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n",
+    "<gpx version=\"1.1\" creator=\"listen-gps\" xmlns=\"http://www.topografix.com/GPX/1/1\">\r\n",
+    "<trk><trkseg>\r\n",
+);
+const GPX_FOOTER: &str = "</trkseg></trk></gpx>\r\n";
+
+/// Longest line written for a fix
+const MAX_LINE_LEN: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Gpx,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "CSV",
+            Format::Gpx => "GPX",
+        }
+    }
+}
+
+/// The card is missing, isn't FAT formatted or failed a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error;
+
+impl<E: core::fmt::Debug> From<embedded_sdmmc::Error<E>> for Error {
+    fn from(_: embedded_sdmmc::Error<E>) -> Self {
+        Error
+    }
+}
+
+impl From<core::fmt::Error> for Error {
+    fn from(_: core::fmt::Error) -> Self {
+        Error
+    }
+}
+
+/// Logging state shared between the tasks receiving fixes and idle writing them
+pub struct Track {
+    /// Logging is on in this format
+    format: Option<Format>,
+    queue: Deque<GpsFix, QUEUE_LEN>,
+}
+
+impl Track {
+    pub const fn new() -> Self {
+        Self {
+            format: None,
+            queue: Deque::new(),
+        }
+    }
+
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// Start logging in `format`, or stop if `None`
+    pub fn set_format(&mut self, format: Option<Format>) {
+        self.format = format;
+        if format.is_none() {
+            self.queue.clear();
+        }
+    }
+
+    /// Queue a fix for logging if logging is on and the fix is valid and dated.
+    /// The oldest queued fix is dropped when the card falls behind.
+    pub fn push(&mut self, fix: &GpsFix) {
+        if self.format.is_none() || !fix.is_valid() || fix.date.is_none() {
+            return;
+        }
+        if self.queue.is_full() {
+            self.queue.pop_front();
+        }
+        let _ = self.queue.push_back(*fix);
+    }
+
+    /// Next fix to write and its format
+    pub fn pop(&mut self) -> Option<(GpsFix, Format)> {
+        let format = self.format?;
+        self.queue.pop_front().map(|fix| (fix, format))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SPI1 in mode 0 with 8 bit frames, owning the chip select pin
+pub struct Spi {
+    spi1: SPI1,
+    gpiob: GPIOB,
+}
+
+impl Spi {
+    fn transfer_byte(&mut self, byte: u8) -> u8 {
+        while self.spi1.sr.read().txe().bit_is_clear() {}
+        // SAFETY: 8 bit access to DR sends a single frame, a 16 bit access would pack two
+        unsafe { core::ptr::write_volatile(self.spi1.dr.as_ptr() as *mut u8, byte) };
+        while self.spi1.sr.read().rxne().bit_is_clear() {}
+        // SAFETY: as above, FRXTH raises RXNE for each byte
+        unsafe { core::ptr::read_volatile(self.spi1.dr.as_ptr() as *const u8) }
+    }
+
+    fn select(&mut self, selected: bool) {
+        // CS is active low, lower half of BSRR sets pins, upper half resets them
+        let bit = if selected { 1 << 16 } else { 1 } << CS_PIN;
+        // SAFETY: setting or resetting one pin, zero bits have no effect
+        self.gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    fn set_clock(&mut self, br: u8) {
+        self.spi1.cr1.modify(|_, w| w.spe().clear_bit());
+        self.spi1.cr1.modify(|_, w| w.br().bits(br).spe().set_bit());
+    }
+}
+
+impl ErrorType for Spi {
+    type Error = Infallible;
+}
+
+impl SpiDevice for Spi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.select(true);
+        for operation in operations {
+            match operation {
+                Operation::Read(words) => {
+                    for word in words.iter_mut() {
+                        *word = self.transfer_byte(0xFF);
+                    }
+                }
+                Operation::Write(words) => {
+                    for &word in words.iter() {
+                        self.transfer_byte(word);
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    for i in 0..read.len().max(write.len()) {
+                        let byte = self.transfer_byte(write.get(i).copied().unwrap_or(0xFF));
+                        if let Some(word) = read.get_mut(i) {
+                            *word = byte;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(words) => {
+                    for word in words.iter_mut() {
+                        *word = self.transfer_byte(*word);
+                    }
+                }
+                Operation::DelayNs(ns) => Delay.delay_ns(*ns),
+            }
+        }
+        while self.spi1.sr.read().bsy().bit_is_set() {}
+        self.select(false);
+        Ok(())
+    }
+}
+
+/// Busy wait delay at [`clocks::SYSCLK_HZ`]
+pub struct Delay;
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        let cycles = u64::from(ns) * u64::from(clocks::SYSCLK_HZ) / 1_000_000_000;
+        cortex_m::asm::delay(cycles as u32 + 1);
+    }
+}
+
+/// FAT date and time of the fix being written, high and low half
+static FILE_TIME: AtomicU32 = AtomicU32::new(0);
+
+/// Timestamps files with the time of the fix being written rather than reading the RTC
+pub struct FixTime;
+
+impl FixTime {
+    fn set(date: &Date, time: &Time) {
+        let fat_date = (date.year.saturating_sub(1980) << 9)
+            | u16::from(date.month) << 5
+            | u16::from(date.day);
+        let fat_time =
+            u16::from(time.hour) << 11 | u16::from(time.minute) << 5 | u16::from(time.second / 2);
+        FILE_TIME.store(
+            u32::from(fat_date) << 16 | u32::from(fat_time),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl TimeSource for FixTime {
+    fn get_timestamp(&self) -> Timestamp {
+        let packed = FILE_TIME.load(Ordering::Relaxed);
+        Timestamp::from_fat((packed >> 16) as u16, packed as u16)
+    }
+}
+
+type Card = SdCard<Spi, Delay>;
+
+struct OpenFile {
+    volume: RawVolume,
+    dir: RawDirectory,
+    file: RawFile,
+    date: Date,
+    format: Format,
+}
+
+/// Writes queued fixes to the card
+pub struct Logger {
+    /// Only `None` while being reset
+    volume_mgr: Option<VolumeManager<Card, FixTime, 1, 1, 1>>,
+    open: Option<OpenFile>,
+}
+
+impl Logger {
+    /// Configure SPI1 and its pins. The card is only initialized when the first fix is written.
+    /// GPIOB and SPI1 clocks are enabled here.
+    pub fn new(rcc: &RCC, gpiob: GPIOB, spi1: SPI1) -> Self {
+        rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb2enr.modify(|_, w| w.spi1en().set_bit());
+
+        // CS idles high
+        // SAFETY: setting one pin
+        gpiob.bsrr.write(|w| unsafe { w.bits(1 << CS_PIN) });
+        gpiob.moder.modify(|_, w| {
+            w.moder3()
+                .alternate()
+                .moder4()
+                .alternate()
+                .moder5()
+                .alternate()
+                .moder1()
+                .output()
+        });
+        gpiob.ospeedr.modify(|_, w| {
+            w.ospeedr3()
+                .very_high_speed()
+                .ospeedr5()
+                .very_high_speed()
+                .ospeedr1()
+                .very_high_speed()
+        });
+        // The card drives MISO open drain until initialized
+        gpiob.pupdr.modify(|_, w| w.pupdr4().pull_up());
+        gpiob
+            .afrl
+            .modify(|_, w| w.afrl3().af5().afrl4().af5().afrl5().af5());
+
+        // Master with software chip select, 8 bit frames raising RXNE per byte
+        // SAFETY: 0b0111 selects 8 bit frames
+        spi1.cr2
+            .write(|w| unsafe { w.ds().bits(0b0111).frxth().set_bit() });
+        spi1.cr1.write(|w| {
+            w.mstr()
+                .set_bit()
+                .ssm()
+                .set_bit()
+                .ssi()
+                .set_bit()
+                .br()
+                .bits(INIT_BR)
+                .spe()
+                .set_bit()
+        });
+
+        let card = SdCard::new(Spi { spi1, gpiob }, Delay);
+        Self {
+            volume_mgr: Some(VolumeManager::new_with_limits(card, FixTime, 0)),
+            open: None,
+        }
+    }
+
+    /// Append `fix` to the file of its date, opening or rotating files as needed.
+    /// After an error everything is closed and the card initialized again on the next fix.
+    pub fn write(&mut self, fix: &GpsFix, format: Format) -> Result<(), Error> {
+        let result = self.try_write(fix, format);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    /// Close the file, done when logging stops so the card can be removed
+    pub fn close(&mut self) {
+        let (Some(open), Some(volume_mgr)) = (self.open.take(), self.volume_mgr.as_mut()) else {
+            return;
+        };
+        let closed = volume_mgr.close_file(open.file).is_ok()
+            && volume_mgr.close_dir(open.dir).is_ok()
+            && volume_mgr.close_volume(open.volume).is_ok();
+        if !closed {
+            self.reset();
+        }
+    }
+
+    fn try_write(&mut self, fix: &GpsFix, format: Format) -> Result<(), Error> {
+        let (Some(date), Some(time)) = (fix.date, fix.timestamp) else {
+            return Ok(());
+        };
+        FixTime::set(&date, &time);
+        if self
+            .open
+            .as_ref()
+            .is_some_and(|open| open.date != date || open.format != format)
+        {
+            self.close();
+        }
+        let file = match &self.open {
+            Some(open) => open.file,
+            None => self.open_file(date, format)?,
+        };
+        let volume_mgr = self.volume_mgr.as_mut().ok_or(Error)?;
+
+        let mut line = String::<MAX_LINE_LEN>::new();
+        match format {
+            Format::Csv => write_csv(&mut line, fix, &date, &time)?,
+            Format::Gpx => {
+                write_gpx(&mut line, fix, &date, &time)?;
+                line.push_str(GPX_FOOTER).map_err(|_| Error)?;
+                // Overwrite the closing tags, which follow the new point again
+                let length = volume_mgr.file_length(file)?;
+                let offset = length.saturating_sub(GPX_FOOTER.len() as u32);
+                volume_mgr.file_seek_from_start(file, offset)?;
+            }
+        }
+        volume_mgr.write(file, line.as_bytes())?;
+        volume_mgr.flush_file(file)?;
+        Ok(())
+    }
+
+    fn open_file(&mut self, date: Date, format: Format) -> Result<RawFile, Error> {
+        let volume_mgr = self.volume_mgr.as_mut().ok_or(Error)?;
+        if volume_mgr.device().num_bytes().is_ok() {
+            // Initialized, go faster than the initialization clock
+            volume_mgr.device().spi(|spi| spi.set_clock(FAST_BR));
+        }
+        let volume = volume_mgr.open_raw_volume(VolumeIdx(0))?;
+        let dir = volume_mgr.open_root_dir(volume)?;
+        let mut name = String::<12>::new();
+        write!(
+            name,
+            "{:02}{:02}{:02}.{}",
+            date.year % 100,
+            date.month,
+            date.day,
+            format.extension()
+        )?;
+        let file =
+            volume_mgr.open_file_in_dir(dir, name.as_str(), Mode::ReadWriteCreateOrAppend)?;
+        self.open = Some(OpenFile {
+            volume,
+            dir,
+            file,
+            date,
+            format,
+        });
+        if volume_mgr.file_length(file)? == 0 {
+            let header = match format {
+                Format::Csv => CSV_HEADER,
+                Format::Gpx => GPX_HEADER,
+            };
+            volume_mgr.write(file, header.as_bytes())?;
+            if format == Format::Gpx {
+                volume_mgr.write(file, GPX_FOOTER.as_bytes())?;
+            }
+        }
+        Ok(file)
+    }
+
+    /// Drop all handles, which may be stale after a card error, and start over
+    fn reset(&mut self) {
+        self.open = None;
+        if let Some(volume_mgr) = self.volume_mgr.take() {
+            let (card, time) = volume_mgr.free();
+            card.mark_card_uninit();
+            card.spi(|spi| spi.set_clock(INIT_BR));
+            self.volume_mgr = Some(VolumeManager::new_with_limits(card, time, 0));
+        }
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.mmmZ`
+fn write_timestamp(out: &mut impl Write, date: &Date, time: &Time) -> core::fmt::Result {
+    write!(
+        out,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        date.year, date.month, date.day, time.hour, time.minute, time.second, time.millisecond
+    )
+}
+
+fn write_csv(out: &mut impl Write, fix: &GpsFix, date: &Date, time: &Time) -> core::fmt::Result {
+    write_timestamp(out, date, time)?;
+    write!(
+        out,
+        ",{},{},{},{},{},{},{}\r\n",
+        Decimal(fix.lat.into(), 7),
+        Decimal(fix.lon.into(), 7),
+        Decimal(fix.altitude.into(), 3),
+        Decimal(fix.speed.into(), 3),
+        Decimal(fix.course.into(), 2),
+        fix.sats,
+        Decimal(fix.hdop.into(), 2)
+    )
+}
+
+fn write_gpx(out: &mut impl Write, fix: &GpsFix, date: &Date, time: &Time) -> core::fmt::Result {
+    write!(
+        out,
+        "<trkpt lat=\"{}\" lon=\"{}\"><ele>{}</ele><time>",
+        Decimal(fix.lat.into(), 7),
+        Decimal(fix.lon.into(), 7),
+        Decimal(fix.altitude.into(), 3)
+    )?;
+    write_timestamp(out, date, time)?;
+    write!(
+        out,
+        "</time><sat>{}</sat><hdop>{}</hdop></trkpt>\r\n",
+        fix.sats,
+        Decimal(fix.hdop.into(), 2)
+    )
+}
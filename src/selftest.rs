@@ -0,0 +1,243 @@
+//! Self-test of the subsystems the bridge can exercise on its own, run by `SELFTEST` and reported
+//! as passed, failed or skipped each:
+//!
+//! - `UART`: USART1 sends [`UART_PATTERN`] to itself. The USART has no loopback mode, LBDM only
+//!   sets the length of a LIN break, so it is switched to single-wire half-duplex, where the
+//!   receiver listens to its own TX pin, and no jumper is needed. The GPS ignores the pattern,
+//!   which has no `$` or UBX sync byte, and what it sends meanwhile, a few milliseconds at its
+//!   baud, is lost. The USART1 task runs this part, as it owns the USART, see [`request_uart`].
+//! - `QUEUE`: a [`RingBuffer`] is filled to capacity and drained twice, the second time across
+//!   the end of its storage, comparing every byte.
+//! - `FLASH`: [`SCRATCH_PAGE`], reserved for the test before the track log, is erased, checked
+//!   blank, programmed, read back and erased again. Each erase stalls the CPU for about 22ms.
+//! - `RTC`: the subsecond counter advances within [`RTC_WAIT_MS`].
+//! - `POWER`: the GPS power pin is toggled while the GPS is off and must read back both levels,
+//!   the GPS being switched on for about a microsecond. While it is on the item is skipped,
+//!   cutting its power would lose the fix. No current sense is wired, so the supply of the GPS
+//!   isn't checked.
+//!
+//! USART2 hands the test to idle, see [`crate::jobs`], so its waits, about 50ms in all, hold off
+//! no other task, and the UART part holds off those of the USART1 priority for a few
+//! milliseconds. The erases are another matter: on the dual-bank L476 code keeps running from
+//! the first bank while [`SCRATCH_PAGE`], in the second, is erased, but the single-bank L432 and
+//! L452 stall every instruction fetch for each erase, holding off every task, SysTick and USART2
+//! included, so the test may lose a tick or overrun a byte from the host there.
+
+use crate::chip::pac::{usart1, FLASH};
+use crate::clocks;
+use crate::config;
+use crate::flash;
+use crate::hal::PowerPin;
+use crate::ringbuf::RingBuffer;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Flash page erased and programmed by the test, the one before the pages reserved for the track
+/// log, see `memory/*.x`
+pub const SCRATCH_PAGE: usize = config::FIRST_PAGE - config::LOG_PAGES - 1;
+
+/// Bytes USART1 sends itself, none of them starting a sentence or a UBX frame
+pub const UART_PATTERN: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+
+/// Time the RTC subsecond counter is given to advance, several of its ticks
+pub const RTC_WAIT_MS: u32 = 2;
+
+/// Bytes of the pattern written to the queue and to flash
+const PATTERN_LEN: usize = 64;
+
+/// Longest wait for a byte to be sent or received
+const TIMEOUT_MS: u32 = 10;
+
+/// Polls per millisecond while waiting
+const POLLS_PER_MS: u32 = 100;
+
+/// USART1 is asked to run [`uart`], cleared once it has
+static UART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Outcome of the last [`uart`] run by USART1, published by clearing [`UART_REQUESTED`]
+static UART_PASSED: AtomicBool = AtomicBool::new(false);
+
+/// Bytes differing from their neighbours and from the erased state of flash
+const PATTERN: [u8; PATTERN_LEN] = {
+    let mut pattern = [0; PATTERN_LEN];
+    let mut i = 0;
+    while i < PATTERN_LEN {
+        pattern[i] = (i as u8).wrapping_mul(37) ^ 0x5A;
+        i += 1;
+    }
+    pattern
+};
+
+/// Outcome of an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Not run in the state the bridge is in
+    Skip,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "SKIP",
+        }
+    }
+}
+
+impl From<bool> for Outcome {
+    fn from(passed: bool) -> Self {
+        if passed {
+            Outcome::Pass
+        } else {
+            Outcome::Fail
+        }
+    }
+}
+
+/// Outcome of each item, true if it passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Results {
+    pub uart: bool,
+    pub queue: bool,
+    pub flash: bool,
+    pub rtc: bool,
+    pub power: Outcome,
+}
+
+impl Results {
+    /// Items in reporting order, named as reported
+    pub fn items(&self) -> [(&'static str, Outcome); 5] {
+        [
+            ("UART", self.uart.into()),
+            ("QUEUE", self.queue.into()),
+            ("FLASH", self.flash.into()),
+            ("RTC", self.rtc.into()),
+            ("POWER", self.power),
+        ]
+    }
+}
+
+/// Ask the USART1 task to run [`uart`] the next time it runs, see [`run_requested_uart`]
+pub fn request_uart() {
+    UART_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Run [`uart`] if [`request_uart`] asked for it, called by the USART1 task, which owns it
+pub fn run_requested_uart(usart1: &usart1::RegisterBlock) {
+    if UART_REQUESTED.load(Ordering::Relaxed) {
+        UART_PASSED.store(uart(usart1), Ordering::Relaxed);
+        UART_REQUESTED.store(false, Ordering::Release);
+    }
+}
+
+/// Outcome of the requested [`uart`], `None` until the USART1 task has run it
+pub fn uart_result() -> Option<bool> {
+    (!UART_REQUESTED.load(Ordering::Acquire)).then(|| UART_PASSED.load(Ordering::Relaxed))
+}
+
+/// Send [`UART_PATTERN`] from USART1 to itself in half-duplex mode, restoring its configuration
+/// afterwards. Its task must not run meanwhile.
+pub fn uart(usart1: &usart1::RegisterBlock) -> bool {
+    // Let a byte on its way to the GPS go out
+    if !wait(|| usart1.isr.read().tc().bit_is_set()) {
+        return false;
+    }
+    let cr3 = usart1.cr3.read().bits();
+    // HDSEL can only be changed while the USART is disabled, RX DMA would take the echo
+    usart1.cr1.modify(|_, w| w.ue().clear_bit());
+    usart1
+        .cr3
+        .modify(|_, w| w.dmar().clear_bit().hdsel().set_bit());
+    usart1.cr1.modify(|_, w| w.ue().set_bit());
+    let _ = usart1.rdr.read();
+    usart1
+        .icr
+        .write(|w| w.orecf().set_bit().fecf().set_bit().ncf().set_bit());
+
+    // Each byte is received before the next is sent, so the transmit register is empty
+    let passed = UART_PATTERN.iter().all(|&byte| {
+        usart1.tdr.write(|w| w.tdr().bits(byte.into()));
+        wait(|| usart1.isr.read().rxne().bit_is_set())
+            && usart1.rdr.read().rdr().bits() as u8 == byte
+    });
+
+    wait(|| usart1.isr.read().tc().bit_is_set());
+    usart1.cr1.modify(|_, w| w.ue().clear_bit());
+    // SAFETY: the value read above
+    usart1.cr3.write(|w| unsafe { w.bits(cr3) });
+    usart1.cr1.modify(|_, w| w.ue().set_bit());
+    // The GPS may have been cut off mid byte
+    usart1
+        .icr
+        .write(|w| w.orecf().set_bit().fecf().set_bit().ncf().set_bit());
+    passed
+}
+
+/// Fill a ring buffer and drain it, once from the start of its storage and once across its end
+pub fn queue() -> bool {
+    let mut queue = RingBuffer::<PATTERN_LEN>::new();
+    [0, PATTERN_LEN / 2 + 1].into_iter().all(|offset| {
+        for _ in 0..offset {
+            let _ = queue.push(0);
+            let _ = queue.pop();
+        }
+        queue.write(&PATTERN).is_ok()
+            && queue.push(0).is_err()
+            && PATTERN.iter().all(|&byte| queue.pop() == Some(byte))
+            && queue.is_empty()
+    })
+}
+
+/// Erase [`SCRATCH_PAGE`], program it, read it back and erase it again
+pub fn flash(flash: &FLASH) -> bool {
+    let address = flash::page_address(SCRATCH_PAGE);
+    flash::unlocked(flash, |flash| {
+        flash::erase_page(flash, SCRATCH_PAGE).is_ok()
+            && flash::read(address, flash::PAGE_SIZE)
+                .iter()
+                .all(|&byte| byte == 0xFF)
+            && flash::program(flash, address, &PATTERN).is_ok()
+            && flash::read(address, PATTERN_LEN) == PATTERN
+            && flash::erase_page(flash, SCRATCH_PAGE).is_ok()
+    })
+}
+
+/// Check the RTC subsecond counter, read by `subseconds`, advances within [`RTC_WAIT_MS`]
+pub fn rtc(mut subseconds: impl FnMut() -> u32) -> bool {
+    let start = subseconds();
+    cortex_m::asm::delay(clocks::SYSCLK_HZ / 1000 * RTC_WAIT_MS);
+    subseconds() != start
+}
+
+/// Toggle the GPS power pin and check it reads back both levels, skipped while the GPS is on
+pub fn power(pin: &impl PowerPin) -> Outcome {
+    if pin.is_powered() {
+        return Outcome::Skip;
+    }
+    if settled_level(pin) {
+        return Outcome::Fail;
+    }
+    pin.set_power(true);
+    let on = settled_level(pin);
+    pin.set_power(false);
+    (on && !settled_level(pin)).into()
+}
+
+/// Level of the pin once the input synchronizer has caught up with its output
+fn settled_level(pin: &impl PowerPin) -> bool {
+    cortex_m::asm::delay(clocks::SYSCLK_HZ / 1_000_000);
+    pin.level()
+}
+
+/// Poll `done` for up to [`TIMEOUT_MS`]
+fn wait(mut done: impl FnMut() -> bool) -> bool {
+    for _ in 0..TIMEOUT_MS * POLLS_PER_MS {
+        if done() {
+            return true;
+        }
+        cortex_m::asm::delay(clocks::SYSCLK_HZ / 1000 / POLLS_PER_MS);
+    }
+    done()
+}
@@ -0,0 +1,129 @@
+//! Satellites in view, assembled from multi-part GSV messages so that `SATS?` and the display
+//! see the whole sky of an epoch rather than one sentence of it.
+//!
+//! A GSV message replaces the satellites of its talker once its last part is received, a part
+//! out of sequence discards the message. The used flag comes from the latest GSA, the u-blox 7
+//! sends one per epoch with the PRNs of all systems, numbered as in GSV.
+
+use crate::nmea::{Gsa, Gsv, Sentence};
+use heapless::Vec;
+
+/// Satellites kept, more than the GP-735T reports in view
+pub const MAX_SATELLITES: usize = 32;
+
+/// Satellites of the latest complete GSV messages
+pub type Table = Vec<Satellite, MAX_SATELLITES>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Satellite {
+    /// Talker of the GSV message, e.g. `GP`
+    pub talker: [u8; 2],
+    pub prn: u8,
+    /// Elevation in degrees
+    pub elevation: Option<u8>,
+    /// Azimuth in degrees
+    pub azimuth: Option<u16>,
+    /// Signal to noise ratio in dBHz, `None` when not tracking
+    pub snr: Option<u8>,
+    /// Used in the fix according to the latest GSA
+    pub used: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sky {
+    satellites: Table,
+    /// Parts received of the message in progress
+    pending: Table,
+    pending_talker: [u8; 2],
+    /// Number of the next part expected, 0 between messages
+    next_part: u8,
+    /// PRNs used in the fix, unused slots are 0
+    used: [u8; 12],
+}
+
+impl Sky {
+    pub const fn new() -> Self {
+        Self {
+            satellites: Vec::new(),
+            pending: Vec::new(),
+            pending_talker: [0; 2],
+            next_part: 0,
+            used: [0; 12],
+        }
+    }
+
+    pub fn satellites(&self) -> &Table {
+        &self.satellites
+    }
+
+    /// Satellites used in the fix
+    pub fn used_count(&self) -> usize {
+        self.satellites
+            .iter()
+            .filter(|satellite| satellite.used)
+            .count()
+    }
+
+    /// Merge a parsed sentence, only GSV and GSA are of interest
+    pub fn update(&mut self, sentence: &Sentence) {
+        match sentence {
+            Sentence::Gsv(gsv) => self.update_gsv(gsv),
+            Sentence::Gsa(gsa) => self.update_gsa(gsa),
+            _ => {}
+        }
+    }
+
+    fn update_gsv(&mut self, gsv: &Gsv) {
+        if gsv.number == 1 {
+            self.pending.clear();
+            self.pending_talker = gsv.talker;
+            self.next_part = 1;
+        }
+        if gsv.number != self.next_part || gsv.talker != self.pending_talker {
+            self.next_part = 0;
+            return;
+        }
+        for satellite in gsv.satellites.iter().flatten() {
+            // Satellites beyond the table are left out
+            let _ = self.pending.push(Satellite {
+                talker: gsv.talker,
+                prn: satellite.prn,
+                elevation: satellite.elevation,
+                azimuth: satellite.azimuth,
+                snr: satellite.snr,
+                used: self.is_used(satellite.prn),
+            });
+        }
+        if gsv.number < gsv.total {
+            self.next_part += 1;
+            return;
+        }
+        self.next_part = 0;
+        let talker = gsv.talker;
+        self.satellites
+            .retain(|satellite| satellite.talker != talker);
+        for satellite in self.pending.iter() {
+            if self.satellites.push(*satellite).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn update_gsa(&mut self, gsa: &Gsa) {
+        self.used = gsa.satellites;
+        let used = self.used;
+        for satellite in self.satellites.iter_mut() {
+            satellite.used = satellite.prn != 0 && used.contains(&satellite.prn);
+        }
+    }
+
+    fn is_used(&self, prn: u8) -> bool {
+        prn != 0 && self.used.contains(&prn)
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::new()
+    }
+}
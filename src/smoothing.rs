@@ -0,0 +1,163 @@
+//! Alpha-beta filter on position and speed, enabled by `SMOOTH ON <alpha>`, for consumers that
+//! react to jitter: the display and the geofence. Binary records, the trip, logs and `STATUS`
+//! keep the fixes as received.
+//!
+//! Each completed fix updates the estimate: the position is predicted from the estimated
+//! velocity, then moved by `alpha` of the difference to the measured one, and the velocity by
+//! `beta` of it per second. `beta` is `alpha² / (2 - alpha)`, the Benedict-Bordner choice for
+//! the least lag at a given noise. Speed over ground is averaged with `alpha` alone. A lower
+//! `alpha` smooths more and lags more, 1 passes fixes through.
+//!
+//! All arithmetic is fixed-point. The filter starts over after an invalid fix, a gap of more than
+//! [`MAX_GAP_MS`] or a jump of more than [`MAX_JUMP`], e.g. crossing the antimeridian.
+
+use crate::fix::GpsFix;
+
+/// Scale of `alpha` and `beta` in the arithmetic
+const SCALE: i64 = 10_000;
+
+/// Longest time between fixes filtered together
+pub const MAX_GAP_MS: u32 = 10_000;
+
+/// Largest difference to the prediction filtered, in 1e-7 degrees, about 1km
+pub const MAX_JUMP: i64 = 100_000;
+
+const MS_PER_DAY: u32 = 86_400_000;
+
+/// Estimate of one coordinate
+#[derive(Debug, Clone, Copy)]
+struct Axis {
+    /// 1e-7 degrees
+    position: i64,
+    /// 1e-10 degrees per second
+    velocity: i64,
+}
+
+impl Axis {
+    fn new(position: i32) -> Self {
+        Self {
+            position: position.into(),
+            velocity: 0,
+        }
+    }
+
+    /// Difference of `measured` to the position predicted after `dt_ms`
+    fn residual(&self, measured: i32, dt_ms: i64) -> i64 {
+        i64::from(measured) - self.predict(dt_ms)
+    }
+
+    fn predict(&self, dt_ms: i64) -> i64 {
+        self.position + self.velocity * dt_ms / 1_000_000
+    }
+
+    fn update(&mut self, residual: i64, dt_ms: i64, alpha: i64, beta: i64) {
+        self.position = self.predict(dt_ms) + alpha * residual / SCALE;
+        self.velocity += beta * residual * 1_000_000 / (SCALE * dt_ms);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    lat: Axis,
+    lon: Axis,
+    /// Millimetres per second
+    speed: i64,
+    /// UTC time of day of the last fix in milliseconds
+    ms: u32,
+}
+
+impl State {
+    fn new(fix: &GpsFix, ms: u32) -> Self {
+        Self {
+            lat: Axis::new(fix.lat),
+            lon: Axis::new(fix.lon),
+            speed: fix.speed.into(),
+            ms,
+        }
+    }
+}
+
+pub struct Smoother {
+    /// Alpha in hundredths, 0 when off
+    alpha: u8,
+    state: Option<State>,
+}
+
+impl Smoother {
+    /// Largest alpha in hundredths, 1
+    pub const MAX_ALPHA: u8 = 100;
+
+    pub const fn new() -> Self {
+        Self {
+            alpha: 0,
+            state: None,
+        }
+    }
+
+    /// Alpha in hundredths, 0 when off
+    pub fn alpha(&self) -> u8 {
+        self.alpha
+    }
+
+    /// Switch smoothing on with `alpha` in hundredths, off with 0, starting over either way
+    pub fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha.min(Self::MAX_ALPHA);
+        self.state = None;
+    }
+
+    /// Account for a completed fix
+    pub fn update(&mut self, fix: &GpsFix) {
+        if self.alpha == 0 {
+            return;
+        }
+        let (Some(time), true) = (fix.timestamp, fix.is_valid()) else {
+            self.state = None;
+            return;
+        };
+        let ms = ((u32::from(time.hour) * 60 + u32::from(time.minute)) * 60
+            + u32::from(time.second))
+            * 1000
+            + u32::from(time.millisecond);
+        let Some(state) = self.state.as_mut() else {
+            self.state = Some(State::new(fix, ms));
+            return;
+        };
+        // Wraps at midnight
+        let dt_ms = (ms + MS_PER_DAY - state.ms) % MS_PER_DAY;
+        if dt_ms == 0 || dt_ms > MAX_GAP_MS {
+            *state = State::new(fix, ms);
+            return;
+        }
+        let dt_ms = i64::from(dt_ms);
+        let lat_residual = state.lat.residual(fix.lat, dt_ms);
+        let lon_residual = state.lon.residual(fix.lon, dt_ms);
+        if lat_residual.abs() > MAX_JUMP || lon_residual.abs() > MAX_JUMP {
+            *state = State::new(fix, ms);
+            return;
+        }
+        let alpha = i64::from(self.alpha) * SCALE / 100;
+        let beta = alpha * alpha / (2 * SCALE - alpha);
+        state.lat.update(lat_residual, dt_ms, alpha, beta);
+        state.lon.update(lon_residual, dt_ms, alpha, beta);
+        state.speed += alpha * (i64::from(fix.speed) - state.speed) / SCALE;
+        state.ms = ms;
+    }
+
+    /// `fix` with the estimated position and speed, unchanged when off or without an estimate
+    pub fn smooth(&self, fix: &GpsFix) -> GpsFix {
+        let mut smoothed = *fix;
+        if let (Some(state), true) = (self.state, fix.is_valid()) {
+            // Estimates stay within reach of the measured coordinates and speed
+            smoothed.lat = state.lat.position as i32;
+            smoothed.lon = state.lon.position as i32;
+            smoothed.speed = state.speed.max(0) as u32;
+        }
+        smoothed
+    }
+}
+
+impl Default for Smoother {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,79 @@
+//! Source of fixes, selected by the `SRC` host command.
+//!
+//! By default the GPS outputs NMEA and the fix is merged from its sentences. `SRC UBX` switches
+//! the GPS with UBX-CFG-MSG to UBX-NAV-PVT alone, one message per epoch carrying time, date,
+//! position, velocity and fix status, which takes fewer bytes on USART1 and no text parsing. The
+//! host keeps receiving NMEA in `MODE NMEA`: GGA and RMC are synthesized from every NAV-PVT with
+//! [`crate::nmea::encode`] and pass the filter like received sentences.
+//!
+//! NAV-PVT carries no HDOP, PDOP stands in for it and is never lower, and no satellites in view,
+//! so `SATS?` stays empty. The source isn't saved, a GPS power cycle reverts it to NMEA and the
+//! frames of `SRC UBX` are sent again once it talks.
+
+use crate::ubx;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::Vec;
+
+pub const UBX_ID_CFG_MSG: u8 = 0x01;
+
+/// NMEA standard message class of UBX-CFG-MSG
+const CLASS_NMEA: u8 = 0xF0;
+
+/// NMEA message IDs the GPS outputs by default: GGA, GLL, GSA, GSV, RMC, VTG
+const NMEA_IDS: [u8; 6] = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05];
+
+/// UBX-CFG-MSG frame setting the rate on the port it is received on
+const CFG_MSG_FRAME_LEN: usize = ubx::FRAME_OVERHEAD + 3;
+
+/// One UBX-CFG-MSG frame for NAV-PVT and each NMEA message
+pub type Frames = Vec<u8, { (NMEA_IDS.len() + 1) * CFG_MSG_FRAME_LEN }>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// NMEA sentences, the default
+    Nmea,
+    /// UBX-NAV-PVT messages
+    Ubx,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Nmea => "NMEA",
+            Source::Ubx => "UBX",
+        }
+    }
+}
+
+static UBX: AtomicBool = AtomicBool::new(false);
+
+pub fn source() -> Source {
+    match UBX.load(Ordering::Relaxed) {
+        true => Source::Ubx,
+        false => Source::Nmea,
+    }
+}
+
+/// Record the source once its frames are queued
+pub fn select(source: Source) {
+    UBX.store(source == Source::Ubx, Ordering::Relaxed);
+}
+
+/// UBX-CFG-MSG frames enabling NAV-PVT and disabling the NMEA messages, or the reverse
+pub fn frames(source: Source) -> Frames {
+    let (pvt_rate, nmea_rate) = match source {
+        Source::Nmea => (0, 1),
+        Source::Ubx => (1, 0),
+    };
+    let mut frames = Frames::new();
+    let _ = frames.extend_from_slice(&cfg_msg(ubx::CLASS_NAV, ubx::ID_NAV_PVT, pvt_rate));
+    for id in NMEA_IDS {
+        let _ = frames.extend_from_slice(&cfg_msg(CLASS_NMEA, id, nmea_rate));
+    }
+    frames
+}
+
+/// UBX-CFG-MSG frame outputting a message every `rate` epochs, 0 disables it
+fn cfg_msg(class: u8, id: u8, rate: u8) -> ubx::Frame {
+    ubx::encode(ubx::CLASS_CFG, UBX_ID_CFG_MSG, &[class, id, rate]).unwrap_or_default()
+}
@@ -0,0 +1,149 @@
+//! Speed alarm set by `SPEEDALARM <kmh>`, for fleet and asset tracking. It is raised once the
+//! speed over ground is above the limit for [`TRIGGER_FIXES`] fixes in a row and cleared once it
+//! is [`HYSTERESIS_KMH`] below the limit for as many, each reported as
+//! `$PBRIDGE,ALARM,SPEED,ON|OFF,<speed_kmh>,<limit_kmh>*hh`. Fixes without a position are
+//! skipped, so losing the fix doesn't clear it.
+//!
+//! With the `speed-alarm` feature PA11 (D10) is driven high while the alarm is raised. PA11 is
+//! USB DM, so the feature excludes `usb`.
+
+use crate::fix::GpsFix;
+use crate::nmea;
+use core::fmt::{self, Write};
+use heapless::String;
+
+#[cfg(all(feature = "speed-alarm", feature = "usb"))]
+compile_error!("feature `speed-alarm` uses PA11, USB DM, and excludes `usb`");
+
+/// Consecutive fixes beyond the limit before the alarm changes
+pub const TRIGGER_FIXES: u8 = 3;
+
+/// Margin below the limit before the alarm clears
+pub const HYSTERESIS_KMH: u16 = 5;
+
+/// Highest limit accepted
+pub const MAX_LIMIT_KMH: u16 = 999;
+
+/// GPIOA pin of the alarm output
+#[cfg(feature = "speed-alarm")]
+const ALARM_PIN: u32 = 11;
+
+/// Millimetres per second in a km/h, as a fraction
+const MM_PER_S_PER_KMH: (u32, u32) = (2500, 9);
+
+/// Alarm raised or cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub raised: bool,
+    /// Speed of the fix that changed the alarm, in millimetres per second
+    pub speed: u32,
+    pub limit_kmh: u16,
+}
+
+pub struct SpeedAlarm {
+    /// 0 when off
+    limit_kmh: u16,
+    raised: bool,
+    /// Consecutive fixes beyond the limit
+    count: u8,
+}
+
+impl SpeedAlarm {
+    pub const fn new() -> Self {
+        Self {
+            limit_kmh: 0,
+            raised: false,
+            count: 0,
+        }
+    }
+
+    /// Speed limit, 0 when off
+    pub fn limit_kmh(&self) -> u16 {
+        self.limit_kmh
+    }
+
+    /// Set the limit, 0 turns the alarm off. The alarm starts cleared.
+    pub fn set_limit(&mut self, limit_kmh: u16) {
+        *self = Self {
+            limit_kmh,
+            ..Self::new()
+        };
+    }
+
+    /// Account for a completed fix, returning the change of the alarm if any
+    pub fn update(&mut self, fix: &GpsFix) -> Option<Event> {
+        if self.limit_kmh == 0 || !fix.is_valid() {
+            return None;
+        }
+        let beyond = if self.raised {
+            fix.speed <= kmh_to_mm_per_s(self.limit_kmh.saturating_sub(HYSTERESIS_KMH))
+        } else {
+            fix.speed > kmh_to_mm_per_s(self.limit_kmh)
+        };
+        if !beyond {
+            self.count = 0;
+            return None;
+        }
+        self.count += 1;
+        if self.count < TRIGGER_FIXES {
+            return None;
+        }
+        self.count = 0;
+        self.raised = !self.raised;
+        Some(Event {
+            raised: self.raised,
+            speed: fix.speed,
+            limit_kmh: self.limit_kmh,
+        })
+    }
+}
+
+impl Default for SpeedAlarm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn kmh_to_mm_per_s(kmh: u16) -> u32 {
+    let (numerator, denominator) = MM_PER_S_PER_KMH;
+    u32::from(kmh) * numerator / denominator
+}
+
+/// Write the `$PBRIDGE,ALARM,SPEED` sentence including line ending, speed in km/h to a tenth
+pub fn write_sentence(out: &mut impl Write, event: &Event) -> fmt::Result {
+    let (numerator, denominator) = MM_PER_S_PER_KMH;
+    // Tenths of a km/h, rounded
+    let speed = (u64::from(event.speed) * 10 * u64::from(denominator) + u64::from(numerator) / 2)
+        / u64::from(numerator);
+    let mut body = String::<48>::new();
+    write!(
+        body,
+        "PBRIDGE,ALARM,SPEED,{},{}.{},{}",
+        if event.raised { "ON" } else { "OFF" },
+        speed / 10,
+        speed % 10,
+        event.limit_kmh
+    )?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// Alarm output on PA11, configured as push-pull output low. GPIOA clock must be enabled.
+#[cfg(feature = "speed-alarm")]
+pub fn init_alarm(gpioa: &crate::chip::pac::GPIOA) {
+    set_alarm(false);
+    // SAFETY: 0b01 is output mode, push-pull by default, other pins are left unchanged
+    gpioa.moder.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b11 << (2 * ALARM_PIN)) | 0b01 << (2 * ALARM_PIN))
+    });
+}
+
+/// Drive the alarm output
+#[cfg(feature = "speed-alarm")]
+pub fn set_alarm(on: bool) {
+    // SAFETY: BSRR writes are atomic and only touch PA11, other GPIOA pins are left to their users
+    let gpioa = unsafe { &*crate::chip::pac::GPIOA::ptr() };
+    // Lower half of BSRR sets pins, upper half resets them
+    let bit = if on { 1 } else { 1 << 16 } << ALARM_PIN;
+    // SAFETY: zero bits have no effect
+    gpioa.bsrr.write(|w| unsafe { w.bits(bit) });
+}
@@ -0,0 +1,285 @@
+//! Fixes and sentences streamed from an SPI slave port, enabled by the `spi-slave` feature, for a
+//! host without a spare UART.
+//!
+//! SPI3 runs as a slave in mode 0, 8 bit frames MSB first: SCK on PB3 (D13), MISO on PB4 (D12)
+//! and NSS on PA4 (A3), all alternate function 6, MOSI isn't used. PB1 (D6) is the data ready
+//! output, high while a batch waits to be read. PB3 to PB5 are the pins of the SD card, which
+//! can't be combined with it, and the board's LED moves off PA4, see [`crate::board`].
+//!
+//! Frames are collected into a batch of up to [`BUFFER_SIZE`] bytes while DMA2 channel 2 holds
+//! the previous one ready for the master. A transaction, NSS low to high, reads a batch: its
+//! length as a little endian u16, then the frames. Each frame is its payload length, the record
+//! type, the payload and the CRC-16/CCITT-FALSE of the three before it, little endian:
+//! [`RECORD_FIX`] with the [`FixRecord`] of `MODE BIN`, or [`RECORD_SENTENCE`] with a sentence
+//! including its line ending. The master reads the length, then as many bytes more in the same
+//! transaction. The next batch is loaded once NSS rises, the rest of a batch read only in part is
+//! dropped. A master that doesn't watch data ready may read while nothing waits, a batch length
+//! of 0, and may catch a batch being loaded, which fails its CRC.
+//!
+//! `SPI FIX|NMEA|ALL` selects what is streamed: completed fixes passing the [`crate::quality`]
+//! gate, sentences from the GPS passing their checksum and the `FILTER`, or both, the default.
+//! They are streamed whatever `MODE`, forwarding to the host is unaffected.
+
+#[cfg(feature = "sd-log")]
+compile_error!("feature `spi-slave` takes PB3 to PB5 from `sd-log`");
+
+use crate::board::{self, AfPin};
+use crate::chip::pac::{DMA2, EXTI, GPIOA, GPIOB, RCC, SPI3};
+use crate::protocol::{self, FixRecord};
+use core::sync::atomic::{compiler_fence, AtomicU8, Ordering};
+
+/// Bytes of each batch buffer, including the length
+pub const BUFFER_SIZE: usize = 512;
+
+/// Record type of a [`FixRecord`], as in binary output
+pub const RECORD_FIX: u8 = protocol::RECORD_FIX;
+
+/// Record type of an NMEA sentence
+pub const RECORD_SENTENCE: u8 = 0x02;
+
+/// Length of a batch
+const HEADER_LEN: usize = 2;
+
+/// Length, type and CRC of a frame
+const FRAME_OVERHEAD: usize = 4;
+
+/// NSS on GPIOA, its EXTI line raises EXTI4
+pub const NSS: AfPin = AfPin { pin: 4, af: 6 };
+
+/// Data ready output on GPIOB
+const DRDY_PIN: u32 = 1;
+
+/// What is streamed, see [`Content`]
+static CONTENT: AtomicU8 = AtomicU8::new(Content::All as u8);
+
+/// What is streamed, selected by `SPI`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    Fix,
+    Nmea,
+    All,
+}
+
+impl Content {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Content::Fix => "FIX",
+            Content::Nmea => "NMEA",
+            Content::All => "ALL",
+        }
+    }
+
+    pub fn fixes(&self) -> bool {
+        *self != Content::Nmea
+    }
+
+    pub fn sentences(&self) -> bool {
+        *self != Content::Fix
+    }
+}
+
+pub fn content() -> Content {
+    match CONTENT.load(Ordering::Relaxed) {
+        0 => Content::Fix,
+        1 => Content::Nmea,
+        _ => Content::All,
+    }
+}
+
+pub fn set_content(content: Content) {
+    CONTENT.store(content as u8, Ordering::Relaxed);
+}
+
+/// SPI3 TX DMA channel sending from two alternating batch buffers.
+///
+/// Only channel 2 registers of DMA2 are touched.
+pub struct Stream {
+    spi3: SPI3,
+    buffers: &'static mut [[u8; BUFFER_SIZE]; 2],
+    /// Index of the buffer being filled, the other one is loaded
+    filling: usize,
+    /// Bytes in the filling buffer, including the length
+    len: usize,
+    /// Frames in the filling buffer
+    frames: u32,
+    /// Frames of the loaded batch, none when nothing waits
+    loaded_frames: u32,
+    /// Frames that didn't fit or weren't read
+    dropped: u32,
+}
+
+impl Stream {
+    /// Route SPI3 and its pins, set up DMA2 channel 2 and the NSS EXTI line and load an empty
+    /// batch into `buffers`, an RTIC init local. DMA2, GPIOB and SPI3 clocks are enabled here,
+    /// GPIOA's must be.
+    pub fn new(
+        rcc: &RCC,
+        gpioa: &GPIOA,
+        gpiob: &GPIOB,
+        dma2: &DMA2,
+        exti: &EXTI,
+        spi3: SPI3,
+        buffers: &'static mut [[u8; BUFFER_SIZE]; 2],
+    ) -> Self {
+        rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+        rcc.ahb2enr.modify(|_, w| w.gpioben().set_bit());
+        rcc.apb1enr1.modify(|_, w| w.spi3en().set_bit());
+
+        board::set_alternate(gpioa, NSS);
+        gpiob.moder.modify(|_, w| {
+            w.moder3()
+                .alternate()
+                .moder4()
+                .alternate()
+                .moder1()
+                .output()
+        });
+        gpiob.ospeedr.modify(|_, w| w.ospeedr4().very_high_speed());
+        gpiob.afrl.modify(|_, w| w.afrl3().af6().afrl4().af6());
+
+        // Channel 2 request 3 is SPI3_TX
+        dma2.cselr.modify(|_, w| w.c2s().map3());
+        dma2.cpar2
+            .write(|w| unsafe { w.pa().bits(spi3.dr.as_ptr() as u32) });
+        dma2.ccr2.write(|w| {
+            w.dir()
+                .from_memory()
+                .minc()
+                .enabled()
+                .psize()
+                .bits8()
+                .msize()
+                .bits8()
+        });
+
+        // NSS rising ends a transaction, GPIOA is the EXTI source of every line after reset
+        // SAFETY: only the bit of the NSS line is set
+        exti.rtsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << NSS.pin) });
+        exti.imr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << NSS.pin) });
+
+        let mut stream = Self {
+            spi3,
+            buffers,
+            filling: 0,
+            len: HEADER_LEN,
+            frames: 0,
+            loaded_frames: 0,
+            dropped: 0,
+        };
+        stream.load();
+        stream
+    }
+
+    /// Queue a completed fix
+    pub fn push_fix(&mut self, record: &FixRecord) {
+        self.push(RECORD_FIX, &record.to_bytes());
+    }
+
+    /// Queue a sentence including its line ending
+    pub fn push_sentence(&mut self, sentence: &[u8]) {
+        self.push(RECORD_SENTENCE, sentence);
+    }
+
+    /// Frames waiting to be read, loaded or not
+    pub fn queued(&self) -> u32 {
+        self.frames + self.loaded_frames
+    }
+
+    /// Frames dropped since boot
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Handle the EXTI interrupt of NSS: count what the master left unread and load the next
+    /// batch once it rises
+    pub fn on_deselect(&mut self) {
+        // SAFETY: PR1 bits are cleared by writing one, other lines are unaffected
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.pr1.write(|w| unsafe { w.bits(1 << NSS.pin) });
+        if !nss_high() {
+            return;
+        }
+        // SAFETY: CNDTR is only read
+        let dma2 = unsafe { &*DMA2::ptr() };
+        let unread =
+            dma2.cndtr2.read().ndt().bits() != 0 || self.spi3.sr.read().ftlvl().bits() != 0;
+        if unread {
+            self.dropped = self.dropped.saturating_add(self.loaded_frames);
+        }
+        self.load();
+    }
+
+    /// Append a frame to the filling buffer, dropped if it doesn't fit, and load it right away
+    /// if nothing waits for the master
+    fn push(&mut self, kind: u8, payload: &[u8]) {
+        let end = self.len + FRAME_OVERHEAD + payload.len();
+        if payload.len() > usize::from(u8::MAX) || end > BUFFER_SIZE {
+            self.dropped = self.dropped.saturating_add(1);
+            return;
+        }
+        let frame = &mut self.buffers[self.filling][self.len..end];
+        frame[0] = payload.len() as u8;
+        frame[1] = kind;
+        frame[2..2 + payload.len()].copy_from_slice(payload);
+        let crc = protocol::crc16(&frame[..2 + payload.len()]);
+        frame[2 + payload.len()..].copy_from_slice(&crc.to_le_bytes());
+        self.len = end;
+        self.frames += 1;
+        if self.loaded_frames == 0 && nss_high() {
+            self.load();
+        }
+    }
+
+    /// Reset SPI3, which empties its TX FIFO, and hand the filling buffer to DMA
+    fn load(&mut self) {
+        let frames_len = (self.len - HEADER_LEN) as u16;
+        self.buffers[self.filling][..HEADER_LEN].copy_from_slice(&frames_len.to_le_bytes());
+
+        // SAFETY: RCC reset bits of SPI3 only, the register is otherwise written in init
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.apb1rstr1.modify(|_, w| w.spi3rst().set_bit());
+        rcc.apb1rstr1.modify(|_, w| w.spi3rst().clear_bit());
+
+        // SAFETY: channel 2 is disabled while reconfigured and only reconfigured here
+        let dma2 = unsafe { &*DMA2::ptr() };
+        dma2.ccr2.modify(|_, w| w.en().disabled());
+        dma2.cmar2
+            .write(|w| unsafe { w.ma().bits(self.buffers[self.filling].as_ptr() as u32) });
+        dma2.cndtr2.write(|w| w.ndt().bits(self.len as u16));
+        // Make sure buffer contents are written before DMA starts reading
+        compiler_fence(Ordering::Release);
+        dma2.ccr2.modify(|_, w| w.en().enabled());
+
+        // Slave with hardware NSS, 8 bit frames fed by DMA
+        // SAFETY: 0b0111 selects 8 bit frames
+        self.spi3
+            .cr2
+            .write(|w| unsafe { w.ds().bits(0b0111).txdmaen().set_bit() });
+        self.spi3.cr1.write(|w| w.spe().set_bit());
+
+        self.loaded_frames = self.frames;
+        self.filling ^= 1;
+        self.len = HEADER_LEN;
+        self.frames = 0;
+        set_data_ready(self.loaded_frames != 0);
+    }
+}
+
+/// The master isn't in a transaction
+fn nss_high() -> bool {
+    // SAFETY: reading the input data register has no side effects
+    let gpioa = unsafe { &*GPIOA::ptr() };
+    gpioa.idr.read().bits() & 1 << NSS.pin != 0
+}
+
+fn set_data_ready(ready: bool) {
+    let bit = match ready {
+        true => 1 << DRDY_PIN,
+        false => 1 << (DRDY_PIN + 16),
+    };
+    // SAFETY: BSRR writes are atomic and only touch the data ready pin
+    let gpiob = unsafe { &*GPIOB::ptr() };
+    gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+}
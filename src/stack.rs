@@ -0,0 +1,86 @@
+//! Stack usage, the stack never used so far reported by `STATS` and warned of once it runs low.
+//!
+//! The stack grows down from the top of RAM towards the statics, see `memory/*.x`. The
+//! Cortex-M4 has no stack limit register, MSPLIM comes with ARMv8-M, so an overflow would
+//! silently corrupt the statics, the buffers sized by `BRIDGE_*` settings among them. Instead
+//! init [`paint`]s the stack below its own frame with [`PAINT`] and [`free`] counts the painted
+//! words left at the bottom, the high-water mark of every task since boot. A word a frame
+//! reserved but never wrote still counts as free, so the mark is a little optimistic.
+//!
+//! RTC wakeup [`check`]s it with every battery sample and sends
+//! `$PBRIDGE,WARN,LOW_STACK,<bytes>*hh` once it drops below [`LOW_STACK`]. The mark isn't cleared
+//! by `STATS CLR`, repainting would overwrite the frames of the tasks running.
+
+use crate::nmea;
+use core::fmt::{self, Write};
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::String;
+
+/// Word the unused stack is filled with
+pub const PAINT: u32 = 0x5AC5_5AC5;
+
+/// Free stack below which the warning is sent, room for an interrupt or two more
+pub const LOW_STACK: usize = 1024;
+
+/// Bytes below the stack pointer left unpainted, for the painting loop itself
+const SLACK: usize = 64;
+
+/// The warning has been sent
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    /// End of the statics, provided by cortex-m-rt
+    static __sheap: u32;
+    /// Top of the stack, provided by cortex-m-rt
+    static _stack_start: u32;
+}
+
+/// Lowest address of the stack
+fn bottom() -> usize {
+    addr_of!(__sheap) as usize
+}
+
+/// Address the stack grows down from
+fn top() -> usize {
+    addr_of!(_stack_start) as usize
+}
+
+/// Fill the stack below the caller with [`PAINT`], called first thing in init, with interrupts
+/// disabled
+pub fn paint() {
+    let end = cortex_m::register::msp::read() as usize - SLACK;
+    let mut word = bottom() as *mut u32;
+    while (word as usize) < end {
+        // SAFETY: below the stack pointer and above the statics, nothing lives there yet
+        unsafe {
+            word.write_volatile(PAINT);
+            word = word.add(1);
+        }
+    }
+}
+
+/// Bytes of stack never used since boot
+pub fn free() -> usize {
+    let mut word = bottom() as *const u32;
+    // SAFETY: words between the statics and the top of RAM, the ones in use by a task are read
+    // only, and a task overwriting one concurrently only makes the stack count as used
+    while (word as usize) < top() && unsafe { word.read_volatile() } == PAINT {
+        // SAFETY: as above
+        word = unsafe { word.add(1) };
+    }
+    word as usize - bottom()
+}
+
+/// The free stack the first time it is below [`LOW_STACK`], `None` otherwise
+pub fn check() -> Option<usize> {
+    let free = free();
+    (free < LOW_STACK && !WARNED.swap(true, Ordering::Relaxed)).then_some(free)
+}
+
+/// Write the `$PBRIDGE,WARN,LOW_STACK,<bytes>*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, free: usize) -> fmt::Result {
+    let mut body = String::<40>::new();
+    write!(body, "PBRIDGE,WARN,LOW_STACK,{}", free)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
@@ -0,0 +1,174 @@
+//! Error and traffic counters reported by the `STATS` command.
+//! Counters are atomic so interrupt handlers increment them without a resource lock.
+//! They wrap around rather than saturate. Queue high-water marks and times to first fix are kept
+//! alongside them.
+
+use crate::router::Sink;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, count: u32) {
+        self.0.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn clear(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Highest value recorded, such as the fill level of a queue
+pub struct Peak(AtomicU32);
+
+impl Peak {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn record(&self, value: usize) {
+        self.0.fetch_max(value as u32, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn clear(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Minimum, average and maximum of durations in milliseconds. Each is updated on its own, a
+/// reader preempting [`Durations::record`] may see a count without its time.
+pub struct Durations {
+    count: AtomicU32,
+    /// Sum of the durations, wrapping after about 49 days
+    total_ms: AtomicU32,
+    min_ms: AtomicU32,
+    max_ms: AtomicU32,
+}
+
+impl Durations {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU32::new(0),
+            total_ms: AtomicU32::new(0),
+            min_ms: AtomicU32::new(u32::MAX),
+            max_ms: AtomicU32::new(0),
+        }
+    }
+
+    pub fn record(&self, ms: u32) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ms.fetch_add(ms, Ordering::Relaxed);
+        self.min_ms.fetch_min(ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Minimum, average and maximum, `None` before the first duration
+    pub fn summary(&self) -> Option<(u32, u32, u32)> {
+        let count = self.count();
+        (count > 0).then(|| {
+            (
+                self.min_ms.load(Ordering::Relaxed),
+                self.total_ms.load(Ordering::Relaxed) / count,
+                self.max_ms.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    fn clear(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.total_ms.store(0, Ordering::Relaxed);
+        self.min_ms.store(u32::MAX, Ordering::Relaxed);
+        self.max_ms.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counters summed over both USARTs
+pub struct Stats {
+    /// Overrun errors, each losing at least one received byte
+    pub overruns: Counter,
+    /// Framing errors, a stop bit was missing
+    pub framing_errors: Counter,
+    /// Noise detected while sampling a received bit
+    pub noise_errors: Counter,
+    /// Bytes discarded because an output queue was full
+    pub dropped_bytes: Counter,
+    /// Whole lines dropped by each [`Sink`] on a full queue, by its `OVERFLOW` policy
+    pub dropped_lines: [Counter; Sink::ALL.len()],
+    /// NMEA sentences received and parsed, and UBX-NAV-PVT messages with `SRC UBX`
+    pub sentences: Counter,
+    /// NMEA sentences rejected by their checksum or fields, unsupported types aren't counted
+    pub invalid_sentences: Counter,
+    /// NMEA sentences failing or missing their checksum, not forwarded unless `PASSTHRU RAW`
+    pub checksum_errors: Counter,
+    /// Most bytes waiting in the host TX buffer
+    pub host_tx_peak: Peak,
+    /// Most bytes waiting in the GPS TX queue
+    pub gps_tx_peak: Peak,
+    /// Most bytes received from the GPS in one DMA flush
+    pub gps_rx_peak: Peak,
+    /// Times to first fix since GPS power on, see [`crate::ttff`]
+    pub ttff: Durations,
+    /// Highest CPU load of a window in permille, see [`crate::load`]
+    pub cpu_load_peak: Peak,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            overruns: Counter::new(),
+            framing_errors: Counter::new(),
+            noise_errors: Counter::new(),
+            dropped_bytes: Counter::new(),
+            dropped_lines: [Counter::new(), Counter::new(), Counter::new()],
+            sentences: Counter::new(),
+            invalid_sentences: Counter::new(),
+            checksum_errors: Counter::new(),
+            host_tx_peak: Peak::new(),
+            gps_tx_peak: Peak::new(),
+            gps_rx_peak: Peak::new(),
+            ttff: Durations::new(),
+            cpu_load_peak: Peak::new(),
+        }
+    }
+
+    /// Reset all counters to zero
+    pub fn clear(&self) {
+        self.overruns.clear();
+        self.framing_errors.clear();
+        self.noise_errors.clear();
+        self.dropped_bytes.clear();
+        for counter in &self.dropped_lines {
+            counter.clear();
+        }
+        self.sentences.clear();
+        self.invalid_sentences.clear();
+        self.checksum_errors.clear();
+        self.host_tx_peak.clear();
+        self.gps_tx_peak.clear();
+        self.gps_rx_peak.clear();
+        self.ttff.clear();
+        self.cpu_load_peak.clear();
+    }
+}
+
+pub static STATS: Stats = Stats::new();
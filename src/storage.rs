@@ -0,0 +1,187 @@
+//! Non-volatile storage shared by the configuration, the track log and the event log, so each
+//! only lays out its records.
+//!
+//! A [`Storage`] is a byte range read anywhere, written where it is erased and erased in
+//! blocks of [`Storage::ERASE_SIZE`] bytes: internal flash in 2KB pages, see
+//! [`crate::flash::Flash`], or the external EEPROM in pages written with all ones, see
+//! `crate::eeprom`. A [`Partition`] is a range of blocks of one, e.g. the configuration page.
+//!
+//! Records are fixed length and kept in slots, record `n` at `n` times its length, with all
+//! ones marking an erased slot. Each carries the CRC-32 of the bytes before it, [`seal`] and
+//! [`is_sealed`], so a record cut short by a reset or torn by the bus is skipped. The SD card
+//! logger writes text files for a PC through FAT instead, see `crate::sdlog`.
+
+use crate::flash;
+use core::ops::Range;
+
+/// Reading, writing or erasing failed, the range is out of bounds, or data read back differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error;
+
+impl From<flash::Error> for Error {
+    fn from(_: flash::Error) -> Self {
+        Error
+    }
+}
+
+/// Bytes compared at once while checking that a range is erased
+const BLANK_CHUNK: usize = 32;
+
+pub trait Storage {
+    /// Bytes erased at once, the storage is a whole number of them
+    const ERASE_SIZE: usize;
+
+    /// Bytes available
+    fn capacity(&self) -> usize;
+
+    /// Read `buffer.len()` bytes from `offset`
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// Write `data` at `offset`, where it is erased unless the storage is written in place
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+
+    /// Erase block number `block` to all ones
+    fn erase(&mut self, block: usize) -> Result<(), Error>;
+
+    /// Blocks available
+    fn blocks(&self) -> usize {
+        self.capacity() / Self::ERASE_SIZE
+    }
+
+    /// Read the record in slot `slot` of `LEN` byte slots
+    fn read_slot<const LEN: usize>(&mut self, slot: usize) -> Result<[u8; LEN], Error> {
+        let mut record = [0; LEN];
+        self.read(slot * LEN, &mut record)?;
+        Ok(record)
+    }
+
+    /// Write `record` into the erased slot `slot` of `LEN` byte slots
+    fn write_slot<const LEN: usize>(
+        &mut self,
+        slot: usize,
+        record: &[u8; LEN],
+    ) -> Result<(), Error> {
+        self.write(slot * LEN, record)
+    }
+
+    /// `len` bytes from `offset` are all ones
+    fn is_erased(&mut self, mut offset: usize, len: usize) -> Result<bool, Error> {
+        let end = offset + len;
+        let mut chunk = [0; BLANK_CHUNK];
+        while offset < end {
+            let chunk = &mut chunk[..BLANK_CHUNK.min(end - offset)];
+            self.read(offset, chunk)?;
+            if chunk.iter().any(|&byte| byte != 0xFF) {
+                return Ok(false);
+            }
+            offset += chunk.len();
+        }
+        Ok(true)
+    }
+
+    /// Erase block `block` unless it is erased already, sparing flash an erase cycle
+    fn erase_if_used(&mut self, block: usize) -> Result<(), Error> {
+        if !self.is_erased(block * Self::ERASE_SIZE, Self::ERASE_SIZE)? {
+            self.erase(block)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Storage> Storage for &mut S {
+    const ERASE_SIZE: usize = S::ERASE_SIZE;
+
+    fn capacity(&self) -> usize {
+        (**self).capacity()
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        (**self).read(offset, buffer)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        (**self).write(offset, data)
+    }
+
+    fn erase(&mut self, block: usize) -> Result<(), Error> {
+        (**self).erase(block)
+    }
+}
+
+/// Blocks of a storage seen as a storage of their own, offsets counted from the first
+pub struct Partition<S> {
+    storage: S,
+    first: usize,
+    blocks: usize,
+}
+
+impl<S: Storage> Partition<S> {
+    /// `blocks` blocks of `storage` from block `first`
+    pub fn new(storage: S, first: usize, blocks: usize) -> Self {
+        Self {
+            storage,
+            first,
+            blocks,
+        }
+    }
+
+    /// Offset in the whole storage of `len` bytes at `offset`, if they are in the partition
+    fn offset(&self, offset: usize, len: usize) -> Result<usize, Error> {
+        if offset + len > self.capacity() {
+            return Err(Error);
+        }
+        Ok(self.first * S::ERASE_SIZE + offset)
+    }
+}
+
+impl<S: Storage> Storage for Partition<S> {
+    const ERASE_SIZE: usize = S::ERASE_SIZE;
+
+    fn capacity(&self) -> usize {
+        self.blocks * S::ERASE_SIZE
+    }
+
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let offset = self.offset(offset, buffer.len())?;
+        self.storage.read(offset, buffer)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let offset = self.offset(offset, data.len())?;
+        self.storage.write(offset, data)
+    }
+
+    fn erase(&mut self, block: usize) -> Result<(), Error> {
+        if block >= self.blocks {
+            return Err(Error);
+        }
+        self.storage.erase(self.first + block)
+    }
+}
+
+/// Store the CRC-32 of the bytes of `record` before `crc` in it, little endian and cut to the
+/// length of `crc`, 2 or 4 bytes
+pub fn seal(record: &mut [u8], crc: Range<usize>) {
+    let value = crc32(&record[..crc.start]).to_le_bytes();
+    let len = crc.len();
+    record[crc].copy_from_slice(&value[..len]);
+}
+
+/// `record` holds the CRC written by [`seal`]
+pub fn is_sealed(record: &[u8], crc: Range<usize>) -> bool {
+    let value = crc32(&record[..crc.start]).to_le_bytes();
+    record.get(crc.clone()) == value.get(..crc.len())
+}
+
+/// CRC-32 as used by Ethernet and zlib, bitwise to save the table's flash
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
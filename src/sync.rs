@@ -0,0 +1,57 @@
+//! Interrupt priorities and the rules for state shared between them. There is no code here, the
+//! priorities are set on the RTIC tasks in `main.rs` and this is the one place they are argued.
+//!
+//! | Priority | Tasks | Why |
+//! |---|---|---|
+//! | 5 | TIM2 | Captures the timepulse, the timestamp must not wait for other handlers |
+//! | 4 | SysTick | A tick held up for more than its period is lost, [`crate::timer`] callbacks are short |
+//! | 3 | USART2 | Receives the host a byte at a time, RXNE overruns after one character time |
+//! | 2 | USART1, DMA1_CH5, DMA1_CH7, USB_FS, LPUART1, I2C1, EXTI4 | DMA and the USB peripheral buffer data, the Bluetooth module sends commands slowly at 9600 baud, I2C1 stretches the clock and the SPI master waits for data ready, so they tolerate latency |
+//! | 1 | EXTI9_5, RTC_WKUP | Button debouncing and Stop wakeups, nothing is lost by waiting |
+//! | 0 | idle | SD card, display, EEPROM and the commands USART2 hands over, blocking for milliseconds |
+//!
+//! USART2 preempts USART1 so a flush of a full DMA window or a fix completing an epoch doesn't
+//! overrun a command byte. Commands that wait on storage or hardware, `SAVE`, `SELFTEST`,
+//! `LOG EVENTS <n>`, `DUMP` and `ERASE`, are handed to idle, see [`crate::jobs`], which owns the
+//! EEPROM and the configuration records outright. `SELFTEST` pends USART1 to test its own UART,
+//! see [`crate::selftest`], so USART1 keeps a single owner. USART1 only programs the flash log,
+//! idle erases its blocks ahead, see `flashlog`. A running `DUMP` reads the flash log
+//! from DMA1_CH7 under the FLASH lock, taken with that of the TX buffer it fills anyway, and
+//! reading flash doesn't stall.
+//! DMA1_CH5 only pends USART1 and runs at its priority, so a flush is never interrupted by the
+//! request for the next one. USB_FS and LPUART1 likewise only queue command lines in
+//! [`crate::inbox`] and pend USART2, so every command runs in USART2 whatever its port, or in
+//! idle once USART2 has handed it over.
+//!
+//! Invariants, to keep when adding state:
+//!
+//! - There are no `static mut`. Peripherals are taken once in `init` and moved into the
+//!   resources or task locals of their users, and `'static` buffers such as those of DMA are
+//!   RTIC init locals, so a new peripheral gets an owner the same way rather than a global.
+//!   Registers reached through `ptr()` are limited to writes that only touch bits the module
+//!   owns, like BSRR, each with a `SAFETY` comment.
+//! - State of more than one task is an RTIC shared resource and only touched inside `lock`,
+//!   a critical section raising BASEPRI to the highest priority of the tasks sharing it. Locks
+//!   are held for a copy or a short update, never across a wait on hardware, and a line for the
+//!   host is written to `host_tx` under one lock so lines of different tasks never interleave.
+//!   A sink set to `OVERFLOW BLOCK` holds a line that doesn't fit rather than waiting for room,
+//!   its send interrupt queues it and SysTick ends the wait, see [`crate::router::Held`].
+//! - Settings and flags outside the resources are single atomics, e.g. [`crate::filter`] and
+//!   [`crate::quality`]. Writers store whole values and readers load them with `Relaxed`, as no
+//!   other memory is published through them. A setting spread over several atomics changes one
+//!   of them per command, so a reader sees it either before or after the command.
+//! - Counters shared by several priorities are changed by read-modify-write instructions, e.g.
+//!   `fetch_add` in [`crate::stats`], and flags handed from one task to another are taken with
+//!   `swap`, so a preempting writer is never lost between a load and a store.
+//! - The software timer table is used from SysTick and from any task starting a timer, so it is
+//!   a `cortex_m` critical-section mutex, see [`crate::timer`].
+//!   The queue of events for the EEPROM log is one as well, as any task records them.
+//! - The byte queues are resources rather than split single-producer single-consumer halves, as
+//!   neither has a single producer: USART1 queues the saved configuration for the GPS next to
+//!   the commands of USART2, and every task reporting to the host writes `host_tx`. RTIC hands
+//!   out `&mut` to a queue only inside a lock, so producers never alias each other or the
+//!   consumer.
+//! - DMA buffers have a single CPU owner, the task holding the resource. The CPU reads the RX
+//!   buffer only behind the DMA write position, and fills the TX buffer DMA isn't sending, with
+//!   compiler fences ordering the buffer accesses against the channel registers, see
+//!   [`crate::dma`].
@@ -0,0 +1,122 @@
+//! Millisecond tick counter and software timers driven by SysTick.
+//!
+//! SysTick interrupts [`TICK_HZ`] times a second, advancing [`now_ms`] by [`TICK_MS`] and
+//! running the callbacks of due timers from [`on_tick`]. The SysTick task preempts the UART
+//! tasks, see [`crate::sync`], so callbacks must be short and only touch atomics or pend tasks.
+//! SysTick halts in Stop mode, where neither the counter nor the timers advance.
+//!
+//! Timers live in a static table of [`MAX_TIMERS`] entries, started with [`start_oneshot`] or
+//! [`start_periodic`] and stopped with [`cancel`]. A one-shot timer frees its entry before its
+//! callback runs, so the callback may start it again.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+
+pub const TICK_HZ: u32 = 100;
+
+/// Milliseconds per tick, the resolution of [`now_ms`] and the timers
+pub const TICK_MS: u32 = 1000 / TICK_HZ;
+
+/// Entries in the timer table
+pub const MAX_TIMERS: usize = 8;
+
+/// Index of a started timer in the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(usize);
+
+/// Every entry of the timer table is in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableFull;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    callback: fn(),
+    /// [`now_ms`] at which the callback runs next
+    due: u32,
+    /// Interval of a periodic timer
+    period: Option<u32>,
+}
+
+static TICKS_MS: AtomicU32 = AtomicU32::new(0);
+
+static TIMERS: Mutex<RefCell<[Option<Entry>; MAX_TIMERS]>> =
+    Mutex::new(RefCell::new([None; MAX_TIMERS]));
+
+/// Start SysTick from the core clock
+pub fn init(mut syst: SYST, sysclk_hz: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk_hz / TICK_HZ - 1);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+}
+
+/// Milliseconds since [`init`], excluding time in Stop mode. Wraps after 49 days.
+pub fn now_ms() -> u32 {
+    TICKS_MS.load(Ordering::Relaxed)
+}
+
+/// Run `callback` once, `delay_ms` from now rounded up to a tick
+pub fn start_oneshot(delay_ms: u32, callback: fn()) -> Result<TimerId, TableFull> {
+    start(delay_ms, None, callback)
+}
+
+/// Run `callback` every `period_ms`, rounded up to a tick, starting one period from now
+pub fn start_periodic(period_ms: u32, callback: fn()) -> Result<TimerId, TableFull> {
+    let period = period_ms.max(TICK_MS);
+    start(period, Some(period), callback)
+}
+
+/// Stop a timer, a one-shot timer that already ran may have been reused by another
+pub fn cancel(id: TimerId) {
+    interrupt::free(|cs| TIMERS.borrow(cs).borrow_mut()[id.0] = None);
+}
+
+fn start(delay_ms: u32, period: Option<u32>, callback: fn()) -> Result<TimerId, TableFull> {
+    // A due time equal to now would run at the next tick, before `delay_ms` has passed
+    let due = now_ms().wrapping_add(delay_ms.max(1));
+    interrupt::free(|cs| {
+        let mut timers = TIMERS.borrow(cs).borrow_mut();
+        let (index, entry) = timers
+            .iter_mut()
+            .enumerate()
+            .find(|(_, entry)| entry.is_none())
+            .ok_or(TableFull)?;
+        *entry = Some(Entry {
+            callback,
+            due,
+            period,
+        });
+        Ok(TimerId(index))
+    })
+}
+
+/// Advance the counter by a tick and run the due callbacks, called from the SysTick interrupt.
+/// Callbacks run outside the critical section, so they may start and cancel timers.
+pub fn on_tick() {
+    let now = TICKS_MS
+        .fetch_add(TICK_MS, Ordering::Relaxed)
+        .wrapping_add(TICK_MS);
+    for index in 0..MAX_TIMERS {
+        let callback = interrupt::free(|cs| {
+            let mut timers = TIMERS.borrow(cs).borrow_mut();
+            let slot = &mut timers[index];
+            let entry = (*slot)?;
+            // Due times are at most half the counter range ahead
+            if (now.wrapping_sub(entry.due) as i32) < 0 {
+                return None;
+            }
+            *slot = entry.period.map(|period| Entry {
+                due: entry.due.wrapping_add(period),
+                ..entry
+            });
+            Some(entry.callback)
+        });
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+}
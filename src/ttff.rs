@@ -0,0 +1,70 @@
+//! Time to first fix, from turning the GPS on to its first valid fix.
+//!
+//! [`Ttff::on_tick`] runs on every SysTick [`timer`](crate::timer) tick, so times have the tick
+//! resolution. The fix left over from before the GPS was turned off is dropped when it is turned
+//! on, otherwise it would count as a fix at once. Each time is reported to the host as
+//! `$PBRIDGE,TTFF,<ms>*hh` and added to the minimum, average and maximum in [`STATS`], reported
+//! by `TTFF?` and cleared by `STATS CLR`. Power cycles of the supervisor, the button and `PWR`
+//! are all timed, so the statistics compare antenna placement or aiding over many starts.
+
+use crate::nmea;
+use crate::stats::STATS;
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// Change seen by [`Ttff::on_tick`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The GPS was turned on, the fix has to be invalidated
+    PoweredOn,
+    /// First valid fix after `ms` milliseconds, already recorded in [`STATS`]
+    Fix { ms: u32 },
+}
+
+pub struct Ttff {
+    /// GPS power seen at the last tick
+    powered: bool,
+    /// [`crate::timer::now_ms`] of power on while waiting for the first fix
+    since: Option<u32>,
+}
+
+impl Ttff {
+    pub const fn new() -> Self {
+        Self {
+            powered: false,
+            since: None,
+        }
+    }
+
+    /// Check the GPS at `now` milliseconds, see [`crate::timer::now_ms`]
+    pub fn on_tick(&mut self, now: u32, gps_power: bool, fix_valid: bool) -> Option<Event> {
+        if !gps_power {
+            self.powered = false;
+            self.since = None;
+            return None;
+        }
+        if !self.powered {
+            self.powered = true;
+            self.since = Some(now);
+            return Some(Event::PoweredOn);
+        }
+        let since = self.since.filter(|_| fix_valid)?;
+        self.since = None;
+        let ms = now.wrapping_sub(since);
+        STATS.ttff.record(ms);
+        Some(Event::Fix { ms })
+    }
+}
+
+impl Default for Ttff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the `$PBRIDGE,TTFF,<ms>*hh` sentence including line ending
+pub fn write_sentence(out: &mut impl Write, ms: u32) -> fmt::Result {
+    let mut body = String::<32>::new();
+    write!(body, "PBRIDGE,TTFF,{}", ms)?;
+    write!(out, "${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
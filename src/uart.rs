@@ -0,0 +1,122 @@
+//! Baud changes of a UART at runtime, without cutting off what was queued at the old baud.
+//!
+//! [`reconfigure`] switches a port that is done sending. The host port changes baud through
+//! [`Reconfigure`]: `BAUD` responds at the old baud and requests the change, which waits until
+//! the TX DMA buffers are drained and the last byte has been shifted out (TC), then switches. A
+//! host holding CTS, or a transfer that never completes, would keep the old baud forever, so
+//! after [`DRAIN_TIMEOUT_MS`] whatever is still queued is dropped, counted as dropped bytes in
+//! `STATS`, and the port switches anyway.
+
+use crate::baud::Unsupported;
+use crate::chip::pac::Interrupt;
+use crate::dma::DoubleBufferTx;
+use crate::hal::SerialPort;
+use crate::stats::STATS;
+use crate::timer::{self, TimerId};
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::peripheral::NVIC;
+
+/// Longest wait for the queued bytes to be sent before a baud change, the two default TX
+/// buffers of 256 bytes take about 530ms at 9600 baud
+pub const DRAIN_TIMEOUT_MS: u32 = 1000;
+
+/// The drain timeout of the pending change has expired
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Switch `port` to `baud` from a `clock_hz` kernel clock and stop waiting for TC. A byte being
+/// shifted out is cut short, so the port must be done sending.
+pub fn reconfigure(port: &impl SerialPort, clock_hz: u32, baud: u32) -> Result<(), Unsupported> {
+    // BRR can only be written while the USART is disabled
+    port.set_enabled(false);
+    let result = port.set_baud(clock_hz, baud);
+    port.listen_transmit_complete(false);
+    port.set_enabled(true);
+    result
+}
+
+/// Timer callback ending the wait for the host port to drain
+fn drain_timed_out() {
+    TIMED_OUT.store(true, Ordering::Relaxed);
+    NVIC::pend(Interrupt::USART2);
+}
+
+/// Baud change of the host port that has been switched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Switched {
+    pub baud: u32,
+    /// Queued bytes weren't sent within [`DRAIN_TIMEOUT_MS`] and were dropped, counted in
+    /// [`STATS`]
+    pub timed_out: bool,
+}
+
+/// Baud change of the host port waiting for its queue to drain
+pub struct Reconfigure {
+    pending: Option<u32>,
+    /// Timer of the drain timeout
+    timeout: Option<TimerId>,
+}
+
+impl Reconfigure {
+    pub const fn new() -> Self {
+        Self {
+            pending: None,
+            timeout: None,
+        }
+    }
+
+    /// Baud the port switches to once drained
+    pub fn pending(&self) -> Option<u32> {
+        self.pending
+    }
+
+    /// Switch `port` to `baud` once the bytes queued so far have been sent, replacing a change
+    /// that is still pending
+    pub fn request(&mut self, port: &impl SerialPort, baud: u32) {
+        self.cancel_timeout();
+        TIMED_OUT.store(false, Ordering::Relaxed);
+        // Without a free timer the change waits for the drain alone
+        self.timeout = timer::start_oneshot(DRAIN_TIMEOUT_MS, drain_timed_out).ok();
+        self.pending = Some(baud);
+        port.listen_transmit_complete(true);
+    }
+
+    /// Checked on every interrupt of the host port: switch once `host_tx` is drained and TC
+    /// is set, or drop what is queued once timed out
+    pub fn poll(
+        &mut self,
+        port: &impl SerialPort,
+        clock_hz: u32,
+        host_tx: &mut DoubleBufferTx,
+    ) -> Option<Switched> {
+        let baud = self.pending?;
+        let timed_out = TIMED_OUT.swap(false, Ordering::Relaxed);
+        if timed_out {
+            // The one-shot timer has freed its entry
+            self.timeout = None;
+            STATS.dropped_bytes.add(host_tx.discard() as u32);
+        } else if !host_tx.is_idle() {
+            // Another transfer started, wait for it to complete
+            port.clear_transmit_complete();
+            return None;
+        } else if !port.is_transmit_complete() {
+            return None;
+        }
+        self.cancel_timeout();
+        self.pending = None;
+        // Checked by the command
+        let _ = reconfigure(port, clock_hz, baud);
+        Some(Switched { baud, timed_out })
+    }
+
+    fn cancel_timeout(&mut self) {
+        if let Some(timeout) = self.timeout.take() {
+            timer::cancel(timeout);
+        }
+    }
+}
+
+impl Default for Reconfigure {
+    fn default() -> Self {
+        Self::new()
+    }
+}
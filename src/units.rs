@@ -0,0 +1,25 @@
+//! Speed and altitude units of the status line and binary fix records, selected by `UNITS`.
+//! Fixes are kept in millimetres, the conversion is done as they are sent, see [`crate::geo`].
+//! NMEA passed through from the GPS keeps its own units.
+
+use crate::geo::{AltitudeUnit, SpeedUnit};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+static SPEED: AtomicU8 = AtomicU8::new(SpeedUnit::Mps as u8);
+static ALTITUDE: AtomicU8 = AtomicU8::new(AltitudeUnit::M as u8);
+
+pub fn speed_unit() -> SpeedUnit {
+    SpeedUnit::from_code(SPEED.load(Ordering::Relaxed)).unwrap_or(SpeedUnit::Mps)
+}
+
+pub fn set_speed_unit(unit: SpeedUnit) {
+    SPEED.store(unit.code(), Ordering::Relaxed);
+}
+
+pub fn altitude_unit() -> AltitudeUnit {
+    AltitudeUnit::from_code(ALTITUDE.load(Ordering::Relaxed)).unwrap_or(AltitudeUnit::M)
+}
+
+pub fn set_altitude_unit(unit: AltitudeUnit) {
+    ALTITUDE.store(unit.code(), Ordering::Relaxed);
+}
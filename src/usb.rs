@@ -0,0 +1,161 @@
+//! USB CDC-ACM virtual COM port carrying the NMEA stream, enabled by the `usb` feature. Commands
+//! received on it are run like those of the host, see [`crate::inbox`].
+//!
+//! Lines are queued whole in a [`QUEUE_LEN`] byte queue while a terminal has the port open, by
+//! priority class, and handed to the port as its packets go out. A line that doesn't fit is
+//! handled by the `OVERFLOW` policy of USB, see [`crate::linequeue`].
+//!
+//! The USB FS peripheral uses PA11 (DM) and PA12 (DP), so GPS power control moves from A12 to A8.
+//! USB is clocked from HSI48, which the CRS trims to the host's start of frame packets every
+//! millisecond, so no crystal is needed. The CRS only trims HSI48, MSI can't be used.
+//! The APB1 clock must be at least 10MHz to serve the packet memory, which needs a clock feature.
+
+use crate::chip::pac::{CRS, GPIOA, PWR, RCC, USB};
+use crate::clocks;
+use crate::linequeue::LineQueue;
+use crate::router::{Class, Sink};
+use stm32_usbd::{UsbBus, UsbPeripheral};
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+#[cfg(not(any(feature = "clock-msi-48", feature = "clock-pll-80")))]
+compile_error!("feature `usb` requires `clock-msi-48` or `clock-pll-80`");
+
+/// Bytes of lines waiting for the port
+pub const QUEUE_LEN: usize = 512;
+
+/// pid.codes shared VID/PID for CDC-ACM serial devices
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+
+/// USB FS peripheral handed to the bus driver
+pub struct Peripheral {
+    _usb: USB,
+}
+
+// SAFETY: the registers are only accessed through the bus driver, which serializes access
+unsafe impl Sync for Peripheral {}
+
+// SAFETY: addresses and memory layout of the STM32L4 USB FS peripheral
+unsafe impl UsbPeripheral for Peripheral {
+    const REGISTERS: *const () = USB::PTR as *const ();
+    const DP_PULL_UP_FEATURE: bool = true;
+    const EP_MEMORY: *const () = 0x4000_6C00 as *const ();
+    const EP_MEMORY_SIZE: usize = 1024;
+    const EP_MEMORY_ACCESS_2X16: bool = true;
+
+    fn enable() {
+        // SAFETY: sets a single clock enable bit, RCC is otherwise only configured during init
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.apb1enr1.modify(|_, w| w.usbfsen().set_bit());
+    }
+
+    fn startup_delay() {
+        // Transceiver startup time is 1us
+        cortex_m::asm::delay(clocks::SYSCLK_HZ / 1_000_000);
+    }
+}
+
+pub type Bus = UsbBus<Peripheral>;
+
+/// Start HSI48 with CRS trimming, validate the USB supply and configure PA11/PA12 as alternate
+/// function 10. PWR and GPIOA clocks must be enabled. The bus allocator is stored in `bus`, an
+/// RTIC init local, as the port borrows it for good.
+pub fn init(
+    rcc: &RCC,
+    crs: &CRS,
+    pwr: &PWR,
+    gpioa: &GPIOA,
+    usb: USB,
+    bus: &'static mut Option<UsbBusAllocator<Bus>>,
+) -> &'static UsbBusAllocator<Bus> {
+    rcc.crrcr.modify(|_, w| w.hsi48on().set_bit());
+    while rcc.crrcr.read().hsi48rdy().bit_is_clear() {}
+    rcc.ccipr.modify(|_, w| w.clk48sel().hsi48());
+    rcc.apb1enr1.modify(|_, w| w.crsen().set_bit());
+    // USB SOF is the default synchronization source
+    crs.cr
+        .modify(|_, w| w.autotrimen().set_bit().cen().set_bit());
+    pwr.cr2.modify(|_, w| w.usv().set_bit());
+
+    gpioa
+        .moder
+        .modify(|_, w| w.moder11().alternate().moder12().alternate());
+    gpioa.ospeedr.modify(|_, w| {
+        w.ospeedr11()
+            .very_high_speed()
+            .ospeedr12()
+            .very_high_speed()
+    });
+    gpioa.afrh.modify(|_, w| w.afrh11().af10().afrh12().af10());
+
+    bus.insert(UsbBus::new(Peripheral { _usb: usb }))
+}
+
+/// Virtual COM port device
+pub struct Serial {
+    device: UsbDevice<'static, Bus>,
+    port: SerialPort<'static, Bus>,
+    queue: LineQueue<QUEUE_LEN>,
+}
+
+impl Serial {
+    pub fn new(bus: &'static UsbBusAllocator<Bus>) -> Self {
+        let port = SerialPort::new(bus);
+        let strings = StringDescriptors::default()
+            .manufacturer("binotation")
+            .product("GP-735T GPS");
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .strings(&[strings])
+            // Only fails for more than 16 languages
+            .unwrap()
+            .device_class(USB_CLASS_CDC)
+            .build();
+        Self {
+            device,
+            port,
+            queue: LineQueue::new(Sink::Usb),
+        }
+    }
+
+    /// Handle the USB interrupt and hand queued lines to the port, or drop them once the
+    /// terminal has closed it
+    pub fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.port]);
+        if self.port.dtr() {
+            send(&mut self.port, &mut self.queue);
+        } else {
+            self.queue.clear();
+        }
+    }
+
+    /// Take bytes received from the host into `buffer`, returns how many, 0 once none are left
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        self.port.read(buffer).unwrap_or(0)
+    }
+
+    /// Queue `line` of `class` whole by the overflow policy of USB if a terminal has the port
+    /// open
+    pub fn write(&mut self, class: Class, line: &[u8]) {
+        if !self.port.dtr() {
+            return;
+        }
+        self.queue.push(class, line);
+        send(&mut self.port, &mut self.queue);
+    }
+
+    /// Called by SysTick at `now_ms`, see [`LineQueue::on_tick`]
+    pub fn on_tick(&mut self, now_ms: u32) {
+        self.queue.on_tick(now_ms);
+    }
+}
+
+/// Hand queued bytes to the port until its buffer is full
+fn send(port: &mut SerialPort<'static, Bus>, queue: &mut LineQueue<QUEUE_LEN>) {
+    while !queue.is_empty() {
+        match port.write(queue.peek()) {
+            Ok(written) => queue.consume(written),
+            Err(_) => break,
+        }
+    }
+}
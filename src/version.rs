@@ -0,0 +1,40 @@
+//! Firmware version and the STM32 unique device ID, reported by `VERSION?`.
+//!
+//! The version is the crate's from `Cargo.toml` and the git hash that of the commit built, see
+//! `build.rs`. The 96-bit UID is programmed by ST from the wafer lot, wafer number and die
+//! position, so the IDs of parts from one lot differ in a few bits only. [`short_id`] mixes them
+//! into 32 bits with a CRC-32, included in every `$PBRIDGE,STATUS` sentence to tell bridges on a
+//! shared bus apart.
+
+use crate::storage;
+use core::fmt::{self, Write};
+
+/// Version of `Cargo.toml`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short commit hash, `unknown` if not built from a git checkout
+pub const GIT_HASH: &str = env!("BRIDGE_GIT_HASH");
+
+/// Unique device ID, three words in system memory
+const UID: *const [u32; 3] = 0x1FFF_7590 as *const [u32; 3];
+
+/// The 96-bit unique device ID, lowest word first
+pub fn uid() -> [u32; 3] {
+    // SAFETY: the UID is readable in system memory of every STM32L4
+    unsafe { core::ptr::read_volatile(UID) }
+}
+
+/// CRC-32 of the UID
+pub fn short_id() -> u32 {
+    let mut bytes = [0; 12];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(uid()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    storage::crc32(&bytes)
+}
+
+/// Write the UID as 24 hexadecimal digits, highest word first
+pub fn write_uid(out: &mut impl Write) -> fmt::Result {
+    let [low, middle, high] = uid();
+    write!(out, "{:08X}{:08X}{:08X}", high, middle, low)
+}
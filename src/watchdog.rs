@@ -0,0 +1,57 @@
+//! Independent watchdog supervising GPS reception.
+//!
+//! The IWDG runs from LSI and keeps counting through Sleep and Stop, resetting the MCU unless
+//! reloaded within [`TIMEOUT_MS`]. [`Watchdog::service`] only reloads it while USART1 has
+//! received bytes since the previous reload or the GPS is off on purpose, including sleeping
+//! between fixes in power save mode, see [`crate::psm`]. A hung interrupt chain or a GPS that
+//! stopped talking therefore ends in a reset rather than a silent lockup.
+//! The RTC wakeup timer keeps the core from staying in Stop past the timeout, see
+//! [`crate::rtc::Rtc::enable_wakeup`].
+
+use crate::chip::pac::IWDG;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Nominal time without reload until reset. LSI is only accurate to a few percent.
+pub const TIMEOUT_MS: u16 = 4000;
+
+/// Longest time between wakeups from Stop that still reloads in time
+pub const SERVICE_PERIOD_S: u16 = 1;
+
+/// Largest value of the 12 bit reload register
+const MAX_RELOAD: u16 = 0xFFF;
+
+/// USART1 received bytes since the last reload
+static RX_ACTIVITY: AtomicBool = AtomicBool::new(false);
+
+/// Record that the USART1 RX path is alive
+pub fn rx_activity() {
+    RX_ACTIVITY.store(true, Ordering::Relaxed);
+}
+
+pub struct Watchdog {
+    iwdg: IWDG,
+}
+
+impl Watchdog {
+    /// Start the IWDG, which also starts LSI. Once started it can't be stopped until reset.
+    pub fn start(iwdg: IWDG) -> Self {
+        const _: () = assert!(TIMEOUT_MS <= MAX_RELOAD);
+        iwdg.kr.write(|w| w.key().start());
+        // Prescaler and reload registers are write protected until enabled by the key
+        iwdg.kr.write(|w| w.key().enable());
+        // 32kHz LSI / 32 counts milliseconds
+        iwdg.pr.write(|w| w.pr().divide_by32());
+        iwdg.rlr.write(|w| w.rl().bits(TIMEOUT_MS));
+        while iwdg.sr.read().bits() != 0 {}
+        iwdg.kr.write(|w| w.key().reset());
+        Self { iwdg }
+    }
+
+    /// Reload the counter if USART1 received bytes since the last reload or `gps_off` is set
+    /// because the GPS was turned off on purpose
+    pub fn service(&mut self, gps_off: bool) {
+        if RX_ACTIVITY.swap(false, Ordering::Relaxed) || gps_off {
+            self.iwdg.kr.write(|w| w.key().reset());
+        }
+    }
+}
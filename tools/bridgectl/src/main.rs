@@ -0,0 +1,247 @@
+//! Host companion of the GP-735T bridge: sends commands to its host UART, prints the responses,
+//! uploads geofence zones and decodes the binary output of `MODE BIN`.
+//!
+//! ```text
+//! bridgectl [-p <port>] [-b <baud>] <command> [<args>]
+//! ```
+//!
+//! Forwarded sentences, `$PBRIDGE` sentences and UBX acknowledgements arriving while a command
+//! waits for its response are skipped, `listen` prints everything.
+
+mod port;
+
+use gp735t_proto::geo::{AltitudeUnit, SpeedUnit};
+use gp735t_proto::protocol::{FixRecord, RECORD_FIX, TEMPERATURE_UNKNOWN};
+use port::{Event, Port};
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+
+/// Host baud of the bridge at boot without a saved configuration
+const DEFAULT_BAUD: u32 = 115_200;
+
+/// Longest wait for a response, saving the configuration erases a flash page
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Zones the bridge stores
+const MAX_ZONES: usize = 4;
+
+const USAGE: &str = "\
+usage: bridgectl [-p <port>] [-b <baud>] <command> [<args>]
+
+options:
+  -p <port>   serial port of the bridge's host UART, /dev/ttyUSB0 by default
+  -b <baud>   host baud of the bridge, 115200 by default
+
+commands:
+  info                      firmware version and status
+  status                    GPS power, navigation rate and the latest fix
+  stats [clr]               error counters, or reset them
+  filter [<types>]          report or select the forwarded sentence types
+  rate <hz>                 navigation rate, 1, 5 or 10
+  baud [gps] <rate>         host or GPS baud, follows a host baud change
+  mode [nmea|bin]           report or select the output
+  zones <file>              replace the geofence zones with `<lat> <lon> <radius_m>` lines
+  save                      store the settings in flash
+  send <command>...         send any command line
+  listen                    print the output, decoding binary fix records";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("bridgectl: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    let mut path = DEFAULT_PORT.to_owned();
+    let mut baud = DEFAULT_BAUD;
+    let mut args = args.into_iter();
+    let command = loop {
+        match args.next().as_deref() {
+            Some("-p") => path = args.next().ok_or("-p needs a port")?,
+            Some("-b") => baud = args.next().ok_or("-b needs a baud")?.parse()?,
+            Some("-h" | "--help") | None => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            Some(command) => break command.to_owned(),
+        }
+    };
+    let args: Vec<String> = args.collect();
+    let mut port = Port::open(&path, baud)?;
+
+    match (command.as_str(), args.as_slice()) {
+        ("info", []) => {
+            println!("{}", request(&mut port, "VERSION?")?);
+            println!("{}", request(&mut port, "STATUS")?);
+            println!("{}", request(&mut port, "MODE")?);
+            println!("{}", request(&mut port, "FILTER")?);
+            Ok(())
+        }
+        ("status", []) => print(&mut port, "STATUS"),
+        ("stats", []) => print(&mut port, "STATS"),
+        ("stats", [clr]) if clr.eq_ignore_ascii_case("clr") => print(&mut port, "STATS CLR"),
+        ("filter", []) => print(&mut port, "FILTER"),
+        ("filter", [types]) => print(&mut port, &format!("FILTER {}", types)),
+        ("rate", [hz]) => print(&mut port, &format!("RATE {}", hz)),
+        ("baud", [rate]) => host_baud(&mut port, rate.parse()?),
+        ("baud", [gps, rate]) if gps.eq_ignore_ascii_case("gps") => {
+            print(&mut port, &format!("BAUD GPS {}", rate))
+        }
+        ("mode", []) => print(&mut port, "MODE"),
+        ("mode", [mode]) => print(&mut port, &format!("MODE {}", mode)),
+        ("zones", [file]) => upload_zones(&mut port, file),
+        ("save", []) => print(&mut port, "SAVE"),
+        ("send", [_, ..]) => print(&mut port, &args.join(" ")),
+        ("listen", []) => listen(&mut port),
+        _ => Err("unknown command or arguments, see bridgectl -h".into()),
+    }
+}
+
+/// Send a command and return its response, `ERR` responses as errors
+fn request(port: &mut Port, line: &str) -> Result<String> {
+    port.send(line)?;
+    while let Some(event) = port.next_event(RESPONSE_TIMEOUT)? {
+        let Event::Line(response) = event else {
+            continue;
+        };
+        if response.starts_with('$')
+            || response.starts_with("UBX ACK")
+            || response.starts_with("UBX NAK")
+        {
+            continue;
+        }
+        if let Some(reason) = response.strip_prefix("ERR ") {
+            return Err(format!("{}: {}", line, reason).into());
+        }
+        return Ok(response);
+    }
+    Err(format!("{}: no response", line).into())
+}
+
+fn print(port: &mut Port, line: &str) -> Result<()> {
+    println!("{}", request(port, line)?);
+    Ok(())
+}
+
+/// Change the host baud: `OK` arrives at the old baud, `BAUD <rate>` at the new one
+fn host_baud(port: &mut Port, baud: u32) -> Result<()> {
+    print(port, &format!("BAUD {}", baud))?;
+    port.set_baud(baud)?;
+    while let Some(event) = port.next_event(RESPONSE_TIMEOUT)? {
+        if let Event::Line(line) = event {
+            if line.starts_with("BAUD ") {
+                println!("{}", line);
+                return Ok(());
+            }
+        }
+    }
+    Err("no confirmation at the new baud".into())
+}
+
+/// Replace the geofence zones with those of a file, one `<lat> <lon> <radius_m>` per line.
+/// Blank lines and lines starting with `#` are skipped.
+fn upload_zones(port: &mut Port, file: &str) -> Result<()> {
+    let contents = fs::read_to_string(file)?;
+    let zones: Vec<Vec<&str>> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    if zones.len() > MAX_ZONES {
+        return Err(format!("{} zones, the bridge stores {}", zones.len(), MAX_ZONES).into());
+    }
+    // Check the whole file before the zones on the bridge are removed
+    if let Some(id) = zones.iter().position(|fields| fields.len() != 3) {
+        return Err(format!("zone {}: expected <lat> <lon> <radius_m>", id).into());
+    }
+    request(port, "ZONE CLR")?;
+    for (id, fields) in zones.iter().enumerate() {
+        request(port, &format!("ZONE {} {}", id, fields.join(" ")))?;
+        println!("{}", request(port, &format!("ZONE {}", id))?);
+    }
+    println!(
+        "{} zones uploaded, `bridgectl save` keeps them",
+        zones.len()
+    );
+    Ok(())
+}
+
+/// Print lines as received and binary fix records decoded, until interrupted
+fn listen(port: &mut Port) -> Result<()> {
+    loop {
+        match port.next_event(RESPONSE_TIMEOUT)? {
+            Some(Event::Line(line)) => println!("{}", line),
+            Some(Event::Frame(RECORD_FIX, record)) => match FixRecord::from_bytes(&record) {
+                Some(fix) => println!("{}", format_fix(&fix)),
+                None => println!("FIX record of {} bytes", record.len()),
+            },
+            Some(Event::Frame(kind, record)) => {
+                println!("record type {:02X} of {} bytes", kind, record.len())
+            }
+            None => {}
+        }
+    }
+}
+
+/// One line of a binary fix record, in units a person reads
+fn format_fix(fix: &FixRecord) -> String {
+    let fix_type = match fix.fix_type {
+        2 => "2D",
+        3 => "3D",
+        _ => "NONE",
+    };
+    let time = match fix.ms_of_day {
+        u32::MAX => "--:--:--.---".to_owned(),
+        ms => format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        ),
+    };
+    let speed_unit = match fix.speed_unit {
+        SpeedUnit::Mps => "m/s",
+        SpeedUnit::Kmh => "km/h",
+        SpeedUnit::Mph => "mph",
+        SpeedUnit::Knots => "kn",
+    };
+    let altitude_unit = match fix.altitude_unit {
+        AltitudeUnit::M => "m",
+        AltitudeUnit::Ft => "ft",
+    };
+    let temperature = match fix.temperature {
+        TEMPERATURE_UNKNOWN => "-".to_owned(),
+        temperature => format!("{:.1}C", f64::from(temperature) / 10.0),
+    };
+    format!(
+        "FIX {} {:04}-{:02}-{:02} {} lat={:.7} lon={:.7} alt={:.3}{} speed={:.3}{} \
+         course={:.2} sats={} hdop={:.2} temp={}",
+        fix_type,
+        fix.year,
+        fix.month,
+        fix.day,
+        time,
+        f64::from(fix.lat) * 1e-7,
+        f64::from(fix.lon) * 1e-7,
+        f64::from(fix.altitude) / 1000.0,
+        altitude_unit,
+        f64::from(fix.speed) / 1000.0,
+        speed_unit,
+        f64::from(fix.course) / 100.0,
+        fix.sats,
+        f64::from(fix.hdop) / 100.0,
+        temperature
+    )
+}
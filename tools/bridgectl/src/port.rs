@@ -0,0 +1,129 @@
+//! Serial port of the bridge's host UART, split into response lines and binary frames.
+//!
+//! The port is opened as a file and configured with `stty`, raw with reads returning after at
+//! most [`READ_TIMEOUT_DS`] tenths of a second, so no serial port crate is needed on Linux.
+
+use gp735t_proto::protocol::{self, MAX_FRAME_LEN};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Longest wait of a single read, in tenths of a second as `stty time` takes it
+const READ_TIMEOUT_DS: u32 = 1;
+
+/// Output of the bridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Text line without line ending: a response, a forwarded sentence or a `$PBRIDGE` sentence
+    Line(String),
+    /// Binary record of `MODE BIN`, its type and contents
+    Frame(u8, Vec<u8>),
+}
+
+pub struct Port {
+    path: String,
+    file: File,
+    /// Bytes received and not yet returned as an event
+    received: Vec<u8>,
+}
+
+impl Port {
+    pub fn open(path: &str, baud: u32) -> io::Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path, error)))?;
+        configure(path, baud)?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            received: Vec::new(),
+        })
+    }
+
+    /// Follow a baud change of the bridge
+    pub fn set_baud(&mut self, baud: u32) -> io::Result<()> {
+        configure(&self.path, baud)
+    }
+
+    /// Send a command line, the line ending is appended
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\r\n")?;
+        self.file.flush()
+    }
+
+    /// Next line or frame received within `timeout`, `None` once it has passed
+    pub fn next_event(&mut self, timeout: Duration) -> io::Result<Option<Event>> {
+        let start = Instant::now();
+        loop {
+            if let Some(event) = self.take_event() {
+                return Ok(Some(event));
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            let mut buffer = [0; 256];
+            let count = self.file.read(&mut buffer)?;
+            self.received.extend_from_slice(&buffer[..count]);
+        }
+    }
+
+    /// Split the first complete line or frame off the received bytes
+    fn take_event(&mut self) -> Option<Event> {
+        loop {
+            match self.received.first()? {
+                0 => {
+                    // A frame starts with a zero and ends at the next one
+                    let end = self.received[1..].iter().position(|&byte| byte == 0);
+                    match end {
+                        Some(len) => {
+                            let decoded = protocol::decode_frame(&self.received[1..1 + len]);
+                            if let Ok((kind, record)) = decoded {
+                                self.received.drain(..len + 2);
+                                return Some(Event::Frame(kind, record.to_vec()));
+                            }
+                        }
+                        None if self.received.len() < MAX_FRAME_LEN => return None,
+                        None => {}
+                    }
+                    // Not a frame, the zero ended one that was cut short
+                    self.received.remove(0);
+                }
+                _ => {
+                    let end = self
+                        .received
+                        .iter()
+                        .position(|&byte| byte == b'\n' || byte == 0)?;
+                    let line: Vec<u8> = match self.received[end] {
+                        b'\n' => self.received.drain(..=end).collect(),
+                        // Text cut short by a frame
+                        _ => self.received.drain(..end).collect(),
+                    };
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end_matches(['\r', '\n']);
+                    if !line.is_empty() {
+                        return Some(Event::Line(line.to_owned()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Set the baud and raw mode of a serial port with `stty`
+fn configure(path: &str, baud: u32) -> io::Result<()> {
+    let status = Command::new("stty")
+        .arg("-F")
+        .arg(path)
+        .arg(baud.to_string())
+        .args(["raw", "-echo", "min", "0", "time"])
+        .arg(READ_TIMEOUT_DS.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("stty can't configure {}", path)));
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Deliberate corruption of the sentences sent, each kind with its own probability in percent.
+
+/// xorshift64* generator, reproducible from its seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `0..bound`
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound.max(1) as u64) as usize
+    }
+
+    /// True with a probability of `percent`
+    pub fn chance(&mut self, percent: f64) -> bool {
+        (self.next() % 1_000_000) as f64 / 10_000.0 < percent
+    }
+}
+
+/// Probabilities of each kind of corruption in percent
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rates {
+    /// A sentence with one character changed, so its checksum fails
+    pub bad_checksum: f64,
+    /// A sentence cut short without line ending, the next `$` resynchronizes
+    pub partial: f64,
+    /// Random bytes between sentences, including zero bytes
+    pub noise: f64,
+    /// An epoch repeated back to back without pacing, more than the bridge forwards in time
+    /// once the GPS baud is above the host's
+    pub burst: f64,
+}
+
+/// Counts of the corruptions sent
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counts {
+    pub sentences: u64,
+    pub bad_checksum: u64,
+    pub partial: u64,
+    pub noise: u64,
+    pub burst: u64,
+}
+
+/// Epochs sent by a burst
+pub const BURST_EPOCHS: usize = 20;
+
+pub struct Corrupter {
+    rates: Rates,
+    rng: Rng,
+    pub counts: Counts,
+}
+
+impl Corrupter {
+    pub fn new(rates: Rates, seed: u64) -> Self {
+        Self {
+            rates,
+            rng: Rng::new(seed),
+            counts: Counts::default(),
+        }
+    }
+
+    /// Bytes to send for an epoch of sentences with line endings
+    pub fn epoch(&mut self, sentences: &[String]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for sentence in sentences {
+            self.sentence(sentence.as_bytes(), &mut bytes);
+        }
+        if self.rng.chance(self.rates.burst) {
+            self.counts.burst += 1;
+            let epoch = bytes.clone();
+            for _ in 1..BURST_EPOCHS {
+                bytes.extend_from_slice(&epoch);
+            }
+        }
+        bytes
+    }
+
+    fn sentence(&mut self, sentence: &[u8], out: &mut Vec<u8>) {
+        self.counts.sentences += 1;
+        if self.rng.chance(self.rates.noise) {
+            self.counts.noise += 1;
+            for _ in 0..1 + self.rng.below(16) {
+                out.push(self.rng.next() as u8);
+            }
+        }
+        // Characters between `$` and `*`
+        let body = 1..sentence.len().saturating_sub(5).max(1);
+        if self.rng.chance(self.rates.bad_checksum) && !body.is_empty() {
+            self.counts.bad_checksum += 1;
+            let mut sentence = sentence.to_vec();
+            let index = body.start + self.rng.below(body.len());
+            // Flipping a low bit keeps the character printable and off `$` and `*`
+            sentence[index] ^= match sentence[index] {
+                b'%' | b'+' => 2,
+                _ => 1,
+            };
+            out.extend_from_slice(&sentence);
+        } else if self.rng.chance(self.rates.partial) {
+            self.counts.partial += 1;
+            out.extend_from_slice(&sentence[..1 + self.rng.below(sentence.len() - 2)]);
+        } else {
+            out.extend_from_slice(sentence);
+        }
+    }
+}
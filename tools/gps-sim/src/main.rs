@@ -0,0 +1,217 @@
+//! GPS simulator for testing the GP-735T bridge in the loop: sends NMEA from a log file or a
+//! synthesized track out of a host serial port wired to the bridge's USART1 RX, in place of the
+//! GPS, and injects deliberate corruption to exercise its parser and error paths.
+//!
+//! ```text
+//! gps-sim [-p <port>] [-b <baud>] [<options>] replay <file> | track
+//! ```
+//!
+//! Compare the corruption counts printed on exit with the bridge's `STATS`.
+
+mod corrupt;
+mod track;
+
+use corrupt::{Corrupter, Rates};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::process::{Command, ExitCode};
+use std::thread;
+use std::time::{Duration, Instant};
+use track::Track;
+
+const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+
+/// Baud of the GP-735T at power on
+const DEFAULT_BAUD: u32 = 9600;
+
+const USAGE: &str = "\
+usage: gps-sim [-p <port>] [-b <baud>] [<options>] replay <file> | track
+
+Sends NMEA out of a serial port in place of the GPS, `-p -` writes to stdout.
+
+options:
+  -p <port>             serial port wired to the bridge's USART1 RX, /dev/ttyUSB0 by default
+  -b <baud>             GPS baud, 9600 by default
+  --rate <hz>           epochs per second, 1 by default
+  --count <epochs>      stop after this many epochs, replay stops at the end of the file
+  --loop                replay the file again from the start
+  --seed <n>            seed of the corruption, the same seed corrupts the same sentences
+  --bad-checksum <pct>  sentences with a character changed so their checksum fails
+  --partial <pct>       sentences cut short without line ending
+  --noise <pct>         random bytes before sentences
+  --burst <pct>         epochs sent 20 times back to back without pacing
+
+track options:
+  --lat <deg> --lon <deg>  centre of the circle driven, -33.8568 151.2153 by default
+  --radius <m>             radius of the circle, 200 by default
+  --speed <m/s>            speed along the circle, 10 by default";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+struct Options {
+    path: String,
+    baud: u32,
+    rate_hz: f64,
+    count: Option<u64>,
+    repeat: bool,
+    seed: u64,
+    rates: Rates,
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+    speed_mps: f64,
+}
+
+enum Source {
+    Replay(String),
+    Track,
+}
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("gps-sim: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    let mut options = Options {
+        path: DEFAULT_PORT.to_owned(),
+        baud: DEFAULT_BAUD,
+        rate_hz: 1.0,
+        count: None,
+        repeat: false,
+        seed: 1,
+        rates: Rates::default(),
+        lat: -33.8568,
+        lon: 151.2153,
+        radius_m: 200.0,
+        speed_mps: 10.0,
+    };
+    let mut args = args.into_iter();
+    let source = loop {
+        let Some(arg) = args.next() else {
+            println!("{}", USAGE);
+            return Ok(());
+        };
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "-p" => options.path = value()?,
+            "-b" => options.baud = value()?.parse()?,
+            "--rate" => options.rate_hz = value()?.parse()?,
+            "--count" => options.count = Some(value()?.parse()?),
+            "--loop" => options.repeat = true,
+            "--seed" => options.seed = value()?.parse()?,
+            "--bad-checksum" => options.rates.bad_checksum = value()?.parse()?,
+            "--partial" => options.rates.partial = value()?.parse()?,
+            "--noise" => options.rates.noise = value()?.parse()?,
+            "--burst" => options.rates.burst = value()?.parse()?,
+            "--lat" => options.lat = value()?.parse()?,
+            "--lon" => options.lon = value()?.parse()?,
+            "--radius" => options.radius_m = value()?.parse()?,
+            "--speed" => options.speed_mps = value()?.parse()?,
+            "replay" => break Source::Replay(value()?),
+            "track" => break Source::Track,
+            _ => return Err(format!("unknown argument {}, see gps-sim -h", arg).into()),
+        }
+    };
+    if args.next().is_some() {
+        return Err("arguments after the source, see gps-sim -h".into());
+    }
+    if !(options.rate_hz > 0.0 && options.rate_hz <= 10.0) {
+        return Err("--rate must be above 0 and at most 10".into());
+    }
+
+    let mut out = open(&options.path, options.baud)?;
+    let mut corrupter = Corrupter::new(options.rates, options.seed);
+    let interval = Duration::from_secs_f64(1.0 / options.rate_hz);
+    let mut epochs: Box<dyn Iterator<Item = Vec<String>>> = match source {
+        Source::Replay(file) => Box::new(replay(&file, options.repeat)?),
+        Source::Track => {
+            let interval_ms = interval.as_millis() as u64;
+            let mut track = Track::new(
+                options.lat,
+                options.lon,
+                options.radius_m,
+                options.speed_mps,
+                interval_ms,
+            );
+            Box::new(std::iter::repeat_with(move || track.next_epoch()))
+        }
+    };
+
+    let start = Instant::now();
+    let mut sent = 0;
+    while options.count.is_none_or(|count| sent < count) {
+        let Some(epoch) = epochs.next() else {
+            break;
+        };
+        let bytes = corrupter.epoch(&epoch);
+        out.write_all(&bytes)?;
+        out.flush()?;
+        sent += 1;
+        // Paced from the start so write times don't add up
+        if let Some(wait) = (start + interval * sent as u32).checked_duration_since(Instant::now())
+        {
+            thread::sleep(wait);
+        }
+    }
+    let counts = corrupter.counts;
+    eprintln!(
+        "gps-sim: {} epochs, {} sentences, {} bad checksums, {} partial, {} noise, {} bursts",
+        sent, counts.sentences, counts.bad_checksum, counts.partial, counts.noise, counts.burst
+    );
+    Ok(())
+}
+
+/// Epochs of a log file, a new one starts at each RMC sentence as the GP-735T sends it first.
+/// Lines that aren't sentences are skipped and line endings replaced by CRLF.
+fn replay(file: &str, repeat: bool) -> Result<impl Iterator<Item = Vec<String>>> {
+    let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+    let mut epochs: Vec<Vec<String>> = Vec::new();
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('$'))
+    {
+        let starts_epoch = line.get(3..6) == Some("RMC");
+        match epochs.last_mut() {
+            Some(epoch) if !starts_epoch => epoch.push(format!("{}\r\n", line)),
+            _ => epochs.push(vec![format!("{}\r\n", line)]),
+        }
+    }
+    if epochs.is_empty() {
+        return Err(format!("{}: no sentences", file).into());
+    }
+    let cycles = if repeat { usize::MAX } else { 1 };
+    Ok(std::iter::repeat_n(epochs, cycles).flatten())
+}
+
+/// Serial port set to raw mode at `baud` with `stty`, or stdout for `-`
+fn open(path: &str, baud: u32) -> Result<Box<dyn Write>> {
+    if path == "-" {
+        return Ok(Box::new(io::stdout()));
+    }
+    let file = File::options()
+        .write(true)
+        .open(path)
+        .map_err(|error| format!("{}: {}", path, error))?;
+    let status = Command::new("stty")
+        .arg("-F")
+        .arg(path)
+        .arg(baud.to_string())
+        .args(["raw", "-echo"])
+        .status()?;
+    if !status.success() {
+        return Err(format!("stty can't configure {}", path).into());
+    }
+    Ok(Box::new(file))
+}
@@ -0,0 +1,127 @@
+//! Synthesized track: a circle around a centre point driven at constant speed, sent as RMC,
+//! GGA and GSA sentences per epoch like the GP-735T does.
+
+use gp735t_proto::nmea;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Metres per degree of latitude, and of longitude at the equator
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+const KNOTS_PER_MPS: f64 = 1.943_844;
+
+pub struct Track {
+    /// Centre in degrees
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+    speed_mps: f64,
+    /// UTC milliseconds since the Unix epoch of the first and the next epoch
+    start_ms: u64,
+    time_ms: u64,
+    interval_ms: u64,
+}
+
+impl Track {
+    /// Start at the current system time with epochs `interval_ms` apart
+    pub fn new(lat: f64, lon: f64, radius_m: f64, speed_mps: f64, interval_ms: u64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        // Epochs are aligned to whole intervals like the receiver's
+        let start_ms = now - now % interval_ms;
+        Self {
+            lat,
+            lon,
+            radius_m,
+            speed_mps,
+            start_ms,
+            time_ms: start_ms,
+            interval_ms,
+        }
+    }
+
+    /// Sentences of the next epoch, each with checksum and line ending
+    pub fn next_epoch(&mut self) -> Vec<String> {
+        let elapsed_s = (self.time_ms - self.start_ms) as f64 / 1000.0;
+        // Angle around the circle, counterclockwise from east
+        let angle = match self.radius_m {
+            radius if radius > 0.0 => self.speed_mps * elapsed_s / radius,
+            _ => 0.0,
+        };
+        let north_m = self.radius_m * angle.sin();
+        let east_m = self.radius_m * angle.cos();
+        let lat = self.lat + north_m / METRES_PER_DEGREE;
+        let lon = self.lon + east_m / (METRES_PER_DEGREE * self.lat.to_radians().cos());
+        // Heading along the circle, clockwise from north
+        let course = (360.0 - angle.to_degrees() % 360.0) % 360.0;
+
+        let time = utc_time(self.time_ms);
+        let date = utc_date(self.time_ms);
+        let (lat_field, lon_field) = (coordinate(lat, 2, 'N', 'S'), coordinate(lon, 3, 'E', 'W'));
+        let knots = self.speed_mps * KNOTS_PER_MPS;
+        let epoch = vec![
+            sentence(&format!(
+                "GPRMC,{},A,{},{},{:.3},{:.2},{},,,A",
+                time, lat_field, lon_field, knots, course, date
+            )),
+            sentence(&format!(
+                "GPGGA,{},{},{},1,08,0.95,35.0,M,20.1,M,,",
+                time, lat_field, lon_field
+            )),
+            sentence("GPGSA,A,3,02,05,12,13,15,18,24,29,,,,,1.80,0.95,1.53"),
+        ];
+        self.time_ms += self.interval_ms;
+        epoch
+    }
+}
+
+/// `$<body>*hh` and CRLF
+pub fn sentence(body: &str) -> String {
+    format!("${}*{:02X}\r\n", body, nmea::checksum(body.as_bytes()))
+}
+
+/// `hhmmss.ss` of the day
+fn utc_time(ms: u64) -> String {
+    let ms_of_day = ms % 86_400_000;
+    format!(
+        "{:02}{:02}{:02}.{:02}",
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000 / 10
+    )
+}
+
+/// `ddmmyy` from days since the Unix epoch, by the civil from days algorithm
+fn utc_date(ms: u64) -> String {
+    let days = (ms / 86_400_000) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:02}{:02}{:02}", day, month, year % 100)
+}
+
+/// `(d)ddmm.mmmmm,<hemisphere>` with `degree_digits` digits of degrees
+fn coordinate(degrees: f64, degree_digits: usize, positive: char, negative: char) -> String {
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+    let degrees = degrees.abs();
+    let whole = degrees.floor();
+    let minutes = (degrees - whole) * 60.0;
+    format!(
+        "{:0width$}{:08.5},{}",
+        whole as u32,
+        minutes,
+        hemisphere,
+        width = degree_digits
+    )
+}